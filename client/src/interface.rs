@@ -148,15 +148,10 @@ fn initialize_userinformation(
     qos_will_switch: &gtk::Switch,
 ) -> UserInformation {
     UserInformation {
-        id_length: client_id_entry.text().len() as u16,
         id: client_id_entry.text().to_string(),
-        username_length: username_entry.text().len() as u16,
         username: Some(username_entry.text().to_string()),
-        password_length: password_entry.text().len() as u16,
         password: Some(password_entry.text().to_string()),
-        will_topic_length: will_topic_entry.text().len() as u16,
         will_topic: Some(will_topic_entry.text().to_string()),
-        will_message_length: will_message_entry.text().len() as u16,
         will_message: Some(will_message_entry.text().to_string()),
         will_qos: qos_will_switch.is_active() as u8,
         keep_alive: 0,
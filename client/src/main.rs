@@ -14,13 +14,11 @@ use std::time::Duration;
 use crate::packet::{
     _send_disconnect_packet, read_packet, send_packet_connection, send_pingreq_packet,
 };
+use codec::remaining_length_read;
 mod interface;
 mod packet;
 mod publish_interface;
 mod subscription_interface;
-use crate::utils::remaining_length_read;
-
-mod utils;
 
 /*static CLIENT_ARGS: usize = 3;*/
 /// Flags required for client connection
@@ -33,15 +31,10 @@ pub struct FlagsConnection {
 }
 ///Information about the client
 pub struct UserInformation {
-    id_length: u16,
     id: String,
-    username_length: u16,
     username: Option<String>,
-    password_length: u16,
     password: Option<String>,
-    will_topic_length: u16,
     will_topic: Option<String>,
-    will_message_length: u16,
     will_message: Option<String>,
     will_qos: u8,
     keep_alive: u16,
@@ -107,46 +100,6 @@ fn client_run(
     Ok(())
 }
 
-fn create_byte_with_flags(flags: &FlagsConnection, will_qos: &u8) -> u8 {
-    let mut byte_flags: u8 = 0;
-    if flags.username {
-        byte_flags |= 0x80;
-    }
-    if flags.password {
-        byte_flags |= 0x40;
-    }
-    if flags.will_retain {
-        byte_flags |= 0x20;
-    }
-    byte_flags |= (will_qos << 3) & 0x18;
-    if flags.will_flag {
-        byte_flags |= 0x04;
-    }
-    if flags.clean_session {
-        byte_flags |= 0x02;
-    }
-    byte_flags
-}
-
-fn calculate_connection_length(
-    flags: &FlagsConnection,
-    user_information: &UserInformation,
-) -> usize {
-    let mut lenght: usize = 12;
-    lenght += user_information.id_length as usize;
-    if flags.username {
-        lenght += (user_information.username_length as usize) + 2;
-    }
-    if flags.password {
-        lenght += (user_information.password_length as usize) + 2;
-    }
-    if flags.will_flag {
-        lenght += (user_information.will_topic_length as usize)
-            + (user_information.will_message_length as usize)
-            + 4;
-    }
-    lenght
-}
 /// Disconnects client from server by sending disconnect packet and shutting down streams
 fn disconnect(stream: &mut TcpStream, signal: Arc<AtomicBool>) {
     _send_disconnect_packet(stream);
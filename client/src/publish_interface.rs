@@ -72,7 +72,7 @@ impl ReceiverObject {
             }
         }
     }
-/// This is used for the topic_updater. When the glib_receiver receives text, the label's text is updated.
+    /// This is used for the topic_updater. When the glib_receiver receives text, the label's text is updated.
     pub fn update_subs_upon_publish(
         &self,
         glib_receiver: glib::Receiver<String>,
@@ -94,7 +94,7 @@ impl ReceiverObject {
             glib::Continue(true)
         });
     }
-/// Spawns a thread that will listen for text sent by the client and then send it through the glib channel. This is where the channel receiver created in "run_client_and_build_windows" is used.
+    /// Spawns a thread that will listen for text sent by the client and then send it through the glib channel. This is where the channel receiver created in "run_client_and_build_windows" is used.
     pub fn start(&mut self, glib_sender: glib::Sender<String>, receiver: Receiver<String>) {
         thread::spawn(move || loop {
             match receiver.recv() {
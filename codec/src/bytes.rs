@@ -0,0 +1,173 @@
+//! # Bytes
+//!
+//! Bounds-checked reading and writing of the data types that make up a packet.
+
+use crate::length::{remaining_length_encode, variable_byte_integer_decode, MAX_REMAINING_LENGTH};
+use crate::properties::{decode_properties, encode_properties, Property};
+use crate::{CodecError, MQTT_VERSION_5};
+
+/// Cursor over the bytes of a packet. Every read fails instead of going out of bounds.
+pub(crate) struct Reader<'a> {
+    buffer: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Reader { buffer, index: 0 }
+    }
+
+    pub fn read_bytes(&mut self, size: usize) -> Result<&'a [u8], CodecError> {
+        let end = self
+            .index
+            .checked_add(size)
+            .ok_or(CodecError::UnexpectedEnd)?;
+        let bytes = self
+            .buffer
+            .get(self.index..end)
+            .ok_or(CodecError::UnexpectedEnd)?;
+        self.index = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, CodecError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, CodecError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, CodecError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_variable_byte_integer(&mut self) -> Result<usize, CodecError> {
+        let (value, size) = variable_byte_integer_decode(self.rest_slice())?;
+        self.index += size;
+        Ok(value)
+    }
+
+    /// Binary data prefixed by its two byte length.
+    pub fn read_binary(&mut self) -> Result<Vec<u8>, CodecError> {
+        let size = self.read_u16()? as usize;
+        Ok(self.read_bytes(size)?.to_vec())
+    }
+
+    /// UTF-8 string prefixed by its two byte length.
+    pub fn read_string(&mut self) -> Result<String, CodecError> {
+        let size = self.read_u16()? as usize;
+        let bytes = self.read_bytes(size)?;
+        match std::str::from_utf8(bytes) {
+            Ok(string) => Ok(string.to_owned()),
+            Err(_) => Err(CodecError::InvalidUtf8),
+        }
+    }
+
+    /// Property section, which only MQTT 5 packets have.
+    pub fn read_properties(&mut self, protocol_version: u8) -> Result<Vec<Property>, CodecError> {
+        if protocol_version != MQTT_VERSION_5 {
+            return Ok(Vec::new());
+        }
+        let (properties, size) = decode_properties(self.rest_slice())?;
+        self.index += size;
+        Ok(properties)
+    }
+
+    /// All the bytes not read yet.
+    pub fn read_rest(&mut self) -> &'a [u8] {
+        let rest = self.rest_slice();
+        self.index = self.buffer.len();
+        rest
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index >= self.buffer.len()
+    }
+
+    fn rest_slice(&self) -> &'a [u8] {
+        self.buffer.get(self.index..).unwrap_or_default()
+    }
+}
+
+pub(crate) fn write_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend(&value.to_be_bytes());
+}
+
+/// Write binary data prefixed by its two byte length.
+pub(crate) fn write_binary(buffer: &mut Vec<u8>, data: &[u8]) -> Result<(), CodecError> {
+    if data.len() > u16::MAX as usize {
+        return Err(CodecError::DataTooLong(data.len()));
+    }
+    write_u16(buffer, data.len() as u16);
+    buffer.extend(data);
+    Ok(())
+}
+
+/// Write a UTF-8 string prefixed by its two byte length.
+pub(crate) fn write_string(buffer: &mut Vec<u8>, string: &str) -> Result<(), CodecError> {
+    write_binary(buffer, string.as_bytes())
+}
+
+/// Write the property section if the packet is for a MQTT 5 peer.
+pub(crate) fn write_properties(
+    buffer: &mut Vec<u8>,
+    properties: &[Property],
+    protocol_version: u8,
+) -> Result<(), CodecError> {
+    if protocol_version == MQTT_VERSION_5 {
+        buffer.append(&mut encode_properties(properties)?);
+    }
+    Ok(())
+}
+
+/// Complete packet: first byte, remaining length and `body`.
+pub(crate) fn with_fixed_header(byte_0: u8, mut body: Vec<u8>) -> Result<Vec<u8>, CodecError> {
+    if body.len() > MAX_REMAINING_LENGTH {
+        return Err(CodecError::PacketTooLarge(body.len()));
+    }
+    let mut packet = vec![byte_0];
+    packet.append(&mut remaining_length_encode(body.len()));
+    packet.append(&mut body);
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_lectura_fuera_de_rango_es_error() {
+        let mut reader = Reader::new(&[0, 5, 97, 115]);
+        assert_eq!(reader.read_string(), Err(CodecError::UnexpectedEnd));
+        let mut reader = Reader::new(&[0]);
+        assert_eq!(reader.read_u16(), Err(CodecError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test02_string_escrito_se_lee_igual() {
+        let mut buffer: Vec<u8> = Vec::new();
+        write_string(&mut buffer, "as/ti").unwrap();
+        let mut reader = Reader::new(&buffer);
+        assert_eq!(reader.read_string(), Ok("as/ti".to_owned()));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test03_string_invalido_es_error() {
+        let mut reader = Reader::new(&[0, 2, 0xC3, 0x28]);
+        assert_eq!(reader.read_string(), Err(CodecError::InvalidUtf8));
+    }
+
+    #[test]
+    fn test04_dato_demasiado_largo_es_error() {
+        let mut buffer: Vec<u8> = Vec::new();
+        let data = vec![0; 70000];
+        assert_eq!(
+            write_binary(&mut buffer, &data),
+            Err(CodecError::DataTooLong(70000))
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! # Connect
+//!
+//! _Connect_ and _Connack_ packets.
+
+use crate::bytes::{
+    with_fixed_header, write_binary, write_properties, write_string, write_u16, Reader,
+};
+use crate::properties::Property;
+use crate::{CodecError, MQTT_VERSION, MQTT_VERSION_3, MQTT_VERSION_5};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const MQTT_NAME: [u8; 6] = [0x00, 0x04, 0x4D, 0x51, 0x54, 0x54];
+const MQISDP_NAME: [u8; 8] = [0x00, 0x06, 0x4D, 0x51, 0x49, 0x73, 0x64, 0x70];
+
+/// Message the server publishes on behalf of a client that disconnects without notice.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LastWill {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+    pub properties: Vec<Property>,
+}
+
+/// _Connect_ packet, the first one a client sends.
+///
+/// `protocol_version` is the protocol level: 3 for MQTT 3.1, 4 for 3.1.1 and 5 for 5.0.
+/// On MQTT 5 `clean_session` is the _Clean Start_ flag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connect {
+    pub protocol_version: u8,
+    pub clean_session: bool,
+    pub keep_alive: u16,
+    pub properties: Vec<Property>,
+    pub client_id: String,
+    pub will: Option<LastWill>,
+    pub username: Option<String>,
+    pub password: Option<Vec<u8>>,
+}
+
+impl Connect {
+    /// Decode the variable header and payload of a _Connect_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if:
+    /// - The protocol name is not "MQTT" or "MQIsdp" ([`CodecError::InvalidProtocolName`]).
+    /// - The protocol level is not supported or doesn't match the name
+    ///   ([`CodecError::UnsupportedProtocolLevel`]).
+    /// - The packet is truncated or malformed.
+    pub fn decode(buffer: &[u8]) -> Result<Self, CodecError> {
+        let legacy = if buffer.starts_with(&MQISDP_NAME) {
+            true
+        } else if buffer.starts_with(&MQTT_NAME) {
+            false
+        } else {
+            return Err(CodecError::InvalidProtocolName);
+        };
+        let mut reader = Reader::new(buffer);
+        reader.read_string()?;
+        let protocol_version = reader.read_u8()?;
+        verify_protocol_level(protocol_version, legacy)?;
+
+        let flags = reader.read_u8()?;
+        if flags & 0x01 == 0x01 {
+            return Err(CodecError::MalformedPacket(
+                "Reserved connect flag set".to_owned(),
+            ));
+        }
+        let flag_username = flags & 0x80 == 0x80;
+        let flag_password = flags & 0x40 == 0x40;
+        let flag_will_retain = flags & 0x20 == 0x20;
+        let flag_will_qos = (flags & 0x18) >> 3;
+        let flag_will = flags & 0x04 == 0x04;
+        let clean_session = flags & 0x02 == 0x02;
+        if flag_will_qos > 2 {
+            return Err(CodecError::InvalidQos(flag_will_qos));
+        }
+
+        let keep_alive = reader.read_u16()?;
+        let properties = reader.read_properties(protocol_version)?;
+        let client_id = reader.read_string()?;
+
+        let mut will = None;
+        if flag_will {
+            let will_properties = reader.read_properties(protocol_version)?;
+            let topic = reader.read_string()?;
+            let payload = reader.read_binary()?;
+            will = Some(LastWill {
+                topic,
+                payload,
+                qos: flag_will_qos,
+                retain: flag_will_retain,
+                properties: will_properties,
+            });
+        }
+
+        let mut username = None;
+        if flag_username {
+            username = Some(reader.read_string()?);
+        }
+        let mut password = None;
+        if flag_password {
+            password = Some(reader.read_binary()?);
+        }
+
+        Ok(Connect {
+            protocol_version,
+            clean_session,
+            keep_alive,
+            properties,
+            client_id,
+            will,
+            username,
+            password,
+        })
+    }
+
+    /// Encode the complete _Connect_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the protocol level is not supported or a field is too long.
+    pub fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        let mut body: Vec<u8> = Vec::new();
+        match self.protocol_version {
+            MQTT_VERSION_3 => body.extend(&MQISDP_NAME),
+            MQTT_VERSION | MQTT_VERSION_5 => body.extend(&MQTT_NAME),
+            level => return Err(CodecError::UnsupportedProtocolLevel(level)),
+        }
+        body.push(self.protocol_version);
+
+        let mut flags: u8 = 0;
+        if self.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.password.is_some() {
+            flags |= 0x40;
+        }
+        if let Some(will) = &self.will {
+            if will.qos > 2 {
+                return Err(CodecError::InvalidQos(will.qos));
+            }
+            if will.retain {
+                flags |= 0x20;
+            }
+            flags |= will.qos << 3;
+            flags |= 0x04;
+        }
+        if self.clean_session {
+            flags |= 0x02;
+        }
+        body.push(flags);
+        write_u16(&mut body, self.keep_alive);
+        write_properties(&mut body, &self.properties, self.protocol_version)?;
+        write_string(&mut body, &self.client_id)?;
+        if let Some(will) = &self.will {
+            write_properties(&mut body, &will.properties, self.protocol_version)?;
+            write_string(&mut body, &will.topic)?;
+            write_binary(&mut body, &will.payload)?;
+        }
+        if let Some(username) = &self.username {
+            write_string(&mut body, username)?;
+        }
+        if let Some(password) = &self.password {
+            write_binary(&mut body, password)?;
+        }
+        with_fixed_header(CONNECT, body)
+    }
+}
+
+/// Check the protocol level is supported and matches the protocol name:
+/// 3 goes with "MQIsdp", 4 and 5 with "MQTT".
+fn verify_protocol_level(protocol_version: u8, legacy: bool) -> Result<(), CodecError> {
+    let supported = if legacy {
+        protocol_version == MQTT_VERSION_3
+    } else {
+        protocol_version == MQTT_VERSION || protocol_version == MQTT_VERSION_5
+    };
+    if supported {
+        return Ok(());
+    }
+    Err(CodecError::UnsupportedProtocolLevel(protocol_version))
+}
+
+/// _Connack_ packet, the server answer to a _Connect_.
+///
+/// `return_code` is the MQTT 3.1.1 return code, or the reason code for MQTT 5.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnAck {
+    pub session_present: bool,
+    pub return_code: u8,
+    pub properties: Vec<Property>,
+}
+
+impl ConnAck {
+    /// Decode the variable header of a _Connack_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated or malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let mut reader = Reader::new(buffer);
+        let session_present = reader.read_u8()? & 0x01 == 0x01;
+        let return_code = reader.read_u8()?;
+        let properties = reader.read_properties(protocol_version)?;
+        Ok(ConnAck {
+            session_present,
+            return_code,
+            properties,
+        })
+    }
+
+    /// Encode the complete _Connack_ packet.
+    ///
+    /// MQTT 3.1 has no session present flag, so it is always sent as 0.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        let session_present = self.session_present && protocol_version != MQTT_VERSION_3;
+        let mut body: Vec<u8> = vec![session_present as u8, self.return_code];
+        write_properties(&mut body, &self.properties, protocol_version)?;
+        with_fixed_header(CONNACK, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::properties::{PropertyValue, SESSION_EXPIRY_INTERVAL};
+
+    fn connect_con_will(protocol_version: u8) -> Connect {
+        Connect {
+            protocol_version,
+            clean_session: true,
+            keep_alive: 60,
+            properties: Vec::new(),
+            client_id: "sensor".to_owned(),
+            will: Some(LastWill {
+                topic: "as/ti".to_owned(),
+                payload: vec![0, 159, 146, 150],
+                qos: 1,
+                retain: true,
+                properties: Vec::new(),
+            }),
+            username: Some("homero".to_owned()),
+            password: Some(b"dona".to_vec()),
+        }
+    }
+
+    #[test]
+    fn test01_nombre_protocolo_correto() {
+        let mut connect = connect_con_will(MQTT_VERSION);
+        connect.will = None;
+        let encoded = connect.encode().unwrap();
+        assert_eq!(&encoded[2..8], &MQTT_NAME);
+        assert_eq!(Connect::decode(&encoded[2..]), Ok(connect));
+    }
+
+    #[test]
+    fn test02_nombre_protocolo_incorrecto() {
+        let nombre: [u8; 7] = [0x00, 0x04, 0x4E, 0x51, 0x54, 0x14, 0x04];
+        assert_eq!(
+            Connect::decode(nombre.as_ref()),
+            Err(CodecError::InvalidProtocolName)
+        );
+    }
+
+    #[test]
+    fn test03_version_protocolo_incorrecta() {
+        let mut buffer: Vec<u8> = MQTT_NAME.to_vec();
+        buffer.push(45);
+        assert_eq!(
+            Connect::decode(&buffer),
+            Err(CodecError::UnsupportedProtocolLevel(45))
+        );
+    }
+
+    #[test]
+    fn test04_nombre_protocolo_mqisdp_correcto() {
+        let connect = connect_con_will(MQTT_VERSION_3);
+        let encoded = connect.encode().unwrap();
+        assert_eq!(&encoded[2..10], &MQISDP_NAME);
+        assert_eq!(Connect::decode(&encoded[2..]), Ok(connect));
+    }
+
+    #[test]
+    fn test05_nivel_de_protocolo_que_no_corresponde_al_nombre_es_rechazado() {
+        let mut mqtt_nivel_3: Vec<u8> = MQTT_NAME.to_vec();
+        mqtt_nivel_3.push(MQTT_VERSION_3);
+        assert_eq!(
+            Connect::decode(&mqtt_nivel_3),
+            Err(CodecError::UnsupportedProtocolLevel(MQTT_VERSION_3))
+        );
+        let mut mqisdp_nivel_4: Vec<u8> = MQISDP_NAME.to_vec();
+        mqisdp_nivel_4.push(MQTT_VERSION);
+        assert_eq!(
+            Connect::decode(&mqisdp_nivel_4),
+            Err(CodecError::UnsupportedProtocolLevel(MQTT_VERSION))
+        );
+    }
+
+    #[test]
+    fn test06_connect_mqtt5_con_propiedades_se_decodifica_igual() {
+        let mut connect = connect_con_will(MQTT_VERSION_5);
+        connect.properties = vec![Property::new(
+            SESSION_EXPIRY_INTERVAL,
+            PropertyValue::FourByteInteger(3600),
+        )];
+        let encoded = connect.encode().unwrap();
+        assert_eq!(Connect::decode(&encoded[2..]), Ok(connect));
+    }
+
+    #[test]
+    fn test07_connect_truncado_devuelve_error() {
+        let encoded = connect_con_will(MQTT_VERSION).encode().unwrap();
+        for end in 8..encoded.len() {
+            assert!(Connect::decode(&encoded[2..end]).is_err());
+        }
+    }
+
+    #[test]
+    fn test08_connack_mqtt31_no_tiene_session_present() {
+        let connack = ConnAck {
+            session_present: true,
+            return_code: 0,
+            properties: Vec::new(),
+        };
+        assert_eq!(connack.encode(MQTT_VERSION), Ok(vec![0x20, 2, 1, 0]));
+        assert_eq!(connack.encode(MQTT_VERSION_3), Ok(vec![0x20, 2, 0, 0]));
+        assert_eq!(connack.encode(MQTT_VERSION_5), Ok(vec![0x20, 3, 1, 0, 0]));
+    }
+}
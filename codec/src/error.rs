@@ -0,0 +1,66 @@
+//! # Error
+//!
+//! Errors found while decoding or encoding a packet.
+
+use std::fmt;
+use std::io::ErrorKind;
+
+/// Reason why a packet couldn't be decoded or encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecError {
+    /// The buffer ended before the packet was complete.
+    UnexpectedEnd,
+    /// A variable byte integer takes more than four bytes.
+    MalformedVariableByteInteger,
+    /// A string is not valid UTF-8.
+    InvalidUtf8,
+    /// The _Connect_ packet doesn't start with "MQTT" or "MQIsdp".
+    InvalidProtocolName,
+    /// The protocol level is not supported or doesn't match the protocol name.
+    UnsupportedProtocolLevel(u8),
+    /// QoS level greater than 2.
+    InvalidQos(u8),
+    /// MQTT 5 property identifier not defined by the protocol.
+    UnknownProperty(u8),
+    /// Packet type not defined by the protocol.
+    UnknownPacketType(u8),
+    /// A string or binary field doesn't fit its two byte length.
+    DataTooLong(usize),
+    /// The packet doesn't fit the maximum remaining length.
+    PacketTooLarge(usize),
+    /// Any other violation of the packet format.
+    MalformedPacket(String),
+    /// Couldn't read from the stream.
+    Io(ErrorKind),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::UnexpectedEnd => write!(f, "Packet ended unexpectedly"),
+            CodecError::MalformedVariableByteInteger => {
+                write!(f, "Malformed variable byte integer")
+            }
+            CodecError::InvalidUtf8 => write!(f, "String is not valid UTF-8"),
+            CodecError::InvalidProtocolName => write!(f, "Wrong connection protocol name"),
+            CodecError::UnsupportedProtocolLevel(level) => {
+                write!(f, "Unsupported protocol level {}", level)
+            }
+            CodecError::InvalidQos(qos) => write!(f, "Invalid QoS level {}", qos),
+            CodecError::UnknownProperty(id) => write!(f, "Unknown property identifier {}", id),
+            CodecError::UnknownPacketType(byte_0) => {
+                write!(f, "Unknown packet type {:#04x}", byte_0)
+            }
+            CodecError::DataTooLong(size) => {
+                write!(f, "Field of {} bytes doesn't fit a two byte length", size)
+            }
+            CodecError::PacketTooLarge(size) => {
+                write!(f, "Packet of {} bytes exceeds the maximum size", size)
+            }
+            CodecError::MalformedPacket(reason) => write!(f, "Malformed packet: {}", reason),
+            CodecError::Io(kind) => write!(f, "Error reading from the stream: {:?}", kind),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
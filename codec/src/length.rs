@@ -0,0 +1,144 @@
+//! # Length
+//!
+//! Variable byte integers, used for the remaining length of every packet and some MQTT 5 fields.
+
+use crate::CodecError;
+use std::io::Read;
+
+/// Maximum value of a variable byte integer.
+pub const MAX_REMAINING_LENGTH: usize = 268_435_455;
+const MAX_BYTES: usize = 4;
+
+/// Read the remaining length of a packet from `stream`, right after its first byte.
+///
+/// # Errors
+///
+/// Returns Error if couldn't read from stream or the length takes more than four bytes.
+pub fn remaining_length_read<R: Read>(stream: &mut R) -> Result<usize, CodecError> {
+    let mut buffer = [0u8; 1];
+    let mut multiplier: usize = 1;
+    let mut value: usize = 0;
+
+    for _ in 0..MAX_BYTES {
+        if let Err(e) = stream.read_exact(&mut buffer) {
+            return Err(CodecError::Io(e.kind()));
+        }
+        value += ((buffer[0] & 0x7F) as usize) * multiplier;
+        if buffer[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        multiplier *= 0x80;
+    }
+
+    Err(CodecError::MalformedVariableByteInteger)
+}
+
+/// Decode the variable byte integer at the start of `buffer`.
+///
+/// Returns its value and the amount of bytes it takes.
+pub fn variable_byte_integer_decode(buffer: &[u8]) -> Result<(usize, usize), CodecError> {
+    let mut multiplier: usize = 1;
+    let mut value: usize = 0;
+
+    for i in 0..MAX_BYTES {
+        let byte = match buffer.get(i) {
+            Some(byte) => *byte,
+            None => return Err(CodecError::UnexpectedEnd),
+        };
+        value += ((byte & 0x7F) as usize) * multiplier;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+        multiplier *= 0x80;
+    }
+
+    Err(CodecError::MalformedVariableByteInteger)
+}
+
+/// Encode `remaining_length` as a variable byte integer.
+pub fn remaining_length_encode(remaining_length: usize) -> Vec<u8> {
+    let mut byte: u8;
+    let mut result = Vec::<u8>::new();
+    let mut length = remaining_length;
+
+    loop {
+        byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 128;
+        }
+        result.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_remaining_length_encode_correcto1() {
+        assert_eq!(remaining_length_encode(64), vec![0x40]);
+    }
+
+    #[test]
+    fn test02_remaining_length_encode_correcto2() {
+        assert_eq!(remaining_length_encode(321), vec![0xC1, 0x02]);
+    }
+
+    #[test]
+    fn test03_remaining_length_encode_incorrecto() {
+        assert_ne!(remaining_length_encode(64), vec![0x42]);
+    }
+
+    #[test]
+    fn test04_remaining_length_decode_correcto1() {
+        assert_eq!(variable_byte_integer_decode(&[0x40]), Ok((64, 1)));
+    }
+
+    #[test]
+    fn test05_remaining_length_decode_correcto2() {
+        assert_eq!(variable_byte_integer_decode(&[0xC1, 0x02]), Ok((321, 2)));
+    }
+
+    #[test]
+    fn test06_remaining_length_decode_incorrecto() {
+        assert_ne!(variable_byte_integer_decode(&[0x40]), Ok((60, 1)));
+    }
+
+    #[test]
+    fn test07_remaining_length_de_mas_de_cuatro_bytes_es_error() {
+        let buffer = [0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(
+            variable_byte_integer_decode(&buffer),
+            Err(CodecError::MalformedVariableByteInteger)
+        );
+        assert_eq!(
+            remaining_length_read(&mut buffer.as_ref()),
+            Err(CodecError::MalformedVariableByteInteger)
+        );
+    }
+
+    #[test]
+    fn test08_remaining_length_truncado_es_error() {
+        assert_eq!(
+            variable_byte_integer_decode(&[0xC1]),
+            Err(CodecError::UnexpectedEnd)
+        );
+        assert!(matches!(
+            remaining_length_read(&mut [0xC1].as_ref()),
+            Err(CodecError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test09_remaining_length_se_lee_del_stream() {
+        let mut stream: &[u8] = &[0xC1, 0x02, 0x30];
+        assert_eq!(remaining_length_read(&mut stream), Ok(321));
+        assert_eq!(stream, &[0x30]);
+    }
+}
@@ -0,0 +1,27 @@
+//! # Codec
+//!
+//! MQTT control packets shared by the client and the server: a typed struct for each packet,
+//! and functions to decode them from bytes and encode them back that fail with a [`CodecError`]
+//! instead of panicking on malformed input.
+//!
+//! Protocol levels 3 (MQTT 3.1), 4 (MQTT 3.1.1) and 5 (MQTT 5.0) are supported.
+
+mod bytes;
+mod connect;
+mod error;
+pub mod length;
+mod packet;
+pub mod properties;
+mod publish;
+mod subscribe;
+
+pub use connect::{ConnAck, Connect, LastWill};
+pub use error::CodecError;
+pub use length::{remaining_length_encode, remaining_length_read};
+pub use packet::{decode_packet, ControlPacket, Disconnect};
+pub use publish::{PubAck, PubComp, PubRec, PubRel, Publish};
+pub use subscribe::{SubAck, Subscribe, SubscribeTopic, UnsubAck, Unsubscribe};
+
+pub const MQTT_VERSION_3: u8 = 3;
+pub const MQTT_VERSION: u8 = 4;
+pub const MQTT_VERSION_5: u8 = 5;
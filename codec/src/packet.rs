@@ -0,0 +1,216 @@
+//! # Packet
+//!
+//! Any control packet, decoded from its first byte and the rest of the packet.
+
+use crate::bytes::{with_fixed_header, write_properties, Reader};
+use crate::connect::{ConnAck, Connect};
+use crate::length::variable_byte_integer_decode;
+use crate::properties::Property;
+use crate::publish::{PubAck, PubComp, PubRec, PubRel, Publish};
+use crate::subscribe::{SubAck, Subscribe, UnsubAck, Unsubscribe};
+use crate::{CodecError, MQTT_VERSION_5};
+
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 0xE0;
+
+/// _Disconnect_ packet. Only MQTT 5 carries a reason code and properties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disconnect {
+    pub reason_code: u8,
+    pub properties: Vec<Property>,
+}
+
+impl Disconnect {
+    /// Disconnection with `reason_code` and no properties.
+    pub fn new(reason_code: u8) -> Self {
+        Disconnect {
+            reason_code,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Decode the variable header of a _Disconnect_ packet. An empty one is a normal disconnection.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the properties are malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let mut reader = Reader::new(buffer);
+        let mut disconnect = Disconnect::new(0);
+        if protocol_version == MQTT_VERSION_5 && !reader.is_empty() {
+            disconnect.reason_code = reader.read_u8()?;
+            if !reader.is_empty() {
+                disconnect.properties = reader.read_properties(protocol_version)?;
+            }
+        }
+        Ok(disconnect)
+    }
+
+    /// Encode the complete _Disconnect_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        let mut body: Vec<u8> = Vec::new();
+        if protocol_version == MQTT_VERSION_5 {
+            body.push(self.reason_code);
+            write_properties(&mut body, &self.properties, protocol_version)?;
+        }
+        with_fixed_header(DISCONNECT, body)
+    }
+}
+
+/// Any MQTT control packet.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlPacket {
+    Connect(Connect),
+    ConnAck(ConnAck),
+    Publish(Publish),
+    PubAck(PubAck),
+    PubRec(PubRec),
+    PubRel(PubRel),
+    PubComp(PubComp),
+    Subscribe(Subscribe),
+    SubAck(SubAck),
+    Unsubscribe(Unsubscribe),
+    UnsubAck(UnsubAck),
+    PingReq,
+    PingResp,
+    Disconnect(Disconnect),
+}
+
+impl ControlPacket {
+    /// Decode a packet given its first byte and the rest of the packet after the remaining length.
+    ///
+    /// `protocol_version` is the one negotiated on the connection; the _Connect_ packet carries its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet type is unknown, or the packet is truncated or malformed.
+    pub fn decode(byte_0: u8, buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let packet = match byte_0 & 0xF0 {
+            0x10 => ControlPacket::Connect(Connect::decode(buffer)?),
+            0x20 => ControlPacket::ConnAck(ConnAck::decode(buffer, protocol_version)?),
+            0x30 => ControlPacket::Publish(Publish::decode(byte_0, buffer, protocol_version)?),
+            0x40 => ControlPacket::PubAck(PubAck::decode(buffer, protocol_version)?),
+            0x50 => ControlPacket::PubRec(PubRec::decode(buffer, protocol_version)?),
+            0x60 => ControlPacket::PubRel(PubRel::decode(buffer, protocol_version)?),
+            0x70 => ControlPacket::PubComp(PubComp::decode(buffer, protocol_version)?),
+            0x80 => ControlPacket::Subscribe(Subscribe::decode(buffer, protocol_version)?),
+            0x90 => ControlPacket::SubAck(SubAck::decode(buffer, protocol_version)?),
+            0xA0 => ControlPacket::Unsubscribe(Unsubscribe::decode(buffer, protocol_version)?),
+            0xB0 => ControlPacket::UnsubAck(UnsubAck::decode(buffer, protocol_version)?),
+            0xC0 => ControlPacket::PingReq,
+            0xD0 => ControlPacket::PingResp,
+            0xE0 => ControlPacket::Disconnect(Disconnect::decode(buffer, protocol_version)?),
+            _ => return Err(CodecError::UnknownPacketType(byte_0)),
+        };
+        Ok(packet)
+    }
+
+    /// Encode the complete packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a field is invalid or too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        match self {
+            ControlPacket::Connect(connect) => connect.encode(),
+            ControlPacket::ConnAck(connack) => connack.encode(protocol_version),
+            ControlPacket::Publish(publish) => publish.encode(protocol_version),
+            ControlPacket::PubAck(puback) => puback.encode(protocol_version),
+            ControlPacket::PubRec(pubrec) => pubrec.encode(protocol_version),
+            ControlPacket::PubRel(pubrel) => pubrel.encode(protocol_version),
+            ControlPacket::PubComp(pubcomp) => pubcomp.encode(protocol_version),
+            ControlPacket::Subscribe(subscribe) => subscribe.encode(protocol_version),
+            ControlPacket::SubAck(suback) => suback.encode(protocol_version),
+            ControlPacket::Unsubscribe(unsubscribe) => unsubscribe.encode(protocol_version),
+            ControlPacket::UnsubAck(unsuback) => unsuback.encode(protocol_version),
+            ControlPacket::PingReq => with_fixed_header(PINGREQ, Vec::new()),
+            ControlPacket::PingResp => with_fixed_header(PINGRESP, Vec::new()),
+            ControlPacket::Disconnect(disconnect) => disconnect.encode(protocol_version),
+        }
+    }
+}
+
+/// Decode a complete packet, fixed header included.
+///
+/// # Errors
+///
+/// Returns Error if the remaining length doesn't match the size of the packet, or the packet
+/// can't be decoded.
+pub fn decode_packet(packet: &[u8], protocol_version: u8) -> Result<ControlPacket, CodecError> {
+    let byte_0 = *packet.first().ok_or(CodecError::UnexpectedEnd)?;
+    let (remaining_length, length_size) = variable_byte_integer_decode(&packet[1..])?;
+    let start = 1 + length_size;
+    if packet.len() - start != remaining_length {
+        return Err(CodecError::MalformedPacket(
+            "Remaining length doesn't match the packet size".to_owned(),
+        ));
+    }
+    ControlPacket::decode(byte_0, &packet[start..], protocol_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MQTT_VERSION;
+
+    #[test]
+    fn test01_pingreq_y_pingresp() {
+        assert_eq!(
+            ControlPacket::PingReq.encode(MQTT_VERSION),
+            Ok(vec![0xC0, 0])
+        );
+        assert_eq!(
+            decode_packet(&[0xD0, 0], MQTT_VERSION),
+            Ok(ControlPacket::PingResp)
+        );
+    }
+
+    #[test]
+    fn test02_disconnect_mqtt5_lleva_reason_code() {
+        let disconnect = Disconnect::new(0x8E);
+        assert_eq!(disconnect.encode(MQTT_VERSION), Ok(vec![0xE0, 0]));
+        let encoded = disconnect.encode(MQTT_VERSION_5).unwrap();
+        assert_eq!(encoded, vec![0xE0, 2, 0x8E, 0]);
+        assert_eq!(
+            decode_packet(&encoded, MQTT_VERSION_5),
+            Ok(ControlPacket::Disconnect(disconnect))
+        );
+        assert_eq!(
+            Disconnect::decode(&[], MQTT_VERSION_5),
+            Ok(Disconnect::new(0))
+        );
+    }
+
+    #[test]
+    fn test03_paquete_de_tipo_desconocido_o_longitud_incorrecta_es_error() {
+        assert_eq!(
+            ControlPacket::decode(0x00, &[], MQTT_VERSION),
+            Err(CodecError::UnknownPacketType(0x00))
+        );
+        assert!(decode_packet(&[0x40, 3, 0, 9], MQTT_VERSION).is_err());
+        assert!(decode_packet(&[], MQTT_VERSION).is_err());
+    }
+
+    #[test]
+    fn test04_publish_completo_se_decodifica() {
+        let publish = Publish {
+            dup: true,
+            qos: 2,
+            retain: false,
+            topic_name: "as".to_owned(),
+            packet_identifier: Some(9),
+            properties: Vec::new(),
+            payload: b"miau".to_vec(),
+        };
+        let encoded = publish.encode(MQTT_VERSION).unwrap();
+        assert_eq!(
+            decode_packet(&encoded, MQTT_VERSION),
+            Ok(ControlPacket::Publish(publish))
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! # Properties
+//!
+//! MQTT 5 properties, found in the variable header of most packets (and in the _Connect_ payload for the last will).
+
+use crate::bytes::{write_binary, write_string, write_u16, Reader};
+use crate::length::{remaining_length_encode, variable_byte_integer_decode};
+use crate::CodecError;
+
+pub const PAYLOAD_FORMAT_INDICATOR: u8 = 0x01;
+pub const MESSAGE_EXPIRY_INTERVAL: u8 = 0x02;
+pub const CONTENT_TYPE: u8 = 0x03;
+pub const RESPONSE_TOPIC: u8 = 0x08;
+pub const CORRELATION_DATA: u8 = 0x09;
+pub const SUBSCRIPTION_IDENTIFIER: u8 = 0x0B;
+pub const SESSION_EXPIRY_INTERVAL: u8 = 0x11;
+pub const ASSIGNED_CLIENT_IDENTIFIER: u8 = 0x12;
+pub const SERVER_KEEP_ALIVE: u8 = 0x13;
+pub const AUTHENTICATION_METHOD: u8 = 0x15;
+pub const AUTHENTICATION_DATA: u8 = 0x16;
+pub const REQUEST_PROBLEM_INFORMATION: u8 = 0x17;
+pub const WILL_DELAY_INTERVAL: u8 = 0x18;
+pub const REQUEST_RESPONSE_INFORMATION: u8 = 0x19;
+pub const RESPONSE_INFORMATION: u8 = 0x1A;
+pub const SERVER_REFERENCE: u8 = 0x1C;
+pub const REASON_STRING: u8 = 0x1F;
+pub const RECEIVE_MAXIMUM: u8 = 0x21;
+pub const TOPIC_ALIAS_MAXIMUM: u8 = 0x22;
+pub const TOPIC_ALIAS: u8 = 0x23;
+pub const MAXIMUM_QOS: u8 = 0x24;
+pub const RETAIN_AVAILABLE: u8 = 0x25;
+pub const USER_PROPERTY: u8 = 0x26;
+pub const MAXIMUM_PACKET_SIZE: u8 = 0x27;
+pub const WILDCARD_SUBSCRIPTION_AVAILABLE: u8 = 0x28;
+pub const SUBSCRIPTION_IDENTIFIER_AVAILABLE: u8 = 0x29;
+pub const SHARED_SUBSCRIPTION_AVAILABLE: u8 = 0x2A;
+
+/// Value of a property, according to the data type of its identifier.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Byte(u8),
+    TwoByteInteger(u16),
+    FourByteInteger(u32),
+    VariableByteInteger(u32),
+    Utf8String(String),
+    BinaryData(Vec<u8>),
+    Utf8StringPair(String, String),
+}
+
+/// A single property: its identifier and value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub id: u8,
+    pub value: PropertyValue,
+}
+
+impl Property {
+    pub fn new(id: u8, value: PropertyValue) -> Self {
+        Property { id, value }
+    }
+}
+
+/// Decode the property section at the start of `buffer`, length prefix included.
+///
+/// Returns the properties and the amount of bytes the section takes.
+///
+/// # Errors
+///
+/// Returns Error if the section is truncated or has an unknown property identifier.
+pub fn decode_properties(buffer: &[u8]) -> Result<(Vec<Property>, usize), CodecError> {
+    let (length, length_size) = variable_byte_integer_decode(buffer)?;
+    let end = length_size + length;
+    let section = match buffer.get(length_size..end) {
+        Some(section) => section,
+        None => return Err(CodecError::UnexpectedEnd),
+    };
+    let mut reader = Reader::new(section);
+    let mut properties: Vec<Property> = Vec::new();
+    while !reader.is_empty() {
+        let id = reader.read_u8()?;
+        let value = match id {
+            PAYLOAD_FORMAT_INDICATOR
+            | REQUEST_PROBLEM_INFORMATION
+            | REQUEST_RESPONSE_INFORMATION
+            | MAXIMUM_QOS
+            | RETAIN_AVAILABLE
+            | WILDCARD_SUBSCRIPTION_AVAILABLE
+            | SUBSCRIPTION_IDENTIFIER_AVAILABLE
+            | SHARED_SUBSCRIPTION_AVAILABLE => PropertyValue::Byte(reader.read_u8()?),
+            SERVER_KEEP_ALIVE | RECEIVE_MAXIMUM | TOPIC_ALIAS_MAXIMUM | TOPIC_ALIAS => {
+                PropertyValue::TwoByteInteger(reader.read_u16()?)
+            }
+            MESSAGE_EXPIRY_INTERVAL
+            | SESSION_EXPIRY_INTERVAL
+            | WILL_DELAY_INTERVAL
+            | MAXIMUM_PACKET_SIZE => PropertyValue::FourByteInteger(reader.read_u32()?),
+            SUBSCRIPTION_IDENTIFIER => {
+                PropertyValue::VariableByteInteger(reader.read_variable_byte_integer()? as u32)
+            }
+            CONTENT_TYPE
+            | RESPONSE_TOPIC
+            | ASSIGNED_CLIENT_IDENTIFIER
+            | AUTHENTICATION_METHOD
+            | RESPONSE_INFORMATION
+            | SERVER_REFERENCE
+            | REASON_STRING => PropertyValue::Utf8String(reader.read_string()?),
+            CORRELATION_DATA | AUTHENTICATION_DATA => {
+                PropertyValue::BinaryData(reader.read_binary()?)
+            }
+            USER_PROPERTY => {
+                let name = reader.read_string()?;
+                let value = reader.read_string()?;
+                PropertyValue::Utf8StringPair(name, value)
+            }
+            _ => return Err(CodecError::UnknownProperty(id)),
+        };
+        properties.push(Property::new(id, value));
+    }
+    Ok((properties, end))
+}
+
+/// Encode `properties` as a property section, length prefix included.
+///
+/// # Errors
+///
+/// Returns Error if a string or binary value is longer than 65535 bytes.
+pub fn encode_properties(properties: &[Property]) -> Result<Vec<u8>, CodecError> {
+    let mut body: Vec<u8> = Vec::new();
+    for property in properties {
+        body.push(property.id);
+        match &property.value {
+            PropertyValue::Byte(byte) => body.push(*byte),
+            PropertyValue::TwoByteInteger(value) => write_u16(&mut body, *value),
+            PropertyValue::FourByteInteger(value) => body.extend(&value.to_be_bytes()),
+            PropertyValue::VariableByteInteger(value) => {
+                body.append(&mut remaining_length_encode(*value as usize))
+            }
+            PropertyValue::Utf8String(string) => write_string(&mut body, string)?,
+            PropertyValue::BinaryData(data) => write_binary(&mut body, data)?,
+            PropertyValue::Utf8StringPair(name, value) => {
+                write_string(&mut body, name)?;
+                write_string(&mut body, value)?;
+            }
+        }
+    }
+    let mut buffer = remaining_length_encode(body.len());
+    buffer.append(&mut body);
+    Ok(buffer)
+}
+
+/// Value of the four byte integer property `id`, if present.
+pub fn four_byte_property(properties: &[Property], id: u8) -> Option<u32> {
+    properties.iter().find_map(|property| match property.value {
+        PropertyValue::FourByteInteger(value) if property.id == id => Some(value),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_propiedades_vacias() {
+        assert_eq!(encode_properties(&[]), Ok(vec![0]));
+        assert_eq!(decode_properties(&[0, 0x30]), Ok((Vec::new(), 1)));
+    }
+
+    #[test]
+    fn test02_propiedades_codificadas_se_decodifican_igual() {
+        let properties = vec![
+            Property::new(PAYLOAD_FORMAT_INDICATOR, PropertyValue::Byte(1)),
+            Property::new(TOPIC_ALIAS_MAXIMUM, PropertyValue::TwoByteInteger(10)),
+            Property::new(
+                SESSION_EXPIRY_INTERVAL,
+                PropertyValue::FourByteInteger(3600),
+            ),
+            Property::new(
+                SUBSCRIPTION_IDENTIFIER,
+                PropertyValue::VariableByteInteger(321),
+            ),
+            Property::new(
+                CONTENT_TYPE,
+                PropertyValue::Utf8String("text/plain".to_owned()),
+            ),
+            Property::new(CORRELATION_DATA, PropertyValue::BinaryData(vec![0, 255])),
+            Property::new(
+                USER_PROPERTY,
+                PropertyValue::Utf8StringPair("origen".to_owned(), "sensor".to_owned()),
+            ),
+        ];
+        let encoded = encode_properties(&properties).unwrap();
+        assert_eq!(decode_properties(&encoded), Ok((properties, encoded.len())));
+    }
+
+    #[test]
+    fn test03_propiedades_truncadas_devuelven_error() {
+        assert!(decode_properties(&[5, MESSAGE_EXPIRY_INTERVAL, 0, 0]).is_err());
+        assert!(decode_properties(&[2, MESSAGE_EXPIRY_INTERVAL, 0]).is_err());
+    }
+
+    #[test]
+    fn test04_identificador_desconocido_devuelve_error() {
+        assert_eq!(
+            decode_properties(&[2, 0x7F, 0]),
+            Err(CodecError::UnknownProperty(0x7F))
+        );
+    }
+}
@@ -0,0 +1,421 @@
+//! # Publish
+//!
+//! _Publish_ packet and its acknowledgements: _Puback_ for QoS 1, and _Pubrec_, _Pubrel_ and
+//! _Pubcomp_ for QoS 2.
+
+use crate::bytes::{with_fixed_header, write_properties, write_string, write_u16, Reader};
+use crate::properties::Property;
+use crate::{CodecError, MQTT_VERSION_5};
+
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+
+/// _Publish_ packet. `packet_identifier` is only present when `qos` is greater than 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publish {
+    pub dup: bool,
+    pub qos: u8,
+    pub retain: bool,
+    pub topic_name: String,
+    pub packet_identifier: Option<u16>,
+    pub properties: Vec<Property>,
+    pub payload: Vec<u8>,
+}
+
+impl Publish {
+    /// Decode a _Publish_ packet given its first byte and the rest of the packet after the remaining length.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if:
+    /// - Both QoS bits are set ([`CodecError::InvalidQos`]).
+    /// - The packet is truncated or the topic name isn't valid UTF-8.
+    pub fn decode(byte_0: u8, buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let qos = (byte_0 & 0x06) >> 1;
+        if qos > 2 {
+            return Err(CodecError::InvalidQos(qos));
+        }
+        let mut reader = Reader::new(buffer);
+        let topic_name = reader.read_string()?;
+        let mut packet_identifier = None;
+        if qos > 0 {
+            packet_identifier = Some(reader.read_u16()?);
+        }
+        let properties = reader.read_properties(protocol_version)?;
+        Ok(Publish {
+            dup: byte_0 & 0x08 == 0x08,
+            qos,
+            retain: byte_0 & 0x01 == 0x01,
+            topic_name,
+            packet_identifier,
+            properties,
+            payload: reader.read_rest().to_vec(),
+        })
+    }
+
+    /// Encode the complete _Publish_ packet. Properties are only included for MQTT 5.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the QoS level is invalid, a QoS 1 or 2 publish has no packet identifier,
+    /// or the packet is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        if self.qos > 2 {
+            return Err(CodecError::InvalidQos(self.qos));
+        }
+        let mut byte_0 = PUBLISH | (self.qos << 1);
+        if self.dup {
+            byte_0 |= 0x08;
+        }
+        if self.retain {
+            byte_0 |= 0x01;
+        }
+        let mut body: Vec<u8> = Vec::new();
+        write_string(&mut body, &self.topic_name)?;
+        if self.qos > 0 {
+            match self.packet_identifier {
+                Some(packet_identifier) => write_u16(&mut body, packet_identifier),
+                None => {
+                    return Err(CodecError::MalformedPacket(
+                        "QoS 1 and 2 publishes need a packet identifier".to_owned(),
+                    ))
+                }
+            }
+        }
+        write_properties(&mut body, &self.properties, protocol_version)?;
+        body.extend(&self.payload);
+        with_fixed_header(byte_0, body)
+    }
+}
+
+/// Decode the packet identifier, and for MQTT 5 the optional reason code and properties,
+/// shared by the publish acknowledgements.
+fn decode_acknowledgement(
+    buffer: &[u8],
+    protocol_version: u8,
+) -> Result<(u16, u8, Vec<Property>), CodecError> {
+    let mut reader = Reader::new(buffer);
+    let packet_identifier = reader.read_u16()?;
+    let mut reason_code = 0;
+    let mut properties = Vec::new();
+    if protocol_version == MQTT_VERSION_5 && !reader.is_empty() {
+        reason_code = reader.read_u8()?;
+        if !reader.is_empty() {
+            properties = reader.read_properties(protocol_version)?;
+        }
+    }
+    Ok((packet_identifier, reason_code, properties))
+}
+
+/// Encode a publish acknowledgement. MQTT 5 peers only get the reason code and properties
+/// when they aren't the default ones.
+fn encode_acknowledgement(
+    byte_0: u8,
+    packet_identifier: u16,
+    reason_code: u8,
+    properties: &[Property],
+    protocol_version: u8,
+) -> Result<Vec<u8>, CodecError> {
+    let mut body: Vec<u8> = Vec::new();
+    write_u16(&mut body, packet_identifier);
+    if protocol_version == MQTT_VERSION_5 && (reason_code != 0 || !properties.is_empty()) {
+        body.push(reason_code);
+        write_properties(&mut body, properties, protocol_version)?;
+    }
+    with_fixed_header(byte_0, body)
+}
+
+/// _Puback_ packet, the answer to a QoS 1 publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubAck {
+    pub packet_identifier: u16,
+    pub reason_code: u8,
+    pub properties: Vec<Property>,
+}
+
+/// _Pubrec_ packet, the first answer to a QoS 2 publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubRec {
+    pub packet_identifier: u16,
+    pub reason_code: u8,
+    pub properties: Vec<Property>,
+}
+
+/// _Pubrel_ packet, the answer to a _Pubrec_.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubRel {
+    pub packet_identifier: u16,
+    pub reason_code: u8,
+    pub properties: Vec<Property>,
+}
+
+/// _Pubcomp_ packet, the answer to a _Pubrel_ that completes a QoS 2 delivery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubComp {
+    pub packet_identifier: u16,
+    pub reason_code: u8,
+    pub properties: Vec<Property>,
+}
+
+impl PubAck {
+    /// Successful acknowledgement of `packet_identifier`.
+    pub fn new(packet_identifier: u16) -> Self {
+        PubAck {
+            packet_identifier,
+            reason_code: 0,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Decode the variable header of a _Puback_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated or malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_acknowledgement(buffer, protocol_version)?;
+        Ok(PubAck {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    /// Encode the complete _Puback_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        encode_acknowledgement(
+            PUBACK,
+            self.packet_identifier,
+            self.reason_code,
+            &self.properties,
+            protocol_version,
+        )
+    }
+}
+
+impl PubRec {
+    /// Successful reception of `packet_identifier`.
+    pub fn new(packet_identifier: u16) -> Self {
+        PubRec {
+            packet_identifier,
+            reason_code: 0,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Decode the variable header of a _Pubrec_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated or malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_acknowledgement(buffer, protocol_version)?;
+        Ok(PubRec {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    /// Encode the complete _Pubrec_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        encode_acknowledgement(
+            PUBREC,
+            self.packet_identifier,
+            self.reason_code,
+            &self.properties,
+            protocol_version,
+        )
+    }
+}
+
+impl PubRel {
+    /// Release of `packet_identifier`.
+    pub fn new(packet_identifier: u16) -> Self {
+        PubRel {
+            packet_identifier,
+            reason_code: 0,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Decode the variable header of a _Pubrel_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated or malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_acknowledgement(buffer, protocol_version)?;
+        Ok(PubRel {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    /// Encode the complete _Pubrel_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        encode_acknowledgement(
+            PUBREL,
+            self.packet_identifier,
+            self.reason_code,
+            &self.properties,
+            protocol_version,
+        )
+    }
+}
+
+impl PubComp {
+    /// Successful completion of `packet_identifier`.
+    pub fn new(packet_identifier: u16) -> Self {
+        PubComp {
+            packet_identifier,
+            reason_code: 0,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Decode the variable header of a _Pubcomp_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated or malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_acknowledgement(buffer, protocol_version)?;
+        Ok(PubComp {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    /// Encode the complete _Pubcomp_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        encode_acknowledgement(
+            PUBCOMP,
+            self.packet_identifier,
+            self.reason_code,
+            &self.properties,
+            protocol_version,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::properties::{PropertyValue, CONTENT_TYPE};
+    use crate::MQTT_VERSION;
+
+    fn publish_qos1() -> Publish {
+        Publish {
+            dup: false,
+            qos: 1,
+            retain: true,
+            topic_name: "as/ti".to_owned(),
+            packet_identifier: Some(14),
+            properties: Vec::new(),
+            payload: b"hola".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test01_publish_qos1_se_codifica_segun_el_protocolo() {
+        assert_eq!(
+            publish_qos1().encode(MQTT_VERSION),
+            Ok(vec![
+                0x33, 13, 0, 5, 97, 115, 47, 116, 105, 0, 14, 104, 111, 108, 97
+            ])
+        );
+    }
+
+    #[test]
+    fn test02_publish_codificado_se_decodifica_igual() {
+        let mut publish = publish_qos1();
+        let encoded = publish.encode(MQTT_VERSION).unwrap();
+        assert_eq!(
+            Publish::decode(encoded[0], &encoded[2..], MQTT_VERSION),
+            Ok(publish.clone())
+        );
+        publish.properties = vec![Property::new(
+            CONTENT_TYPE,
+            PropertyValue::Utf8String("text/plain".to_owned()),
+        )];
+        let encoded = publish.encode(MQTT_VERSION_5).unwrap();
+        assert_eq!(
+            Publish::decode(encoded[0], &encoded[2..], MQTT_VERSION_5),
+            Ok(publish)
+        );
+    }
+
+    #[test]
+    fn test03_publish_con_qos_invalido_o_truncado_es_error() {
+        assert_eq!(
+            Publish::decode(0x36, &[0, 1, 97, 0, 1], MQTT_VERSION),
+            Err(CodecError::InvalidQos(3))
+        );
+        assert_eq!(
+            Publish::decode(0x32, &[0, 1, 97, 0], MQTT_VERSION),
+            Err(CodecError::UnexpectedEnd)
+        );
+        assert_eq!(
+            Publish::decode(0x30, &[0, 9, 97], MQTT_VERSION),
+            Err(CodecError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test04_publish_qos1_sin_packet_identifier_no_se_codifica() {
+        let mut publish = publish_qos1();
+        publish.packet_identifier = None;
+        assert!(publish.encode(MQTT_VERSION).is_err());
+    }
+
+    #[test]
+    fn test05_acknowledgements_mqtt311_y_mqtt5() {
+        assert_eq!(PubAck::new(9).encode(MQTT_VERSION), Ok(vec![0x40, 2, 0, 9]));
+        assert_eq!(
+            PubRel::new(9).encode(MQTT_VERSION_5),
+            Ok(vec![0x62, 2, 0, 9])
+        );
+        let pubrec = PubRec {
+            packet_identifier: 9,
+            reason_code: 0x80,
+            properties: Vec::new(),
+        };
+        let encoded = pubrec.encode(MQTT_VERSION_5).unwrap();
+        assert_eq!(encoded, vec![0x50, 4, 0, 9, 0x80, 0]);
+        assert_eq!(PubRec::decode(&encoded[2..], MQTT_VERSION_5), Ok(pubrec));
+        assert_eq!(
+            PubComp::decode(&[0, 9], MQTT_VERSION_5),
+            Ok(PubComp::new(9))
+        );
+        assert_eq!(
+            PubComp::decode(&[0], MQTT_VERSION),
+            Err(CodecError::UnexpectedEnd)
+        );
+    }
+}
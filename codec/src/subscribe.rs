@@ -0,0 +1,299 @@
+//! # Subscribe
+//!
+//! _Subscribe_ and _Unsubscribe_ packets and their acknowledgements.
+
+use crate::bytes::{with_fixed_header, write_properties, write_string, write_u16, Reader};
+use crate::properties::Property;
+use crate::{CodecError, MQTT_VERSION_5};
+
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const UNSUBSCRIBE: u8 = 0xA2;
+const UNSUBACK: u8 = 0xB0;
+
+/// Topic filter of a _Subscribe_ packet and the QoS level requested for it.
+///
+/// On MQTT 3.1.1 `qos` is the whole requested QoS byte, so an invalid level can be refused on the
+/// _Suback_. On MQTT 5 it is taken from the subscription options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscribeTopic {
+    pub topic_filter: String,
+    pub qos: u8,
+}
+
+/// _Subscribe_ packet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscribe {
+    pub packet_identifier: u16,
+    pub properties: Vec<Property>,
+    pub topics: Vec<SubscribeTopic>,
+}
+
+impl Subscribe {
+    /// Decode the variable header and payload of a _Subscribe_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated, malformed or has no topic filters.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let mut reader = Reader::new(buffer);
+        let packet_identifier = reader.read_u16()?;
+        let properties = reader.read_properties(protocol_version)?;
+        let mut topics: Vec<SubscribeTopic> = Vec::new();
+        while !reader.is_empty() {
+            let topic_filter = reader.read_string()?;
+            let options = reader.read_u8()?;
+            let qos = if protocol_version == MQTT_VERSION_5 {
+                options & 0x03
+            } else {
+                options
+            };
+            topics.push(SubscribeTopic { topic_filter, qos });
+        }
+        if topics.is_empty() {
+            return Err(CodecError::MalformedPacket(
+                "Subscribe without topic filters".to_owned(),
+            ));
+        }
+        Ok(Subscribe {
+            packet_identifier,
+            properties,
+            topics,
+        })
+    }
+
+    /// Encode the complete _Subscribe_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a requested QoS level is invalid or a field is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        let mut body: Vec<u8> = Vec::new();
+        write_u16(&mut body, self.packet_identifier);
+        write_properties(&mut body, &self.properties, protocol_version)?;
+        for topic in &self.topics {
+            if topic.qos > 2 {
+                return Err(CodecError::InvalidQos(topic.qos));
+            }
+            write_string(&mut body, &topic.topic_filter)?;
+            body.push(topic.qos);
+        }
+        with_fixed_header(SUBSCRIBE, body)
+    }
+}
+
+/// _Suback_ packet, with the granted QoS level (or failure code) for each topic filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubAck {
+    pub packet_identifier: u16,
+    pub properties: Vec<Property>,
+    pub return_codes: Vec<u8>,
+}
+
+impl SubAck {
+    /// Decode the variable header and payload of a _Suback_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated or malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let mut reader = Reader::new(buffer);
+        let packet_identifier = reader.read_u16()?;
+        let properties = reader.read_properties(protocol_version)?;
+        Ok(SubAck {
+            packet_identifier,
+            properties,
+            return_codes: reader.read_rest().to_vec(),
+        })
+    }
+
+    /// Encode the complete _Suback_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        let mut body: Vec<u8> = Vec::new();
+        write_u16(&mut body, self.packet_identifier);
+        write_properties(&mut body, &self.properties, protocol_version)?;
+        body.extend(&self.return_codes);
+        with_fixed_header(SUBACK, body)
+    }
+}
+
+/// _Unsubscribe_ packet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsubscribe {
+    pub packet_identifier: u16,
+    pub properties: Vec<Property>,
+    pub topic_filters: Vec<String>,
+}
+
+impl Unsubscribe {
+    /// Decode the variable header and payload of an _Unsubscribe_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated, malformed or has no topic filters.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let mut reader = Reader::new(buffer);
+        let packet_identifier = reader.read_u16()?;
+        let properties = reader.read_properties(protocol_version)?;
+        let mut topic_filters: Vec<String> = Vec::new();
+        while !reader.is_empty() {
+            topic_filters.push(reader.read_string()?);
+        }
+        if topic_filters.is_empty() {
+            return Err(CodecError::MalformedPacket(
+                "Unsubscribe without topic filters".to_owned(),
+            ));
+        }
+        Ok(Unsubscribe {
+            packet_identifier,
+            properties,
+            topic_filters,
+        })
+    }
+
+    /// Encode the complete _Unsubscribe_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a field is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        let mut body: Vec<u8> = Vec::new();
+        write_u16(&mut body, self.packet_identifier);
+        write_properties(&mut body, &self.properties, protocol_version)?;
+        for topic_filter in &self.topic_filters {
+            write_string(&mut body, topic_filter)?;
+        }
+        with_fixed_header(UNSUBSCRIBE, body)
+    }
+}
+
+/// _Unsuback_ packet. Only MQTT 5 carries a reason code for each topic filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsubAck {
+    pub packet_identifier: u16,
+    pub properties: Vec<Property>,
+    pub reason_codes: Vec<u8>,
+}
+
+impl UnsubAck {
+    /// Decode the variable header and payload of an _Unsuback_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet is truncated or malformed.
+    pub fn decode(buffer: &[u8], protocol_version: u8) -> Result<Self, CodecError> {
+        let mut reader = Reader::new(buffer);
+        let packet_identifier = reader.read_u16()?;
+        let properties = reader.read_properties(protocol_version)?;
+        Ok(UnsubAck {
+            packet_identifier,
+            properties,
+            reason_codes: reader.read_rest().to_vec(),
+        })
+    }
+
+    /// Encode the complete _Unsuback_ packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a property is too long.
+    pub fn encode(&self, protocol_version: u8) -> Result<Vec<u8>, CodecError> {
+        let mut body: Vec<u8> = Vec::new();
+        write_u16(&mut body, self.packet_identifier);
+        if protocol_version == MQTT_VERSION_5 {
+            write_properties(&mut body, &self.properties, protocol_version)?;
+            body.extend(&self.reason_codes);
+        }
+        with_fixed_header(UNSUBACK, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MQTT_VERSION;
+
+    #[test]
+    fn test01_subscribe_se_decodifica_con_sus_topicos() {
+        let buffer = [0, 57, 0, 2, 97, 115, 1, 0, 1, 35, 3];
+        assert_eq!(
+            Subscribe::decode(&buffer, MQTT_VERSION),
+            Ok(Subscribe {
+                packet_identifier: 57,
+                properties: Vec::new(),
+                topics: vec![
+                    SubscribeTopic {
+                        topic_filter: "as".to_owned(),
+                        qos: 1
+                    },
+                    SubscribeTopic {
+                        topic_filter: "#".to_owned(),
+                        qos: 3
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test02_subscribe_mqtt5_toma_el_qos_de_las_opciones() {
+        let buffer = [0, 57, 0, 0, 2, 97, 115, 0x2E];
+        let subscribe = Subscribe::decode(&buffer, MQTT_VERSION_5).unwrap();
+        assert_eq!(subscribe.topics[0].qos, 2);
+    }
+
+    #[test]
+    fn test03_subscribe_sin_topicos_o_truncado_es_error() {
+        assert!(Subscribe::decode(&[0, 57], MQTT_VERSION).is_err());
+        assert_eq!(
+            Subscribe::decode(&[0, 57, 0, 2, 97, 115], MQTT_VERSION),
+            Err(CodecError::UnexpectedEnd)
+        );
+        assert!(Unsubscribe::decode(&[0, 57], MQTT_VERSION).is_err());
+    }
+
+    #[test]
+    fn test04_unsubscribe_codificado_se_decodifica_igual() {
+        let unsubscribe = Unsubscribe {
+            packet_identifier: 32,
+            properties: Vec::new(),
+            topic_filters: vec!["as/ti".to_owned(), "ma/#".to_owned()],
+        };
+        let encoded = unsubscribe.encode(MQTT_VERSION_5).unwrap();
+        assert_eq!(encoded[0], 0xA2);
+        assert_eq!(
+            Unsubscribe::decode(&encoded[2..], MQTT_VERSION_5),
+            Ok(unsubscribe)
+        );
+    }
+
+    #[test]
+    fn test05_unsuback_solo_lleva_reason_codes_en_mqtt5() {
+        let unsuback = UnsubAck {
+            packet_identifier: 32,
+            properties: Vec::new(),
+            reason_codes: vec![0, 0x11],
+        };
+        assert_eq!(unsuback.encode(MQTT_VERSION), Ok(vec![0xB0, 2, 0, 32]));
+        assert_eq!(
+            unsuback.encode(MQTT_VERSION_5),
+            Ok(vec![0xB0, 5, 0, 32, 0, 0, 0x11])
+        );
+    }
+
+    #[test]
+    fn test06_suback_se_codifica_con_los_qos_otorgados() {
+        let suback = SubAck {
+            packet_identifier: 54,
+            properties: Vec::new(),
+            return_codes: vec![1, 0x80],
+        };
+        let encoded = suback.encode(MQTT_VERSION).unwrap();
+        assert_eq!(encoded, vec![0x90, 4, 0, 54, 1, 0x80]);
+        assert_eq!(SubAck::decode(&encoded[2..], MQTT_VERSION), Ok(suback));
+    }
+}
@@ -0,0 +1,244 @@
+//! # ACL
+//!
+//! Topics each client may publish to (write) or subscribe to (read). Without an ACL file every
+//! client can use every topic; with one, only what its rules grant. The file has one rule per
+//! line:
+//!
+//! ```text
+//! # Before any section, rules apply to every client
+//! topic read public/#
+//! # Rules of the user authenticated as franco
+//! user franco
+//! topic readwrite house/#
+//! # Rules of the client with id sensor-01
+//! client sensor-01
+//! topic write sensors/%c/#
+//! ```
+//!
+//! `%u` and `%c` are replaced by the username and client id. `topic <filter>` grants both.
+
+use crate::client::Client;
+use crate::wildcard::compare_topic;
+use std::collections::HashMap;
+use std::fs;
+use tracing::info;
+
+const LEVEL_SEPARATOR: char = '/';
+const SINGLE_LEVEL_WILDCARD: &str = "+";
+const MULTI_LEVEL_WILDCARD: &str = "#";
+const USERNAME_PLACEHOLDER: &str = "%u";
+const CLIENT_ID_PLACEHOLDER: &str = "%c";
+
+struct Rule {
+    read: bool,
+    write: bool,
+    filter: String,
+}
+
+/// Section of the file the next rules belong to.
+enum Section {
+    Everyone,
+    User(String),
+    Client(String),
+}
+
+#[derive(Default)]
+pub struct Acl {
+    enabled: bool,
+    everyone: Vec<Rule>,
+    users: HashMap<String, Vec<Rule>>,
+    clients: HashMap<String, Vec<Rule>>,
+}
+
+impl Acl {
+    /// ACL that allows every client to use every topic.
+    pub fn new() -> Self {
+        Acl::default()
+    }
+
+    /// Load the rules of the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error, with the line number, if the file can't be read or a line is invalid.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = match fs::read_to_string(path) {
+            Ok(file) => file,
+            Err(error) => return Err(format!("Error reading {}: {}", path, error)),
+        };
+        let mut acl = Acl {
+            enabled: true,
+            ..Acl::default()
+        };
+        let mut section = Section::Everyone;
+        for (number, line) in file.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["user", name] => section = Section::User((*name).to_owned()),
+                ["client", id] => section = Section::Client((*id).to_owned()),
+                ["topic", filter] => acl.add(&section, true, true, filter),
+                ["topic", "read", filter] => acl.add(&section, true, false, filter),
+                ["topic", "write", filter] => acl.add(&section, false, true, filter),
+                ["topic", "readwrite", filter] => acl.add(&section, true, true, filter),
+                _ => return Err(format!("{}:{}: invalid ACL rule", path, number + 1)),
+            }
+        }
+        info!("Loaded ACL from {}.", path);
+        Ok(acl)
+    }
+
+    /// Whether `client` may publish to `topic`.
+    pub fn can_publish(&self, client: &Client, topic: &str) -> bool {
+        self.allows(client, |rule, filter| {
+            rule.write && compare_topic(topic, filter)
+        })
+    }
+
+    /// Whether `client` may subscribe to `filter`, which has to be within a filter it can read.
+    pub fn can_subscribe(&self, client: &Client, filter: &str) -> bool {
+        self.allows(client, |rule, rule_filter| {
+            rule.read && filter_covers(rule_filter, filter)
+        })
+    }
+
+    fn add(&mut self, section: &Section, read: bool, write: bool, filter: &str) {
+        let rule = Rule {
+            read,
+            write,
+            filter: filter.to_owned(),
+        };
+        match section {
+            Section::Everyone => self.everyone.push(rule),
+            Section::User(name) => self.users.entry(name.clone()).or_default().push(rule),
+            Section::Client(id) => self.clients.entry(id.clone()).or_default().push(rule),
+        }
+    }
+
+    /// Whether any rule of the client grants the access, given each rule and its filter after
+    /// replacing the placeholders.
+    fn allows<F: Fn(&Rule, &str) -> bool>(&self, client: &Client, grants: F) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        let username = client.username.as_deref();
+        let user_rules = username.and_then(|name| self.users.get(name));
+        let client_rules = self.clients.get(&client.client_id);
+        self.everyone
+            .iter()
+            .chain(user_rules.into_iter().flatten())
+            .chain(client_rules.into_iter().flatten())
+            .any(
+                |rule| match substitute(&rule.filter, username, &client.client_id) {
+                    Some(filter) => grants(rule, &filter),
+                    None => false,
+                },
+            )
+    }
+}
+
+/// Replace `%u` and `%c` in `filter`. Returns None if a placeholder has no value, or its value has
+/// wildcards or separators that would widen the filter.
+fn substitute(filter: &str, username: Option<&str>, client_id: &str) -> Option<String> {
+    let valid = |value: &str| {
+        !value.is_empty()
+            && !value.contains(LEVEL_SEPARATOR)
+            && !value.contains(SINGLE_LEVEL_WILDCARD)
+            && !value.contains(MULTI_LEVEL_WILDCARD)
+    };
+    let mut filter = filter.to_owned();
+    if filter.contains(USERNAME_PLACEHOLDER) {
+        let username = username.filter(|name| valid(name))?;
+        filter = filter.replace(USERNAME_PLACEHOLDER, username);
+    }
+    if filter.contains(CLIENT_ID_PLACEHOLDER) {
+        if !valid(client_id) {
+            return None;
+        }
+        filter = filter.replace(CLIENT_ID_PLACEHOLDER, client_id);
+    }
+    Some(filter)
+}
+
+/// Whether every topic matching `filter` also matches `rule_filter`.
+fn filter_covers(rule_filter: &str, filter: &str) -> bool {
+    let rule_levels: Vec<&str> = rule_filter.split(LEVEL_SEPARATOR).collect();
+    let levels: Vec<&str> = filter.split(LEVEL_SEPARATOR).collect();
+    for (index, rule_level) in rule_levels.iter().enumerate() {
+        if *rule_level == MULTI_LEVEL_WILDCARD {
+            return true;
+        }
+        let level = match levels.get(index) {
+            Some(level) => *level,
+            None => return false,
+        };
+        let covered = match *rule_level {
+            SINGLE_LEVEL_WILDCARD => level != MULTI_LEVEL_WILDCARD,
+            _ => level == *rule_level,
+        };
+        if !covered {
+            return false;
+        }
+    }
+    levels.len() == rule_levels.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::test_channel;
+
+    fn client(client_id: &str, username: Option<&str>) -> Client {
+        let (channel, _receiver) = test_channel(1);
+        let mut client = Client::new(1, channel);
+        client.client_id = client_id.to_owned();
+        client.username = username.map(str::to_owned);
+        client
+    }
+
+    fn acl(rules: &str, name: &str) -> Acl {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        fs::write(&path, rules).unwrap();
+        let acl = Acl::load(&path.to_string_lossy());
+        let _ = fs::remove_file(&path);
+        acl.unwrap()
+    }
+
+    #[test]
+    fn test01_reglas_por_usuario_y_client_id_con_sustitucion() {
+        let acl = acl(
+            "# Para todos\ntopic read publico/#\n\nuser franco\ntopic readwrite casa/%u/+\nclient sensor-01\ntopic write sensores/%c\n",
+            "acl01.txt",
+        );
+        let franco = client("celular", Some("franco"));
+        let sensor = client("sensor-01", None);
+        assert!(acl.can_subscribe(&franco, "publico/noticias/#"));
+        assert!(!acl.can_publish(&franco, "publico/noticias"));
+        assert!(acl.can_publish(&franco, "casa/franco/luz"));
+        assert!(acl.can_subscribe(&franco, "casa/franco/+"));
+        assert!(!acl.can_subscribe(&franco, "casa/franco/#"));
+        assert!(!acl.can_publish(&franco, "casa/emanuel/luz"));
+        assert!(acl.can_publish(&sensor, "sensores/sensor-01"));
+        assert!(!acl.can_publish(&sensor, "sensores/sensor-02"));
+        assert!(!acl.can_publish(&sensor, "casa/franco/luz"));
+        assert!(Acl::new().can_publish(&sensor, "casa/franco/luz"));
+    }
+
+    #[test]
+    fn test02_nombres_con_wildcards_no_amplian_las_reglas() {
+        let acl = acl("topic readwrite clientes/%c/#\n", "acl02.txt");
+        assert!(!acl.can_subscribe(&client("#", None), "clientes/otro/#"));
+        assert!(!acl.can_subscribe(&client("a/b", None), "clientes/a/b"));
+        assert!(acl.can_subscribe(&client("ab", None), "clientes/ab"));
+    }
+
+    #[test]
+    fn test03_regla_invalida_se_informa_con_su_numero() {
+        let path = std::env::temp_dir().join(format!("acl03.txt-{}", std::process::id()));
+        fs::write(&path, "user franco\ntopic leer casa/#\n").unwrap();
+        let error = Acl::load(&path.to_string_lossy()).err().unwrap();
+        assert!(error.ends_with(":2: invalid ACL rule"));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,323 @@
+//! # Admin
+//!
+//! HTTP API to inspect and manage a running broker, served on the address of `[admin]`. Every
+//! request must carry the token of the configuration as `Authorization: Bearer <token>`, and
+//! the answers are JSON:
+//!
+//! | Request | |
+//! |---|---|
+//! | `GET /clients` | Connected clients |
+//! | `GET /sessions` | Connected clients and persistent sessions |
+//! | `DELETE /clients/<client id>` | Disconnect the client, keeping its session if persistent |
+//! | `DELETE /sessions/<client id>` | Discard the session, disconnecting its client |
+//! | `GET /retained?filter=<filter>` | Retained messages matching the filter, `#` by default |
+//! | `GET /retained/<topic>` | Retained message of the topic |
+//! | `DELETE /retained/<topic>` | Delete the retained message of the topic |
+//! | `POST /publish` | Publish `{"topic", "payload", "qos", "retain"}` as the broker |
+//!
+//! Client ids, topics and filters are percent-encoded. The sessions come from the clients map,
+//! and the rest is asked to the coordinator, which owns the retained messages.
+
+use crate::broker::{publish_as_broker, SharedPublisher};
+use crate::client::Client;
+use crate::coordinator::{AdminCommand, ClientCommand, CoordinatorCommand, Message};
+use crate::http::{decode_component, HttpServer, Request, Response};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+/// How long a request waits for the coordinator to answer.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_RETAINED_FILTER: &str = "#";
+
+/// What the admin API reaches of the broker.
+pub struct AdminApi {
+    pub lock_clients: Arc<Mutex<HashMap<usize, Client>>>,
+    pub coordinator_sender: Arc<Mutex<Sender<ClientCommand>>>,
+    pub publisher: SharedPublisher,
+}
+
+impl AdminApi {
+    fn handle(&self, request: &Request) -> Response {
+        let path = request.path.trim_start_matches('/');
+        let (resource, name) = match path.split_once('/') {
+            Some((resource, name)) => match decode_component(name) {
+                Some(name) => (resource, Some(name)),
+                None => return error(400, "Invalid percent-encoding"),
+            },
+            None => (path, None),
+        };
+        match (request.method.as_str(), resource, name) {
+            ("GET", "clients", None) => self.list_sessions(false),
+            ("GET", "sessions", None) => self.list_sessions(true),
+            ("DELETE", "clients", Some(client_id)) => {
+                self.ask_found(|answer| AdminCommand::Kick { client_id, answer })
+            }
+            ("DELETE", "sessions", Some(client_id)) => {
+                self.ask_found(|answer| AdminCommand::Purge { client_id, answer })
+            }
+            ("GET", "retained", None) => {
+                let filter = request
+                    .query_param("filter")
+                    .unwrap_or_else(|| DEFAULT_RETAINED_FILTER.to_owned());
+                match self.ask(|answer| AdminCommand::ListRetained { filter, answer }) {
+                    Some(retained) => Response::json(
+                        200,
+                        &Value::Array(
+                            retained
+                                .iter()
+                                .map(|(topic, message)| retained_json(topic, message))
+                                .collect(),
+                        ),
+                    ),
+                    None => not_running(),
+                }
+            }
+            ("GET", "retained", Some(topic)) => {
+                let filter = topic.clone();
+                match self.ask(|answer| AdminCommand::ListRetained { filter, answer }) {
+                    Some(retained) => match retained.iter().find(|(found, _)| *found == topic) {
+                        Some((topic, message)) => {
+                            Response::json(200, &retained_json(topic, message))
+                        }
+                        None => error(404, "No retained message on the topic"),
+                    },
+                    None => not_running(),
+                }
+            }
+            ("DELETE", "retained", Some(topic)) => {
+                self.ask_found(|answer| AdminCommand::DeleteRetained { topic, answer })
+            }
+            ("POST", "publish", None) => self.publish(&request.body),
+            _ => error(404, "Unknown request"),
+        }
+    }
+
+    /// Clients with a client id that are connected or, if `persistent`, keep their session.
+    fn list_sessions(&self, persistent: bool) -> Response {
+        let locked = match self.lock_clients.lock() {
+            Ok(locked) => locked,
+            Err(_) => return not_running(),
+        };
+        let mut clients: Vec<&Client> = locked
+            .values()
+            .filter(|client| {
+                !client.client_id.is_empty()
+                    && (!client.disconnected || (persistent && client.clean_session == 0))
+            })
+            .collect();
+        clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        Response::json(
+            200,
+            &Value::Array(clients.into_iter().map(session_json).collect()),
+        )
+    }
+
+    /// Publish the message of a JSON body, with QoS 0 and without retain unless it says so.
+    fn publish(&self, body: &[u8]) -> Response {
+        let message: Value = match serde_json::from_slice(body) {
+            Ok(message) => message,
+            Err(_) => return error(400, "The body must be a JSON object"),
+        };
+        let topic = match message["topic"].as_str() {
+            Some(topic) => topic,
+            None => return error(400, "topic is required"),
+        };
+        let payload = message["payload"]
+            .as_str()
+            .unwrap_or("")
+            .as_bytes()
+            .to_vec();
+        let qos = message["qos"].as_u64().unwrap_or(0);
+        let retain = message["retain"].as_bool().unwrap_or(false);
+        if qos > 2 {
+            return error(400, "qos must be 0, 1 or 2");
+        }
+        match publish_as_broker(
+            &self.publisher,
+            &self.coordinator_sender,
+            topic,
+            payload,
+            qos as u8,
+            retain,
+        ) {
+            Ok(_) => Response::empty(204),
+            Err(publish_error) if publish_error.kind() == ErrorKind::InvalidInput => {
+                error(400, &publish_error.to_string())
+            }
+            Err(publish_error) if publish_error.kind() == ErrorKind::PermissionDenied => {
+                error(403, &publish_error.to_string())
+            }
+            Err(_) => not_running(),
+        }
+    }
+
+    /// Send the command the coordinator answers on the channel given to `command`, and wait for
+    /// the answer. Returns None if the coordinator stopped.
+    fn ask<T, F>(&self, command: F) -> Option<T>
+    where
+        F: FnOnce(Sender<T>) -> AdminCommand,
+    {
+        let (answer, receiver) = mpsc::channel();
+        let command = ClientCommand {
+            thread_id: 0,
+            command: CoordinatorCommand::Admin(command(answer)),
+        };
+        match self.coordinator_sender.lock() {
+            Ok(sender) => sender.send(command).ok()?,
+            Err(_) => return None,
+        }
+        receiver.recv_timeout(ANSWER_TIMEOUT).ok()
+    }
+
+    /// Answer no content if the coordinator found what the command refers to.
+    fn ask_found<F>(&self, command: F) -> Response
+    where
+        F: FnOnce(Sender<bool>) -> AdminCommand,
+    {
+        match self.ask(command) {
+            Some(true) => Response::empty(204),
+            Some(false) => error(404, "Not found"),
+            None => not_running(),
+        }
+    }
+}
+
+/// HTTP server of the admin API, on its own thread.
+pub struct AdminServer {
+    server: HttpServer,
+}
+
+impl AdminServer {
+    /// Serve `api` on `address` to the requests with `token`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the address couldn't be bound or the thread couldn't be spawned.
+    pub fn start(address: &str, token: String, api: AdminApi) -> std::io::Result<Self> {
+        let server = HttpServer::start("Admin", address, move |request| {
+            if !is_authorized(request, &token) {
+                info!("Admin request without a valid token refused.");
+                return error(401, "Missing or invalid token");
+            }
+            api.handle(request)
+        })?;
+        Ok(AdminServer { server })
+    }
+
+    /// Stop accepting requests and wait for the thread to finish.
+    pub fn stop(&mut self) {
+        self.server.stop();
+    }
+}
+
+/// Whether the request carries `token` as its bearer token, compared in constant time.
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let given = match request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(given) => given.trim().as_bytes(),
+        None => return false,
+    };
+    let expected = token.as_bytes();
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn session_json(client: &Client) -> Value {
+    let subscriptions: Vec<Value> = client
+        .topics
+        .iter()
+        .map(|subscription| json!({"topic": subscription.topic, "qos": subscription.qos}))
+        .collect();
+    json!({
+        "client_id": client.client_id,
+        "username": client.username,
+        "peer": client.peer_address,
+        "connected": !client.disconnected,
+        "persistent": client.clean_session == 0,
+        "protocol_version": client.protocol_version,
+        "subscriptions": subscriptions,
+        "queued": client.publishes_received.len() + client.pubrels_pending.len(),
+    })
+}
+
+/// The payload is a string if it is UTF-8, and else hexadecimal in `payload_hex`.
+fn retained_json(topic: &str, message: &Message) -> Value {
+    let mut retained = json!({
+        "topic": topic,
+        "qos": message.qos,
+        "size": message.payload.len(),
+    });
+    match std::str::from_utf8(&message.payload) {
+        Ok(payload) => retained["payload"] = json!(payload),
+        Err(_) => {
+            let hex: String = message
+                .payload
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            retained["payload_hex"] = json!(hex);
+        }
+    }
+    retained
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(status, &json!({ "error": message }))
+}
+
+fn not_running() -> Response {
+    error(503, "The broker is not running")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request {
+        Request {
+            method: "GET".to_owned(),
+            path: "/clients".to_owned(),
+            query: String::new(),
+            headers: authorization
+                .map(|value| vec![("authorization".to_owned(), value.to_owned())])
+                .unwrap_or_default(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test01_token_por_header_authorization() {
+        assert!(is_authorized(&request(Some("Bearer secreto")), "secreto"));
+        assert!(!is_authorized(&request(Some("Bearer secret")), "secreto"));
+        assert!(!is_authorized(&request(Some("Basic secreto")), "secreto"));
+        assert!(!is_authorized(&request(None), "secreto"));
+    }
+
+    #[test]
+    fn test02_payload_binario_en_hexadecimal() {
+        let message = Message {
+            payload: vec![0xff, 0x01],
+            qos: 1,
+            properties: Vec::new(),
+        };
+        let retained = retained_json("binario", &message);
+        assert_eq!(retained["payload_hex"], "ff01");
+        assert!(retained.get("payload").is_none());
+        let message = Message {
+            payload: b"hola".to_vec(),
+            ..message
+        };
+        assert_eq!(retained_json("texto", &message)["payload"], "hola");
+    }
+}
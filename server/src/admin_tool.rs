@@ -0,0 +1,424 @@
+//! # Admin tool
+//!
+//! Commands of the `mqtt-admin` binary. The ones about clients, sessions and retained messages
+//! are requests to the admin API of a running broker, and the ones about users and the
+//! configuration work on the files, so they don't need the broker to be running.
+//!
+//! The address and token of the API come from the flags, the `MQTT_RUSTICO_ADMIN_*` environment
+//! variables or the `[admin]` section of the configuration, in that order.
+
+use crate::http::{encode_component, send_request};
+use crate::options::ENV_PREFIX;
+use crate::password_file::run_password_command;
+use crate::{Configuration, Server};
+use serde_json::{json, Value};
+
+pub const USAGE: &str = "Usage: mqtt-admin [OPTIONS] <COMMAND>
+
+Commands:
+  clients list                 Connected clients, with their subscriptions and queued messages
+  clients kick <CLIENT_ID>     Disconnect a client, keeping its session if it is persistent
+  sessions list                Connected clients and persistent sessions
+  sessions purge <CLIENT_ID>   Discard the session of a client, disconnecting it
+  retained list [FILTER]       Retained messages matching the filter, # by default
+  retained delete <TOPIC>      Delete the retained message of a topic
+  users add <USER> [-]         Add a user to the password file of the configuration, asking
+                               for the password, or reading it from stdin with -
+  users remove <USER>          Remove a user from the password file of the configuration
+  config check                 Validate the configuration and the files it refers to
+
+Options:
+  -c, --config <FILE>       Configuration of the broker       [env: MQTT_RUSTICO_CONFIG]
+  -a, --address <ADDRESS>   Address of the admin API          [env: MQTT_RUSTICO_ADMIN_ADDRESS]
+  -t, --token <TOKEN>       Token of the admin API            [env: MQTT_RUSTICO_ADMIN_TOKEN]
+      --json                Print JSON instead of tables
+  -h, --help                Print this help and exit
+
+The address and token default to the [admin] section of the configuration.";
+
+/// Longer payloads are cut in the tables.
+const MAX_PAYLOAD_WIDTH: usize = 40;
+
+/// Flags and command of the tool.
+#[derive(Debug, Default, PartialEq)]
+pub struct AdminOptions {
+    pub config: Option<String>,
+    pub address: Option<String>,
+    pub token: Option<String>,
+    pub json: bool,
+    pub help: bool,
+    pub command: Vec<String>,
+}
+
+impl AdminOptions {
+    /// Options of the arguments after the program name, filling the missing ones with the
+    /// environment variables that `env` returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a flag is unknown or lacks its value.
+    pub fn parse<F>(args: &[String], env: F) -> Result<Self, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut options = AdminOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || match inline_value.clone().or_else(|| args.next().cloned()) {
+                Some(value) => Ok(value),
+                None => Err(format!("{} needs a value", flag)),
+            };
+            match flag {
+                "-c" | "--config" => options.config = Some(value()?),
+                "-a" | "--address" => options.address = Some(value()?),
+                "-t" | "--token" => options.token = Some(value()?),
+                "--json" => options.json = true,
+                "-h" | "--help" => options.help = true,
+                _ if flag.starts_with('-') && options.command.is_empty() => {
+                    return Err(format!("Unknown option {}", flag))
+                }
+                _ => options.command.push(arg.clone()),
+            }
+        }
+        let var = |name: &str| env(&format!("{}{}", ENV_PREFIX, name));
+        if options.config.is_none() {
+            options.config = var("CONFIG");
+        }
+        if options.address.is_none() {
+            options.address = var("ADMIN_ADDRESS");
+        }
+        if options.token.is_none() {
+            options.token = var("ADMIN_TOKEN");
+        }
+        Ok(options)
+    }
+
+    /// Configuration of the file of the options, or the default one if there is none.
+    fn configuration(&self) -> Result<Configuration, String> {
+        let mut cfg = Configuration::new();
+        if let Some(path) = &self.config {
+            cfg.set_config(path)
+                .map_err(|error| format!("Invalid configuration: {}", error))?;
+        }
+        Ok(cfg)
+    }
+
+    /// Address and token of the admin API.
+    fn api(&self) -> Result<(String, String), String> {
+        let (mut address, mut token) = (self.address.clone(), self.token.clone());
+        if address.is_none() || token.is_none() {
+            if let Some((cfg_address, cfg_token)) = self.configuration()?.get_admin() {
+                address = address.or(Some(cfg_address));
+                token = token.or(Some(cfg_token));
+            }
+        }
+        match (address, token) {
+            (Some(address), Some(token)) => Ok((address, token)),
+            (None, _) => {
+                Err("No admin API address, give it with --address or in [admin] of --config".into())
+            }
+            (_, None) => {
+                Err("No admin API token, give it with --token or in [admin] of --config".into())
+            }
+        }
+    }
+}
+
+/// Run the command of `options`, returning what to print. The password of `users add` is read
+/// from stdin, after asking for it on stderr unless it is given as `-`.
+///
+/// # Errors
+///
+/// Returns Error with the usage if the command is invalid, or why it failed.
+pub fn run(options: &AdminOptions) -> Result<String, String> {
+    let command: Vec<&str> = options.command.iter().map(|arg| arg.as_str()).collect();
+    match command.as_slice() {
+        ["clients", "list"] => list_sessions(options, "/clients"),
+        ["sessions", "list"] => list_sessions(options, "/sessions"),
+        ["clients", "kick", client_id] => {
+            let path = format!("/clients/{}", encode_component(client_id));
+            let not_found = format!("Client {} isn't connected", client_id);
+            request(options, "DELETE", &path, &[], &not_found)?;
+            Ok(done(options, &format!("Disconnected client {}", client_id)))
+        }
+        ["sessions", "purge", client_id] => {
+            let path = format!("/sessions/{}", encode_component(client_id));
+            let not_found = format!("Client {} has no session", client_id);
+            request(options, "DELETE", &path, &[], &not_found)?;
+            Ok(done(
+                options,
+                &format!("Purged the session of {}", client_id),
+            ))
+        }
+        ["retained", "list"] => list_retained(options, "#"),
+        ["retained", "list", filter] => list_retained(options, filter),
+        ["retained", "delete", topic] => {
+            let path = format!("/retained/{}", encode_component(topic));
+            let not_found = format!("No retained message on {}", topic);
+            request(options, "DELETE", &path, &[], &not_found)?;
+            Ok(done(
+                options,
+                &format!("Deleted the retained message of {}", topic),
+            ))
+        }
+        ["users", "add", user] | ["users", "add", user, "-"] => {
+            change_users(options, &command[1..])?;
+            Ok(done(options, &format!("Added user {}", user)))
+        }
+        ["users", "remove", user] => {
+            change_users(options, &["remove", user])?;
+            Ok(done(options, &format!("Removed user {}", user)))
+        }
+        ["config", "check"] => {
+            let server = Server {
+                cfg: options.configuration()?,
+            };
+            server
+                .check()
+                .map_err(|error| format!("Invalid configuration: {}", error))?;
+            Ok(done(options, "Configuration OK"))
+        }
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+/// Send a request to the admin API and decode the JSON it answers, which is null if there is
+/// none. A not found answer is the error `not_found`.
+fn request(
+    options: &AdminOptions,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    not_found: &str,
+) -> Result<Value, String> {
+    let (address, token) = options.api()?;
+    let authorization = format!("Bearer {}", token);
+    let (status, body) = send_request(
+        &address,
+        method,
+        path,
+        &[("Authorization", &authorization)],
+        body,
+    )
+    .map_err(|error| format!("Couldn't reach the admin API at {}: {}", address, error))?;
+    let answer: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    match status {
+        200..=299 => Ok(answer),
+        401 => Err("The admin API refused the token".into()),
+        404 => Err(not_found.to_owned()),
+        _ => Err(match answer["error"].as_str() {
+            Some(error) => error.to_owned(),
+            None => format!("The admin API answered {}", status),
+        }),
+    }
+}
+
+fn list_sessions(options: &AdminOptions, path: &str) -> Result<String, String> {
+    let sessions = request(options, "GET", path, &[], "Unknown request")?;
+    if options.json {
+        return Ok(format!("{}\n", sessions));
+    }
+    let rows = rows(&sessions, |session| {
+        let subscriptions: Vec<String> = session["subscriptions"]
+            .as_array()
+            .map(|subscriptions| {
+                subscriptions
+                    .iter()
+                    .map(|subscription| {
+                        format!("{} ({})", text(&subscription["topic"]), subscription["qos"])
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        vec![
+            text(&session["client_id"]),
+            text(&session["username"]),
+            text(&session["peer"]),
+            yes_no(&session["connected"]),
+            yes_no(&session["persistent"]),
+            session["queued"].to_string(),
+            subscriptions.join(", "),
+        ]
+    });
+    Ok(table(
+        &[
+            "CLIENT ID",
+            "USERNAME",
+            "PEER",
+            "CONNECTED",
+            "PERSISTENT",
+            "QUEUED",
+            "SUBSCRIPTIONS",
+        ],
+        rows,
+    ))
+}
+
+fn list_retained(options: &AdminOptions, filter: &str) -> Result<String, String> {
+    let path = format!("/retained?filter={}", encode_component(filter));
+    let retained = request(options, "GET", &path, &[], "Unknown request")?;
+    if options.json {
+        return Ok(format!("{}\n", retained));
+    }
+    let rows = rows(&retained, |message| {
+        let payload = match message["payload"].as_str() {
+            Some(payload) => payload.to_owned(),
+            None => format!("0x{}", text(&message["payload_hex"])),
+        };
+        vec![
+            text(&message["topic"]),
+            message["qos"].to_string(),
+            message["size"].to_string(),
+            shorten(&payload),
+        ]
+    });
+    Ok(table(&["TOPIC", "QOS", "SIZE", "PAYLOAD"], rows))
+}
+
+/// Run `action` of the `passwd` subcommand on the password file of the configuration.
+fn change_users(options: &AdminOptions, action: &[&str]) -> Result<(), String> {
+    let mut arguments = vec![options.configuration()?.get_password_file()];
+    arguments.extend(action.iter().map(|argument| argument.to_string()));
+    run_password_command(&arguments)
+}
+
+/// What to print when a command fails with `error`.
+pub fn failed(options: &AdminOptions, error: &str) -> String {
+    if options.json {
+        format!("{}\n", json!({ "ok": false, "error": error }))
+    } else {
+        format!("{}\n", error)
+    }
+}
+
+/// What a command that changes something prints once it is done.
+fn done(options: &AdminOptions, message: &str) -> String {
+    if options.json {
+        format!("{}\n", json!({ "ok": true, "message": message }))
+    } else {
+        format!("{}\n", message)
+    }
+}
+
+fn rows<F: Fn(&Value) -> Vec<String>>(list: &Value, row: F) -> Vec<Vec<String>> {
+    list.as_array()
+        .map(|items| items.iter().map(row).collect())
+        .unwrap_or_default()
+}
+
+/// Columns of `rows` under `headers`, each as wide as its longest cell.
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut lines = vec![headers.iter().map(|header| header.to_string()).collect()];
+    lines.extend(rows);
+    let mut output = String::new();
+    for line in lines {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        output.push_str(cells.join("  ").trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// A string value as it is, and anything else, such as null, as `-`.
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or("-").to_owned()
+}
+
+fn yes_no(value: &Value) -> String {
+    if value.as_bool().unwrap_or(false) {
+        "yes".to_owned()
+    } else {
+        "no".to_owned()
+    }
+}
+
+fn shorten(payload: &str) -> String {
+    if payload.chars().count() <= MAX_PAYLOAD_WIDTH {
+        return payload.to_owned();
+    }
+    let cut: String = payload.chars().take(MAX_PAYLOAD_WIDTH - 3).collect();
+    format!("{}...", cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test01_flags_comando_y_entorno() {
+        let env = |name: &str| match name {
+            "MQTT_RUSTICO_ADMIN_TOKEN" => Some("secreto".to_owned()),
+            "MQTT_RUSTICO_ADMIN_ADDRESS" => Some("127.0.0.1:1".to_owned()),
+            _ => None,
+        };
+        let options = AdminOptions::parse(
+            &args(&[
+                "--json",
+                "-a",
+                "127.0.0.1:7667",
+                "clients",
+                "kick",
+                "-sensor",
+            ]),
+            env,
+        )
+        .unwrap();
+        assert!(options.json);
+        assert_eq!(options.address, Some("127.0.0.1:7667".to_owned()));
+        assert_eq!(options.token, Some("secreto".to_owned()));
+        assert_eq!(options.command, args(&["clients", "kick", "-sensor"]));
+        assert!(AdminOptions::parse(&args(&["--jsn", "clients", "list"]), |_| None).is_err());
+        assert!(AdminOptions::parse(&args(&["--token"]), |_| None).is_err());
+        let options = AdminOptions::parse(&args(&["clients", "list"]), |_| None).unwrap();
+        assert!(options.api().is_err());
+        assert_eq!(
+            run(&AdminOptions::parse(&args(&["clients"]), |_| None).unwrap()),
+            Err(USAGE.to_owned())
+        );
+    }
+
+    #[test]
+    fn test02_tabla_con_columnas_alineadas() {
+        let rows = vec![
+            vec!["sensor-1".to_owned(), "1".to_owned()],
+            vec!["a".to_owned(), "12345".to_owned()],
+        ];
+        assert_eq!(
+            table(&["CLIENT ID", "QOS"], rows),
+            "CLIENT ID  QOS\nsensor-1   1\na          12345\n"
+        );
+        assert_eq!(shorten(&"x".repeat(50)).chars().count(), MAX_PAYLOAD_WIDTH);
+    }
+
+    #[test]
+    fn test03_contraseña_fuera_de_los_argumentos_y_errores_en_json() {
+        let options =
+            AdminOptions::parse(&args(&["users", "add", "juan", "secreto"]), |_| None).unwrap();
+        assert_eq!(run(&options), Err(USAGE.to_owned()));
+        assert_eq!(failed(&options, "Sin servidor"), "Sin servidor\n");
+        let options = AdminOptions::parse(&args(&["--json", "clients", "list"]), |_| None).unwrap();
+        let error: Value = serde_json::from_str(&failed(&options, "Sin \"servidor\"")).unwrap();
+        assert_eq!(error, json!({ "ok": false, "error": "Sin \"servidor\"" }));
+    }
+}
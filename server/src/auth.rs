@@ -0,0 +1,168 @@
+//! # Auth
+//!
+//! Authentication of the clients that connect with a username and password, and authorization of
+//! the topics they use. The server asks an [`AuthProvider`], so the users can live in a file, in
+//! an external store reached through a command, or anywhere a new provider looks for them.
+
+use crate::acl::Acl;
+use crate::client::Client;
+use crate::password_file::PasswordFile;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+/// How long an authentication command may run before it is killed and the client refused.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a client asks to do with a topic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Publish,
+    Subscribe,
+}
+
+/// Decides who can connect and what each client can do.
+pub trait AuthProvider: Send + Sync {
+    /// Whether `username` and `password` are valid credentials for the client with `client_id`.
+    fn authenticate(&self, client_id: &str, username: &str, password: &[u8]) -> bool;
+
+    /// Whether `client` may publish to `topic`, or subscribe to `topic` as a filter.
+    fn authorize(&self, client: &Client, action: Action, topic: &str) -> bool;
+}
+
+/// Users of a password file, and topics allowed by an ACL file. By default there are no users
+/// and every topic is allowed.
+#[derive(Default)]
+pub struct FileAuthProvider {
+    passwords: PasswordFile,
+    acl: Acl,
+}
+
+impl FileAuthProvider {
+    pub fn new(passwords: PasswordFile, acl: Acl) -> Self {
+        FileAuthProvider { passwords, acl }
+    }
+}
+
+impl AuthProvider for FileAuthProvider {
+    fn authenticate(&self, _client_id: &str, username: &str, password: &[u8]) -> bool {
+        match std::str::from_utf8(password) {
+            Ok(password) => self.passwords.verify(username, password),
+            Err(_) => false,
+        }
+    }
+
+    fn authorize(&self, client: &Client, action: Action, topic: &str) -> bool {
+        authorize_with_acl(&self.acl, client, action, topic)
+    }
+}
+
+/// Users checked by an external command, and topics allowed by an ACL file.
+///
+/// The command receives the client id and username on a line each, followed by the password, on
+/// its standard input. It accepts the client by exiting with status 0.
+pub struct CommandAuthProvider {
+    program: String,
+    arguments: Vec<String>,
+    acl: Acl,
+}
+
+impl CommandAuthProvider {
+    /// Provider running `program` with `arguments`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the program is empty.
+    pub fn new(program: &str, arguments: Vec<String>, acl: Acl) -> Result<Self, String> {
+        if program.is_empty() {
+            return Err("The authentication command is empty".to_owned());
+        }
+        Ok(CommandAuthProvider {
+            program: program.to_owned(),
+            arguments,
+            acl,
+        })
+    }
+
+    /// Run the command with the credentials, waiting at most `COMMAND_TIMEOUT`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the command couldn't run or timed out.
+    fn run(&self, client_id: &str, username: &str, password: &[u8]) -> std::io::Result<bool> {
+        let mut child = Command::new(&self.program)
+            .args(&self.arguments)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let mut input = format!("{}\n{}\n", client_id, username).into_bytes();
+            input.extend_from_slice(password);
+            // The command may exit without reading its input, which isn't an error.
+            let _ = stdin.write_all(&input);
+        }
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status.success());
+            }
+            if start.elapsed() > COMMAND_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Authentication command timed out",
+                ));
+            }
+            thread::sleep(COMMAND_POLL_INTERVAL);
+        }
+    }
+}
+
+impl AuthProvider for CommandAuthProvider {
+    fn authenticate(&self, client_id: &str, username: &str, password: &[u8]) -> bool {
+        if client_id.contains('\n') || username.contains('\n') {
+            warn!("Credentials with line breaks can't be passed to the authentication command.");
+            return false;
+        }
+        match self.run(client_id, username, password) {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                error!("Error running the authentication command: {}", error);
+                false
+            }
+        }
+    }
+
+    fn authorize(&self, client: &Client, action: Action, topic: &str) -> bool {
+        authorize_with_acl(&self.acl, client, action, topic)
+    }
+}
+
+fn authorize_with_acl(acl: &Acl, client: &Client, action: Action, topic: &str) -> bool {
+    match action {
+        Action::Publish => acl.can_publish(client, topic),
+        Action::Subscribe => acl.can_subscribe(client, topic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_comando_recibe_las_credenciales_por_stdin() {
+        let script =
+            "read id; read user; read -r pass; [ \"$id/$user/$pass\" = \"sensor/franco/123pop\" ]";
+        let provider =
+            CommandAuthProvider::new("sh", vec!["-c".to_owned(), script.to_owned()], Acl::new())
+                .unwrap();
+        assert!(provider.authenticate("sensor", "franco", b"123pop"));
+        assert!(!provider.authenticate("sensor", "franco", b"321triat"));
+        assert!(!provider.authenticate("sensor\nfranco", "franco", b"123pop"));
+        assert!(CommandAuthProvider::new("", Vec::new(), Acl::new()).is_err());
+    }
+}
@@ -0,0 +1,694 @@
+//! # Broker
+//!
+//! Embeddable MQTT broker. A [`BrokerBuilder`] starts the coordinator threads and the event loop
+//! with a [`Configuration`], and the running [`Broker`] can publish and subscribe in-process,
+//! without going through TCP.
+//!
+//! In-process clients are registered in the coordinator like any other client, so the ACL and the
+//! hooks apply to them: the publisher has client id `$local-publisher`, and each subscriber
+//! `$local-<n>`.
+
+use crate::acl::Acl;
+use crate::admin::{AdminApi, AdminServer};
+use crate::auth::{AuthProvider, CommandAuthProvider, FileAuthProvider};
+use crate::client::Client;
+use crate::configuration::Configuration;
+use crate::coordinator::{run_coordinator, ClientCommand, CoordinatorCommand, NewConnection};
+use crate::hooks::{Hooks, MessageHook};
+use crate::metrics::{Metrics, MetricsServer};
+use crate::network::{ClientChannel, EventLoop, EventLoopHandle, ListenerConfig, CLOSE_CONNECTION};
+use crate::password_file::PasswordFile;
+use crate::retained_store::RetainedStore;
+use crate::session_store::SessionStore;
+use crate::stacked_messages::run_stacked_coordinator;
+use crate::sys_topics::run_sys_timer;
+use crate::tls::load_server_config;
+use codec::{
+    decode_packet, ControlPacket, Disconnect, PubRel, Publish, Subscribe, SubscribeTopic,
+    MQTT_VERSION_5,
+};
+use mio::Waker;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info};
+
+const PUBLISHER_CLIENT_ID: &str = "$local-publisher";
+const SUBSCRIBER_CLIENT_ID_PREFIX: &str = "$local-";
+/// How long `subscribe` and `publish` wait for the coordinator to answer.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(5);
+const SUBSCRIPTION_REFUSED: u8 = 0x80;
+
+/// Packets the coordinator queues for an in-process client.
+type LocalReceiver = Receiver<(usize, Vec<u8>)>;
+
+/// Options of a broker before starting it.
+pub struct BrokerBuilder {
+    config: Configuration,
+    auth: Option<Arc<dyn AuthProvider>>,
+    hooks: Vec<Box<dyn MessageHook>>,
+}
+
+impl BrokerBuilder {
+    pub fn new(config: Configuration) -> Self {
+        BrokerBuilder {
+            config,
+            auth: None,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Authenticate and authorize the clients with `auth`, instead of the password, command and
+    /// ACL files of the configuration.
+    pub fn auth_provider(mut self, auth: Arc<dyn AuthProvider>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Register `hook` after the built-in hooks of the configuration.
+    pub fn hook(mut self, hook: Box<dyn MessageHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Load the files the configuration refers to, without starting the broker: the auth
+    /// provider, the hooks and the TLS certificates.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if one of them is missing or invalid.
+    pub fn check(&self) -> std::io::Result<()> {
+        let cfg = &self.config;
+        if self.auth.is_none() {
+            auth_provider(cfg)?;
+        }
+        Hooks::from_names(&cfg.get_hooks()).map_err(invalid_configuration)?;
+        if let Some((certificate, key)) = cfg.get_tls_files() {
+            load_server_config(&certificate, &key, cfg.get_tls_client_ca().as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// Launch the Coordinator, the Stacked messages coordinator and the event loop that handles
+    /// every client connection, each on its own thread, the timer of the `$SYS` topics, and the
+    /// metrics server and admin API if the configuration has their addresses.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a file of the configuration is invalid, a listener couldn't be bound or a
+    /// thread couldn't be spawned.
+    pub fn start(self) -> std::io::Result<Broker> {
+        let cfg = self.config;
+        let clients: HashMap<usize, Client> = HashMap::new();
+        let lock_clients = Arc::new(Mutex::new(clients));
+        let lock_clients_stacked_messages = lock_clients.clone();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mutex_clients_sender = Arc::new(Mutex::new(clients_sender));
+        let auth = match self.auth {
+            Some(auth) => auth,
+            None => auth_provider(&cfg)?,
+        };
+        let mut hooks = Hooks::from_names(&cfg.get_hooks()).map_err(invalid_configuration)?;
+        for hook in self.hooks {
+            hooks.register(hook);
+        }
+        let retained_messages = match cfg.get_retained_store() {
+            Some(path) => RetainedStore::load(&path)?,
+            None => RetainedStore::new(),
+        };
+        let mut sessions = match cfg.get_session_store() {
+            Some(directory) => SessionStore::open(&directory)?,
+            None => SessionStore::new(),
+        };
+        let tls = match cfg.get_tls_files() {
+            Some((certificate, key)) => Some(load_server_config(
+                &certificate,
+                &key,
+                cfg.get_tls_client_ca().as_deref(),
+            )?),
+            None => None,
+        };
+        let metrics = Arc::new(Metrics::new());
+        let metrics_server = match cfg.get_metrics_address() {
+            Some(address) => Some(MetricsServer::start(&address, metrics.clone())?),
+            None => None,
+        };
+        let mut listeners: Vec<ListenerConfig> = Vec::new();
+        for listener in cfg.get_listeners() {
+            debug!("Listener: {:?}", listener);
+            listeners.push(ListenerConfig {
+                address: listener.address,
+                tls: if listener.tls { tls.clone() } else { None },
+                password_required: listener.password_required,
+            });
+        }
+        let mut event_loop = EventLoop::new(
+            listeners,
+            lock_clients.clone(),
+            mutex_clients_sender.clone(),
+            auth.clone(),
+            cfg.get_wills_on_shutdown(),
+            cfg.get_limits(),
+            metrics.clone(),
+        )?;
+        restore_sessions(&mut sessions, &mut event_loop, &lock_clients)?;
+        let control = event_loop.handle();
+        let waker = event_loop.waker();
+        let coordinator_clients = lock_clients.clone();
+        let coordinator_metrics = metrics.clone();
+        let stacked_metrics = metrics.clone();
+        let coordinator = thread::Builder::new()
+            .name("Coordinator".into())
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    coordinator_clients,
+                    retained_messages,
+                    sessions,
+                    auth,
+                    hooks,
+                    coordinator_metrics,
+                )
+            })?;
+        let (stop_stacked, stacked_receiver) = mpsc::channel();
+        let stacked = thread::Builder::new()
+            .name("Stacked messages coordinator".into())
+            .spawn(move || {
+                run_stacked_coordinator(
+                    lock_clients_stacked_messages,
+                    stacked_receiver,
+                    stacked_metrics,
+                )
+            })?;
+        let (stop_sys_timer, sys_timer_receiver) = mpsc::channel();
+        let sys_timer = match cfg.get_sys_interval() {
+            Some(interval) => {
+                let sender = mutex_clients_sender.clone();
+                Some(
+                    thread::Builder::new()
+                        .name("$SYS timer".into())
+                        .spawn(move || run_sys_timer(sender, interval, sys_timer_receiver))?,
+                )
+            }
+            None => None,
+        };
+        let event_loop = thread::Builder::new()
+            .name("Event loop".into())
+            .spawn(move || event_loop.run())?;
+        let mut broker = Broker {
+            lock_clients,
+            coordinator_sender: mutex_clients_sender,
+            waker,
+            control,
+            next_local_id: AtomicUsize::new(usize::MAX),
+            publisher: Arc::new(Mutex::new(None)),
+            event_loop: Some(event_loop),
+            coordinator: Some(coordinator),
+            stacked: Some(stacked),
+            stop_stacked: Some(stop_stacked),
+            sys_timer,
+            stop_sys_timer: Some(stop_sys_timer),
+            metrics,
+            metrics_server,
+            admin_server: None,
+        };
+        let (thread_id, receiver) = broker.connect_local(Some(PUBLISHER_CLIENT_ID))?;
+        if let Ok(mut publisher) = broker.publisher.lock() {
+            *publisher = Some(Publisher {
+                thread_id,
+                receiver,
+                last_packet_id: 0,
+            });
+        }
+        if let Some((address, token)) = cfg.get_admin() {
+            let api = AdminApi {
+                lock_clients: broker.lock_clients.clone(),
+                coordinator_sender: broker.coordinator_sender.clone(),
+                publisher: broker.publisher.clone(),
+            };
+            broker.admin_server = Some(AdminServer::start(&address, token, api)?);
+        }
+        info!("Broker started.");
+        Ok(broker)
+    }
+}
+
+/// In-process client that publishes the messages of [`Broker::publish`] and of the admin API.
+pub(crate) struct Publisher {
+    thread_id: usize,
+    /// Acknowledgements of the coordinator, which are discarded.
+    receiver: LocalReceiver,
+    last_packet_id: u16,
+}
+
+/// The broker publisher, which is None once the broker stops.
+pub(crate) type SharedPublisher = Arc<Mutex<Option<Publisher>>>;
+
+/// Publish a message with the broker publisher, as if a client had sent it.
+///
+/// # Errors
+///
+/// Returns Error if the topic has wildcards or is empty, the QoS is invalid, the ACL denies the
+/// publish or a hook drops it, or the broker stopped.
+pub(crate) fn publish_as_broker(
+    publisher: &SharedPublisher,
+    coordinator_sender: &Arc<Mutex<Sender<ClientCommand>>>,
+    topic: &str,
+    payload: Vec<u8>,
+    qos: u8,
+    retain: bool,
+) -> std::io::Result<()> {
+    if topic.is_empty() || topic.contains(['+', '#']) || qos > 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Invalid topic or QoS to publish",
+        ));
+    }
+    let mut locked = match publisher.lock() {
+        Ok(locked) => locked,
+        Err(_) => return Err(Error::other("Publisher lock poisoned")),
+    };
+    let publisher = match locked.as_mut() {
+        Some(publisher) => publisher,
+        None => return Err(not_running()),
+    };
+    while publisher.receiver.try_recv().is_ok() {}
+    let packet_identifier = if qos > 0 {
+        publisher.last_packet_id = publisher.last_packet_id.wrapping_add(1).max(1);
+        Some(publisher.last_packet_id)
+    } else {
+        None
+    };
+    let publish = Publish {
+        dup: false,
+        qos,
+        retain,
+        topic_name: topic.to_owned(),
+        packet_identifier,
+        properties: Vec::new(),
+        payload,
+    };
+    let send = |command| {
+        send_command(coordinator_sender, publisher.thread_id, command).map_err(|_| not_running())
+    };
+    let (answer_sender, answer) = mpsc::channel();
+    send(CoordinatorCommand::BrokerPublish(publish, answer_sender))?;
+    let routed = match answer.recv_timeout(ANSWER_TIMEOUT) {
+        Ok(routed) => routed,
+        Err(RecvTimeoutError::Timeout) => {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "The coordinator didn't answer the publish",
+            ))
+        }
+        Err(RecvTimeoutError::Disconnected) => return Err(not_running()),
+    };
+    if let (2, Some(packet_identifier)) = (qos, packet_identifier) {
+        send(CoordinatorCommand::PubRel(PubRel::new(packet_identifier)))?;
+    }
+    if !routed {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("Publish to {} denied or dropped", topic),
+        ));
+    }
+    Ok(())
+}
+
+/// Handle to shut down or drain a [`Broker`] from another thread, such as a signal handler.
+#[derive(Clone)]
+pub struct BrokerHandle {
+    control: EventLoopHandle,
+}
+
+impl BrokerHandle {
+    /// Ask the broker to shut down, which makes [`Broker::wait`] return.
+    pub fn shutdown(&self) {
+        self.control.stop();
+    }
+
+    /// While draining, new clients are refused with server unavailable, but the connected ones
+    /// keep being served.
+    pub fn set_draining(&self, draining: bool) {
+        self.control.set_draining(draining);
+    }
+}
+
+/// A running broker. Dropping it shuts it down.
+pub struct Broker {
+    lock_clients: Arc<Mutex<HashMap<usize, Client>>>,
+    coordinator_sender: Arc<Mutex<Sender<ClientCommand>>>,
+    waker: Arc<Waker>,
+    control: EventLoopHandle,
+    /// In-process clients take their ids from the top, so they never clash with the connections
+    /// of the event loop.
+    next_local_id: AtomicUsize,
+    publisher: SharedPublisher,
+    event_loop: Option<JoinHandle<std::io::Result<()>>>,
+    coordinator: Option<JoinHandle<()>>,
+    stacked: Option<JoinHandle<()>>,
+    stop_stacked: Option<Sender<()>>,
+    sys_timer: Option<JoinHandle<()>>,
+    stop_sys_timer: Option<Sender<()>>,
+    metrics: Arc<Metrics>,
+    metrics_server: Option<MetricsServer>,
+    admin_server: Option<AdminServer>,
+}
+
+impl Broker {
+    pub fn builder(config: Configuration) -> BrokerBuilder {
+        BrokerBuilder::new(config)
+    }
+
+    /// Publish a message to the subscribers of `topic`, as if a client had sent it.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the topic has wildcards or is empty, the QoS is invalid, the ACL denies the
+    /// publish or a hook drops it, or the broker stopped.
+    pub fn publish(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        qos: u8,
+        retain: bool,
+    ) -> std::io::Result<()> {
+        publish_as_broker(
+            &self.publisher,
+            &self.coordinator_sender,
+            topic,
+            payload,
+            qos,
+            retain,
+        )
+    }
+
+    /// Subscribe an in-process client to `topic_filter`. The messages are delivered through a
+    /// channel, so they are always sent with QoS 0.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the ACL refused the subscription or the broker stopped.
+    pub fn subscribe(&self, topic_filter: &str) -> std::io::Result<Subscriber> {
+        let (thread_id, receiver) = self.connect_local(None)?;
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: topic_filter.to_owned(),
+                qos: 0,
+            }],
+        };
+        self.send(thread_id, CoordinatorCommand::Subscribe(subscribe))?;
+        let subscriber = Subscriber {
+            thread_id,
+            receiver,
+            coordinator_sender: self.coordinator_sender.clone(),
+        };
+        let deadline = Instant::now() + ANSWER_TIMEOUT;
+        loop {
+            match subscriber.next_packet(deadline) {
+                Some(ControlPacket::SubAck(suback)) => {
+                    if suback.return_codes.contains(&SUBSCRIPTION_REFUSED) {
+                        return Err(Error::new(
+                            ErrorKind::PermissionDenied,
+                            format!("Subscription to {} refused", topic_filter),
+                        ));
+                    }
+                    return Ok(subscriber);
+                }
+                Some(_) => {}
+                None => return Err(not_running()),
+            }
+        }
+    }
+
+    /// Stop accepting connections, close the connected clients, save their sessions and stop
+    /// every thread. MQTT 5 clients receive a _Disconnect_ with the server shutting down reason
+    /// code, and the last wills are published only if `will_on_shutdown` is configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the event loop had failed.
+    pub fn shutdown(mut self) -> std::io::Result<()> {
+        self.control.stop();
+        self.join()
+    }
+
+    /// Counters and gauges of the broker, which the metrics server serves.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Clients the coordinator keeps, connected or with a session.
+    #[cfg(test)]
+    pub(crate) fn client_count(&self) -> usize {
+        self.lock_clients.lock().map_or(0, |locked| locked.len())
+    }
+
+    pub fn handle(&self) -> BrokerHandle {
+        BrokerHandle {
+            control: self.control.clone(),
+        }
+    }
+
+    /// Block until the broker is shut down through a [`BrokerHandle`] or the event loop fails,
+    /// then stop every thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error why the event loop failed.
+    pub fn wait(mut self) -> std::io::Result<()> {
+        self.join()
+    }
+
+    /// Register an in-process client and connect it, with `client_id` or else a subscriber id.
+    fn connect_local(&self, client_id: Option<&str>) -> std::io::Result<(usize, LocalReceiver)> {
+        let thread_id = self.next_local_id.fetch_sub(1, Ordering::SeqCst);
+        let client_id = match client_id {
+            Some(client_id) => client_id.to_owned(),
+            None => format!("{}{}", SUBSCRIBER_CLIENT_ID_PREFIX, usize::MAX - thread_id),
+        };
+        let (sender, receiver) = mpsc::channel();
+        let channel = ClientChannel::new(thread_id, sender, self.waker.clone());
+        match self.lock_clients.lock() {
+            Ok(mut locked) => {
+                locked.insert(thread_id, Client::new(thread_id, channel));
+            }
+            Err(_) => {
+                return Err(Error::other("Unable to get the clients lock"));
+            }
+        }
+        let connection = NewConnection {
+            client_id,
+            username: None,
+            protocol_version: MQTT_VERSION_5,
+            clean_session: true,
+            clean_start: true,
+            assigned_client_id: false,
+            will: None,
+        };
+        self.send(thread_id, CoordinatorCommand::Connect(connection))?;
+        Ok((thread_id, receiver))
+    }
+
+    fn send(&self, thread_id: usize, command: CoordinatorCommand) -> std::io::Result<()> {
+        send_command(&self.coordinator_sender, thread_id, command).map_err(|_| not_running())
+    }
+
+    /// Wait for the event loop to return, then stop the coordinator threads.
+    fn join(&mut self) -> std::io::Result<()> {
+        if let Some(mut admin_server) = self.admin_server.take() {
+            admin_server.stop();
+        }
+        let result = match self.event_loop.take() {
+            Some(event_loop) => match event_loop.join() {
+                Ok(result) => result,
+                Err(_) => Err(Error::other("The event loop panicked")),
+            },
+            None => Ok(()),
+        };
+        if let Ok(mut publisher) = self.publisher.lock() {
+            *publisher = None;
+        }
+        self.stop_sys_timer.take();
+        if let Some(sys_timer) = self.sys_timer.take() {
+            if sys_timer.join().is_err() {
+                error!("The $SYS timer panicked.");
+            }
+        }
+        if self.send(0, CoordinatorCommand::Shutdown).is_err() {
+            debug!("The coordinator had already stopped.");
+        }
+        if let Some(coordinator) = self.coordinator.take() {
+            if coordinator.join().is_err() {
+                error!("The coordinator panicked.");
+            }
+        }
+        self.stop_stacked.take();
+        if let Some(stacked) = self.stacked.take() {
+            if stacked.join().is_err() {
+                error!("The stacked messages coordinator panicked.");
+            }
+        }
+        if let Some(mut metrics_server) = self.metrics_server.take() {
+            metrics_server.stop();
+        }
+        info!("Broker stopped.");
+        result
+    }
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        if self.event_loop.is_some() {
+            self.control.stop();
+            if let Err(error) = self.join() {
+                error!("Error stopping the broker: {}", error);
+            }
+        }
+    }
+}
+
+/// In-process client subscribed with [`Broker::subscribe`]. Dropping it unsubscribes.
+pub struct Subscriber {
+    thread_id: usize,
+    receiver: LocalReceiver,
+    coordinator_sender: Arc<Mutex<Sender<ClientCommand>>>,
+}
+
+impl Subscriber {
+    /// Wait for the next message. Returns None if the broker stopped.
+    pub fn recv(&self) -> Option<Publish> {
+        loop {
+            match self.receiver.recv() {
+                Ok((_, packet)) => match decode_local(&packet)? {
+                    ControlPacket::Publish(publish) => return Some(publish),
+                    _ => continue,
+                },
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Wait at most `timeout` for the next message.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Publish> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.next_packet(deadline)? {
+                ControlPacket::Publish(publish) => return Some(publish),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Next packet the coordinator sent to the subscriber before `deadline`.
+    fn next_packet(&self, deadline: Instant) -> Option<ControlPacket> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.receiver.recv_timeout(timeout) {
+            Ok((_, packet)) => decode_local(&packet),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let disconnect = CoordinatorCommand::Disconnect(Disconnect::new(0));
+        if send_command(&self.coordinator_sender, self.thread_id, disconnect).is_err() {
+            debug!("The coordinator had already stopped.");
+        }
+    }
+}
+
+/// Decode a packet queued for an in-process client. Returns None if the coordinator closed it.
+fn decode_local(packet: &[u8]) -> Option<ControlPacket> {
+    if packet == [CLOSE_CONNECTION] {
+        return None;
+    }
+    match decode_packet(packet, MQTT_VERSION_5) {
+        Ok(packet) => Some(packet),
+        Err(_) => {
+            error!("Error decoding a packet for an in-process client.");
+            None
+        }
+    }
+}
+
+fn send_command(
+    coordinator_sender: &Arc<Mutex<Sender<ClientCommand>>>,
+    thread_id: usize,
+    command: CoordinatorCommand,
+) -> Result<(), ()> {
+    match coordinator_sender.lock() {
+        Ok(sender) => sender
+            .send(ClientCommand { thread_id, command })
+            .map_err(|_| ()),
+        Err(_) => Err(()),
+    }
+}
+
+fn not_running() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "The broker is not running")
+}
+
+/// Provider that authenticates with the configured command, or else with the password file,
+/// and authorizes with the ACL file.
+///
+/// # Errors
+///
+/// Returns Error if a file is invalid, so the broker doesn't start.
+fn auth_provider(cfg: &Configuration) -> std::io::Result<Arc<dyn AuthProvider>> {
+    let acl = match cfg.get_acl_file() {
+        Some(path) => Acl::load(&path).map_err(invalid_configuration)?,
+        None => Acl::new(),
+    };
+    let auth: Arc<dyn AuthProvider> = match cfg.get_auth_command() {
+        Some(mut command) => {
+            let program = command.remove(0);
+            Arc::new(
+                CommandAuthProvider::new(&program, command, acl).map_err(invalid_configuration)?,
+            )
+        }
+        None => {
+            let passwords =
+                PasswordFile::load(&cfg.get_password_file()).map_err(invalid_configuration)?;
+            Arc::new(FileAuthProvider::new(passwords, acl))
+        }
+    };
+    Ok(auth)
+}
+
+/// Register the saved sessions as disconnected clients, so they keep receiving messages until
+/// their clients connect again.
+fn restore_sessions(
+    sessions: &mut SessionStore,
+    event_loop: &mut EventLoop,
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+) -> std::io::Result<()> {
+    let restored = sessions.load_all()?;
+    match lock_clients.lock() {
+        Ok(mut locked) => {
+            for session in restored {
+                let (thread_id, channel) = event_loop.detached_channel();
+                locked.insert(thread_id, Client::restore(thread_id, channel, session));
+            }
+        }
+        Err(_) => {
+            error!("Error restoring the persistent sessions");
+        }
+    }
+    Ok(())
+}
+
+fn invalid_configuration(error: String) -> std::io::Error {
+    error!("{}", error);
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
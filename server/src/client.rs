@@ -2,8 +2,10 @@
 //!
 //! Useful structures to handle clients.
 
+use crate::network::ClientChannel;
+use crate::session_store::Session;
 use crate::wildcard::compare_topic;
-use std::sync::mpsc::Sender;
+use codec::{ControlPacket, LastWill, Publish, MQTT_VERSION};
 
 /// A structure to save subscription topics with QoS level.
 pub struct Subscription {
@@ -12,79 +14,130 @@ pub struct Subscription {
 }
 
 /// A structure containing the client's info that the server needs, such as:
-/// - `channel` to send packets to the client.
+/// - `username` it authenticated with, or the name of its client certificate.
+/// - `channel` to queue packets for the client connection.
 /// - `topics` to which the client is subscribed.
 /// - `publishes_received`, storing all packets that need to be sent to the client.
+/// - `pubrels_pending`, identifiers of QoS 2 messages sent to the client that await its _Pubcomp_.
+/// - `qos2_received`, identifiers of QoS 2 messages received from the client that await its _Pubrel_.
 pub struct Client {
     pub thread_id: usize,
     pub client_id: String,
-    pub channel: Sender<Vec<u8>>,
+    pub username: Option<String>,
+    pub channel: ClientChannel,
     pub topics: Vec<Subscription>,
-    pub publishes_received: Vec<Vec<u8>>,
+    pub publishes_received: Vec<Publish>,
+    pub pubrels_pending: Vec<u16>,
+    pub qos2_received: Vec<u16>,
+    pub last_packet_id: u16,
     pub clean_session: u8,
-    pub lastwill_topic: Option<String>,
-    pub lastwill_message: Option<String>,
-    pub lastwill_qos: u8,
-    pub lastwill_retained: bool,
+    pub protocol_version: u8,
+    pub lastwill: Option<LastWill>,
     pub disconnected: bool,
+    /// Address of the other end of the connection, if it is a network client.
+    pub peer_address: Option<String>,
 }
 
 impl Client {
     /// Creates a new Client.
-    pub fn new(thread_id: usize, channel: Sender<Vec<u8>>) -> Self {
+    pub fn new(thread_id: usize, channel: ClientChannel) -> Self {
         Client {
             thread_id,
             client_id: "".to_owned(),
+            username: None,
             channel,
             topics: Vec::new(),
             publishes_received: Vec::new(),
+            pubrels_pending: Vec::new(),
+            qos2_received: Vec::new(),
+            last_packet_id: 0,
             clean_session: 0,
-            lastwill_topic: None,
-            lastwill_message: None,
-            lastwill_qos: 0,
-            lastwill_retained: false,
+            protocol_version: MQTT_VERSION,
+            lastwill: None,
             disconnected: true,
+            peer_address: None,
         }
     }
 
-    /// Subscribe the client to `topic` with `qos` level.
+    /// Client of a session restored from disk, disconnected until its client connects again.
+    pub fn restore(thread_id: usize, channel: ClientChannel, session: Session) -> Self {
+        let mut client = Client::new(thread_id, channel);
+        client.client_id = session.client_id;
+        client.protocol_version = session.protocol_version;
+        client.topics = session.topics;
+        client.publishes_received = session.publishes_received;
+        client.pubrels_pending = session.pubrels_pending;
+        client.qos2_received = session.qos2_received;
+        client.last_packet_id = session.last_packet_id;
+        client
+    }
+
+    /// Subscribe the client to `topic` with `qos` level, replacing the QoS of an existing
+    /// subscription to the same topic.
     pub fn subscribe(&mut self, topic: String, qos: u8) {
-        let new_subscription = Subscription { topic, qos };
-        self.topics.push(new_subscription);
+        match self.topics.iter_mut().find(|r| r.topic == topic) {
+            Some(subscription) => subscription.qos = qos,
+            None => self.topics.push(Subscription { topic, qos }),
+        }
     }
 
-    /// Unsubscribe the client from `topic`.
-    pub fn unsubscribe(&mut self, topic: String) {
+    /// Unsubscribe the client from `topic`. Returns whether the client was subscribed to it.
+    pub fn unsubscribe(&mut self, topic: String) -> bool {
         if let Some(indice) = self.topics.iter().position(|r| *(r.topic) == topic) {
             self.topics.remove(indice);
+            return true;
         }
+        false
     }
 
-    /// Whether client is subscribed to `topic` with QoS 1 or not.
-    pub fn is_subscribed_to_qos1(&self, topic: &str) -> bool {
-        let mut subscribed: bool = false;
+    /// Maximum QoS level granted among the client subscriptions matching `topic`, if any.
+    pub fn subscribed_qos(&self, topic: &str) -> Option<u8> {
+        let mut qos: Option<u8> = None;
         for topic_aux in &self.topics {
-            if topic_aux.qos == 1 && compare_topic(topic, topic_aux.topic.as_str()) {
-                subscribed = true;
+            if compare_topic(topic, topic_aux.topic.as_str()) && qos < Some(topic_aux.qos) {
+                qos = Some(topic_aux.qos);
             }
         }
-        subscribed
+        qos
     }
 
-    /// Whether the client is subscribed to `topic` or not.
-    pub fn is_subscribed_to(&self, topic: &str) -> bool {
-        let mut subscribed: bool = false;
-        for topic_aux in &self.topics {
-            if compare_topic(topic, topic_aux.topic.as_str()) {
-                subscribed = true;
+    /// Next packet identifier to use on a QoS 1 or 2 message sent to the client.
+    ///
+    /// Skips 0 and the identifiers still in use by unacknowledged messages.
+    pub fn next_packet_id(&mut self) -> u16 {
+        loop {
+            self.last_packet_id = self.last_packet_id.wrapping_add(1);
+            let candidate = self.last_packet_id;
+            if candidate != 0
+                && !self.pubrels_pending.contains(&candidate)
+                && !self
+                    .publishes_received
+                    .iter()
+                    .any(|publish| publish.packet_identifier == Some(candidate))
+            {
+                return candidate;
             }
         }
-        subscribed
+    }
+
+    /// Encode `packet` with the client protocol version and queue it for the client connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the packet can't be encoded or the channel is closed.
+    pub fn send_packet(&self, packet: &ControlPacket) -> Result<(), String> {
+        let buffer = match packet.encode(self.protocol_version) {
+            Ok(buffer) => buffer,
+            Err(error) => return Err(error.to_string()),
+        };
+        self.channel.send(buffer)
     }
 
     /// Clear topic subscriptions list and publishes queue.
     pub fn remove_subscriptions_and_queue(&mut self) {
         self.topics = Vec::new();
         self.publishes_received = Vec::new();
+        self.pubrels_pending = Vec::new();
+        self.qos2_received = Vec::new();
     }
 }
@@ -0,0 +1,289 @@
+//! # Config parser
+//!
+//! Parser of the subset of TOML used by the configuration file:
+//!
+//! ```text
+//! # Comments start with a hash
+//! log_file = "server.log"
+//! hooks = ["timestamp", "utf8_only"]
+//!
+//! [auth]
+//! password_file = "users.txt"
+//!
+//! # A section that can be repeated
+//! [[listener]]
+//! address = "0.0.0.0:1883"
+//! password = true
+//! ```
+//!
+//! Values are strings, integers, booleans or single line arrays of them. Every section and value
+//! keeps its line, so the configuration can report where an invalid one is.
+
+/// A value of the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Name of the type of the value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// A `key = value` line.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+/// The entries of a `[name]` or `[[name]]` header, or the ones before any header, which have an
+/// empty name.
+#[derive(Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    /// Whether the header is `[[name]]`, which can be repeated.
+    pub repeated: bool,
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+/// Sections of `text`, in order, starting with the entries before any header.
+///
+/// # Errors
+///
+/// Returns Error with the line number and what is wrong if a line isn't a header, an entry, a
+/// comment nor blank, a key is repeated within its section, or a `[name]` section is repeated.
+pub fn parse(text: &str) -> Result<Vec<Section>, (usize, String)> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        repeated: false,
+        line: 0,
+        entries: Vec::new(),
+    }];
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = index + 1;
+        let content = strip_comment(raw_line).trim();
+        if content.is_empty() {
+            continue;
+        }
+        if content.starts_with('[') {
+            let section = parse_header(content, line)?;
+            if !section.repeated
+                && sections
+                    .iter()
+                    .any(|previous| previous.name == section.name)
+            {
+                return Err((line, format!("Section [{}] is repeated", section.name)));
+            }
+            sections.push(section);
+            continue;
+        }
+        let (key, value) = match content.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err((line, "Expected `key = value`".to_owned())),
+        };
+        if !is_bare_key(key) {
+            return Err((line, format!("Invalid key `{}`", key)));
+        }
+        let value = parse_value(value).map_err(|message| (line, message))?;
+        let section = match sections.last_mut() {
+            Some(section) => section,
+            None => return Err((line, "Entry outside of a section".to_owned())),
+        };
+        if section.entries.iter().any(|entry| entry.key == key) {
+            return Err((line, format!("Key `{}` is repeated", key)));
+        }
+        section.entries.push(Entry {
+            key: key.to_owned(),
+            value,
+            line,
+        });
+    }
+    Ok(sections)
+}
+
+/// The line up to a `#` that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.chars().all(|character| {
+            character.is_ascii_alphanumeric() || character == '_' || character == '-'
+        })
+}
+
+fn parse_header(content: &str, line: usize) -> Result<Section, (usize, String)> {
+    let (name, repeated) = if let Some(name) = content
+        .strip_prefix("[[")
+        .and_then(|rest| rest.strip_suffix("]]"))
+    {
+        (name.trim(), true)
+    } else if let Some(name) = content
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        (name.trim(), false)
+    } else {
+        return Err((line, "Unclosed section header".to_owned()));
+    };
+    if !is_bare_key(name) {
+        return Err((line, format!("Invalid section name `{}`", name)));
+    }
+    Ok(Section {
+        name: name.to_owned(),
+        repeated,
+        line,
+        entries: Vec::new(),
+    })
+}
+
+/// Parse the value after the `=`, which must take the whole text.
+fn parse_value(text: &str) -> Result<Value, String> {
+    let characters: Vec<char> = text.chars().collect();
+    let mut position = 0;
+    let value = value_at(&characters, &mut position)?;
+    skip_spaces(&characters, &mut position);
+    if position != characters.len() {
+        return Err(format!("Unexpected text after the value: `{}`", text));
+    }
+    Ok(value)
+}
+
+fn value_at(characters: &[char], position: &mut usize) -> Result<Value, String> {
+    skip_spaces(characters, position);
+    match characters.get(*position) {
+        Some('"') => string_at(characters, position),
+        Some('[') => array_at(characters, position),
+        Some(_) => {
+            let start = *position;
+            while *position < characters.len()
+                && !matches!(characters[*position], ',' | ']' | ' ' | '\t')
+            {
+                *position += 1;
+            }
+            let word: String = characters[start..*position].iter().collect();
+            match word.as_str() {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => match word.replace('_', "").parse::<i64>() {
+                    Ok(number) => Ok(Value::Integer(number)),
+                    Err(_) => Err(format!("Invalid value `{}`, strings must be quoted", word)),
+                },
+            }
+        }
+        None => Err("Missing value".to_owned()),
+    }
+}
+
+fn string_at(characters: &[char], position: &mut usize) -> Result<Value, String> {
+    let mut string = String::new();
+    *position += 1;
+    while let Some(character) = characters.get(*position) {
+        *position += 1;
+        match character {
+            '"' => return Ok(Value::String(string)),
+            '\\' => {
+                let escaped = match characters.get(*position) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    _ => return Err("Invalid escape sequence".to_owned()),
+                };
+                string.push(escaped);
+                *position += 1;
+            }
+            _ => string.push(*character),
+        }
+    }
+    Err("Unclosed string".to_owned())
+}
+
+fn array_at(characters: &[char], position: &mut usize) -> Result<Value, String> {
+    let mut values: Vec<Value> = Vec::new();
+    *position += 1;
+    loop {
+        skip_spaces(characters, position);
+        if characters.get(*position) == Some(&']') {
+            *position += 1;
+            return Ok(Value::Array(values));
+        }
+        values.push(value_at(characters, position)?);
+        skip_spaces(characters, position);
+        match characters.get(*position) {
+            Some(',') => *position += 1,
+            Some(']') => {}
+            _ => return Err("Unclosed array".to_owned()),
+        }
+    }
+}
+
+fn skip_spaces(characters: &[char], position: &mut usize) {
+    while matches!(characters.get(*position), Some(' ') | Some('\t')) {
+        *position += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_secciones_valores_y_comentarios() {
+        let text = "# Comentario\nlog_file = \"a # b.log\" # al final\n\n[auth]\nacl_file = \"acl.txt\"\n[[listener]]\naddress = \"127.0.0.1:1883\"\npassword = true\n[[listener]]\nmax = 1_000\nhooks = [\"timestamp\", \"utf8_only\",]\n";
+        let sections = parse(text).unwrap();
+        assert_eq!(sections.len(), 4);
+        assert_eq!(
+            sections[0].entries[0].value,
+            Value::String("a # b.log".to_owned())
+        );
+        assert_eq!(sections[1].name, "auth");
+        assert_eq!(sections[1].entries[0].line, 5);
+        assert!(sections[2].repeated && sections[3].repeated);
+        assert_eq!(sections[2].entries[1].value, Value::Boolean(true));
+        assert_eq!(sections[3].entries[0].value, Value::Integer(1000));
+        assert_eq!(
+            sections[3].entries[1].value,
+            Value::Array(vec![
+                Value::String("timestamp".to_owned()),
+                Value::String("utf8_only".to_owned())
+            ])
+        );
+    }
+
+    #[test]
+    fn test02_errores_informan_su_linea() {
+        assert_eq!(parse("\n\nport 1883\n").unwrap_err().0, 3);
+        assert_eq!(parse("ip = 127.0.0.1\n").unwrap_err().0, 1);
+        assert_eq!(parse("[auth]\n[auth]\n").unwrap_err().0, 2);
+        assert_eq!(parse("a = 1\na = 2\n").unwrap_err().0, 2);
+        assert_eq!(parse("a = \"sin cerrar\n").unwrap_err().0, 1);
+        assert_eq!(parse("[auth\n").unwrap_err().0, 1);
+        assert_eq!(parse("a = [1, 2\n").unwrap_err().0, 1);
+    }
+}
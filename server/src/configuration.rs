@@ -1,111 +1,740 @@
-use std::collections::HashMap;
-use tracing::{error, info};
+//! # Configuration
+//!
+//! Settings of the server, read from a sectioned file with the syntax described in the
+//! `config_parser` module:
+//!
+//! ```text
+//! hooks = ["timestamp"]
+//! will_on_shutdown = true
+//! sys_interval = 10
+//!
+//! [log]
+//! file = "logs/server.log"
+//! level = "info"
+//! modules = ["server::network=debug"]
+//! rotation = "daily"
+//! max_files = 7
+//! format = "json"
+//!
+//! [[listener]]
+//! address = "0.0.0.0:8883"
+//! tls = true
+//! password = true
+//!
+//! [tls]
+//! cert = "cert.pem"
+//! key = "key.pem"
+//! client_ca = "ca.pem"
+//!
+//! [auth]
+//! password_file = "src/users.txt"
+//! acl_file = "acl.txt"
+//! command = ["auth.sh", "--realm", "mqtt"]
+//!
+//! [persistence]
+//! retained_store = "retained.log"
+//! session_store = "sessions"
+//!
+//! [metrics]
+//! address = "127.0.0.1:9090"
+//!
+//! [admin]
+//! address = "127.0.0.1:7667"
+//! token = "secreto"
+//!
+//! [limits]
+//! max_connections = 1000
+//! max_packet_size = 65536
+//! connect_timeout = 10
+//! ```
+//!
+//! Every key is optional. Unknown keys and sections, values of the wrong type and invalid
+//! addresses are errors that tell the line where they are.
+use crate::config_parser::{parse, Entry, Section, Value};
+use std::convert::TryFrom;
+use std::time::Duration;
+use tracing::info;
 
-const DEFAULT_PORT: u16 = 7666;
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7666";
 const DEFAULT_LOGFILE: &str = "logfile.txt";
-const DEFAULT_IP: &str = "127.0.0.1";
+const DEFAULT_LOG_LEVEL: &str = "trace";
+const LOG_LEVELS: [&str; 6] = ["trace", "debug", "info", "warn", "error", "off"];
+const RETAINED_STORE_FILE: &str = "retained.log";
+const SESSION_STORE_DIRECTORY: &str = "sessions";
 const DEFAULT_PASSWORD: bool = true;
+const DEFAULT_PASSWORD_FILE: &str = "./src/users.txt";
+const DEFAULT_WILLS_ON_SHUTDOWN: bool = true;
+/// Seconds between the publications of the `$SYS` topics.
+const DEFAULT_SYS_INTERVAL: usize = 10;
+const MAX_SYS_INTERVAL: i64 = 86_400;
+/// Largest remaining length of a MQTT packet.
+const MAX_PACKET_SIZE: i64 = 268_435_455;
+const DEFAULT_MAX_PACKET_SIZE: usize = 1 << 20;
+/// Seconds a new connection has to send its _Connect_.
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const MAX_CONNECT_TIMEOUT: i64 = 3_600;
+
+/// An error of the file: its line and what is wrong.
+type ConfigError = (usize, String);
+
+/// A socket the server listens on:
+/// - `tls`, whether its clients connect with TLS, using the certificate and key of the `[tls]`
+///   section.
+/// - `password_required`, whether its clients must log in with a username and password.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerSettings {
+    pub address: String,
+    pub tls: bool,
+    pub password_required: bool,
+}
+
+/// How often a new log file is started. Each file has the date and hour it starts appended to
+/// its name, except with `Never`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Where and what to log:
+/// - `level`, most verbose level logged, and `modules`, the level of each module that logs at
+///   another one, as `(module, level)`.
+/// - `max_files`, log files kept when rotating, deleting the oldest ones. All of them if `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSettings {
+    pub file: String,
+    pub level: String,
+    pub modules: Vec<(String, String)>,
+    pub rotation: LogRotation,
+    pub max_files: Option<usize>,
+    pub format: LogFormat,
+}
+
+/// Resources a client can use:
+/// - `max_connections`, connections open at the same time, unlimited if `None`. The ones over
+///   the limit are closed as soon as they are accepted.
+/// - `max_packet_size`, remaining length of a packet sent by a client, 1 MiB by default. Clients
+///   sending a bigger one are disconnected.
+/// - `connect_timeout`, how long a new connection may take to send its _Connect_ before it is
+///   closed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub max_connections: Option<usize>,
+    pub max_packet_size: usize,
+    pub connect_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_connections: None,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT),
+        }
+    }
+}
 
 pub struct Configuration {
-    port: u16,
-    logfile: String,
-    ip: String,
-    pub password: bool,
+    log: LogSettings,
+    listeners: Vec<ListenerSettings>,
+    password_file: String,
+    acl_file: Option<String>,
+    auth_command: Option<Vec<String>>,
+    hooks: Vec<String>,
+    retained_store: Option<String>,
+    session_store: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_client_ca: Option<String>,
+    wills_on_shutdown: bool,
+    sys_interval: usize,
+    limits: Limits,
+    metrics_address: Option<String>,
+    admin_address: Option<String>,
+    admin_token: Option<String>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration::new()
+    }
 }
 
 impl Configuration {
     pub fn new() -> Self {
         Configuration {
-            port: DEFAULT_PORT,
-            logfile: DEFAULT_LOGFILE.to_string(),
-            ip: DEFAULT_IP.to_string(),
-            password: DEFAULT_PASSWORD,
+            log: LogSettings {
+                file: DEFAULT_LOGFILE.to_string(),
+                level: DEFAULT_LOG_LEVEL.to_string(),
+                modules: Vec::new(),
+                rotation: LogRotation::Never,
+                max_files: None,
+                format: LogFormat::Text,
+            },
+            listeners: Vec::new(),
+            password_file: DEFAULT_PASSWORD_FILE.to_string(),
+            acl_file: None,
+            auth_command: None,
+            hooks: Vec::new(),
+            retained_store: None,
+            session_store: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            wills_on_shutdown: DEFAULT_WILLS_ON_SHUTDOWN,
+            sys_interval: DEFAULT_SYS_INTERVAL,
+            limits: Limits::default(),
+            metrics_address: None,
+            admin_address: None,
+            admin_token: None,
         }
     }
 
+    /// Load the settings of the file at `file_path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the file couldn't be read or is invalid, as `<path>:<line>: <reason>`.
     pub fn set_config(&mut self, file_path: &str) -> Result<(), String> {
-        let map = self.parse(file_path)?;
-        self.set_all_params(map)?;
-        Ok(())
+        let text = match std::fs::read_to_string(file_path) {
+            Ok(text) => text,
+            Err(error) => return Err(format!("{}: {}", file_path, error)),
+        };
+        self.set_all_params(&text)
+            .map_err(|(line, message)| format!("{}:{}: {}", file_path, line, message))
     }
 
-    fn check_number_between(&mut self, number: &str, bottom: u32, top: u32) -> bool {
-        let int_number: u32;
-        match number.parse::<u32>() {
-            Ok(x) => int_number = x,
-            Err(_) => return false,
+    fn set_all_params(&mut self, text: &str) -> Result<(), ConfigError> {
+        let mut listeners: Vec<(usize, ListenerSettings)> = Vec::new();
+        let mut tls_line = 0;
+        for section in parse(text)? {
+            match (section.name.as_str(), section.repeated) {
+                ("", _) => self.set_general(&section)?,
+                ("listener", true) => listeners.push((section.line, parse_listener(&section)?)),
+                ("tls", false) => {
+                    tls_line = section.line;
+                    self.set_tls(&section)?
+                }
+                ("auth", false) => self.set_auth(&section)?,
+                ("persistence", false) => self.set_persistence(&section)?,
+                ("limits", false) => self.set_limits(&section)?,
+                ("log", false) => self.set_log(&section)?,
+                ("metrics", false) => self.set_metrics(&section)?,
+                ("admin", false) => self.set_admin(&section)?,
+                ("listener", false) => {
+                    return Err((
+                        section.line,
+                        "Listeners are declared with [[listener]]".into(),
+                    ))
+                }
+                (name, true) => {
+                    return Err((
+                        section.line,
+                        format!("Section [{}] can't be repeated", name),
+                    ))
+                }
+                (name, false) => return Err((section.line, format!("Unknown section [{}]", name))),
+            }
         }
-
-        if int_number <= top && int_number >= bottom {
-            return true;
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err((tls_line, "cert and key must be set together".into()));
         }
-        false
+        if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+            return Err((tls_line, "client_ca requires cert and key".into()));
+        }
+        for (line, listener) in listeners.iter() {
+            if listener.tls && self.tls_cert.is_none() {
+                return Err((
+                    *line,
+                    "TLS listeners require the cert and key of [tls]".into(),
+                ));
+            }
+        }
+        if !listeners.is_empty() {
+            self.listeners = listeners
+                .into_iter()
+                .map(|(_, listener)| listener)
+                .collect();
+            for listener in self.listeners.iter() {
+                info!("Listener: {:?}", listener);
+            }
+        }
+        Ok(())
     }
 
-    fn parse(&mut self, file_path: &str) -> Result<HashMap<String, String>, String> {
-        let file: String = match std::fs::read_to_string(file_path) {
-            Ok(file) => file,
-            Err(_) => return Err("Error when trying to open the file".to_string()),
-        };
-        let mut map: HashMap<String, String> = HashMap::new();
-        let lines = file.lines();
+    /// Keys before any section.
+    fn set_general(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "hooks" => {
+                    self.hooks = strings(entry)?;
+                    info!("Registered hooks: {:?}", self.hooks);
+                }
+                "will_on_shutdown" => self.wills_on_shutdown = boolean(entry)?,
+                "sys_interval" => self.sys_interval = integer_between(entry, 0, MAX_SYS_INTERVAL)?,
+                _ => return Err(unknown_key(entry)),
+            }
+        }
+        Ok(())
+    }
 
-        for line in lines {
-            let name_and_value: Vec<&str> = line.split('=').collect();
-            let config_name: String = name_and_value[0]
-                .to_lowercase()
-                .replace(' ', "")
-                .to_string();
-            let value: String = name_and_value[1].replace(' ', "").to_string();
-            map.insert(config_name, value);
+    fn set_log(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "file" => {
+                    self.log.file = string(entry)?;
+                    info!("Loaded log file : {}", self.log.file);
+                }
+                "level" => {
+                    let level = string(entry)?;
+                    self.set_log_level(&level)
+                        .map_err(|message| (entry.line, message))?;
+                }
+                "modules" => {
+                    let mut modules: Vec<(String, String)> = Vec::new();
+                    for directive in strings(entry)? {
+                        let (module, level) = match directive.split_once('=') {
+                            Some((module, level)) if is_log_level(level) && !module.is_empty() => {
+                                (module.to_owned(), level.to_lowercase())
+                            }
+                            _ => {
+                                return Err((
+                                    entry.line,
+                                    format!(
+                                        "Invalid module level `{}`, expected `<module>=<level>`",
+                                        directive
+                                    ),
+                                ))
+                            }
+                        };
+                        modules.push((module, level));
+                    }
+                    self.log.modules = modules;
+                }
+                "rotation" => {
+                    self.log.rotation = match string(entry)?.as_str() {
+                        "never" => LogRotation::Never,
+                        "hourly" => LogRotation::Hourly,
+                        "daily" => LogRotation::Daily,
+                        _ => return Err(one_of(entry, "never, hourly, daily")),
+                    }
+                }
+                "max_files" => self.log.max_files = Some(integer_between(entry, 1, i64::MAX)?),
+                "format" => {
+                    self.log.format = match string(entry)?.as_str() {
+                        "text" => LogFormat::Text,
+                        "json" => LogFormat::Json,
+                        _ => return Err(one_of(entry, "text, json")),
+                    }
+                }
+                _ => return Err(unknown_key(entry)),
+            }
         }
-        Ok(map)
+        Ok(())
     }
 
-    fn set_all_params(&mut self, map: HashMap<String, String>) -> Result<(), String> {
-        if let Some(logfile_) = map.get("logfile") {
-            self.logfile = logfile_.to_string();
-            info!("Loaded log file : {}", self.logfile);
+    fn set_tls(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "cert" => self.tls_cert = Some(string(entry)?),
+                "key" => self.tls_key = Some(string(entry)?),
+                "client_ca" => {
+                    let client_ca = string(entry)?;
+                    info!("Client certificates verified with: {}", client_ca);
+                    self.tls_client_ca = Some(client_ca);
+                }
+                _ => return Err(unknown_key(entry)),
+            }
         }
-        if let Some(port_) = map.get("port") {
-            if !self.check_number_between(port_, 0, 65536) {
-                return Err("Unvalid port".to_string());
+        Ok(())
+    }
+
+    fn set_auth(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "password_file" => self.password_file = string(entry)?,
+                "acl_file" => self.acl_file = Some(string(entry)?),
+                "command" => {
+                    let command = match entry.value {
+                        Value::Array(_) => strings(entry)?,
+                        _ => vec![string(entry)?],
+                    };
+                    if command.first().is_none_or(|program| program.is_empty()) {
+                        return Err((entry.line, "command can't be empty".into()));
+                    }
+                    info!("Users authenticated by: {:?}", command);
+                    self.auth_command = Some(command);
+                }
+                _ => return Err(unknown_key(entry)),
             }
-            match port_.parse::<u16>() {
-                Ok(port) => {
-                    self.port = port;
+        }
+        Ok(())
+    }
+
+    fn set_persistence(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "retained_store" => {
+                    let path = string(entry)?;
+                    info!("Retained messages stored at: {}", path);
+                    self.retained_store = Some(path);
                 }
-                Err(_) => {
-                    error!("Error while parsing ip from config file");
-                    return Err("Error while parsing ip from config file".into());
+                "session_store" => {
+                    let directory = string(entry)?;
+                    info!("Persistent sessions stored at: {}", directory);
+                    self.session_store = Some(directory);
                 }
+                _ => return Err(unknown_key(entry)),
             }
         }
-        if let Some(ip_) = map.get("ip") {
-            self.ip = ip_.to_string();
+        Ok(())
+    }
+
+    fn set_metrics(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "address" => self.metrics_address = Some(address(entry)?),
+                _ => return Err(unknown_key(entry)),
+            }
         }
-        if let Some(password_) = map.get("password") {
-            match password_.parse::<u32>() {
-                Ok(pass) => {
-                    if pass == 0 {
-                        self.password = false
+        Ok(())
+    }
+
+    /// The admin API is only served with a token.
+    fn set_admin(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "address" => self.admin_address = Some(address(entry)?),
+                "token" => match string(entry)? {
+                    token if token.is_empty() => {
+                        return Err((entry.line, "token can't be empty".into()))
                     }
+                    token => self.admin_token = Some(token),
+                },
+                _ => return Err(unknown_key(entry)),
+            }
+        }
+        if self.admin_address.is_some() && self.admin_token.is_none() {
+            return Err((section.line, "The admin API requires a token".into()));
+        }
+        Ok(())
+    }
+
+    fn set_limits(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "max_connections" => {
+                    self.limits.max_connections = Some(integer_between(entry, 1, i64::MAX)?)
                 }
-                Err(_) => {
-                    error!("Error while parsing password from config file");
-                    return Err("Error while parsing password from config file".into());
+                "max_packet_size" => {
+                    self.limits.max_packet_size = integer_between(entry, 1, MAX_PACKET_SIZE)?
                 }
+                "connect_timeout" => {
+                    let seconds = integer_between(entry, 1, MAX_CONNECT_TIMEOUT)?;
+                    self.limits.connect_timeout = Duration::from_secs(seconds as u64);
+                }
+                _ => return Err(unknown_key(entry)),
             }
         }
         Ok(())
     }
 
-    pub fn get_address(&self) -> String {
-        format!("{}:{}", self.ip, self.port)
+    /// Replace the host of the first listener, keeping its port. IPv6 hosts may omit the
+    /// brackets.
+    pub fn set_bind(&mut self, host: &str) {
+        let host = if host.contains(':') && !host.starts_with('[') {
+            format!("[{}]", host)
+        } else {
+            host.to_owned()
+        };
+        let listener = self.first_listener();
+        let port = match listener.address.rsplit_once(':') {
+            Some((_, port)) => port.to_owned(),
+            None => String::new(),
+        };
+        listener.address = format!("{}:{}", host, port);
+    }
+
+    /// Replace the port of the first listener, keeping its host.
+    pub fn set_port(&mut self, port: u16) {
+        let listener = self.first_listener();
+        let host = match listener.address.rsplit_once(':') {
+            Some((host, _)) => host.to_owned(),
+            None => listener.address.clone(),
+        };
+        listener.address = format!("{}:{}", host, port);
+    }
+
+    /// The first declared listener, or else the default one, which becomes declared.
+    fn first_listener(&mut self) -> &mut ListenerSettings {
+        if self.listeners.is_empty() {
+            self.listeners = self.get_listeners();
+        }
+        &mut self.listeners[0]
+    }
+
+    /// # Errors
+    ///
+    /// Returns Error if `level` isn't trace, debug, info, warn, error nor off.
+    pub fn set_log_level(&mut self, level: &str) -> Result<(), String> {
+        if !is_log_level(level) {
+            return Err(format!(
+                "Unknown log level `{}`, must be one of {}",
+                level,
+                LOG_LEVELS.join(", ")
+            ));
+        }
+        self.log.level = level.to_lowercase();
+        Ok(())
+    }
+
+    /// Keep the retained messages and the persistent sessions in `directory`.
+    pub fn set_data_dir(&mut self, directory: &str) {
+        let directory = std::path::Path::new(directory);
+        self.retained_store = Some(
+            directory
+                .join(RETAINED_STORE_FILE)
+                .to_string_lossy()
+                .into_owned(),
+        );
+        self.session_store = Some(
+            directory
+                .join(SESSION_STORE_DIRECTORY)
+                .to_string_lossy()
+                .into_owned(),
+        );
+    }
+
+    /// Paths of the certificate chain and private key of the TLS listener, if it's enabled.
+    pub fn get_tls_files(&self) -> Option<(String, String)> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+            _ => None,
+        }
+    }
+
+    /// Bundle of the certificate authorities that issue the client certificates, if the TLS
+    /// listener requires them.
+    pub fn get_tls_client_ca(&self) -> Option<String> {
+        self.tls_client_ca.clone()
+    }
+
+    /// Sockets to listen on. Without `[[listener]]` sections, a plain listener on 127.0.0.1:7666
+    /// that requires a password.
+    pub fn get_listeners(&self) -> Vec<ListenerSettings> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        vec![ListenerSettings {
+            address: DEFAULT_ADDRESS.to_owned(),
+            tls: false,
+            password_required: DEFAULT_PASSWORD,
+        }]
+    }
+
+    /// Whether the clients closed by a shutdown publish their last will.
+    pub fn get_wills_on_shutdown(&self) -> bool {
+        self.wills_on_shutdown
     }
 
     pub fn get_log_file(&self) -> String {
-        self.logfile.to_string()
+        self.log.file.to_string()
+    }
+
+    /// Most verbose level of the logged events: trace, debug, info, warn, error or off.
+    pub fn get_log_level(&self) -> String {
+        self.log.level.clone()
+    }
+
+    pub fn get_log_settings(&self) -> LogSettings {
+        self.log.clone()
+    }
+
+    /// Path of the file with the users and their hashed passwords.
+    pub fn get_password_file(&self) -> String {
+        self.password_file.to_string()
+    }
+
+    /// Path of the file with the topics each client may use, if they are restricted.
+    pub fn get_acl_file(&self) -> Option<String> {
+        self.acl_file.clone()
+    }
+
+    /// Program, followed by its arguments, that checks the credentials instead of the password
+    /// file, if any.
+    pub fn get_auth_command(&self) -> Option<Vec<String>> {
+        self.auth_command.clone()
+    }
+
+    /// Names of the built-in hooks to register, in order.
+    pub fn get_hooks(&self) -> Vec<String> {
+        self.hooks.clone()
+    }
+
+    /// Path of the retained messages log, if they have to survive restarts.
+    pub fn get_retained_store(&self) -> Option<String> {
+        self.retained_store.clone()
+    }
+
+    /// Directory of the persistent sessions, if they have to survive restarts.
+    pub fn get_session_store(&self) -> Option<String> {
+        self.session_store.clone()
+    }
+
+    /// Address of the HTTP server of the metrics, if they are served.
+    pub fn get_metrics_address(&self) -> Option<String> {
+        self.metrics_address.clone()
+    }
+
+    /// Time between the publications of the `$SYS` topics, or None if they are not published.
+    pub fn get_sys_interval(&self) -> Option<Duration> {
+        match self.sys_interval {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds as u64)),
+        }
+    }
+
+    /// Address of the admin API and the token its requests must carry, if it is served.
+    pub fn get_admin(&self) -> Option<(String, String)> {
+        match (&self.admin_address, &self.admin_token) {
+            (Some(address), Some(token)) => Some((address.clone(), token.clone())),
+            _ => None,
+        }
+    }
+
+    /// Limits of the connections and packets of the clients.
+    pub fn get_limits(&self) -> Limits {
+        self.limits
+    }
+}
+
+/// Listener of a `[[listener]]` section, which must have an `address`.
+///
+/// # Errors
+///
+/// Returns Error if the address is missing or invalid, or a key is unknown.
+fn parse_listener(section: &Section) -> Result<ListenerSettings, ConfigError> {
+    let mut settings = ListenerSettings {
+        address: String::new(),
+        tls: false,
+        password_required: false,
+    };
+    for entry in section.entries.iter() {
+        match entry.key.as_str() {
+            "address" => settings.address = address(entry)?,
+            "tls" => settings.tls = boolean(entry)?,
+            "password" => settings.password_required = boolean(entry)?,
+            _ => return Err(unknown_key(entry)),
+        }
+    }
+    if settings.address.is_empty() {
+        return Err((section.line, "A listener needs an address".into()));
+    }
+    Ok(settings)
+}
+
+fn is_log_level(level: &str) -> bool {
+    LOG_LEVELS.contains(&level.to_lowercase().as_str())
+}
+
+fn unknown_key(entry: &Entry) -> ConfigError {
+    (entry.line, format!("Unknown key `{}`", entry.key))
+}
+
+fn one_of(entry: &Entry, values: &str) -> ConfigError {
+    (
+        entry.line,
+        format!("`{}` must be one of {}", entry.key, values),
+    )
+}
+
+fn wrong_type(entry: &Entry, expected: &str) -> ConfigError {
+    (
+        entry.line,
+        format!(
+            "`{}` must be {}, not {}",
+            entry.key,
+            expected,
+            entry.value.type_name()
+        ),
+    )
+}
+
+fn string(entry: &Entry) -> Result<String, ConfigError> {
+    match &entry.value {
+        Value::String(string) => Ok(string.clone()),
+        _ => Err(wrong_type(entry, "a string")),
+    }
+}
+
+fn boolean(entry: &Entry) -> Result<bool, ConfigError> {
+    match entry.value {
+        Value::Boolean(boolean) => Ok(boolean),
+        _ => Err(wrong_type(entry, "a boolean")),
+    }
+}
+
+fn strings(entry: &Entry) -> Result<Vec<String>, ConfigError> {
+    let values = match &entry.value {
+        Value::Array(values) => values,
+        _ => return Err(wrong_type(entry, "an array of strings")),
+    };
+    let mut strings: Vec<String> = Vec::new();
+    for value in values {
+        match value {
+            Value::String(string) => strings.push(string.clone()),
+            _ => return Err(wrong_type(entry, "an array of strings")),
+        }
+    }
+    Ok(strings)
+}
+
+fn integer_between(entry: &Entry, bottom: i64, top: i64) -> Result<usize, ConfigError> {
+    let number = match entry.value {
+        Value::Integer(number) => number,
+        _ => return Err(wrong_type(entry, "an integer")),
+    };
+    if number < bottom || number > top {
+        return Err((
+            entry.line,
+            format!("`{}` must be between {} and {}", entry.key, bottom, top),
+        ));
+    }
+    match usize::try_from(number) {
+        Ok(number) => Ok(number),
+        Err(_) => Err((entry.line, format!("`{}` is too large", entry.key))),
+    }
+}
+
+/// A `<host>:<port>` address, with a port between 1 and 65535. IPv6 hosts go between brackets.
+fn address(entry: &Entry) -> Result<String, ConfigError> {
+    let address = string(entry)?;
+    let invalid = |reason: &str| {
+        (
+            entry.line,
+            format!("Invalid address `{}`: {}", address, reason),
+        )
+    };
+    let (host, port) = match address.rsplit_once(':') {
+        Some(parts) => parts,
+        None => return Err(invalid("missing the port")),
+    };
+    if host.is_empty() {
+        return Err(invalid("missing the host"));
+    }
+    if host.contains(':') && !(host.starts_with('[') && host.ends_with(']')) {
+        return Err(invalid("IPv6 hosts go between brackets"));
+    }
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(address.clone()),
+        _ => Err(invalid("the port must be between 1 and 65535")),
     }
 }
 
@@ -116,8 +745,23 @@ mod tests {
     #[test]
     fn test01_direccion_correcta() {
         let mut aux = Configuration::new();
+        assert_eq!(
+            aux.get_listeners(),
+            vec![ListenerSettings {
+                address: "127.0.0.1:7666".to_owned(),
+                tls: false,
+                password_required: true,
+            }]
+        );
         aux.set_config("src/testcfg.txt").unwrap();
-        assert_eq!(aux.get_address(), "127.0.0.1:1883");
+        assert_eq!(
+            aux.get_listeners(),
+            vec![ListenerSettings {
+                address: "127.0.0.1:1883".to_owned(),
+                tls: false,
+                password_required: true,
+            }]
+        );
     }
 
     #[test]
@@ -126,4 +770,221 @@ mod tests {
         aux.set_config("src/testcfg.txt").unwrap();
         assert_eq!(aux.get_log_file(), "file.log");
     }
+
+    #[test]
+    fn test03_retained_store_opcional() {
+        let mut aux = Configuration::new();
+        aux.set_config("src/testcfg.txt").unwrap();
+        assert_eq!(aux.get_retained_store(), None);
+        aux.set_config("src/testingConfigs/cfgv.txt").unwrap();
+        assert_eq!(
+            aux.get_retained_store(),
+            Some("retained_test.log".to_owned())
+        );
+    }
+
+    #[test]
+    fn test04_tls_requiere_certificado_y_clave() {
+        let mut aux = Configuration::new();
+        assert_eq!(
+            aux.set_all_params("[tls]\ncert = \"cert.pem\"\n")
+                .unwrap_err()
+                .0,
+            1
+        );
+        let mut aux = Configuration::new();
+        aux.set_all_params(
+            "[[listener]]\naddress = \"0.0.0.0:8883\"\ntls = true\n[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            aux.get_tls_files(),
+            Some(("cert.pem".to_owned(), "key.pem".to_owned()))
+        );
+
+        let mut aux = Configuration::new();
+        let error = aux
+            .set_all_params("\n[[listener]]\naddress = \"0.0.0.0:8883\"\ntls = true\n")
+            .unwrap_err();
+        assert_eq!(error.0, 2);
+    }
+
+    #[test]
+    fn test05_listeners_declarados_reemplazan_al_por_defecto() {
+        let mut aux = Configuration::new();
+        aux.set_config("src/testingConfigs/cfgae.txt").unwrap();
+        assert_eq!(
+            aux.get_listeners(),
+            vec![
+                ListenerSettings {
+                    address: "127.0.0.1:1915".to_owned(),
+                    tls: false,
+                    password_required: false,
+                },
+                ListenerSettings {
+                    address: "[::1]:1916".to_owned(),
+                    tls: false,
+                    password_required: true,
+                },
+            ]
+        );
+        assert!(Configuration::new()
+            .set_all_params("[[listener]]\ntls = false\n")
+            .is_err());
+        assert!(Configuration::new()
+            .set_all_params("[[listener]]\naddress = \"0.0.0.0:1883\"\nanonimo = true\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test06_errores_con_numero_de_linea() {
+        let mut aux = Configuration::new();
+        let error = aux
+            .set_all_params("# Comentario\n\nhooks = []\nport = 1883\n")
+            .unwrap_err();
+        assert_eq!(error, (4, "Unknown key `port`".to_owned()));
+        let error = aux
+            .set_all_params("[[listener]]\naddress = \"127.0.0.1:65536\"\n")
+            .unwrap_err();
+        assert_eq!(error.0, 2);
+        let error = aux
+            .set_all_params("[[listener]]\naddress = \"127.0.0.1:0\"\n")
+            .unwrap_err();
+        assert_eq!(error.0, 2);
+        let error = aux.set_all_params("will_on_shutdown = 0\n").unwrap_err();
+        assert_eq!(
+            error,
+            (
+                1,
+                "`will_on_shutdown` must be a boolean, not an integer".to_owned()
+            )
+        );
+        assert_eq!(aux.set_all_params("[server]\n").unwrap_err().0, 1);
+        assert_eq!(
+            aux.set_all_params("[limits]\nmax_packet_size = 0\n")
+                .unwrap_err()
+                .0,
+            2
+        );
+        assert_eq!(
+            Configuration::new()
+                .set_config("src/testingConfigs/no_existe.txt")
+                .unwrap_err(),
+            format!(
+                "src/testingConfigs/no_existe.txt: {}",
+                std::fs::read_to_string("src/testingConfigs/no_existe.txt").unwrap_err()
+            )
+        );
+    }
+
+    #[test]
+    fn test07_limites() {
+        let mut aux = Configuration::new();
+        assert_eq!(aux.get_limits(), Limits::default());
+        assert_eq!(aux.get_limits().max_packet_size, 1 << 20);
+        assert_eq!(aux.get_limits().connect_timeout, Duration::from_secs(10));
+        aux.set_all_params(
+            "[limits]\nmax_connections = 2\nmax_packet_size = 1_024\nconnect_timeout = 3\n",
+        )
+        .unwrap();
+        assert_eq!(
+            aux.get_limits(),
+            Limits {
+                max_connections: Some(2),
+                max_packet_size: 1024,
+                connect_timeout: Duration::from_secs(3),
+            }
+        );
+        assert!(aux
+            .set_all_params("[limits]\nconnect_timeout = 0\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test08_logs_con_rotacion_y_niveles_por_modulo() {
+        let mut aux = Configuration::new();
+        aux.set_all_params(
+            "[log]\nfile = \"logs/a.log\"\nlevel = \"INFO\"\nmodules = [\"server::network=debug\"]\nrotation = \"hourly\"\nmax_files = 24\nformat = \"json\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            aux.get_log_settings(),
+            LogSettings {
+                file: "logs/a.log".to_owned(),
+                level: "info".to_owned(),
+                modules: vec![("server::network".to_owned(), "debug".to_owned())],
+                rotation: LogRotation::Hourly,
+                max_files: Some(24),
+                format: LogFormat::Json,
+            }
+        );
+        assert_eq!(
+            aux.set_all_params("[log]\nrotation = \"weekly\"\n")
+                .unwrap_err()
+                .0,
+            2
+        );
+        assert_eq!(
+            aux.set_all_params("[log]\n\nmodules = [\"server::network\"]\n")
+                .unwrap_err()
+                .0,
+            3
+        );
+    }
+
+    #[test]
+    fn test09_intervalo_de_los_topicos_sys() {
+        let mut aux = Configuration::new();
+        assert_eq!(aux.get_sys_interval(), Some(Duration::from_secs(10)));
+        aux.set_all_params("sys_interval = 0\n").unwrap();
+        assert_eq!(aux.get_sys_interval(), None);
+        assert!(aux.set_all_params("sys_interval = -1\n").is_err());
+    }
+
+    #[test]
+    fn test10_api_de_administracion_requiere_token() {
+        let mut aux = Configuration::new();
+        assert_eq!(aux.get_admin(), None);
+        assert_eq!(
+            aux.set_all_params("\n[admin]\naddress = \"127.0.0.1:7667\"\n")
+                .unwrap_err()
+                .0,
+            2
+        );
+        assert_eq!(
+            aux.set_all_params("[admin]\ntoken = \"\"\n").unwrap_err().0,
+            2
+        );
+        aux.set_all_params("[admin]\naddress = \"127.0.0.1:7667\"\ntoken = \"abc\"\n")
+            .unwrap();
+        assert_eq!(
+            aux.get_admin(),
+            Some(("127.0.0.1:7667".to_owned(), "abc".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test11_comando_de_autenticacion_con_argumentos() {
+        let mut aux = Configuration::new();
+        assert_eq!(aux.get_auth_command(), None);
+        aux.set_all_params("[auth]\ncommand = \"/usr/bin/check user\"\n")
+            .unwrap();
+        assert_eq!(
+            aux.get_auth_command(),
+            Some(vec!["/usr/bin/check user".to_owned()])
+        );
+        aux.set_all_params("[auth]\ncommand = [\"check-user\", \"--realm\", \"x\"]\n")
+            .unwrap();
+        assert_eq!(
+            aux.get_auth_command(),
+            Some(vec![
+                "check-user".to_owned(),
+                "--realm".to_owned(),
+                "x".to_owned()
+            ])
+        );
+        assert!(aux.set_all_params("[auth]\ncommand = []\n").is_err());
+        assert!(aux.set_all_params("[auth]\ncommand = [\"\"]\n").is_err());
+        assert!(aux.set_all_params("[auth]\ncommand = 3\n").is_err());
+    }
 }
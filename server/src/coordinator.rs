@@ -1,166 +1,823 @@
+use crate::auth::{Action, AuthProvider};
 use crate::client::{Client, Subscription};
-use crate::packet::{bytes2string, Packet, SUCCESSFUL_CONNECTION};
-use crate::server::PacketThings;
-use crate::utils::remaining_length_encode;
+use crate::hooks::{Hooks, PublishAction};
+use crate::metrics::Metrics;
+use crate::network::CLOSE_CONNECTION;
+use crate::packet::{
+    connack_reason_code_v5, publish_acknowledgement, REASON_ADMINISTRATIVE_ACTION,
+    REASON_DISCONNECT_WITH_WILL, REASON_NOT_AUTHORIZED, REASON_SESSION_TAKEN_OVER,
+    SUCCESSFUL_CONNECTION,
+};
+use crate::retained_store::RetainedStore;
+use crate::session_store::SessionStore;
+use crate::sys_topics::BrokerStatus;
+use crate::topic_tree::SubscriptionTree;
 use crate::wildcard::compare_topic;
-use rand::Rng;
+use codec::properties::{
+    Property, PropertyValue, ASSIGNED_CLIENT_IDENTIFIER, SHARED_SUBSCRIPTION_AVAILABLE,
+    SUBSCRIPTION_IDENTIFIER, SUBSCRIPTION_IDENTIFIER_AVAILABLE, TOPIC_ALIAS,
+};
+use codec::{
+    ConnAck, ControlPacket, Disconnect, LastWill, PubAck, PubComp, PubRec, PubRel, Publish, SubAck,
+    Subscribe, UnsubAck, Unsubscribe, MQTT_VERSION_5,
+};
 use std::collections::HashMap;
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
-use tracing::{debug, error, info, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span, warn, Span};
+
+const REASON_SUCCESS: u8 = 0x00;
+const REASON_NO_SUBSCRIPTION_EXISTED: u8 = 0x11;
+const REASON_UNSPECIFIED_ERROR: u8 = 0x80;
+const REASON_IMPLEMENTATION_SPECIFIC_ERROR: u8 = 0x83;
+/// How often the sessions changed by queued and acknowledged messages are saved.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A published message, as the broker routes it to subscribers or retains it.
+///
+/// `properties` are the MQTT 5 properties forwarded to MQTT 5 subscribers.
+#[derive(Clone)]
+pub struct Message {
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub properties: Vec<Property>,
+}
+
+/// A connection accepted by a Client Listener, as the coordinator registers it.
+///
+/// `clean_start` is only set for MQTT 5 clients, which discard their previous session on
+/// connection. `assigned_client_id` tells whether the server generated the client id.
+pub struct NewConnection {
+    pub client_id: String,
+    pub username: Option<String>,
+    pub protocol_version: u8,
+    pub clean_session: bool,
+    pub clean_start: bool,
+    pub assigned_client_id: bool,
+    pub will: Option<LastWill>,
+}
+
+/// What a Client Listener asks the coordinator to do.
+pub enum CoordinatorCommand {
+    Connect(NewConnection),
+    Publish(Publish),
+    /// A publish of the broker itself, answering whether it was routed.
+    BrokerPublish(Publish, Sender<bool>),
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    PubAck(PubAck),
+    PubRec(PubRec),
+    PubRel(PubRel),
+    PubComp(PubComp),
+    /// The client sent a _Disconnect_ packet.
+    Disconnect(Disconnect),
+    /// The connection was closed or timed out without a _Disconnect_ packet.
+    ConnectionLost,
+    /// It is time to publish the status of the broker to the `$SYS` topics.
+    PublishSysTopics,
+    Admin(AdminCommand),
+    /// The broker is shutting down, so the coordinator must return.
+    Shutdown,
+}
+
+/// What the admin API asks the coordinator, with the channel where it answers.
+pub enum AdminCommand {
+    /// Close the connection of the client, keeping its session if it is persistent. Answers
+    /// whether the client was connected.
+    Kick {
+        client_id: String,
+        answer: Sender<bool>,
+    },
+    /// Discard the session of the client, closing its connection if it is connected. Answers
+    /// whether the client had a session.
+    Purge {
+        client_id: String,
+        answer: Sender<bool>,
+    },
+    /// Retained messages whose topic matches `filter`, sorted by topic.
+    ListRetained {
+        filter: String,
+        answer: Sender<Vec<(String, Message)>>,
+    },
+    /// Delete the retained message of `topic`. Answers whether there was one.
+    DeleteRetained { topic: String, answer: Sender<bool> },
+}
+
+/// A command from the connection with `thread_id`.
+pub struct ClientCommand {
+    pub thread_id: usize,
+    pub command: CoordinatorCommand,
+}
 
 /// Receives messages from the Client Listener and take decisions
 ///
 pub fn run_coordinator(
-    coordinator_receiver: Receiver<PacketThings>,
+    coordinator_receiver: Receiver<ClientCommand>,
     lock_clients: Arc<Mutex<HashMap<usize, Client>>>,
+    mut retained_messages: RetainedStore,
+    mut sessions: SessionStore,
+    auth: Arc<dyn AuthProvider>,
+    mut hooks: Hooks,
+    metrics: Arc<Metrics>,
 ) {
-    let mut retained_messages: HashMap<String, Vec<u8>> = HashMap::new();
+    let launched = Instant::now();
+    let mut subscriptions = index_subscriptions(&lock_clients);
+    metrics.set_retained_messages(retained_messages.count());
+    let _coordinator = info_span!("coordinator").entered();
     info!("Launched thread Coordinator.");
+    let mut sessions_saved = Instant::now();
     loop {
-        match coordinator_receiver.recv() {
-            Ok(mut packet) => match packet.packet_type {
-                Packet::Connect => {
-                    info!("Connection packet received.");
-                    process_client_id_and_info(&lock_clients, &mut packet, &retained_messages)
-                }
-                Packet::Subscribe => {
-                    info!("Subscribe packet received.");
-                    let vector_with_qos = process_subscribe(&lock_clients, &packet);
-                    send_subback(&lock_clients, &packet, vector_with_qos);
-                    send_retained_messages(&lock_clients, &packet, &mut retained_messages)
-                }
-                Packet::Unsubscribe => {
-                    info!("Unsubscribe packet received.");
-                    unsubscribe_process(&lock_clients, &packet);
-                    send_unsubback(&lock_clients, packet)
-                }
-                Packet::Publish => {
-                    debug!("Publish packet received.");
-                    let topic_name = process_publish(&mut packet);
-                    if topic_name.is_empty() {
-                        continue;
+        if sessions_saved.elapsed() >= SESSION_SAVE_INTERVAL {
+            save_changed_sessions(&lock_clients, &mut sessions);
+            sessions_saved = Instant::now();
+        }
+        match coordinator_receiver.recv_timeout(SESSION_SAVE_INTERVAL) {
+            Ok(ClientCommand { thread_id, command }) => {
+                let connecting = match &command {
+                    CoordinatorCommand::Connect(connection) => Some(connection.client_id.as_str()),
+                    _ => None,
+                };
+                let _client = client_span(&lock_clients, thread_id, connecting).entered();
+                match command {
+                    CoordinatorCommand::Connect(connection) => {
+                        info!("Connection packet received.");
+                        process_client_id_and_info(
+                            &lock_clients,
+                            thread_id,
+                            connection,
+                            &retained_messages,
+                            &mut subscriptions,
+                            &mut sessions,
+                        );
+                        notify_hooks(&lock_clients, thread_id, |client| hooks.on_connect(client));
                     }
-                    if is_to_retained(&packet) {
-                        let publish_packet =
-                            send_publish_to_customer(&lock_clients, &mut packet, &topic_name);
-                        retained_messages.insert(topic_name, publish_packet);
-                    } else {
-                        send_publish_to_customer(&lock_clients, &mut packet, &topic_name);
+                    CoordinatorCommand::Subscribe(subscribe) => {
+                        info!("Subscribe packet received.");
+                        let vector_with_qos = process_subscribe(
+                            &lock_clients,
+                            thread_id,
+                            &subscribe,
+                            &mut subscriptions,
+                            &mut sessions,
+                            auth.as_ref(),
+                        );
+                        notify_hooks(&lock_clients, thread_id, |client| {
+                            for (topic, qos) in subscribe.topics.iter().zip(vector_with_qos.iter())
+                            {
+                                if *qos <= 2 {
+                                    hooks.on_subscribe(client, &topic.topic_filter, *qos);
+                                }
+                            }
+                        });
+                        send_subback(&lock_clients, thread_id, &subscribe, vector_with_qos);
+                        send_retained_messages(
+                            &lock_clients,
+                            thread_id,
+                            &subscribe,
+                            &retained_messages,
+                            &mut sessions,
+                        )
+                    }
+                    CoordinatorCommand::Unsubscribe(unsubscribe) => {
+                        info!("Unsubscribe packet received.");
+                        let reason_codes = unsubscribe_process(
+                            &lock_clients,
+                            thread_id,
+                            &unsubscribe,
+                            &mut subscriptions,
+                            &mut sessions,
+                        );
+                        notify_hooks(&lock_clients, thread_id, |client| {
+                            for (topic_filter, reason_code) in
+                                unsubscribe.topic_filters.iter().zip(reason_codes.iter())
+                            {
+                                if *reason_code == REASON_SUCCESS {
+                                    hooks.on_unsubscribe(client, topic_filter);
+                                }
+                            }
+                        });
+                        send_unsubback(&lock_clients, thread_id, &unsubscribe, reason_codes)
+                    }
+                    CoordinatorCommand::Publish(mut publish) => {
+                        debug!("Publish packet received.");
+                        let started = Instant::now();
+                        let (qos, packet_identifier) = (publish.qos, publish.packet_identifier);
+                        let decision = decide_publish(
+                            &lock_clients,
+                            thread_id,
+                            &mut publish,
+                            auth.as_ref(),
+                            &mut hooks,
+                            &mut sessions,
+                        );
+                        let reason_code = match decision {
+                            PublishDecision::Refuse(reason_code) => reason_code,
+                            _ => REASON_SUCCESS,
+                        };
+                        acknowledge_publish(
+                            &lock_clients,
+                            thread_id,
+                            qos,
+                            packet_identifier,
+                            reason_code,
+                            &mut sessions,
+                        );
+                        if decision == PublishDecision::Route {
+                            let message = send_publish_to_customer(
+                                &lock_clients,
+                                &publish,
+                                &subscriptions,
+                                &mut sessions,
+                            );
+                            metrics.routed(started.elapsed());
+                            if publish.retain {
+                                retained_messages.retain(publish.topic_name, message);
+                                metrics.set_retained_messages(retained_messages.count());
+                            }
+                        }
+                    }
+                    CoordinatorCommand::BrokerPublish(mut publish, answer) => {
+                        let started = Instant::now();
+                        let decision = decide_publish(
+                            &lock_clients,
+                            thread_id,
+                            &mut publish,
+                            auth.as_ref(),
+                            &mut hooks,
+                            &mut sessions,
+                        );
+                        let routed = decision == PublishDecision::Route;
+                        if routed {
+                            let message = send_publish_to_customer(
+                                &lock_clients,
+                                &publish,
+                                &subscriptions,
+                                &mut sessions,
+                            );
+                            metrics.routed(started.elapsed());
+                            if publish.retain {
+                                retained_messages.retain(publish.topic_name, message);
+                                metrics.set_retained_messages(retained_messages.count());
+                            }
+                        }
+                        if answer.send(routed).is_err() {
+                            debug!("The broker publisher stopped waiting.");
+                        }
+                    }
+                    CoordinatorCommand::PubAck(puback) => {
+                        remove_publishes(
+                            &lock_clients,
+                            thread_id,
+                            puback.packet_identifier,
+                            &mut sessions,
+                        );
+                    }
+                    CoordinatorCommand::PubRec(pubrec) => {
+                        process_pubrec(&lock_clients, thread_id, &pubrec, &mut sessions);
+                    }
+                    CoordinatorCommand::PubRel(pubrel) => {
+                        process_pubrel(
+                            &lock_clients,
+                            thread_id,
+                            pubrel.packet_identifier,
+                            &mut sessions,
+                        );
+                    }
+                    CoordinatorCommand::PubComp(pubcomp) => {
+                        process_pubcomp(
+                            &lock_clients,
+                            thread_id,
+                            pubcomp.packet_identifier,
+                            &mut sessions,
+                        );
+                    }
+                    CoordinatorCommand::Disconnect(disconnect) => {
+                        debug!("Disconnect packet received.");
+                        notify_hooks(&lock_clients, thread_id, |client| {
+                            hooks.on_disconnect(client)
+                        });
+                        if disconnect.reason_code == REASON_DISCONNECT_WITH_WILL {
+                            if let Some((topic_name, message)) = send_lastwill(
+                                &lock_clients,
+                                thread_id,
+                                &subscriptions,
+                                &mut sessions,
+                                auth.as_ref(),
+                            ) {
+                                retained_messages.retain(topic_name, message);
+                                metrics.set_retained_messages(retained_messages.count());
+                            }
+                        }
+                        close_process(&lock_clients, thread_id, &mut subscriptions, &mut sessions);
+                        forget_client(&lock_clients, thread_id);
+                    }
+                    CoordinatorCommand::ConnectionLost => {
+                        debug!("Disgraceful disconnect packet received.");
+                        notify_hooks(&lock_clients, thread_id, |client| {
+                            hooks.on_disconnect(client)
+                        });
+                        close_disgraceful(
+                            &lock_clients,
+                            thread_id,
+                            &mut subscriptions,
+                            &mut sessions,
+                        );
+                        if let Some((topic_name, message)) = send_lastwill(
+                            &lock_clients,
+                            thread_id,
+                            &subscriptions,
+                            &mut sessions,
+                            auth.as_ref(),
+                        ) {
+                            retained_messages.retain(topic_name, message);
+                            metrics.set_retained_messages(retained_messages.count());
+                        }
+                        forget_client(&lock_clients, thread_id);
+                    }
+                    CoordinatorCommand::PublishSysTopics => {
+                        let status =
+                            broker_status(&lock_clients, launched, &retained_messages, &metrics);
+                        for (topic_name, payload) in status.messages() {
+                            let publish = Publish {
+                                dup: false,
+                                qos: 0,
+                                retain: false,
+                                topic_name,
+                                packet_identifier: None,
+                                properties: Vec::new(),
+                                payload: payload.into_bytes(),
+                            };
+                            send_publish_to_customer(
+                                &lock_clients,
+                                &publish,
+                                &subscriptions,
+                                &mut sessions,
+                            );
+                        }
+                    }
+                    CoordinatorCommand::Admin(command) => {
+                        info!("Admin command received.");
+                        process_admin_command(
+                            command,
+                            &lock_clients,
+                            &mut subscriptions,
+                            &mut sessions,
+                            &mut retained_messages,
+                        );
+                        metrics.set_retained_messages(retained_messages.count());
+                    }
+                    CoordinatorCommand::Shutdown => {
+                        save_sessions(&lock_clients, &mut sessions);
+                        info!("Coordinator stopped.");
+                        return;
                     }
                 }
-                Packet::PubAck => {
-                    remove_publishes(&lock_clients, &mut packet);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                save_changed_sessions(&lock_clients, &mut sessions);
+                info!("Every client listener is gone, coordinator stopped.");
+                return;
+            }
+        }
+    }
+}
+
+fn process_admin_command(
+    command: AdminCommand,
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
+    retained_messages: &mut RetainedStore,
+) {
+    let answered = match command {
+        AdminCommand::Kick { client_id, answer } => {
+            let kicked = match find_session(lock_clients, &client_id) {
+                Some((thread_id, true)) => {
+                    kick_client(lock_clients, thread_id, subscriptions, sessions);
+                    true
                 }
-                Packet::Disconnect => {
-                    debug!("Disconnect packet received.");
-                    close_process(&lock_clients, &packet);
+                _ => false,
+            };
+            answer.send(kicked).is_ok()
+        }
+        AdminCommand::Purge { client_id, answer } => {
+            let purged = match find_session(lock_clients, &client_id) {
+                Some((thread_id, connected)) => {
+                    purge_session(lock_clients, thread_id, connected, subscriptions, sessions);
+                    true
                 }
-                Packet::Disgrace => {
-                    debug!("Disgraceful disconnect packet received.");
-                    close_disgraceful(&lock_clients, &packet);
-                    let (publish_packet, topic_name, publish_message) =
-                        send_lastwill(&lock_clients, &packet);
-                    if !publish_packet.is_empty() {
-                        retained_messages.insert(topic_name, publish_message.as_bytes().to_vec());
+                None => false,
+            };
+            answer.send(purged).is_ok()
+        }
+        AdminCommand::ListRetained { filter, answer } => {
+            let mut retained: Vec<(String, Message)> = retained_messages
+                .iter()
+                .filter(|(topic, _)| compare_topic(topic, &filter))
+                .map(|(topic, message)| (topic.clone(), message.clone()))
+                .collect();
+            retained.sort_by(|a, b| a.0.cmp(&b.0));
+            answer.send(retained).is_ok()
+        }
+        AdminCommand::DeleteRetained { topic, answer } => {
+            answer.send(retained_messages.remove(&topic)).is_ok()
+        }
+    };
+    if !answered {
+        debug!("The admin API stopped waiting for the answer.");
+    }
+}
+
+/// Thread id of the client with `client_id` and whether it is connected, if it is connected or
+/// its session is persistent.
+fn find_session(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    client_id: &str,
+) -> Option<(usize, bool)> {
+    match lock_clients.lock() {
+        Ok(locked) => locked
+            .values()
+            .find(|client| {
+                client.client_id == client_id && (!client.disconnected || client.clean_session == 0)
+            })
+            .map(|client| (client.thread_id, !client.disconnected)),
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.");
+            None
+        }
+    }
+}
+
+/// Close the connection of the client as an administrative action, which MQTT 5 clients are
+/// told with a _Disconnect_. The last will is published when the connection is closed.
+fn kick_client(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
+) {
+    match lock_clients.lock() {
+        Ok(locked) => {
+            if let Some(client) = locked.get(&thread_id) {
+                if client.protocol_version == MQTT_VERSION_5 {
+                    let disconnect = Disconnect::new(REASON_ADMINISTRATIVE_ACTION);
+                    if client
+                        .send_packet(&ControlPacket::Disconnect(disconnect))
+                        .is_err()
+                    {
+                        debug!("Error sending Disconnect to the client.");
                     }
                 }
-                _ => {
-                    debug!("Unknown packet received.")
+            }
+        }
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.");
+            return;
+        }
+    }
+    close_disgraceful(lock_clients, thread_id, subscriptions, sessions);
+}
+
+/// Discard the subscriptions, queued messages and saved session of the client, closing its
+/// connection if it is `connected`.
+fn purge_session(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    connected: bool,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
+) {
+    match lock_clients.lock() {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => {
+                client.clean_session = 1;
+                if !connected {
+                    client.remove_subscriptions_and_queue();
+                    sessions.save(client);
+                    locked.remove(&thread_id);
+                    subscriptions.remove_session(thread_id);
                 }
-            },
-            Err(_e) => {}
+            }
+            None => return,
+        },
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.");
+            return;
         }
     }
+    if connected {
+        kick_client(lock_clients, thread_id, subscriptions, sessions);
+    }
 }
 
+/// Status of the broker for the `$SYS` topics, counting the sessions of clients that connected.
+fn broker_status(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    launched: Instant,
+    retained_messages: &RetainedStore,
+    metrics: &Metrics,
+) -> BrokerStatus {
+    let mut status = BrokerStatus {
+        uptime: launched.elapsed(),
+        connected_clients: 0,
+        subscriptions: 0,
+        active_subscriptions: 0,
+        retained_messages: retained_messages.count(),
+        totals: metrics.totals(),
+    };
+    match lock_clients.lock() {
+        Ok(locked) => {
+            for client in locked.values() {
+                if client.client_id.is_empty() {
+                    continue;
+                }
+                status.subscriptions += client.topics.len();
+                if !client.disconnected {
+                    status.connected_clients += 1;
+                    status.active_subscriptions += client.topics.len();
+                }
+            }
+        }
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.");
+        }
+    }
+    status
+}
+
+/// Span of the packets of the client of `thread_id`, with its client id, or the one it is
+/// `connecting` with, and its peer address.
+fn client_span(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    connecting: Option<&str>,
+) -> Span {
+    let (mut client_id, peer) = match lock_clients.lock() {
+        Ok(locked) => match locked.get(&thread_id) {
+            Some(client) => (client.client_id.clone(), client.peer_address.clone()),
+            None => (String::new(), None),
+        },
+        Err(_) => (String::new(), None),
+    };
+    if let Some(connecting) = connecting {
+        client_id = connecting.to_owned();
+    }
+    info_span!(
+        "client",
+        id = thread_id,
+        client_id = %client_id,
+        peer = peer.as_deref().unwrap_or("local")
+    )
+}
+
+/// Save the session of every client, so what is pending for them survives a restart.
+fn save_sessions(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, sessions: &mut SessionStore) {
+    match lock_clients.lock() {
+        Ok(locked) => {
+            for client in locked.values() {
+                sessions.save(client);
+            }
+        }
+        Err(_) => {
+            error!("Error saving the persistent sessions");
+        }
+    }
+}
+
+/// Save the sessions marked since the last batch, of the clients that are still in the map.
+fn save_changed_sessions(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    sessions: &mut SessionStore,
+) {
+    let changed = sessions.take_changed();
+    if changed.is_empty() {
+        return;
+    }
+    match lock_clients.lock() {
+        Ok(locked) => {
+            for thread_id in changed {
+                if let Some(client) = locked.get(&thread_id) {
+                    sessions.save(client);
+                }
+            }
+        }
+        Err(_) => {
+            error!("Error saving the persistent sessions");
+        }
+    }
+}
+
+/// Call `notify` with the client of `thread_id`, if it is connected.
+fn notify_hooks<F: FnOnce(&Client)>(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    notify: F,
+) {
+    match lock_clients.lock() {
+        Ok(locked) => {
+            if let Some(client) = locked.get(&thread_id) {
+                if !client.disconnected {
+                    notify(client);
+                }
+            }
+        }
+        Err(_) => {
+            warn!("Unable to get the clients lock.")
+        }
+    }
+}
+
+/// What the coordinator does with a publish.
+#[derive(Debug, PartialEq)]
+enum PublishDecision {
+    /// Send it to the subscribers.
+    Route,
+    /// A QoS 2 message already received, which is discarded.
+    Duplicated,
+    /// The topic is empty, the client may not publish to it or a hook dropped the message, with
+    /// the reason code to acknowledge it with.
+    Refuse(u8),
+}
+
+/// Whether to route the publish of the client with `thread_id` to the subscribers: not if it is a
+/// duplicated QoS 2 message, the client may not publish to its topic or a hook drops it. The
+/// hooks may rewrite `publish`.
+fn decide_publish(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    publish: &mut Publish,
+    auth: &dyn AuthProvider,
+    hooks: &mut Hooks,
+    sessions: &mut SessionStore,
+) -> PublishDecision {
+    if publish.topic_name.is_empty() {
+        return PublishDecision::Refuse(REASON_UNSPECIFIED_ERROR);
+    }
+    if is_duplicated_qos2(lock_clients, thread_id, publish, sessions) {
+        return PublishDecision::Duplicated;
+    }
+    if !is_publish_allowed(lock_clients, thread_id, &publish.topic_name, auth) {
+        warn!("Publish to {} denied.", publish.topic_name);
+        return PublishDecision::Refuse(REASON_NOT_AUTHORIZED);
+    }
+    if intercept_publish(lock_clients, thread_id, publish, hooks) == PublishAction::Drop {
+        return PublishDecision::Refuse(REASON_IMPLEMENTATION_SPECIFIC_ERROR);
+    }
+    PublishDecision::Route
+}
+
+/// Send the _Puback_ or _Pubrec_ of a publish with `qos` 1 or 2 once the coordinator decided on
+/// it. A refused QoS 2 publish ends there for MQTT 5 clients, which don't send a _Pubrel_ after a
+/// failure reason code, so its identifier is released.
+fn acknowledge_publish(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    qos: u8,
+    packet_identifier: Option<u16>,
+    reason_code: u8,
+    sessions: &mut SessionStore,
+) {
+    let acknowledgement = match publish_acknowledgement(qos, packet_identifier, reason_code) {
+        Some(acknowledgement) => acknowledgement,
+        None => return,
+    };
+    match lock_clients.lock() {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => {
+                if client.send_packet(&acknowledgement).is_err() {
+                    debug!("Error sending the acknowledgement of a publish.");
+                }
+                if qos == 2
+                    && reason_code >= REASON_UNSPECIFIED_ERROR
+                    && client.protocol_version == MQTT_VERSION_5
+                {
+                    client
+                        .qos2_received
+                        .retain(|id| Some(*id) != packet_identifier);
+                    sessions.save(client);
+                }
+            }
+            None => {
+                debug!("Client not found on hashmap")
+            }
+        },
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.")
+        }
+    }
+}
+
+/// Pass the publish through the hooks, which may rewrite or drop it. A message rewritten to an
+/// invalid topic or QoS is dropped.
+fn intercept_publish(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    publish: &mut Publish,
+    hooks: &mut Hooks,
+) -> PublishAction {
+    let action = match lock_clients.lock() {
+        Ok(locked) => match locked.get(&thread_id) {
+            Some(client) => hooks.on_publish(client, publish),
+            None => PublishAction::Drop,
+        },
+        Err(_) => {
+            warn!("Unable to get the clients lock.");
+            PublishAction::Drop
+        }
+    };
+    if action == PublishAction::Drop {
+        debug!("Publish to {} dropped by a hook.", publish.topic_name);
+        return PublishAction::Drop;
+    }
+    if publish.topic_name.is_empty() || publish.topic_name.contains(['+', '#']) || publish.qos > 2 {
+        warn!("A hook rewrote a publish with an invalid topic or QoS, dropping it.");
+        return PublishAction::Drop;
+    }
+    PublishAction::Deliver
+}
+
+/// Index the subscriptions of the clients already registered when the coordinator starts.
+fn index_subscriptions(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>) -> SubscriptionTree {
+    let mut subscriptions = SubscriptionTree::new();
+    match lock_clients.lock() {
+        Ok(locked) => {
+            for (thread_id, client) in locked.iter() {
+                for subscription in client.topics.iter() {
+                    subscriptions.subscribe(&subscription.topic, *thread_id, subscription.qos);
+                }
+            }
+        }
+        Err(_) => {
+            warn!("Unable to get the clients lock.")
+        }
+    }
+    subscriptions
+}
+
+/// Publish the client last will to its subscribers.
 ///
-///
+/// Returns the topic and message to retain if the last will has to be retained.
 fn send_lastwill(
     lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
-    packet: &PacketThings,
-) -> (Vec<u8>, String, String) {
-    let mut buffer_packet: Vec<u8> = Vec::new();
-    let mut topic_name = "".to_owned();
-    let mut topic_message = "".to_owned();
+    thread_id: usize,
+    subscriptions: &SubscriptionTree,
+    sessions: &mut SessionStore,
+    auth: &dyn AuthProvider,
+) -> Option<(String, Message)> {
     match lock_clients.lock() {
         Ok(mut locked) => {
-            let mut lastwill_exists = false;
-            let mut lastwill_qos = 0;
-            match locked.get_mut(&packet.thread_id) {
-                Some(client) => {
-                    if let Some(topic) = &client.lastwill_topic {
-                        if let Some(message) = &client.lastwill_message {
-                            lastwill_qos = client.lastwill_qos;
-                            buffer_packet.push(Packet::Publish.into());
-                            if lastwill_qos == 1 {
-                                buffer_packet[0] |= 0x02;
-                            }
-                            topic_name = topic.clone();
-                            topic_message = message.clone();
-                            let mut topic_name_bytes = topic.as_bytes().to_vec();
-                            let mut topic_message_bytes = message.as_bytes().to_vec();
-                            buffer_packet.push(
-                                (4 + topic_name_bytes.len() + topic_message_bytes.len()) as u8,
-                            );
-                            buffer_packet.push(0);
-                            buffer_packet.push(topic_name_bytes.len() as u8);
-                            buffer_packet.append(&mut topic_name_bytes);
-                            let mut rng = rand::thread_rng();
-                            let packet_id: u16 = rng.gen();
-                            let packet_id_left: u8 = (packet_id >> 8) as u8;
-                            let packet_id_right: u8 = packet_id as u8;
-                            buffer_packet.push(packet_id_left);
-                            buffer_packet.push(packet_id_right);
-                            buffer_packet.append(&mut topic_message_bytes);
-                            lastwill_exists = true;
-                        }
+            let (topic_name, message, retain) = match locked.get(&thread_id) {
+                Some(client) => match &client.lastwill {
+                    Some(will) if !auth.authorize(client, Action::Publish, &will.topic) => {
+                        warn!("Last will to {} denied.", will.topic);
+                        return None;
                     }
-                }
+                    Some(will) => (
+                        will.topic.clone(),
+                        Message {
+                            payload: will.payload.clone(),
+                            qos: will.qos,
+                            properties: Vec::new(),
+                        },
+                        will.retain,
+                    ),
+                    None => return None,
+                },
                 None => {
-                    warn!("Error searching client for send lastwill")
-                }
-            }
-            if lastwill_exists {
-                for (_, client_it) in locked.iter_mut() {
-                    if client_it.is_subscribed_to(&topic_name) {
-                        let mut buffer_to_send: Vec<u8> = Vec::new();
-                        buffer_to_send.extend(&buffer_packet);
-                        let mut buffer_clone = buffer_to_send.clone();
-                        buffer_clone[0] |= 0x08;
-                        if (!client_it.disconnected || client_it.clean_session == 0)
-                            && lastwill_qos == 1
-                            && client_it.is_subscribed_to_qos1(&topic_name)
-                        {
-                            client_it.publishes_received.push(buffer_clone);
-                        }
-                        if !client_it.disconnected {
-                            match client_it.channel.send(buffer_to_send) {
-                                Ok(_) => {
-                                    debug!("Publish sent to customer.");
-                                }
-                                Err(_) => {
-                                    debug!("Error sending publish to customer.")
-                                }
-                            }
-                        }
-                    }
+                    debug!("Client already forgotten, there is no last will to send.");
+                    return None;
                 }
+            };
+            for (subscriber_id, qos) in subscriptions.matches(&topic_name) {
+                if let Some(subscriber) = locked.get_mut(&subscriber_id) {
+                    send_publish_to_client(subscriber, &topic_name, &message, qos, sessions);
+                }
+            }
+            if retain {
+                return Some((topic_name, message));
             }
         }
         Err(_) => {
             warn!("Unable to get the clients lock.")
         }
     }
-    (buffer_packet, topic_name, topic_message)
+    None
 }
 /// Set client as disconnect and remove subscripciones if need it.
 ///
-fn close_process(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: &PacketThings) {
+/// The last will is discarded, as the client disconnected normally.
+fn close_process(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
+) {
     match lock_clients.lock() {
-        Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
             Some(client) => {
-                let close: Vec<u8> = vec![255_u8];
+                let close: Vec<u8> = vec![CLOSE_CONNECTION];
                 match client.channel.send(close) {
                     Ok(_) => {}
                     Err(_) => {
@@ -169,8 +826,11 @@ fn close_process(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: &Pac
                 }
                 if client.clean_session == 1 {
                     client.remove_subscriptions_and_queue();
+                    subscriptions.remove_session(thread_id);
                 }
+                client.lastwill = None;
                 client.disconnected = true;
+                sessions.save(client);
             }
             None => {
                 debug!("Error looking client to erase.")
@@ -183,12 +843,17 @@ fn close_process(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: &Pac
 }
 /// Set client as disconnect and remove subscripciones if need it.
 ///
-fn close_disgraceful(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: &PacketThings) {
+fn close_disgraceful(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
+) {
     match lock_clients.lock() {
-        Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
             Some(client) => {
                 if !client.disconnected {
-                    let close: Vec<u8> = vec![255_u8];
+                    let close: Vec<u8> = vec![CLOSE_CONNECTION];
                     match client.channel.send(close) {
                         Ok(_) => {}
                         Err(_) => {
@@ -197,8 +862,10 @@ fn close_disgraceful(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet:
                     }
                     if client.clean_session == 1 {
                         client.remove_subscriptions_and_queue();
+                        subscriptions.remove_session(thread_id);
                     }
                     client.disconnected = true;
+                    sessions.save(client);
                 }
             }
             None => {
@@ -211,19 +878,45 @@ fn close_disgraceful(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet:
     }
 }
 
-fn remove_publishes(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: &mut PacketThings) {
-    let puback_packet_identifier = ((packet.bytes[0] as u16) << 8) + packet.bytes[1] as u16;
+/// Remove the disconnected client if there is no session to keep, because it never sent its
+/// _Connect_ or its session was clean. Its subscriptions were removed when it disconnected.
+fn forget_client(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, thread_id: usize) {
     match lock_clients.lock() {
-        Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
+        Ok(mut locked) => {
+            let forget = match locked.get(&thread_id) {
+                Some(client) => {
+                    client.disconnected
+                        && (client.clean_session == 1 || client.client_id.is_empty())
+                }
+                None => false,
+            };
+            if forget {
+                locked.remove(&thread_id);
+            }
+        }
+        Err(_) => {
+            warn!("Unable to get the clients lock.")
+        }
+    }
+}
+
+fn remove_publishes(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    packet_identifier: u16,
+    sessions: &mut SessionStore,
+) {
+    match lock_clients.lock() {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
             Some(client) => {
-                if let Some(indice2) = client.publishes_received.iter().position(|r| {
-                    let topic_name_len: usize = ((r[2] as usize) << 8) + r[3] as usize;
-                    let publish_packet_identifier =
-                        ((r[topic_name_len + 4] as u16) << 8) + r[topic_name_len + 5] as u16;
-                    publish_packet_identifier == puback_packet_identifier
-                }) {
+                if let Some(indice2) = client
+                    .publishes_received
+                    .iter()
+                    .position(|r| r.packet_identifier == Some(packet_identifier))
+                {
                     info!("Publish removed from vector");
                     client.publishes_received.remove(indice2);
+                    sessions.mark_changed(client);
                 }
             }
             None => {
@@ -236,83 +929,226 @@ fn remove_publishes(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: &
     }
 }
 
-fn is_to_retained(packet: &PacketThings) -> bool {
-    (packet.bytes[0] & 0x01) == 1
+/// The subscriber received a QoS 2 publish: stop resending it and release it with a _Pubrel_,
+/// unless the _Pubrec_ has a failure reason code, which ends the delivery.
+fn process_pubrec(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    pubrec: &PubRec,
+    sessions: &mut SessionStore,
+) {
+    let packet_identifier = pubrec.packet_identifier;
+    match lock_clients.lock() {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => {
+                client
+                    .publishes_received
+                    .retain(|r| r.packet_identifier != Some(packet_identifier));
+                if pubrec.reason_code >= REASON_UNSPECIFIED_ERROR {
+                    info!("QoS 2 publish refused by the client.");
+                    sessions.mark_changed(client);
+                    return;
+                }
+                if !client.pubrels_pending.contains(&packet_identifier) {
+                    client.pubrels_pending.push(packet_identifier);
+                }
+                sessions.mark_changed(client);
+                match client.send_packet(&ControlPacket::PubRel(PubRel::new(packet_identifier))) {
+                    Ok(_) => {
+                        debug!("Pubrel sent to client.")
+                    }
+                    Err(_) => {
+                        debug!("Error sending Pubrel to client.")
+                    }
+                }
+            }
+            None => {
+                debug!("Client not found on hashmap")
+            }
+        },
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.")
+        }
+    }
+}
+
+/// The publisher released a QoS 2 publish, so its packet identifier can be reused.
+fn process_pubrel(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    packet_identifier: u16,
+    sessions: &mut SessionStore,
+) {
+    match lock_clients.lock() {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => {
+                client.qos2_received.retain(|id| *id != packet_identifier);
+                sessions.save(client);
+            }
+            None => {
+                debug!("Client not found on hashmap")
+            }
+        },
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.")
+        }
+    }
+}
+
+/// The subscriber completed a QoS 2 delivery.
+fn process_pubcomp(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    packet_identifier: u16,
+    sessions: &mut SessionStore,
+) {
+    match lock_clients.lock() {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => {
+                client.pubrels_pending.retain(|id| *id != packet_identifier);
+                sessions.mark_changed(client);
+                info!("QoS 2 delivery completed");
+            }
+            None => {
+                debug!("Client not found on hashmap")
+            }
+        },
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.")
+        }
+    }
 }
 
+/// Whether the publish is a QoS 2 message already received from the client and not yet released.
 ///
+/// The first time a QoS 2 packet identifier is seen it is stored until the client sends its _Pubrel_.
+fn is_duplicated_qos2(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    publish: &Publish,
+    sessions: &mut SessionStore,
+) -> bool {
+    let packet_identifier = match (publish.qos, publish.packet_identifier) {
+        (2, Some(packet_identifier)) => packet_identifier,
+        _ => return false,
+    };
+    match lock_clients.lock() {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => {
+                if client.qos2_received.contains(&packet_identifier) {
+                    info!("Duplicated QoS 2 publish discarded.");
+                    return true;
+                }
+                client.qos2_received.push(packet_identifier);
+                sessions.save(client);
+            }
+            None => {
+                debug!("Client not found on hashmap")
+            }
+        },
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.")
+        }
+    }
+    false
+}
+
+/// Register the new connection, taking over the session of a previous client with the same id.
 ///
 fn process_client_id_and_info(
     lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
-    packet: &mut PacketThings,
-    retained_msg: &HashMap<String, Vec<u8>>,
+    thread_id: usize,
+    connection: NewConnection,
+    retained_msg: &RetainedStore,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
 ) {
     match lock_clients.lock() {
         Ok(mut locked) => {
             let mut already_exists = false;
-            let size = packet.bytes[0] as usize;
-            let new_client_id = bytes2string(&packet.bytes[1..(size + 1) as usize]);
-            let clean_session = packet.bytes[(size + 1) as usize];
-            let mut will_topic = None;
-            let mut will_message = None;
-            let mut will_qos = 0;
-            let mut will_retained = false;
-            if packet.bytes[(size + 2) as usize] == 1 {
-                let mut index = (size + 3) as usize;
-                let topic_size = packet.bytes[index] as usize;
-                will_topic = Some(bytes2string(
-                    &packet.bytes[(index + 1) as usize..(index + 1 + topic_size) as usize],
-                ));
-                index = (index + 1 + topic_size) as usize;
-                let message_size = packet.bytes[(index) as usize] as usize;
-                will_message = Some(bytes2string(
-                    &packet.bytes[(index + 1) as usize..(index + 1 + message_size) as usize],
-                ));
-                will_qos = packet.bytes[(index + 1 + message_size) as usize];
-                if packet.bytes[(index + 2 + message_size) as usize] == 1 {
-                    will_retained = true;
-                }
-            }
-            let mut subscriptions: Vec<Subscription> = Vec::new();
-            let mut publishes_received: Vec<Vec<u8>> = Vec::new();
+            let new_client_id = connection.client_id;
+            let protocol_version = connection.protocol_version;
+            let clean_start = connection.clean_start;
+            let mut previous_subscriptions: Vec<Subscription> = Vec::new();
+            let mut publishes_received: Vec<Publish> = Vec::new();
+            let mut pubrels_pending: Vec<u16> = Vec::new();
+            let mut qos2_received: Vec<u16> = Vec::new();
+            let mut last_packet_id: u16 = 0;
             let mut old_thread_id = 0;
             for client in locked.iter_mut() {
-                if client.1.client_id == new_client_id {
+                if client.1.client_id == new_client_id && client.1.thread_id != thread_id {
                     already_exists = true;
-                    subscriptions.append(&mut client.1.topics);
+                    if !client.1.disconnected {
+                        take_over_session(client.1);
+                    }
+                    if clean_start {
+                        old_thread_id = client.1.thread_id;
+                        continue;
+                    }
+                    previous_subscriptions.append(&mut client.1.topics);
                     publishes_received.append(&mut client.1.publishes_received);
+                    pubrels_pending.append(&mut client.1.pubrels_pending);
+                    qos2_received.append(&mut client.1.qos2_received);
+                    last_packet_id = client.1.last_packet_id;
                     old_thread_id = client.1.thread_id;
                 }
             }
             if already_exists {
                 locked.remove(&old_thread_id);
+                subscriptions.remove_session(old_thread_id);
             }
 
-            match locked.get_mut(&packet.thread_id) {
+            match locked.get_mut(&thread_id) {
                 Some(client) => {
                     client.client_id = new_client_id;
-                    if already_exists {
+                    client.protocol_version = protocol_version;
+                    let session_present = already_exists && !clean_start;
+                    if session_present {
                         client.publishes_received.append(&mut publishes_received);
-                        client.topics.append(&mut subscriptions);
+                        client.pubrels_pending.append(&mut pubrels_pending);
+                        client.qos2_received.append(&mut qos2_received);
+                        client.last_packet_id = last_packet_id;
+                        for subscription in previous_subscriptions.iter() {
+                            subscriptions.subscribe(
+                                &subscription.topic,
+                                thread_id,
+                                subscription.qos,
+                            );
+                        }
+                        client.topics.append(&mut previous_subscriptions);
+                        let mut retained_to_send: Vec<Publish> = Vec::new();
                         for topic in client.topics.iter() {
                             for (topic_retained, message_retained) in retained_msg.iter() {
                                 if compare_topic(topic_retained, &(topic.topic)) {
-                                    client.publishes_received.push(message_retained.clone());
+                                    let qos = message_retained.qos.min(topic.qos);
+                                    retained_to_send.push(build_publish(
+                                        topic_retained,
+                                        message_retained,
+                                        qos,
+                                        None,
+                                        true,
+                                    ));
                                 }
                             }
                         }
+                        for mut publish in retained_to_send {
+                            if publish.qos > 0 {
+                                publish.packet_identifier = Some(client.next_packet_id());
+                            }
+                            client.publishes_received.push(publish);
+                        }
                     }
-                    client.lastwill_topic = will_topic;
-                    client.lastwill_message = will_message;
-                    client.lastwill_qos = will_qos;
-                    client.lastwill_retained = will_retained;
+                    client.lastwill = connection.will;
+                    client.username = connection.username;
                     client.disconnected = false;
-                    client.clean_session = clean_session;
-                    if already_exists {
-                        send_connection_result(client, SUCCESSFUL_CONNECTION, 1);
-                    } else {
-                        send_connection_result(client, SUCCESSFUL_CONNECTION, 0);
-                    }
+                    client.clean_session = connection.clean_session as u8;
+                    send_connection_result(
+                        client,
+                        SUCCESSFUL_CONNECTION,
+                        session_present as u8,
+                        connection.assigned_client_id,
+                    );
+                    sessions.save(client);
                 }
                 None => {
                     debug!("Client not found on hashmap")
@@ -325,10 +1161,57 @@ fn process_client_id_and_info(
     }
 }
 
-fn send_connection_result(client: &mut Client, result_code: u8, session: u8) {
-    let buffer = vec![Packet::ConnAck.into(), 0x02, session, result_code];
+/// Disconnect the client because a new connection with the same client id took over its session.
+fn take_over_session(client: &mut Client) {
+    if client.protocol_version == MQTT_VERSION_5 {
+        let disconnect = Disconnect::new(REASON_SESSION_TAKEN_OVER);
+        match client.send_packet(&ControlPacket::Disconnect(disconnect)) {
+            Ok(_) => {
+                info!("Disconnect sent to the client whose session was taken over.")
+            }
+            Err(_) => {
+                debug!("Error sending Disconnect to the client.")
+            }
+        }
+    }
+    match client.channel.send(vec![CLOSE_CONNECTION]) {
+        Ok(_) => {}
+        Err(_) => {
+            warn!("Error sending secret packet.")
+        }
+    }
+    client.disconnected = true;
+}
 
-    match client.channel.send(buffer) {
+/// Send the _Connack_ packet to the client.
+///
+/// MQTT 5 clients also receive the server capabilities and, if it was assigned, their client id.
+fn send_connection_result(
+    client: &mut Client,
+    result_code: u8,
+    session: u8,
+    assigned_client_id: bool,
+) {
+    let mut connack = ConnAck {
+        session_present: session == 1,
+        return_code: result_code,
+        properties: Vec::new(),
+    };
+    if client.protocol_version == MQTT_VERSION_5 {
+        connack.return_code = connack_reason_code_v5(result_code);
+        connack.properties = vec![
+            Property::new(SUBSCRIPTION_IDENTIFIER_AVAILABLE, PropertyValue::Byte(0)),
+            Property::new(SHARED_SUBSCRIPTION_AVAILABLE, PropertyValue::Byte(0)),
+        ];
+        if assigned_client_id {
+            connack.properties.push(Property::new(
+                ASSIGNED_CLIENT_IDENTIFIER,
+                PropertyValue::Utf8String(client.client_id.clone()),
+            ));
+        }
+    }
+
+    match client.send_packet(&ControlPacket::ConnAck(connack)) {
         Ok(_) => {
             info!("Sent the connack sucessfull to the client sender");
         }
@@ -338,45 +1221,49 @@ fn send_connection_result(client: &mut Client, result_code: u8, session: u8) {
     };
 }
 
-/// Sends the publish content to Client Communicator
+/// Whether the client with `thread_id` may publish to `topic_name`.
+fn is_publish_allowed(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    topic_name: &str,
+    auth: &dyn AuthProvider,
+) -> bool {
+    match lock_clients.lock() {
+        Ok(locked) => match locked.get(&thread_id) {
+            Some(client) => auth.authorize(client, Action::Publish, topic_name),
+            None => false,
+        },
+        Err(_) => {
+            warn!("Unable to access lock from coordinador.");
+            false
+        }
+    }
+}
+
+/// Sends the publish content to the client connection
 ///
+/// Returns the message so it can be retained.
 fn send_publish_to_customer(
     lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
-    packet: &mut PacketThings,
-    topic_name: &str,
-) -> Vec<u8> {
-    let publish_qos = packet.bytes[0] & 0x02;
-    packet.bytes.remove(0);
-    let byte_publish: u8 = Packet::Publish.into();
-    let byte_0: u8 = byte_publish | publish_qos;
-    let mut buffer_packet: Vec<u8> = vec![byte_0];
-    let mut remaining_length = remaining_length_encode(packet.bytes.len());
-    buffer_packet.append(&mut remaining_length);
-    buffer_packet.extend(&packet.bytes);
+    publish: &Publish,
+    subscriptions: &SubscriptionTree,
+    sessions: &mut SessionStore,
+) -> Message {
+    let message = Message {
+        payload: publish.payload.clone(),
+        qos: publish.qos,
+        properties: publish
+            .properties
+            .iter()
+            .filter(|property| property.id != TOPIC_ALIAS && property.id != SUBSCRIPTION_IDENTIFIER)
+            .cloned()
+            .collect(),
+    };
     match lock_clients.lock() {
         Ok(mut locked) => {
-            for client in locked.iter_mut() {
-                if client.1.is_subscribed_to(topic_name) {
-                    let mut buffer_to_send: Vec<u8> = Vec::new();
-                    buffer_to_send.extend(&buffer_packet);
-                    let mut buffer_clone = buffer_to_send.clone();
-                    buffer_clone[0] |= 0x08;
-                    if (!client.1.disconnected || client.1.clean_session == 0)
-                        && publish_qos == 2
-                        && client.1.is_subscribed_to_qos1(topic_name)
-                    {
-                        client.1.publishes_received.push(buffer_clone);
-                    }
-                    if !client.1.disconnected {
-                        match client.1.channel.send(buffer_to_send) {
-                            Ok(_) => {
-                                info!("Publish sent to cliente");
-                            }
-                            Err(_) => {
-                                debug!("Error sending Publish to the client")
-                            }
-                        }
-                    }
+            for (thread_id, qos) in subscriptions.matches(&publish.topic_name) {
+                if let Some(client) = locked.get_mut(&thread_id) {
+                    send_publish_to_client(client, &publish.topic_name, &message, qos, sessions);
                 }
             }
         }
@@ -384,25 +1271,81 @@ fn send_publish_to_customer(
             warn!("Unable to access lock from coordinador.")
         }
     }
-    buffer_packet
+    message
 }
 
-fn process_publish(packet: &mut PacketThings) -> String {
-    let topic_name_len: usize = ((packet.bytes[1] as usize) << 8) + packet.bytes[2] as usize;
-    bytes2string(&packet.bytes[3..(3 + topic_name_len)])
+/// Sends a publish to a client subscribed to `topic_name` with `subscription_qos`, with the minimum
+/// QoS between the message and the subscription.
+///
+/// QoS 1 and 2 messages are also queued until the client acknowledges them, if the session is kept,
+/// and the session is saved with the next batch.
+fn send_publish_to_client(
+    client: &mut Client,
+    topic_name: &str,
+    message: &Message,
+    subscription_qos: u8,
+    sessions: &mut SessionStore,
+) {
+    let qos = subscription_qos.min(message.qos);
+    let mut packet_identifier = None;
+    if qos > 0 {
+        packet_identifier = Some(client.next_packet_id());
+    }
+    let publish = build_publish(topic_name, message, qos, packet_identifier, false);
+    if !client.disconnected {
+        match client.send_packet(&ControlPacket::Publish(publish.clone())) {
+            Ok(_) => {
+                info!("Publish sent to cliente");
+            }
+            Err(_) => {
+                debug!("Error sending Publish to the client")
+            }
+        }
+    }
+    if (!client.disconnected || client.clean_session == 0) && qos > 0 {
+        client.publishes_received.push(Publish {
+            dup: true,
+            ..publish
+        });
+        sessions.mark_changed(client);
+    }
 }
 
+/// Build the _Publish_ of `message` on `topic_name`. The packet identifier is only used if `qos`
+/// is greater than 0.
+fn build_publish(
+    topic_name: &str,
+    message: &Message,
+    qos: u8,
+    packet_identifier: Option<u16>,
+    retain: bool,
+) -> Publish {
+    Publish {
+        dup: false,
+        qos,
+        retain,
+        topic_name: topic_name.to_owned(),
+        packet_identifier,
+        properties: message.properties.clone(),
+        payload: message.payload.clone(),
+    }
+}
 
-fn send_unsubback(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: PacketThings) {
-    let buffer: Vec<u8> = vec![
-        Packet::UnsubAck.into(),
-        0x02,
-        packet.bytes[0],
-        packet.bytes[1],
-    ];
+/// Send the _Unsuback_ packet to the client. MQTT 5 clients also receive a reason code per topic.
+fn send_unsubback(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    unsubscribe: &Unsubscribe,
+    reason_codes: Vec<u8>,
+) {
+    let unsuback = UnsubAck {
+        packet_identifier: unsubscribe.packet_identifier,
+        properties: Vec::new(),
+        reason_codes,
+    };
     match lock_clients.lock() {
-        Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
-            Some(client) => match client.channel.send(buffer) {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => match client.send_packet(&ControlPacket::UnsubAck(unsuback)) {
                 Ok(_) => {
                     info!("SubBack sent.");
                 }
@@ -420,18 +1363,28 @@ fn send_unsubback(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: Pac
     }
 }
 
-fn unsubscribe_process(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet: &PacketThings) {
-    let mut index = 2;
-    while index < packet.bytes.len() {
-        let topic_size: usize =
-            ((packet.bytes[index] as usize) << 8) + packet.bytes[index + 1] as usize;
-        index += 2;
-        let topic = bytes2string(&packet.bytes[index..(index + topic_size)]);
-        index += topic_size;
+/// Unsubscribe the client from the topics of the packet.
+///
+/// Returns the MQTT 5 reason code for each topic.
+fn unsubscribe_process(
+    lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
+    thread_id: usize,
+    unsubscribe: &Unsubscribe,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
+) -> Vec<u8> {
+    let mut reason_codes: Vec<u8> = Vec::new();
+    for topic in unsubscribe.topic_filters.iter() {
         match lock_clients.lock() {
-            Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
+            Ok(mut locked) => match locked.get_mut(&thread_id) {
                 Some(client) => {
-                    client.unsubscribe(topic);
+                    subscriptions.unsubscribe(topic, thread_id);
+                    if client.unsubscribe(topic.clone()) {
+                        reason_codes.push(REASON_SUCCESS);
+                    } else {
+                        reason_codes.push(REASON_NO_SUBSCRIPTION_EXISTED);
+                    }
+                    sessions.save(client);
                     info!("Cliente unsubscribed from a topic.")
                 }
                 None => {
@@ -439,28 +1392,28 @@ fn unsubscribe_process(lock_clients: &Arc<Mutex<HashMap<usize, Client>>>, packet
                 }
             },
             Err(_) => {
-                warn!("Error trying to unsubscribe from a topic.")
+                warn!("Error trying to unsubscribe from a topic.");
+                reason_codes.push(REASON_UNSPECIFIED_ERROR);
             }
         }
     }
+    reason_codes
 }
 
 fn send_subback(
     lock_clientes: &Arc<Mutex<HashMap<usize, Client>>>,
-    packet: &PacketThings,
+    thread_id: usize,
+    subscribe: &Subscribe,
     vector_with_qos: Vec<u8>,
 ) {
-    let mut buffer: Vec<u8> = vec![Packet::SubAck.into()];
-    let mut remaining_length = remaining_length_encode(vector_with_qos.len() + 2);
-    buffer.append(&mut remaining_length);
-    buffer.push(packet.bytes[0]);
-    buffer.push(packet.bytes[1]);
-    for bytes in vector_with_qos {
-        buffer.push(bytes);
-    }
+    let suback = SubAck {
+        packet_identifier: subscribe.packet_identifier,
+        properties: Vec::new(),
+        return_codes: vector_with_qos,
+    };
     match lock_clientes.lock() {
-        Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
-            Some(client) => match client.channel.send(buffer) {
+        Ok(mut locked) => match locked.get_mut(&thread_id) {
+            Some(client) => match client.send_packet(&ControlPacket::SubAck(suback)) {
                 Ok(_) => {
                     info!("SubBack sent")
                 }
@@ -480,23 +1433,30 @@ fn send_subback(
 
 fn process_subscribe(
     lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
-    packet: &PacketThings,
+    thread_id: usize,
+    subscribe: &Subscribe,
+    subscriptions: &mut SubscriptionTree,
+    sessions: &mut SessionStore,
+    auth: &dyn AuthProvider,
 ) -> Vec<u8> {
-    let mut index = 2;
     let mut vector_with_qos: Vec<u8> = Vec::new();
-    while index < (packet.bytes.len() - 2) {
-        let topic_size: usize =
-            ((packet.bytes[index] as usize) << 8) + packet.bytes[index + 1] as usize;
-        index += 2;
-        let topic = bytes2string(&packet.bytes[index..(index + topic_size)]);
-        index += topic_size;
-        let qos: u8 = &packet.bytes[index] & 0x01;
-        index += 1;
+    for topic in subscribe.topics.iter() {
+        if topic.qos > 2 {
+            warn!("Invalid QoS level requested on subscription.");
+            vector_with_qos.push(0x80);
+            continue;
+        }
         match lock_clients.lock() {
-            Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
+            Ok(mut locked) => match locked.get_mut(&thread_id) {
+                Some(client) if !auth.authorize(client, Action::Subscribe, &topic.topic_filter) => {
+                    warn!("Subscription to {} denied.", topic.topic_filter);
+                    vector_with_qos.push(0x80);
+                }
                 Some(client) => {
-                    client.subscribe(topic.clone(), qos);
-                    vector_with_qos.push(qos);
+                    client.subscribe(topic.topic_filter.clone(), topic.qos);
+                    subscriptions.subscribe(&topic.topic_filter, thread_id, topic.qos);
+                    sessions.save(client);
+                    vector_with_qos.push(topic.qos);
                     info!("Client subscribed to a topic")
                 }
                 None => {
@@ -511,24 +1471,40 @@ fn process_subscribe(
 
 fn send_retained_messages(
     lock_clients: &Arc<Mutex<HashMap<usize, Client>>>,
-    packet: &PacketThings,
-    retained_messages: &mut HashMap<String, Vec<u8>>,
+    thread_id: usize,
+    subscribe: &Subscribe,
+    retained_messages: &RetainedStore,
+    sessions: &mut SessionStore,
 ) {
-    let mut index = 2;
-    while index < (packet.bytes.len() - 2) {
-        let topic_size: usize =
-            ((packet.bytes[index] as usize) << 8) + packet.bytes[index + 1] as usize;
-        index += 2;
-        let topic = bytes2string(&packet.bytes[index..(index + topic_size)]);
-        index += topic_size + 1;
+    for topic in subscribe.topics.iter() {
         match lock_clients.lock() {
-            Ok(mut locked) => match locked.get_mut(&packet.thread_id) {
+            Ok(mut locked) => match locked.get_mut(&thread_id) {
                 Some(client) => {
+                    if !client
+                        .topics
+                        .iter()
+                        .any(|subscription| subscription.topic == topic.topic_filter)
+                    {
+                        continue;
+                    }
                     for (topic_retained, message_retained) in retained_messages.iter() {
-                        if compare_topic(topic_retained, &topic) {
-                            let mut buffer_to_send: Vec<u8> = Vec::new();
-                            buffer_to_send.extend(message_retained);
-                            match client.channel.send(buffer_to_send) {
+                        if compare_topic(topic_retained, &topic.topic_filter) {
+                            let qos = client
+                                .subscribed_qos(topic_retained)
+                                .unwrap_or(0)
+                                .min(message_retained.qos);
+                            let mut packet_identifier = None;
+                            if qos > 0 {
+                                packet_identifier = Some(client.next_packet_id());
+                            }
+                            let publish = build_publish(
+                                topic_retained,
+                                message_retained,
+                                qos,
+                                packet_identifier,
+                                true,
+                            );
+                            match client.send_packet(&ControlPacket::Publish(publish.clone())) {
                                 Ok(_) => {
                                     info!("Publish retained sent to client")
                                 }
@@ -536,6 +1512,13 @@ fn send_retained_messages(
                                     error!("Error sendint retained Publish to client")
                                 }
                             }
+                            if qos > 0 {
+                                client.publishes_received.push(Publish {
+                                    dup: true,
+                                    ..publish
+                                });
+                                sessions.mark_changed(client);
+                            }
                         }
                     }
                 }
@@ -553,21 +1536,49 @@ fn send_retained_messages(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::FileAuthProvider;
+    use crate::hooks::MessageHook;
+    use crate::network::test_channel;
+    use codec::{decode_packet, SubscribeTopic, MQTT_VERSION};
     use std::sync::mpsc;
     use std::sync::mpsc::Sender;
     use std::thread;
     use std::time;
 
+    fn subscribe(packet_identifier: u16, topic_filter: &str, qos: u8) -> CoordinatorCommand {
+        CoordinatorCommand::Subscribe(Subscribe {
+            packet_identifier,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: topic_filter.to_owned(),
+                qos,
+            }],
+        })
+    }
+
+    fn publish(qos: u8, packet_identifier: Option<u16>, topic_name: &str) -> CoordinatorCommand {
+        CoordinatorCommand::Publish(Publish {
+            dup: false,
+            qos,
+            retain: false,
+            topic_name: topic_name.to_owned(),
+            packet_identifier,
+            properties: Vec::new(),
+            payload: b"miau".to_vec(),
+        })
+    }
+
     #[test]
     fn test01_se_realiza_suscripcion_se_publica_el_coordinador_envia_ese_paquete() {
         //Arrange
         let clients: HashMap<usize, Client> = HashMap::new();
         let lock_clients = Arc::new(Mutex::new(clients));
         let handler_clients_locks = lock_clients.clone();
-        let (clients_sender, coordinator_receiver): (Sender<PacketThings>, Receiver<PacketThings>) =
-            mpsc::channel();
-        let (coordinator_sender, client_receiver): (Sender<Vec<u8>>, Receiver<Vec<u8>>) =
-            mpsc::channel();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let (coordinator_sender, client_receiver) = test_channel(1);
         let mutex_clients_sender = Arc::new(Mutex::new(clients_sender));
         let client_sender = Arc::clone(&mutex_clients_sender);
         let mut client: Client = Client::new(1, coordinator_sender);
@@ -578,54 +1589,41 @@ mod tests {
             .insert(client.thread_id, client);
         thread::Builder::new()
             .name("Coordinator".into())
-            .spawn(move || run_coordinator(coordinator_receiver, lock_clients))
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    lock_clients,
+                    RetainedStore::new(),
+                    SessionStore::new(),
+                    Arc::new(FileAuthProvider::default()),
+                    Hooks::new(),
+                    Arc::new(Metrics::new()),
+                )
+            })
             .unwrap();
         //Act Subscribe
-        let mut buffer_packet: Vec<u8> = Vec::new();
-        let topic_subscribed = "as".to_owned();
-        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
-        buffer_packet.push(0);
-        buffer_packet.push(54);
-        buffer_packet.push(0);
-        buffer_packet.push(topic_subscribed_bytes.len() as u8);
-        buffer_packet.append(&mut topic_subscribed_bytes);
-        buffer_packet.push(1);
-        let packet_to_server = PacketThings {
-            thread_id: 1,
-            packet_type: Packet::Subscribe,
-            bytes: buffer_packet,
-        };
         client_sender
             .lock()
             .unwrap()
-            .send(packet_to_server)
+            .send(ClientCommand {
+                thread_id: 1,
+                command: subscribe(54, "as", 1),
+            })
             .unwrap();
-        let read_back = client_receiver.recv().unwrap();
+        let read_back = client_receiver.recv().unwrap().1;
         //Assert suback
         assert_eq!(read_back[0], 0x90);
         assert_eq!(read_back[3], 54);
         //Act publish
-        buffer_packet = Vec::new();
-        let topic_publish = "as".to_owned();
-        let mut topic_publish_bytes: Vec<u8> = topic_publish.as_bytes().to_vec();
-        let content_publish = "miau".to_owned();
-        let mut content_publish_bytes: Vec<u8> = content_publish.as_bytes().to_vec();
-        buffer_packet.push(0x31);
-        buffer_packet.push(0);
-        buffer_packet.push(topic_publish_bytes.len() as u8);
-        buffer_packet.append(&mut topic_publish_bytes);
-        buffer_packet.append(&mut content_publish_bytes);
-        let packet_to_server = PacketThings {
-            thread_id: 1,
-            packet_type: Packet::Publish,
-            bytes: buffer_packet,
-        };
         client_sender
             .lock()
             .unwrap()
-            .send(packet_to_server)
+            .send(ClientCommand {
+                thread_id: 1,
+                command: publish(0, None, "as"),
+            })
             .unwrap();
-        let read_back = client_receiver.recv().unwrap();
+        let read_back = client_receiver.recv().unwrap().1;
         //Assert publish to client
         assert_eq!(read_back[0], 0x30);
         assert_eq!(read_back[4], 97);
@@ -635,69 +1633,91 @@ mod tests {
     #[test]
     fn test02_se_le_envia_el_mismo_client_id_al_coordinador_y_elimina_el_cliente_auxiliar() {
         //Arrange
-        let (channel_1, _c_1): (Sender<Vec<u8>>, Receiver<Vec<u8>>) = mpsc::channel();
-        let (channel_2, _c_2): (Sender<Vec<u8>>, Receiver<Vec<u8>>) = mpsc::channel();
+        let (channel_1, _c_1) = test_channel(1);
+        let (channel_2, _c_2) = test_channel(2);
         let mut client_1 = Client {
             thread_id: 1,
             client_id: "Homero".to_owned(),
+            username: None,
             channel: channel_1,
             topics: Vec::new(),
             publishes_received: Vec::new(),
+            pubrels_pending: Vec::new(),
+            qos2_received: Vec::new(),
+            last_packet_id: 0,
             clean_session: 1,
-            lastwill_topic: None,
-            lastwill_message: None,
-            lastwill_qos: 0,
-            lastwill_retained: false,
+            protocol_version: MQTT_VERSION,
+            lastwill: None,
             disconnected: false,
+            peer_address: None,
         };
         client_1.subscribe("as/tillero".to_owned(), 1);
         client_1.subscribe("ma/derero".to_owned(), 1);
         let client_2 = Client {
             thread_id: 2,
             client_id: "".to_owned(),
+            username: None,
             channel: channel_2,
             topics: Vec::new(),
             publishes_received: Vec::new(),
+            pubrels_pending: Vec::new(),
+            qos2_received: Vec::new(),
+            last_packet_id: 0,
             clean_session: 1,
-            lastwill_topic: None,
-            lastwill_message: None,
-            lastwill_qos: 0,
-            lastwill_retained: false,
+            protocol_version: MQTT_VERSION,
+            lastwill: None,
             disconnected: false,
+            peer_address: None,
         };
         let mut clients: HashMap<usize, Client> = HashMap::new();
         clients.insert(client_1.thread_id, client_1);
         clients.insert(client_2.thread_id, client_2);
         let lock_clients = Arc::new(Mutex::new(clients));
         let handler_clients_locks = lock_clients.clone();
-        let (clients_sender, coordinator_receiver): (Sender<PacketThings>, Receiver<PacketThings>) =
-            mpsc::channel();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
         thread::Builder::new()
             .name("Coordinator".into())
-            .spawn(move || run_coordinator(coordinator_receiver, lock_clients))
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    lock_clients,
+                    RetainedStore::new(),
+                    SessionStore::new(),
+                    Arc::new(FileAuthProvider::default()),
+                    Hooks::new(),
+                    Arc::new(Metrics::new()),
+                )
+            })
             .unwrap();
-        let mut bytes: Vec<u8> = Vec::new();
-        let mut name = "Homero".to_owned().as_bytes().to_vec();
-        bytes.push(name.len() as u8);
-        bytes.append(&mut name);
-        bytes.push(1); //clean_session
-        bytes.push(0); //not lastwill
-        let mut buffer_packet: Vec<u8> = Vec::new();
-        buffer_packet.append(&mut bytes);
-        let packet_to_server = PacketThings {
-            thread_id: 2,
-            packet_type: Packet::Connect,
-            bytes: buffer_packet,
+        let connection = NewConnection {
+            client_id: "Homero".to_owned(),
+            username: None,
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            clean_start: false,
+            assigned_client_id: false,
+            will: None,
         };
         //Act connect
-        clients_sender.send(packet_to_server).unwrap();
+        clients_sender
+            .send(ClientCommand {
+                thread_id: 2,
+                command: CoordinatorCommand::Connect(connection),
+            })
+            .unwrap();
         //Assert
         thread::sleep(time::Duration::from_millis(20));
         let hashmap = handler_clients_locks.lock().unwrap();
         assert_eq!(hashmap.len(), 1);
         assert_eq!(hashmap.get(&2).unwrap().thread_id, 2);
-        assert!(hashmap.get(&2).unwrap().is_subscribed_to("as/tillero"));
-        assert!(!hashmap.get(&2).unwrap().is_subscribed_to("am/tillero"));
+        assert_eq!(
+            hashmap.get(&2).unwrap().subscribed_qos("as/tillero"),
+            Some(1)
+        );
+        assert_eq!(hashmap.get(&2).unwrap().subscribed_qos("am/tillero"), None);
     }
 
     #[test]
@@ -706,10 +1726,11 @@ mod tests {
         let clients: HashMap<usize, Client> = HashMap::new();
         let lock_clients = Arc::new(Mutex::new(clients));
         let handler_clients_locks = lock_clients.clone();
-        let (clients_sender, coordinator_receiver): (Sender<PacketThings>, Receiver<PacketThings>) =
-            mpsc::channel();
-        let (coordinator_sender, client_receiver): (Sender<Vec<u8>>, Receiver<Vec<u8>>) =
-            mpsc::channel();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let (coordinator_sender, client_receiver) = test_channel(1);
         let mutex_clients_sender = Arc::new(Mutex::new(clients_sender));
         let client_sender = Arc::clone(&mutex_clients_sender);
         let client: Client = Client::new(1, coordinator_sender);
@@ -719,57 +1740,441 @@ mod tests {
             .insert(client.thread_id, client);
         thread::Builder::new()
             .name("Coordinator".into())
-            .spawn(move || run_coordinator(coordinator_receiver, lock_clients))
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    lock_clients,
+                    RetainedStore::new(),
+                    SessionStore::new(),
+                    Arc::new(FileAuthProvider::default()),
+                    Hooks::new(),
+                    Arc::new(Metrics::new()),
+                )
+            })
             .unwrap();
         //Act Subscribe
-        let mut buffer_packet: Vec<u8> = Vec::new();
-        let topic_subscribed = "pepitoelpistolero".to_owned();
-        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
-        buffer_packet.push(0);
-        buffer_packet.push(30); //PacketID
-        buffer_packet.push(0);
-        buffer_packet.push(topic_subscribed_bytes.len() as u8);
-        buffer_packet.append(&mut topic_subscribed_bytes);
-        buffer_packet.push(1);
-        let packet_to_server = PacketThings {
-            thread_id: 1,
-            packet_type: Packet::Subscribe,
-            bytes: buffer_packet,
-        };
         client_sender
             .lock()
             .unwrap()
-            .send(packet_to_server)
+            .send(ClientCommand {
+                thread_id: 1,
+                command: subscribe(30, "pepitoelpistolero", 1),
+            })
             .unwrap();
-        let read_back = client_receiver.recv().unwrap();
+        let read_back = client_receiver.recv().unwrap().1;
         //Assert suback
         assert_eq!(read_back[0], 0x90);
         assert_eq!(read_back[3], 30); //PacketID
                                       //Act unsuscribe
-        buffer_packet = Vec::new();
-        let topic_unsuscribe = "pepitoelpistolero".to_owned();
-        let mut topic_unsuscribe_bytes: Vec<u8> = topic_unsuscribe.as_bytes().to_vec();
-        buffer_packet.push(0);
-        buffer_packet.push(32); //PacketID
-        buffer_packet.push(0);
-        buffer_packet.push(topic_unsuscribe_bytes.len() as u8);
-        buffer_packet.append(&mut topic_unsuscribe_bytes);
-        let packet_to_server = PacketThings {
-            thread_id: 1,
-            packet_type: Packet::Unsubscribe,
-            bytes: buffer_packet,
+        let unsubscribe = Unsubscribe {
+            packet_identifier: 32,
+            properties: Vec::new(),
+            topic_filters: vec!["pepitoelpistolero".to_owned()],
         };
         client_sender
             .lock()
             .unwrap()
-            .send(packet_to_server)
+            .send(ClientCommand {
+                thread_id: 1,
+                command: CoordinatorCommand::Unsubscribe(unsubscribe),
+            })
             .unwrap();
-        let read_back = client_receiver.recv().unwrap();
-        let _packet_received_id = (read_back[0] >> 4) as u8;
+        let read_back = client_receiver.recv().unwrap().1;
         //Assert unsuscribe
         assert_eq!(read_back[0], 0xB0);
         assert_eq!(read_back[1], 2);
         assert_eq!(read_back[2], 0);
         assert_eq!(read_back[3], 32);
     }
+
+    #[test]
+    fn test04_publish_qos2_duplicado_se_envia_una_sola_vez_y_se_libera_con_pubrel() {
+        //Arrange
+        let clients: HashMap<usize, Client> = HashMap::new();
+        let lock_clients = Arc::new(Mutex::new(clients));
+        let handler_clients_locks = lock_clients.clone();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let (coordinator_sender, client_receiver) = test_channel(1);
+        let mut client: Client = Client::new(1, coordinator_sender);
+        client.disconnected = false;
+        client.subscribe("as".to_owned(), 2);
+        handler_clients_locks
+            .lock()
+            .unwrap()
+            .insert(client.thread_id, client);
+        thread::Builder::new()
+            .name("Coordinator".into())
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    lock_clients,
+                    RetainedStore::new(),
+                    SessionStore::new(),
+                    Arc::new(FileAuthProvider::default()),
+                    Hooks::new(),
+                    Arc::new(Metrics::new()),
+                )
+            })
+            .unwrap();
+        //Act publish twice with the same packet identifier
+        for _ in 0..2 {
+            clients_sender
+                .send(ClientCommand {
+                    thread_id: 1,
+                    command: publish(2, Some(9), "as"),
+                })
+                .unwrap();
+        }
+        //Assert both are acknowledged but only one QoS 2 publish goes to the subscriber
+        let pubrec = PubRec::new(9).encode(MQTT_VERSION).unwrap();
+        assert_eq!(client_receiver.recv().unwrap().1, pubrec);
+        let read_back = client_receiver.recv().unwrap().1;
+        assert_eq!(read_back[0], 0x34);
+        let packet_identifier = match decode_packet(&read_back, MQTT_VERSION) {
+            Ok(ControlPacket::Publish(publish)) => publish.packet_identifier.unwrap(),
+            _ => panic!("Expected a publish packet"),
+        };
+        assert_eq!(client_receiver.recv().unwrap().1, pubrec);
+        thread::sleep(time::Duration::from_millis(20));
+        assert!(client_receiver.try_recv().is_err());
+        assert_eq!(
+            handler_clients_locks
+                .lock()
+                .unwrap()
+                .get(&1)
+                .unwrap()
+                .qos2_received,
+            vec![9]
+        );
+        //Act pubrec from subscriber
+        clients_sender
+            .send(ClientCommand {
+                thread_id: 1,
+                command: CoordinatorCommand::PubRec(PubRec::new(packet_identifier)),
+            })
+            .unwrap();
+        //Assert pubrel
+        let read_back = client_receiver.recv().unwrap().1;
+        assert_eq!(
+            read_back,
+            PubRel::new(packet_identifier).encode(MQTT_VERSION).unwrap()
+        );
+        //Act pubrel from publisher and pubcomp from subscriber
+        clients_sender
+            .send(ClientCommand {
+                thread_id: 1,
+                command: CoordinatorCommand::PubRel(PubRel::new(9)),
+            })
+            .unwrap();
+        clients_sender
+            .send(ClientCommand {
+                thread_id: 1,
+                command: CoordinatorCommand::PubComp(PubComp::new(packet_identifier)),
+            })
+            .unwrap();
+        thread::sleep(time::Duration::from_millis(20));
+        //Assert state released
+        let hashmap = handler_clients_locks.lock().unwrap();
+        let client = hashmap.get(&1).unwrap();
+        assert!(client.qos2_received.is_empty());
+        assert!(client.pubrels_pending.is_empty());
+        assert!(client.publishes_received.is_empty());
+    }
+
+    #[test]
+    fn test05_lastwill_con_topic_largo_y_payload_binario_llega_completo() {
+        //Arrange
+        let clients: HashMap<usize, Client> = HashMap::new();
+        let lock_clients = Arc::new(Mutex::new(clients));
+        let handler_clients_locks = lock_clients.clone();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let (channel_1, _c_1) = test_channel(1);
+        let (channel_2, client_receiver) = test_channel(2);
+        let topic = "sensores/".repeat(40);
+        let payload: Vec<u8> = vec![0, 159, 146, 150, 255];
+        let mut subscriber = Client::new(2, channel_2);
+        subscriber.disconnected = false;
+        subscriber.subscribe(topic.clone(), 0);
+        {
+            let mut locked = handler_clients_locks.lock().unwrap();
+            locked.insert(1, Client::new(1, channel_1));
+            locked.insert(2, subscriber);
+        }
+        thread::Builder::new()
+            .name("Coordinator".into())
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    lock_clients,
+                    RetainedStore::new(),
+                    SessionStore::new(),
+                    Arc::new(FileAuthProvider::default()),
+                    Hooks::new(),
+                    Arc::new(Metrics::new()),
+                )
+            })
+            .unwrap();
+        let connection = NewConnection {
+            client_id: "sensor".to_owned(),
+            username: None,
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            clean_start: false,
+            assigned_client_id: false,
+            will: Some(LastWill {
+                topic: topic.clone(),
+                payload: payload.clone(),
+                qos: 0,
+                retain: false,
+                properties: Vec::new(),
+            }),
+        };
+        //Act connect and lose the connection
+        clients_sender
+            .send(ClientCommand {
+                thread_id: 1,
+                command: CoordinatorCommand::Connect(connection),
+            })
+            .unwrap();
+        clients_sender
+            .send(ClientCommand {
+                thread_id: 1,
+                command: CoordinatorCommand::ConnectionLost,
+            })
+            .unwrap();
+        //Assert
+        let read_back = client_receiver.recv().unwrap().1;
+        match decode_packet(&read_back, MQTT_VERSION) {
+            Ok(ControlPacket::Publish(publish)) => {
+                assert_eq!(publish.topic_name, topic);
+                assert_eq!(publish.payload, payload);
+            }
+            _ => panic!("Expected a publish packet"),
+        }
+    }
+
+    /// Hook that moves the messages of `crudo/` to `procesado/`, in upper case, drops the ones of
+    /// `descartar` and records the subscriptions.
+    struct ProcessHook {
+        subscriptions: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MessageHook for ProcessHook {
+        fn on_publish(&mut self, _client: &Client, publish: &mut Publish) -> PublishAction {
+            if publish.topic_name == "descartar" {
+                return PublishAction::Drop;
+            }
+            if let Some(rest) = publish.topic_name.strip_prefix("crudo/") {
+                publish.topic_name = format!("procesado/{}", rest);
+                publish.payload = publish.payload.to_ascii_uppercase();
+            }
+            PublishAction::Deliver
+        }
+
+        fn on_subscribe(&mut self, _client: &Client, topic_filter: &str, _qos: u8) {
+            self.subscriptions
+                .lock()
+                .unwrap()
+                .push(topic_filter.to_owned());
+        }
+    }
+
+    #[test]
+    fn test06_hook_reescribe_topic_y_payload_y_descarta_mensajes() {
+        //Arrange
+        let clients: HashMap<usize, Client> = HashMap::new();
+        let lock_clients = Arc::new(Mutex::new(clients));
+        let handler_clients_locks = lock_clients.clone();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let (coordinator_sender, client_receiver) = test_channel(1);
+        let mut client: Client = Client::new(1, coordinator_sender);
+        client.disconnected = false;
+        handler_clients_locks
+            .lock()
+            .unwrap()
+            .insert(client.thread_id, client);
+        let subscriptions = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = Hooks::new();
+        hooks.register(Box::new(ProcessHook {
+            subscriptions: subscriptions.clone(),
+        }));
+        thread::Builder::new()
+            .name("Coordinator".into())
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    lock_clients,
+                    RetainedStore::new(),
+                    SessionStore::new(),
+                    Arc::new(FileAuthProvider::default()),
+                    hooks,
+                    Arc::new(Metrics::new()),
+                )
+            })
+            .unwrap();
+        //Act subscribe and publish
+        for command in [
+            subscribe(7, "procesado/#", 0),
+            publish(0, None, "descartar"),
+            publish(0, None, "crudo/temperatura"),
+        ] {
+            clients_sender
+                .send(ClientCommand {
+                    thread_id: 1,
+                    command,
+                })
+                .unwrap();
+        }
+        //Assert
+        let suback = client_receiver.recv().unwrap().1;
+        assert_eq!(suback[0], 0x90);
+        let read_back = client_receiver.recv().unwrap().1;
+        match decode_packet(&read_back, MQTT_VERSION) {
+            Ok(ControlPacket::Publish(publish)) => {
+                assert_eq!(publish.topic_name, "procesado/temperatura");
+                assert_eq!(publish.payload, b"MIAU".to_vec());
+            }
+            _ => panic!("Expected a publish packet"),
+        }
+        assert_eq!(*subscriptions.lock().unwrap(), vec!["procesado/#"]);
+    }
+
+    #[test]
+    fn test07_publish_rechazado_y_pubrec_con_error_terminan_el_flujo_qos2() {
+        //Arrange
+        let clients: HashMap<usize, Client> = HashMap::new();
+        let lock_clients = Arc::new(Mutex::new(clients));
+        let handler_clients_locks = lock_clients.clone();
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let (coordinator_sender, client_receiver) = test_channel(1);
+        let mut client: Client = Client::new(1, coordinator_sender);
+        client.disconnected = false;
+        client.protocol_version = MQTT_VERSION_5;
+        client.subscribe("as".to_owned(), 2);
+        handler_clients_locks
+            .lock()
+            .unwrap()
+            .insert(client.thread_id, client);
+        let mut hooks = Hooks::new();
+        hooks.register(Box::new(ProcessHook {
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+        }));
+        thread::Builder::new()
+            .name("Coordinator".into())
+            .spawn(move || {
+                run_coordinator(
+                    coordinator_receiver,
+                    lock_clients,
+                    RetainedStore::new(),
+                    SessionStore::new(),
+                    Arc::new(FileAuthProvider::default()),
+                    hooks,
+                    Arc::new(Metrics::new()),
+                )
+            })
+            .unwrap();
+        //Act publish dropped by the hook, then one routed
+        for command in [publish(2, Some(9), "descartar"), publish(2, Some(10), "as")] {
+            clients_sender
+                .send(ClientCommand {
+                    thread_id: 1,
+                    command,
+                })
+                .unwrap();
+        }
+        //Assert
+        let refused = PubRec {
+            reason_code: REASON_IMPLEMENTATION_SPECIFIC_ERROR,
+            ..PubRec::new(9)
+        };
+        assert_eq!(
+            client_receiver.recv().unwrap().1,
+            refused.encode(MQTT_VERSION_5).unwrap()
+        );
+        assert_eq!(
+            client_receiver.recv().unwrap().1,
+            PubRec::new(10).encode(MQTT_VERSION_5).unwrap()
+        );
+        let packet_identifier =
+            match decode_packet(&client_receiver.recv().unwrap().1, MQTT_VERSION_5) {
+                Ok(ControlPacket::Publish(publish)) => publish.packet_identifier.unwrap(),
+                _ => panic!("Expected a publish packet"),
+            };
+        //Act pubrec with a failure reason code
+        clients_sender
+            .send(ClientCommand {
+                thread_id: 1,
+                command: CoordinatorCommand::PubRec(PubRec {
+                    reason_code: REASON_UNSPECIFIED_ERROR,
+                    ..PubRec::new(packet_identifier)
+                }),
+            })
+            .unwrap();
+        thread::sleep(time::Duration::from_millis(20));
+        //Assert no pubrel and the delivery is over
+        assert!(client_receiver.try_recv().is_err());
+        let hashmap = handler_clients_locks.lock().unwrap();
+        let client = hashmap.get(&1).unwrap();
+        assert_eq!(client.qos2_received, vec![10]);
+        assert!(client.pubrels_pending.is_empty());
+        assert!(client.publishes_received.is_empty());
+    }
+
+    #[test]
+    fn test08_retenidos_con_qos_quedan_encolados_hasta_su_confirmacion() {
+        //Arrange
+        let (channel, client_receiver) = test_channel(1);
+        let mut client = Client::new(1, channel);
+        client.disconnected = false;
+        client.subscribe("casa/#".to_owned(), 1);
+        let lock_clients = Arc::new(Mutex::new(HashMap::from([(1, client)])));
+        let mut retained_messages = RetainedStore::new();
+        for (topic, qos) in [("casa/luz", 1), ("casa/puerta", 0)] {
+            retained_messages.retain(
+                topic.to_owned(),
+                Message {
+                    payload: b"on".to_vec(),
+                    qos,
+                    properties: Vec::new(),
+                },
+            );
+        }
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "casa/#".to_owned(),
+                qos: 1,
+            }],
+        };
+        //Act
+        send_retained_messages(
+            &lock_clients,
+            1,
+            &subscribe,
+            &retained_messages,
+            &mut SessionStore::new(),
+        );
+        //Assert
+        assert_eq!(client_receiver.try_iter().count(), 2);
+        let locked = lock_clients.lock().unwrap();
+        let queued = &locked[&1].publishes_received;
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].topic_name, "casa/luz");
+        assert!(queued[0].dup);
+        assert!(queued[0].retain);
+        assert_eq!(queued[0].packet_identifier, Some(1));
+    }
 }
@@ -0,0 +1,180 @@
+//! # Hooks
+//!
+//! Plugins the coordinator calls on each publish before routing it, to rewrite or drop the
+//! message, and when clients connect, disconnect, subscribe or unsubscribe. Hooks run in the
+//! order they were registered; the built-in ones are registered by name from the configuration.
+
+use crate::client::Client;
+use codec::properties::{Property, PropertyValue, USER_PROPERTY};
+use codec::Publish;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the user property added by the `timestamp` hook.
+const TIMESTAMP_PROPERTY: &str = "timestamp";
+
+/// What to do with a publish after a hook saw it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PublishAction {
+    /// Keep routing the message, as the hook left it.
+    Deliver,
+    /// Discard the message.
+    Drop,
+}
+
+/// Plugin called by the coordinator. Every method does nothing by default.
+pub trait MessageHook: Send {
+    /// Called with each publish from `client` before routing it. The hook can change the topic,
+    /// payload, QoS, retain flag and properties of `publish`, or drop it.
+    fn on_publish(&mut self, _client: &Client, _publish: &mut Publish) -> PublishAction {
+        PublishAction::Deliver
+    }
+
+    /// Called once the client connected.
+    fn on_connect(&mut self, _client: &Client) {}
+
+    /// Called when the client disconnects, before its session is discarded.
+    fn on_disconnect(&mut self, _client: &Client) {}
+
+    /// Called for each topic filter the client subscribed to.
+    fn on_subscribe(&mut self, _client: &Client, _topic_filter: &str, _qos: u8) {}
+
+    /// Called for each topic filter the client unsubscribed from.
+    fn on_unsubscribe(&mut self, _client: &Client, _topic_filter: &str) {}
+}
+
+/// Hooks registered in the coordinator.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Box<dyn MessageHook>>,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Hooks::default()
+    }
+
+    /// Built-in hooks with the given names, in that order:
+    /// - `timestamp` adds the time the broker received the message, in milliseconds since the Unix
+    ///   epoch, as a `timestamp` user property.
+    /// - `utf8_only` drops the messages whose payload isn't valid UTF-8.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a name isn't a built-in hook.
+    pub fn from_names(names: &[String]) -> Result<Self, String> {
+        let mut hooks = Hooks::new();
+        for name in names {
+            match name.as_str() {
+                "timestamp" => hooks.register(Box::new(TimestampHook)),
+                "utf8_only" => hooks.register(Box::new(Utf8OnlyHook)),
+                _ => return Err(format!("Unknown hook {}", name)),
+            }
+        }
+        Ok(hooks)
+    }
+
+    pub fn register(&mut self, hook: Box<dyn MessageHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Pass the publish through every hook, until one drops it.
+    pub fn on_publish(&mut self, client: &Client, publish: &mut Publish) -> PublishAction {
+        for hook in self.hooks.iter_mut() {
+            if hook.on_publish(client, publish) == PublishAction::Drop {
+                return PublishAction::Drop;
+            }
+        }
+        PublishAction::Deliver
+    }
+
+    pub fn on_connect(&mut self, client: &Client) {
+        for hook in self.hooks.iter_mut() {
+            hook.on_connect(client);
+        }
+    }
+
+    pub fn on_disconnect(&mut self, client: &Client) {
+        for hook in self.hooks.iter_mut() {
+            hook.on_disconnect(client);
+        }
+    }
+
+    pub fn on_subscribe(&mut self, client: &Client, topic_filter: &str, qos: u8) {
+        for hook in self.hooks.iter_mut() {
+            hook.on_subscribe(client, topic_filter, qos);
+        }
+    }
+
+    pub fn on_unsubscribe(&mut self, client: &Client, topic_filter: &str) {
+        for hook in self.hooks.iter_mut() {
+            hook.on_unsubscribe(client, topic_filter);
+        }
+    }
+}
+
+struct TimestampHook;
+
+impl MessageHook for TimestampHook {
+    fn on_publish(&mut self, _client: &Client, publish: &mut Publish) -> PublishAction {
+        let milliseconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_millis(),
+            Err(_) => 0,
+        };
+        publish.properties.push(Property::new(
+            USER_PROPERTY,
+            PropertyValue::Utf8StringPair(TIMESTAMP_PROPERTY.to_owned(), milliseconds.to_string()),
+        ));
+        PublishAction::Deliver
+    }
+}
+
+struct Utf8OnlyHook;
+
+impl MessageHook for Utf8OnlyHook {
+    fn on_publish(&mut self, _client: &Client, publish: &mut Publish) -> PublishAction {
+        match std::str::from_utf8(&publish.payload) {
+            Ok(_) => PublishAction::Deliver,
+            Err(_) => PublishAction::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::test_channel;
+
+    fn publish(payload: &[u8]) -> Publish {
+        Publish {
+            dup: false,
+            qos: 0,
+            retain: false,
+            topic_name: "telemetria".to_owned(),
+            packet_identifier: None,
+            properties: Vec::new(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test01_hooks_incluidos_agregan_timestamp_y_descartan_payloads_invalidos() {
+        let (channel, _receiver) = test_channel(1);
+        let client = Client::new(1, channel);
+        let names = vec!["utf8_only".to_owned(), "timestamp".to_owned()];
+        let mut hooks = Hooks::from_names(&names).unwrap();
+        let mut valid = publish(b"22.5");
+        assert_eq!(
+            hooks.on_publish(&client, &mut valid),
+            PublishAction::Deliver
+        );
+        assert_eq!(valid.properties.len(), 1);
+        assert!(matches!(
+            &valid.properties[0].value,
+            PropertyValue::Utf8StringPair(name, _) if name == TIMESTAMP_PROPERTY
+        ));
+        let mut invalid = publish(&[0, 159, 255]);
+        assert_eq!(hooks.on_publish(&client, &mut invalid), PublishAction::Drop);
+        assert!(invalid.properties.is_empty());
+        assert!(Hooks::from_names(&["desconocido".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,495 @@
+//! # HTTP
+//!
+//! Minimal HTTP/1.1 server for the metrics and the admin API, and the client of the
+//! `mqtt-admin` tool. Each server answers one request per connection. Requests are read on a
+//! thread per connection, with a deadline and a limit on the size of the headers, so slow clients
+//! don't hold up the others, and are handled in order on a single thread, so handlers don't need
+//! to be thread safe beyond `Send`.
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// How long a client has to send its whole request.
+const REQUEST_DEADLINE: Duration = Duration::from_secs(5);
+/// Longer request or header lines are refused.
+const MAX_HEADER_LINE: usize = 8 * 1024;
+/// Requests whose request line and headers add up to more are refused.
+const MAX_HEADERS_SIZE: usize = 32 * 1024;
+const BAD_REQUEST: u16 = 400;
+const REQUEST_TIMEOUT: u16 = 408;
+const HEADERS_TOO_LARGE: u16 = 431;
+/// Requests being read at the same time, beyond which new connections are refused.
+const MAX_PENDING_REQUESTS: usize = 64;
+/// How long the client waits for the answer of a server.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Larger request bodies are refused.
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// A request, with its path split from the query and the header names in lowercase.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Decoded value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query.split('&').find_map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            if key == name {
+                decode_component(value)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn json(status: u16, value: &serde_json::Value) -> Self {
+        Response::new(status, "application/json", value.to_string().into_bytes())
+    }
+
+    pub fn empty(status: u16) -> Self {
+        Response::new(status, "text/plain", Vec::new())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len()
+        )
+        .into_bytes();
+        bytes.extend(&self.body);
+        bytes
+    }
+}
+
+/// HTTP server on its own thread, which answers each request with a handler.
+pub struct HttpServer {
+    address: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    handler_thread: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Answer the requests to `address` with `handler`, on a thread called `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the address couldn't be bound or the threads couldn't be spawned.
+    pub fn start<F>(name: &str, address: &str, handler: F) -> std::io::Result<Self>
+    where
+        F: Fn(&Request) -> Response + Send + 'static,
+    {
+        let listener = TcpListener::bind(address)?;
+        let mut address = listener.local_addr()?;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => std::net::Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        info!("{} listening on {}", name, address);
+        let stopping = Arc::new(AtomicBool::new(false));
+        let thread_stopping = stopping.clone();
+        let (requests_sender, requests) = mpsc::channel::<(TcpStream, Request)>();
+        let handler_thread = thread::Builder::new()
+            .name(format!("{} handler", name))
+            .spawn(move || {
+                for (stream, request) in requests {
+                    respond(stream, &handler(&request));
+                }
+            })?;
+        let pending = Arc::new(AtomicUsize::new(0));
+        let thread = thread::Builder::new().name(name.into()).spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopping.load(Ordering::SeqCst) {
+                    return;
+                }
+                match stream {
+                    Ok(stream) => read_in_thread(stream, &requests_sender, &pending),
+                    Err(_) => warn!("Error accepting an HTTP request."),
+                }
+            }
+        })?;
+        Ok(HttpServer {
+            address,
+            stopping,
+            thread: Some(thread),
+            handler_thread: Some(handler_thread),
+        })
+    }
+
+    /// Address the server listens on, with the loopback address if it was bound to every
+    /// interface.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stop accepting requests and wait for the thread to finish.
+    pub fn stop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // The thread is blocked accepting, so it needs a connection to notice.
+        if TcpStream::connect(self.address).is_err() {
+            debug!("The HTTP server had already stopped.");
+        }
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("The HTTP server panicked.");
+            }
+        }
+        // The handler stops once the requests being read are done, which takes at most the
+        // request deadline.
+        if let Some(handler_thread) = self.handler_thread.take() {
+            if handler_thread.join().is_err() {
+                warn!("The HTTP handler panicked.");
+            }
+        }
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.stop();
+        }
+    }
+}
+
+/// Read the request of `stream` on its own thread and pass it to the handler, or answer the
+/// error if it is invalid. The connection is refused if too many requests are being read.
+fn read_in_thread(
+    stream: TcpStream,
+    requests: &Sender<(TcpStream, Request)>,
+    pending: &Arc<AtomicUsize>,
+) {
+    if pending.fetch_add(1, Ordering::SeqCst) >= MAX_PENDING_REQUESTS {
+        pending.fetch_sub(1, Ordering::SeqCst);
+        warn!("Too many HTTP requests being read, connection refused.");
+        respond(stream, &Response::empty(503));
+        return;
+    }
+    let requests = requests.clone();
+    let thread_pending = pending.clone();
+    let spawned = thread::Builder::new()
+        .name("HTTP request".into())
+        .spawn(move || {
+            match read_request(&stream, Instant::now() + REQUEST_DEADLINE) {
+                Ok(request) => {
+                    if requests.send((stream, request)).is_err() {
+                        debug!("The HTTP handler stopped.");
+                    }
+                }
+                Err(status) => respond(stream, &Response::empty(status)),
+            }
+            thread_pending.fetch_sub(1, Ordering::SeqCst);
+        });
+    if spawned.is_err() {
+        pending.fetch_sub(1, Ordering::SeqCst);
+        warn!("Couldn't spawn a thread to read an HTTP request.");
+    }
+}
+
+fn respond(mut stream: TcpStream, response: &Response) {
+    if stream.set_write_timeout(Some(REQUEST_DEADLINE)).is_err() {
+        debug!("Couldn't set the timeout of an HTTP response.");
+    }
+    if stream.write_all(&response.encode()).is_err() {
+        debug!("Couldn't answer an HTTP request.");
+    }
+}
+
+/// Request read from `stream` before `deadline`. Returns Error with the status to answer if it
+/// is invalid, its headers or body are too large or it didn't arrive in time.
+fn read_request(stream: &TcpStream, deadline: Instant) -> Result<Request, u16> {
+    let mut reader = BufReader::new(DeadlineReader { stream, deadline });
+    let mut headers_size = 0;
+    let request_line = read_header_line(&mut reader, &mut headers_size)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or(BAD_REQUEST)?.to_owned();
+    let target = parts.next().ok_or(BAD_REQUEST)?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut headers = Vec::new();
+    loop {
+        let line = read_header_line(&mut reader, &mut headers_size)?;
+        if line.trim().is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or(BAD_REQUEST)?;
+        headers.push((name.trim().to_lowercase(), value.trim().to_owned()));
+    }
+    let mut request = Request {
+        method,
+        path: path.to_owned(),
+        query: query.to_owned(),
+        headers,
+        body: Vec::new(),
+    };
+    let length = match request.header("content-length") {
+        Some(length) => length.parse::<usize>().map_err(|_| BAD_REQUEST)?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(BAD_REQUEST);
+    }
+    request.body = vec![0; length];
+    reader
+        .read_exact(&mut request.body)
+        .map_err(|error| read_error_status(&error))?;
+    Ok(request)
+}
+
+/// Line of the request head, adding its size to `headers_size`. An empty line is returned once
+/// the connection is closed.
+fn read_header_line<R: BufRead>(reader: &mut R, headers_size: &mut usize) -> Result<String, u16> {
+    let mut line = Vec::new();
+    let read = reader
+        .take(MAX_HEADER_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .map_err(|error| read_error_status(&error))?;
+    *headers_size += read;
+    if read > MAX_HEADER_LINE || *headers_size > MAX_HEADERS_SIZE {
+        return Err(HEADERS_TOO_LARGE);
+    }
+    String::from_utf8(line).map_err(|_| BAD_REQUEST)
+}
+
+fn read_error_status(error: &std::io::Error) -> u16 {
+    match error.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => REQUEST_TIMEOUT,
+        _ => BAD_REQUEST,
+    }
+}
+
+/// Stream whose reads fail once `deadline` passes, however slowly the data arrives.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                "HTTP request deadline",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buffer)
+    }
+}
+
+/// Send a request to the server at `address` and wait for its status and body.
+///
+/// # Errors
+///
+/// Returns Error if the server couldn't be reached or its response is invalid.
+pub fn send_request(
+    address: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> std::io::Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, address);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid HTTP response");
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(invalid)?;
+    let status = std::str::from_utf8(&response[..head_end])
+        .ok()
+        .and_then(|head| head.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+    Ok((status, response[head_end + 4..].to_vec()))
+}
+
+/// Escape with `%XX` every byte of `component` but letters, digits and `-._~`.
+pub fn encode_component(component: &str) -> String {
+    component
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Decode the `%XX` escapes, and `+` as a space, of a path segment or query value. Returns None
+/// if an escape is invalid or the result isn't UTF-8.
+pub fn decode_component(component: &str) -> Option<String> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_decodifica_escapes_y_parametros() {
+        assert_eq!(
+            decode_component("%24SYS%2Fbroker%2F%23").unwrap(),
+            "$SYS/broker/#"
+        );
+        assert_eq!(decode_component("a+b").unwrap(), "a b");
+        assert_eq!(
+            decode_component(&encode_component("$SYS/á #+")).unwrap(),
+            "$SYS/á #+"
+        );
+        assert_eq!(encode_component("casa/+"), "casa%2F%2B");
+        assert!(decode_component("%2").is_none());
+        assert!(decode_component("%ff").is_none());
+        let request = Request {
+            method: "GET".to_owned(),
+            path: "/retained".to_owned(),
+            query: "x=1&filter=casa%2F%2B".to_owned(),
+            headers: vec![("authorization".to_owned(), "Bearer abc".to_owned())],
+            body: Vec::new(),
+        };
+        assert_eq!(request.query_param("filter").unwrap(), "casa/+");
+        assert!(request.query_param("y").is_none());
+        assert_eq!(request.header("authorization"), Some("Bearer abc"));
+    }
+
+    fn read_response(stream: &mut TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test02_headers_demasiado_grandes_se_rechazan() {
+        let mut server =
+            HttpServer::start("Prueba", "127.0.0.1:0", |_: &Request| Response::empty(204)).unwrap();
+        let address = server.address();
+        let mut stream = TcpStream::connect(address).unwrap();
+        let header = format!("X-Largo: {}\r\n", "a".repeat(MAX_HEADER_LINE));
+        stream
+            .write_all(format!("GET / HTTP/1.1\r\n{}\r\n", header).as_bytes())
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 431 "));
+        let mut stream = TcpStream::connect(address).unwrap();
+        let header = format!("X-Corto: {}\r\n", "a".repeat(1000));
+        stream
+            .write_all(format!("GET / HTTP/1.1\r\n{}\r\n", header.repeat(40)).as_bytes())
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 431 "));
+        let (status, _) = send_request(&address.to_string(), "GET", "/", &[], b"").unwrap();
+        assert_eq!(status, 204);
+        server.stop();
+    }
+
+    #[test]
+    fn test03_cliente_lento_no_frena_a_los_demas_y_vence_su_plazo() {
+        let mut server =
+            HttpServer::start("Prueba", "127.0.0.1:0", |_: &Request| Response::empty(204)).unwrap();
+        let address = server.address();
+        let mut lento = TcpStream::connect(address).unwrap();
+        lento.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let goteo = lento.try_clone().unwrap();
+        let started = Instant::now();
+        let escritor = thread::spawn(move || {
+            let mut goteo = goteo;
+            while goteo.write_all(b"X").is_ok() && started.elapsed() < REQUEST_DEADLINE * 2 {
+                thread::sleep(Duration::from_millis(300));
+            }
+        });
+        let (status, _) = send_request(&address.to_string(), "GET", "/", &[], b"").unwrap();
+        assert_eq!(status, 204);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(read_response(&mut lento).starts_with("HTTP/1.1 408 "));
+        assert!(started.elapsed() < REQUEST_DEADLINE + Duration::from_secs(1));
+        lento.shutdown(std::net::Shutdown::Both).unwrap();
+        escritor.join().unwrap();
+        server.stop();
+    }
+}
@@ -0,0 +1,3270 @@
+//! # Server
+//!
+//! MQTT broker. The `server` binary runs it with a configuration file, and other programs can
+//! embed it with a [`Broker`], which also publishes and subscribes in-process.
+
+pub mod acl;
+mod admin;
+pub mod admin_tool;
+pub mod auth;
+pub mod broker;
+pub mod client;
+mod config_parser;
+pub mod configuration;
+mod coordinator;
+pub mod hooks;
+mod http;
+pub mod logging;
+pub mod metrics;
+mod network;
+pub mod options;
+mod packet;
+pub mod password_file;
+mod retained_store;
+mod server;
+mod session_store;
+mod stacked_messages;
+mod sys_topics;
+mod tls;
+mod topic_tree;
+mod wildcard;
+
+pub use crate::broker::{Broker, BrokerBuilder, BrokerHandle, Subscriber};
+pub use crate::configuration::{
+    Configuration, Limits, ListenerSettings, LogFormat, LogRotation, LogSettings,
+};
+pub use crate::options::Options;
+pub use crate::password_file::run_password_command;
+pub use crate::server::Server;
+pub use codec::Publish;
+
+#[cfg(test)]
+// The original connection tests read packets with a single-arm `match` and build them with `push`.
+#[allow(clippy::single_match, clippy::vec_init_then_push)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::hooks::{MessageHook, PublishAction};
+    use crate::network::test_channel;
+    use crate::session_store::SessionStore;
+    use codec::properties::{
+        decode_properties, encode_properties, Property, PropertyValue, ASSIGNED_CLIENT_IDENTIFIER,
+        SESSION_EXPIRY_INTERVAL, USER_PROPERTY,
+    };
+    use codec::{
+        remaining_length_encode, remaining_length_read, Connect, LastWill, Publish, Subscribe,
+        SubscribeTopic, MQTT_VERSION, MQTT_VERSION_5,
+    };
+    use rustls::pki_types::ServerName;
+    use std::convert::TryFrom;
+    use std::io::ErrorKind;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::{Shutdown, TcpStream};
+    use std::sync::mpsc;
+    use std::sync::mpsc::{Receiver, Sender};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time;
+
+    #[test]
+    fn test_01_connect_y_connack_exitoso() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfg.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1883").unwrap();
+        let mut buffer: Vec<u8> = Vec::with_capacity(16);
+        buffer.push(0x10); //Connect packet
+        buffer.push(14); //Hardcoded length
+        buffer.push(0);
+        buffer.push(4);
+        buffer.push(77); // M
+        buffer.push(81); // Q
+        buffer.push(84); // T
+        buffer.push(84); // T
+        buffer.push(4); // Protocol Level
+        buffer.push(0); // Connect flags
+        buffer.push(0);
+        buffer.push(100);
+        buffer.push(0);
+        buffer.push(2);
+        let client_id = "20".to_owned();
+        let client_id_bytes = client_id.as_bytes();
+        for byte in client_id_bytes.iter() {
+            buffer.push(*byte);
+        }
+        stream.write_all(&buffer).unwrap();
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x20);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    //let session_present = buffer_paquete[0];
+                    let return_code = buffer_paquete[1];
+                    assert_eq!(return_code, 0);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_02_connect_con_version_incorrecta_devuelve_connack_con_return_code_1() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfga.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1884").unwrap();
+        let mut buffer: Vec<u8> = Vec::with_capacity(16);
+        buffer.push(0x10); //Connect packet
+        buffer.push(14); //Hardcoded length
+        buffer.push(0);
+        buffer.push(4);
+        buffer.push(77); // M
+        buffer.push(81); // Q
+        buffer.push(84); // T
+        buffer.push(84); // T
+        buffer.push(3); // Protocol Level
+        buffer.push(0); // Connect flags
+        buffer.push(0);
+        buffer.push(100);
+        buffer.push(0);
+        buffer.push(2);
+        let client_id = "21".to_owned();
+        let client_id_bytes = client_id.as_bytes();
+        for byte in client_id_bytes.iter() {
+            buffer.push(*byte);
+        }
+        stream.write_all(&buffer).unwrap();
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x20);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let return_code = buffer_paquete[1];
+                    assert_eq!(return_code, 1);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_03_connect_con_userpass_incorrectos_devuelve_connack_con_returncode_4() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgb.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1885").unwrap();
+        let mut buffer: Vec<u8> = Vec::with_capacity(22);
+        buffer.push(0x10); //Connect packet
+        buffer.push(20); //Hardcoded length
+        buffer.push(0);
+        buffer.push(4);
+        buffer.push(77); // M
+        buffer.push(81); // Q
+        buffer.push(84); // T
+        buffer.push(84); // T
+        buffer.push(4); // Protocol Level
+        buffer.push(0xC0); // Connect flags
+        buffer.push(0);
+        buffer.push(100);
+        buffer.push(0);
+        buffer.push(2);
+        let client_id = "22".to_owned();
+        let client_id_bytes = client_id.as_bytes();
+        for byte in client_id_bytes.iter() {
+            buffer.push(*byte);
+        }
+        buffer.push(0);
+        buffer.push(1);
+        let user = "a".to_owned();
+        let user_bytes = user.as_bytes();
+        for byte in user_bytes.iter() {
+            buffer.push(*byte);
+        }
+        buffer.push(0);
+        buffer.push(1);
+        let pass = "a".to_owned();
+        let pass_bytes = pass.as_bytes();
+        for byte in pass_bytes.iter() {
+            buffer.push(*byte);
+        }
+        stream.write_all(&buffer).unwrap();
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x20);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let return_code = buffer_paquete[1];
+                    assert_eq!(return_code, 4);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_04_connect_sin_clientid_devuelve_connack_con_returncode_2() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgc.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1886").unwrap();
+        let mut buffer: Vec<u8> = Vec::with_capacity(14);
+        buffer.push(0x10); //Connect packet
+        buffer.push(12); //Hardcoded length
+        buffer.push(0);
+        buffer.push(4);
+        buffer.push(77); // M
+        buffer.push(81); // Q
+        buffer.push(84); // T
+        buffer.push(84); // T
+        buffer.push(4); // Protocol Level
+        buffer.push(0); // Connect flags
+        buffer.push(0);
+        buffer.push(100);
+        buffer.push(0);
+        buffer.push(0);
+        stream.write_all(&buffer).unwrap();
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x20);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let return_code = buffer_paquete[1];
+                    assert_eq!(return_code, 2);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_05_se_suscribe_publica_y_recibe_ese_publish() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgd.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1887").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/ti/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange publish
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/ti/lle/ro".to_owned();
+        let mut topic_publish_bytes: Vec<u8> = topic_publish.as_bytes().to_vec();
+        let body = "piniata".to_owned();
+        let mut body_bytes: Vec<u8> = body.as_bytes().to_vec();
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.push((4 + topic_publish_bytes.len() + body_bytes.len()) as u8);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_publish_bytes.len() as u8);
+        buffer_publish.append(&mut topic_publish_bytes);
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body_bytes);
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        let mut first_pubback = true;
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    if package_type == 0x40 {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            "as/ti/lle/ro"
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            "piniata"
+                        );
+                        can_go_on = true;
+                        first_pubback = false;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        //Assert publish
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    if first_pubback {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            "as/ti/lle/ro"
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            "piniata"
+                        );
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_06_se_desuscribe_y_no_recibe_ese_publish() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfge.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1888").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange subscribe packet 1
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/tio/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange subscribe packet 2
+        let mut buffer_subscribe_2: Vec<u8> = Vec::new();
+        let topic_subscribed_2 = "car/li/#".to_owned();
+        let mut topic_subscribed_2_bytes: Vec<u8> = topic_subscribed_2.as_bytes().to_vec();
+        buffer_subscribe_2.push(0x80); //Subscribe code
+        buffer_subscribe_2.push((5 + topic_subscribed_2_bytes.len()) as u8);
+        buffer_subscribe_2.push(0);
+        buffer_subscribe_2.push(50);
+        buffer_subscribe_2.push(0);
+        buffer_subscribe_2.push(topic_subscribed_2_bytes.len() as u8);
+        buffer_subscribe_2.append(&mut topic_subscribed_2_bytes);
+        buffer_subscribe_2.push(1);
+        stream.write_all(&buffer_subscribe_2).unwrap();
+        //Assert subscribe exitoso
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(50, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange unsubscribe packet 1
+        let mut buffer_unsubscribe: Vec<u8> = Vec::new();
+        let topic_unsubscribed = "as/tio/#".to_owned();
+        let mut topic_unsubscribed_bytes: Vec<u8> = topic_unsubscribed.as_bytes().to_vec();
+        buffer_unsubscribe.push(0xA0); //Unsubscribe code
+        buffer_unsubscribe.push((4 + topic_unsubscribed_bytes.len()) as u8);
+        buffer_unsubscribe.push(0);
+        buffer_unsubscribe.push(51);
+        buffer_unsubscribe.push(0);
+        buffer_unsubscribe.push(topic_unsubscribed_bytes.len() as u8);
+        buffer_unsubscribe.append(&mut topic_unsubscribed_bytes);
+        stream.write_all(&buffer_unsubscribe).unwrap();
+        //Assert unsubscribe exitoso
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0xB0);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(51, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange publish 1
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/tio/lle/ro".to_owned();
+        let mut topic_publish_bytes: Vec<u8> = topic_publish.as_bytes().to_vec();
+        let body = "piniata".to_owned();
+        let mut body_bytes: Vec<u8> = body.as_bytes().to_vec();
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.push((4 + topic_publish_bytes.len() + body_bytes.len()) as u8);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_publish_bytes.len() as u8);
+        buffer_publish.append(&mut topic_publish_bytes);
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body_bytes);
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck 1
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x40);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(14, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        thread::sleep(time::Duration::from_millis(20));
+        //Arrange publish 2
+        let mut buffer_publish_2: Vec<u8> = Vec::new();
+        let topic_publish_2 = "car/li/tos".to_owned();
+        let mut topic_publish_2_bytes: Vec<u8> = topic_publish_2.as_bytes().to_vec();
+        let body_2 = "el vecino".to_owned();
+        let mut body_bytes_2: Vec<u8> = body_2.as_bytes().to_vec();
+        buffer_publish_2.push(0x32); //Publish code
+        buffer_publish_2.push((4 + topic_publish_2_bytes.len() + body_bytes_2.len()) as u8);
+        buffer_publish_2.push(0);
+        buffer_publish_2.push(topic_publish_2_bytes.len() as u8);
+        buffer_publish_2.append(&mut topic_publish_2_bytes);
+        buffer_publish_2.push(0);
+        buffer_publish_2.push(31); //Packet identifier
+        buffer_publish_2.append(&mut body_bytes_2);
+        stream.write_all(&buffer_publish_2).unwrap();
+        //Assert pubAck 2 & not publish instead
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x40);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(31, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_07_publish_con_retained_envia_mensaje_a_nuevo_suscriptor() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgf.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1889").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange publish 1
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/tio/lle/ro".to_owned();
+        let mut topic_publish_bytes: Vec<u8> = topic_publish.as_bytes().to_vec();
+        let body = "piniata".to_owned();
+        let mut body_bytes: Vec<u8> = body.as_bytes().to_vec();
+        buffer_publish.push(0x33); //Publish code & retain
+        buffer_publish.push((4 + topic_publish_bytes.len() + body_bytes.len()) as u8);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_publish_bytes.len() as u8);
+        buffer_publish.append(&mut topic_publish_bytes);
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body_bytes);
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x40);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(14, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange subscribe packet 1
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/tio/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Assert publish
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type & 0xF0, 0x30);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let topic_name_len: usize = buffer_paquete[1] as usize;
+                    assert_eq!(
+                        bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                        "as/tio/lle/ro"
+                    );
+                    assert_eq!(
+                        bytes2string(&buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()]),
+                        "piniata"
+                    );
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_08_publish_sin_retained_no_envia_mensaje_a_nuevo_suscriptor() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgg.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1890").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange publish 1
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/tio/lle/ro".to_owned();
+        let mut topic_publish_bytes: Vec<u8> = topic_publish.as_bytes().to_vec();
+        let body = "piniata".to_owned();
+        let mut body_bytes: Vec<u8> = body.as_bytes().to_vec();
+        buffer_publish.push(0x32); //Publish code & retain
+        buffer_publish.push((4 + topic_publish_bytes.len() + body_bytes.len()) as u8);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_publish_bytes.len() as u8);
+        buffer_publish.append(&mut topic_publish_bytes);
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body_bytes);
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x40);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(14, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange subscribe packet 1
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/tio/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange publish 1
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/ti/lle/ro".to_owned();
+        let mut topic_publish_bytes: Vec<u8> = topic_publish.as_bytes().to_vec();
+        let body = "piniata".to_owned();
+        let mut body_bytes: Vec<u8> = body.as_bytes().to_vec();
+        buffer_publish.push(0x33); //Publish code & retain
+        buffer_publish.push((4 + topic_publish_bytes.len() + body_bytes.len()) as u8);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_publish_bytes.len() as u8);
+        buffer_publish.append(&mut topic_publish_bytes);
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body_bytes);
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck instead of publish retained
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x40);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(14, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_09_suscriptor_con_lastwill_se_desconecta_sin_avisar() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgh.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let (sender, receiver): (Sender<u8>, Receiver<u8>) = mpsc::channel();
+        thread::spawn(move || {
+            run_client_that_disconnects_ungracefully(receiver);
+        });
+        thread::sleep(time::Duration::from_millis(100)); //Wait for client to do its things
+        let mut stream = TcpStream::connect("127.0.0.1:1891").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange subscribe packet 1
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arange disconnection of first client
+        sender.send(44_u8).unwrap();
+        thread::sleep(time::Duration::from_millis(30)); //Wait for client to do its things
+                                                        //Assert publish from lastwill
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type & 0xF0, 0x30);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let topic_name_len: usize = buffer_paquete[1] as usize;
+                    assert_eq!(bytes2string(&buffer_paquete[2..(2 + topic_name_len)]), "as");
+                    assert_eq!(
+                        bytes2string(&buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()]),
+                        "pepe"
+                    );
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    fn run_client_that_disconnects_ungracefully(receiver: Receiver<u8>) {
+        let mut stream = TcpStream::connect("127.0.0.1:1891").unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.push(0x10); //Connect packet
+        buffer.push(24); //Hardcoded length
+        buffer.push(0);
+        buffer.push(4);
+        buffer.push(77); // M
+        buffer.push(81); // Q
+        buffer.push(84); // T
+        buffer.push(84); // T
+        buffer.push(4); // Protocol Level
+        buffer.push(0x0E); // Connect flags
+        buffer.push(0);
+        buffer.push(100);
+        buffer.push(0);
+        buffer.push(2);
+        let client_id = "24".to_owned();
+        let client_id_bytes = client_id.as_bytes();
+        for byte in client_id_bytes.iter() {
+            buffer.push(*byte);
+        }
+        let last_topic = "as".to_owned();
+        let mut last_topic_bytes = last_topic.as_bytes().to_vec();
+        let last_message = "pepe".to_owned();
+        let mut last_message_bytes = last_message.as_bytes().to_vec();
+        buffer.push(0);
+        buffer.push(last_topic_bytes.len() as u8);
+        buffer.append(&mut last_topic_bytes);
+        buffer.push(0);
+        buffer.push(last_message_bytes.len() as u8);
+        buffer.append(&mut last_message_bytes);
+        stream.write_all(&buffer).unwrap();
+        //Assert connect exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x20);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let return_code = buffer_paquete[1];
+                    assert_eq!(return_code, 0);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        loop {
+            let num = receiver.recv().unwrap();
+            if num == 44 {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_10_se_suscribe_publica_mensaje_largo_y_recibe_ese_publish() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgi.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1892").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/ti/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange publish
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/ti/lle/ro".to_owned();
+        let topic_len: usize = topic_publish.as_bytes().to_vec().len();
+        let body = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Duis tempus metus et lacinia scelerisque. Donec vel est sit amet nulla pellentesque convallis sed sit amet sapien. Donec nec urna posuere, posuere nunc a, bibendum felis. Ut finibus iaculis purus vel imperdiet. Nulla pharetra tristique lacus in hendrerit. Morbi dictum lacus odio, ac fermentum risus efficitur a. Nullam scelerisque sem non dui rhoncus porttitor. Pellentesque quis nibh odio. Praesent eleifend condimentum massa quis luctus. Nunc libero neque, interdum quis scelerisque eget, placerat lacinia sem. Etiam at nunc ac nulla ullamcorper cursus. Pellentesque sollicitudin nisl vitae lorem imperdiet maximus.".to_owned();
+        let body_len: usize = body.as_bytes().to_vec().len();
+        let mut remaining_length: Vec<u8> = remaining_length_encode(topic_len + body_len + 4);
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.append(&mut remaining_length);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_len as u8);
+        buffer_publish.append(&mut topic_publish.as_bytes().to_vec());
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body.as_bytes().to_vec());
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        let mut first_pubback = true;
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    if package_type == 0x40 {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            "as/ti/lle/ro"
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            body
+                        );
+                        can_go_on = true;
+                        first_pubback = false;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        thread::sleep(time::Duration::from_millis(20));
+        //Assert publish
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 1]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    let buff_size = remaining_length_read(&mut stream).unwrap();
+                    if first_pubback {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            topic_publish
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            body
+                        );
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_11_como_el_cliente_no_envia_su_pubback_vuelve_a_recibir_el_mensaje() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgj.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1893").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/ti/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange publish
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/ti/lle/ro".to_owned();
+        let topic_len: usize = topic_publish.as_bytes().to_vec().len();
+        let body = "hola".to_owned();
+        let body_len: usize = body.as_bytes().to_vec().len();
+        let mut remaining_length: Vec<u8> = remaining_length_encode(topic_len + body_len + 4);
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.append(&mut remaining_length);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_len as u8);
+        buffer_publish.append(&mut topic_publish.as_bytes().to_vec());
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body.as_bytes().to_vec());
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        let mut first_pubback = true;
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    if package_type == 0x40 {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            topic_publish
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            body
+                        );
+                        can_go_on = true;
+                        first_pubback = false;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        //Assert publish
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 1]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    let buff_size = remaining_length_read(&mut stream).unwrap();
+                    if first_pubback {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            topic_publish
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            body
+                        );
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        //Assert publish again
+
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 1]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    let buff_size = remaining_length_read(&mut stream).unwrap();
+                    assert_eq!(package_type & 0xF0, 0x30);
+                    let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let topic_name_len: usize = buffer_paquete[1] as usize;
+                    assert_eq!(
+                        bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                        topic_publish
+                    );
+                    assert_eq!(
+                        bytes2string(&buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],),
+                        body
+                    );
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_12_cliente_con_clean_session_no_mantiene_mensajes_encolados_al_desconectarse() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgk.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1894").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/ti/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange publish
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/ti/lle/ro".to_owned();
+        let topic_len: usize = topic_publish.as_bytes().to_vec().len();
+        let body = "hola".to_owned();
+        let body_len: usize = body.as_bytes().to_vec().len();
+        let mut remaining_length: Vec<u8> = remaining_length_encode(topic_len + body_len + 4);
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.append(&mut remaining_length);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_len as u8);
+        buffer_publish.append(&mut topic_publish.as_bytes().to_vec());
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body.as_bytes().to_vec());
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    if num_buffer[0] != 0x40 {
+                        //Publish received so i read and do nothing
+                        let buff_size = remaining_length_read(&mut stream).unwrap();
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                    } else {
+                        let package_type = num_buffer[0];
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        //Disconnect
+        stream
+            .shutdown(Shutdown::Both)
+            .expect("shutdown call failed");
+        let mut new_stream = TcpStream::connect("127.0.0.1:1894").unwrap();
+        connect_and_assert_connection(&mut new_stream, true);
+        thread::sleep(time::Duration::from_millis(2500)); //Wait for stacked coordinator
+                                                          //Arrange new subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "copa".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(51);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        new_stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso instead of publish
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match new_stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    new_stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(51, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_13_cliente_sin_clean_session_mantiene_mensajes_encolados_al_desconectarse() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgl.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1895").unwrap();
+        connect_and_assert_connection(&mut stream, false);
+        //Arrange subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/ti/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Arrange publish
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/ti/lle/ro".to_owned();
+        let topic_len: usize = topic_publish.as_bytes().to_vec().len();
+        let body = "hola".to_owned();
+        let body_len: usize = body.as_bytes().to_vec().len();
+        let mut remaining_length: Vec<u8> = remaining_length_encode(topic_len + body_len + 4);
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.append(&mut remaining_length);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_len as u8);
+        buffer_publish.append(&mut topic_publish.as_bytes().to_vec());
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body.as_bytes().to_vec());
+        stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    if num_buffer[0] != 0x40 {
+                        //Publish received so i read and do nothing
+                        let buff_size = remaining_length_read(&mut stream).unwrap();
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                    } else {
+                        let package_type = num_buffer[0];
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        //Disconnect
+        stream
+            .shutdown(Shutdown::Both)
+            .expect("shutdown call failed");
+        let mut new_stream = TcpStream::connect("127.0.0.1:1895").unwrap();
+        connect_and_assert_connection(&mut new_stream, false);
+        //Assert publish in queue
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match new_stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type & 0xF0, 0x30);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    new_stream.read_exact(&mut buffer_paquete).unwrap();
+                    let topic_name_len: usize = buffer_paquete[1] as usize;
+                    assert_eq!(
+                        bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                        "as/ti/lle/ro"
+                    );
+                    assert_eq!(
+                        bytes2string(&buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],),
+                        "hola"
+                    );
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_14_se_reconecta_habiendo_tenido_clean_session_y_no_guardo_suscripciones() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgm.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1896").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        //Arrange subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/ti/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Disconnect
+        stream
+            .shutdown(Shutdown::Both)
+            .expect("shutdown call failed");
+        let mut new_stream = TcpStream::connect("127.0.0.1:1896").unwrap();
+        connect_and_assert_connection(&mut new_stream, true);
+        //Arrange publish 1
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/tio/lle/ro".to_owned();
+        let mut topic_publish_bytes: Vec<u8> = topic_publish.as_bytes().to_vec();
+        let body = "piniata".to_owned();
+        let mut body_bytes: Vec<u8> = body.as_bytes().to_vec();
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.push((4 + topic_publish_bytes.len() + body_bytes.len()) as u8);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_publish_bytes.len() as u8);
+        buffer_publish.append(&mut topic_publish_bytes);
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body_bytes);
+        new_stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck 1
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match new_stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x40);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    new_stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(14, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        thread::sleep(time::Duration::from_millis(20));
+        //Arrange publish 2
+        let mut buffer_publish_2: Vec<u8> = Vec::new();
+        let topic_publish_2 = "car/li/tos".to_owned();
+        let mut topic_publish_2_bytes: Vec<u8> = topic_publish_2.as_bytes().to_vec();
+        let body_2 = "el vecino".to_owned();
+        let mut body_bytes_2: Vec<u8> = body_2.as_bytes().to_vec();
+        buffer_publish_2.push(0x32); //Publish code
+        buffer_publish_2.push((4 + topic_publish_2_bytes.len() + body_bytes_2.len()) as u8);
+        buffer_publish_2.push(0);
+        buffer_publish_2.push(topic_publish_2_bytes.len() as u8);
+        buffer_publish_2.append(&mut topic_publish_2_bytes);
+        buffer_publish_2.push(0);
+        buffer_publish_2.push(31); //Packet identifier
+        buffer_publish_2.append(&mut body_bytes_2);
+        new_stream.write_all(&buffer_publish_2).unwrap();
+        //Assert pubAck 2 & not publish instead
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match new_stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x40);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    new_stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(31, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_15_se_reconecta_sin_haber_tenido_clean_session_y_guardo_suscripciones() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgn.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1897").unwrap();
+        connect_and_assert_connection(&mut stream, false);
+        //Arrange subscribe packet
+        let mut buffer_subscribe: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/ti/#".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_subscribe.push(0x80); //Subscribe code
+        buffer_subscribe.push((5 + topic_subscribed_bytes.len()) as u8);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(57);
+        buffer_subscribe.push(0);
+        buffer_subscribe.push(topic_subscribed_bytes.len() as u8);
+        buffer_subscribe.append(&mut topic_subscribed_bytes);
+        buffer_subscribe.push(1);
+        stream.write_all(&buffer_subscribe).unwrap();
+        //Assert subscribe exitoso
+        let mut can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x90);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    assert_eq!(57, buffer_paquete[1]);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+        //Disconnect
+        stream
+            .shutdown(Shutdown::Both)
+            .expect("shutdown call failed");
+        let mut new_stream = TcpStream::connect("127.0.0.1:1897").unwrap();
+        connect_and_assert_connection(&mut new_stream, false);
+        //Arrange publish
+        let mut buffer_publish: Vec<u8> = Vec::new();
+        let topic_publish = "as/ti/lle/ro".to_owned();
+        let topic_len: usize = topic_publish.as_bytes().to_vec().len();
+        let body = "hola".to_owned();
+        let body_len: usize = body.as_bytes().to_vec().len();
+        let mut remaining_length: Vec<u8> = remaining_length_encode(topic_len + body_len + 4);
+        buffer_publish.push(0x32); //Publish code
+        buffer_publish.append(&mut remaining_length);
+        buffer_publish.push(0);
+        buffer_publish.push(topic_len as u8);
+        buffer_publish.append(&mut topic_publish.as_bytes().to_vec());
+        buffer_publish.push(0);
+        buffer_publish.push(14); //Packet identifier
+        buffer_publish.append(&mut body.as_bytes().to_vec());
+        new_stream.write_all(&buffer_publish).unwrap();
+        //Assert pubAck
+        let mut first_pubback = true;
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match new_stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    if package_type == 0x40 {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        new_stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                        new_stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            topic_publish
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            body
+                        );
+                        can_go_on = true;
+                        first_pubback = false;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        //Assert publish
+        can_go_on = false;
+        while !can_go_on {
+            let mut num_buffer = [0u8; 1]; //Recibimos 2 bytes
+            match new_stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    let buff_size = remaining_length_read(&mut new_stream).unwrap();
+                    if first_pubback {
+                        assert_eq!(package_type & 0xF0, 0x30);
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        new_stream.read_exact(&mut buffer_paquete).unwrap();
+                        let topic_name_len: usize = buffer_paquete[1] as usize;
+                        assert_eq!(
+                            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+                            topic_publish
+                        );
+                        assert_eq!(
+                            bytes2string(
+                                &buffer_paquete[(4 + topic_name_len)..buffer_paquete.len()],
+                            ),
+                            body
+                        );
+                        can_go_on = true;
+                    } else {
+                        assert_eq!(package_type, 0x40);
+                        let mut buffer_paquete: Vec<u8> = vec![0; buff_size as usize];
+                        new_stream.read_exact(&mut buffer_paquete).unwrap();
+                        assert_eq!(14, buffer_paquete[1]);
+                        can_go_on = true;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_16_publish_qos2_duplicado_llega_una_sola_vez_al_suscriptor() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgo.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut subscriber = TcpStream::connect("127.0.0.1:1898").unwrap();
+        let (package_type, _) = connect_mqtt31(&mut subscriber, "suscriptor");
+        assert_eq!(package_type, 0x20);
+        let topic = "qos/dos".to_owned();
+        let mut buffer_subscribe: Vec<u8> = vec![0x82, (5 + topic.len()) as u8, 0, 57, 0];
+        buffer_subscribe.push(topic.len() as u8);
+        buffer_subscribe.append(&mut topic.as_bytes().to_vec());
+        buffer_subscribe.push(0);
+        subscriber.write_all(&buffer_subscribe).unwrap();
+        let (package_type, _) = read_whole_packet(&mut subscriber);
+        assert_eq!(package_type, 0x90);
+        let mut stream = TcpStream::connect("127.0.0.1:1898").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        let body = "factura".to_owned();
+        let mut buffer_publish: Vec<u8> = vec![0x34, (4 + topic.len() + body.len()) as u8, 0];
+        buffer_publish.push(topic.len() as u8);
+        buffer_publish.append(&mut topic.as_bytes().to_vec());
+        buffer_publish.push(0);
+        buffer_publish.push(20); //Packet identifier
+        buffer_publish.append(&mut body.as_bytes().to_vec());
+        //Act: publish, then the same publish again with DUP before releasing it
+        stream.write_all(&buffer_publish).unwrap();
+        let (pubrec, pubrec_buffer) = read_whole_packet(&mut stream);
+        buffer_publish[0] |= 0x08; // DUP
+        stream.write_all(&buffer_publish).unwrap();
+        let (pubrec_dup, pubrec_dup_buffer) = read_whole_packet(&mut stream);
+        stream.write_all(&[0x62, 2, 0, 20]).unwrap();
+        let (pubcomp, pubcomp_buffer) = read_whole_packet(&mut stream);
+        //Assert
+        assert_eq!((pubrec, pubrec_buffer), (0x50, vec![0, 20]));
+        assert_eq!((pubrec_dup, pubrec_dup_buffer), (0x50, vec![0, 20]));
+        assert_eq!((pubcomp, pubcomp_buffer), (0x70, vec![0, 20]));
+        let (package_type, buffer_paquete) = read_whole_packet(&mut subscriber);
+        assert_eq!(package_type & 0xF0, 0x30);
+        let topic_name_len: usize = buffer_paquete[1] as usize;
+        assert_eq!(
+            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+            topic
+        );
+        assert_eq!(bytes2string(&buffer_paquete[(2 + topic_name_len)..]), body);
+        subscriber
+            .set_read_timeout(Some(time::Duration::from_millis(500)))
+            .unwrap();
+        assert!(subscriber.read(&mut [0u8; 1]).is_err());
+    }
+
+    #[test]
+    fn test_17_connect_mqtt5_sin_client_id_recibe_client_id_asignado() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgp.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1899").unwrap();
+        //Act
+        let (package_type, buffer_paquete) = connect_mqtt5(&mut stream, "");
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete[0], 0); // Session present
+        assert_eq!(buffer_paquete[1], 0); // Reason code
+        let (properties, _) = decode_properties(&buffer_paquete[2..]).unwrap();
+        let assigned = properties
+            .iter()
+            .find(|property| property.id == ASSIGNED_CLIENT_IDENTIFIER)
+            .unwrap();
+        match &assigned.value {
+            PropertyValue::Utf8String(client_id) => assert!(client_id.starts_with("rustico-")),
+            _ => panic!("Assigned client identifier is not a string"),
+        }
+    }
+
+    #[test]
+    fn test_18_clientes_mqtt5_y_mqtt311_reciben_el_publish_en_su_version() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgq.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream_v5 = TcpStream::connect("127.0.0.1:1900").unwrap();
+        let (package_type, buffer_paquete) = connect_mqtt5(&mut stream_v5, "cinco");
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete[1], 0);
+        let mut stream_v4 = TcpStream::connect("127.0.0.1:1900").unwrap();
+        connect_and_assert_connection(&mut stream_v4, true);
+        let topic = "casa/luz".to_owned();
+        //Arrange subscribe packets
+        let mut buffer_subscribe: Vec<u8> = vec![0x82, (6 + topic.len()) as u8, 0, 57, 0, 0];
+        buffer_subscribe.push(topic.len() as u8);
+        buffer_subscribe.append(&mut topic.as_bytes().to_vec());
+        buffer_subscribe.push(0x01); // Subscription options
+        stream_v5.write_all(&buffer_subscribe).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream_v5);
+        assert_eq!(package_type, 0x90);
+        assert_eq!(buffer_paquete, vec![0, 57, 0, 1]);
+        let mut buffer_subscribe: Vec<u8> = vec![0x82, (5 + topic.len()) as u8, 0, 58, 0];
+        buffer_subscribe.push(topic.len() as u8);
+        buffer_subscribe.append(&mut topic.as_bytes().to_vec());
+        buffer_subscribe.push(0);
+        stream_v4.write_all(&buffer_subscribe).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream_v4);
+        assert_eq!(package_type, 0x90);
+        assert_eq!(buffer_paquete, vec![0, 58, 0]);
+        //Act publish with a user property from the MQTT 5 client
+        let properties = encode_properties(&[Property::new(
+            USER_PROPERTY,
+            PropertyValue::Utf8StringPair("origen".to_owned(), "living".to_owned()),
+        )])
+        .unwrap();
+        let body = "encendida".to_owned();
+        let mut buffer_publish: Vec<u8> = vec![0x30];
+        buffer_publish.push((2 + topic.len() + properties.len() + body.len()) as u8);
+        buffer_publish.push(0);
+        buffer_publish.push(topic.len() as u8);
+        buffer_publish.append(&mut topic.as_bytes().to_vec());
+        buffer_publish.append(&mut properties.clone());
+        buffer_publish.append(&mut body.as_bytes().to_vec());
+        stream_v5.write_all(&buffer_publish).unwrap();
+        //Assert MQTT 5 subscriber receives the properties
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream_v5);
+        assert_eq!(package_type & 0xF0, 0x30);
+        let topic_name_len: usize = buffer_paquete[1] as usize;
+        assert_eq!(
+            bytes2string(&buffer_paquete[2..(2 + topic_name_len)]),
+            topic
+        );
+        let properties_end = 2 + topic_name_len + properties.len();
+        assert_eq!(
+            buffer_paquete[(2 + topic_name_len)..properties_end].to_vec(),
+            properties
+        );
+        assert_eq!(bytes2string(&buffer_paquete[properties_end..]), body);
+        //Assert MQTT 3.1.1 subscriber receives no properties
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream_v4);
+        assert_eq!(package_type & 0xF0, 0x30);
+        let topic_name_len: usize = buffer_paquete[1] as usize;
+        assert_eq!(bytes2string(&buffer_paquete[(2 + topic_name_len)..]), body);
+        //Act unsubscribe from a topic never subscribed
+        let other_topic = "casa/puerta".to_owned();
+        let mut buffer_unsubscribe: Vec<u8> = vec![0xA2, (5 + other_topic.len()) as u8, 0, 59, 0];
+        buffer_unsubscribe.push(0);
+        buffer_unsubscribe.push(other_topic.len() as u8);
+        buffer_unsubscribe.append(&mut other_topic.as_bytes().to_vec());
+        stream_v5.write_all(&buffer_unsubscribe).unwrap();
+        //Assert reason code No subscription existed
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream_v5);
+        assert_eq!(package_type, 0xB0);
+        assert_eq!(buffer_paquete, vec![0, 59, 0, 0x11]);
+    }
+
+    #[test]
+    fn test_19_connect_mqtt31_recibe_connack_y_client_id_largo_es_rechazado() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgr.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1901").unwrap();
+        //Act
+        let (package_type, buffer_paquete) = connect_mqtt31(&mut stream, "gateway-31");
+        let mut stream_rechazado = TcpStream::connect("127.0.0.1:1901").unwrap();
+        let (package_type_rechazado, buffer_rechazado) =
+            connect_mqtt31(&mut stream_rechazado, "gateway-con-un-client-id-largo");
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 0]);
+        assert_eq!(package_type_rechazado, 0x20);
+        assert_eq!(buffer_rechazado, vec![0, 2]); // Identifier rejected
+    }
+
+    #[test]
+    fn test_20_lastwill_con_topic_largo_y_payload_binario_llega_sin_cortarse() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgs.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let topic = "edificio/piso/".repeat(20);
+        let payload: Vec<u8> = vec![0, 159, 146, 150, 255, 10];
+        let mut stream = TcpStream::connect("127.0.0.1:1902").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        let subscribe = Subscribe {
+            packet_identifier: 60,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: topic.clone(),
+                qos: 0,
+            }],
+        };
+        stream
+            .write_all(&subscribe.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (package_type, _) = read_whole_packet(&mut stream);
+        assert_eq!(package_type, 0x90);
+        //Act connect with last will and disconnect without notice
+        let connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "medidor".to_owned(),
+            will: Some(LastWill {
+                topic: topic.clone(),
+                payload: payload.clone(),
+                qos: 0,
+                retain: false,
+                properties: Vec::new(),
+            }),
+            username: None,
+            password: None,
+        };
+        let mut stream_will = TcpStream::connect("127.0.0.1:1902").unwrap();
+        stream_will.write_all(&connect.encode().unwrap()).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream_will);
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 0]);
+        stream_will.shutdown(Shutdown::Both).unwrap();
+        //Assert
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        let publish = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION).unwrap();
+        assert_eq!(publish.topic_name, topic);
+        assert_eq!(publish.payload, payload);
+    }
+
+    #[test]
+    fn test_21_muchos_clientes_conectados_a_la_vez_con_connect_partido_reciben_connack() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgt.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut streams: Vec<TcpStream> = (0..50)
+            .map(|_| TcpStream::connect("127.0.0.1:1903").unwrap())
+            .collect();
+        //Act send every connect in two halves
+        let connects: Vec<Vec<u8>> = (0..streams.len())
+            .map(|index| {
+                let connect = Connect {
+                    protocol_version: MQTT_VERSION,
+                    clean_session: true,
+                    keep_alive: 100,
+                    properties: Vec::new(),
+                    client_id: format!("sensor-{}", index),
+                    will: None,
+                    username: None,
+                    password: None,
+                };
+                connect.encode().unwrap()
+            })
+            .collect();
+        for (stream, connect) in streams.iter_mut().zip(connects.iter()) {
+            stream.write_all(&connect[..5]).unwrap();
+        }
+        thread::sleep(time::Duration::from_millis(50));
+        for (stream, connect) in streams.iter_mut().zip(connects.iter()) {
+            stream.write_all(&connect[5..]).unwrap();
+        }
+        //Assert
+        for stream in streams.iter_mut() {
+            let (package_type, buffer_paquete) = read_whole_packet(stream);
+            assert_eq!(package_type, 0x20);
+            assert_eq!(buffer_paquete, vec![0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_22_cliente_mqtt5_sin_actividad_recibe_disconnect_por_keep_alive() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgu.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1904").unwrap();
+        let connect = Connect {
+            protocol_version: MQTT_VERSION_5,
+            clean_session: true,
+            keep_alive: 1,
+            properties: Vec::new(),
+            client_id: "dormido".to_owned(),
+            will: None,
+            username: None,
+            password: None,
+        };
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+        let (package_type, _) = read_whole_packet(&mut stream);
+        assert_eq!(package_type, 0x20);
+        //Act stay silent longer than one and a half times the keep alive
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        //Assert
+        assert_eq!(package_type, 0xE0);
+        assert_eq!(buffer_paquete, vec![0x8D, 0]);
+        let mut rest: Vec<u8> = Vec::new();
+        assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_23_sesion_persistente_guardada_se_restaura_al_arrancar_con_sus_mensajes() {
+        //Arrange session saved by a previous run of the server
+        let directory = "target/sessions_test";
+        let _ = std::fs::remove_dir_all(directory);
+        let (channel, _receiver) = test_channel(1);
+        let mut saved = Client::new(1, channel);
+        saved.client_id = "persistente".to_owned();
+        saved.clean_session = 0;
+        saved.subscribe("as/tillero".to_owned(), 1);
+        saved.last_packet_id = 1;
+        saved.publishes_received.push(Publish {
+            dup: true,
+            qos: 1,
+            retain: false,
+            topic_name: "as/tillero".to_owned(),
+            packet_identifier: Some(1),
+            properties: Vec::new(),
+            payload: b"pendiente".to_vec(),
+        });
+        SessionStore::open(directory).unwrap().save(&saved);
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgw.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+                                                        //Act publish while the persistent client is offline, then reconnect it
+        let mut publisher = TcpStream::connect("127.0.0.1:1906").unwrap();
+        connect_and_assert_connection(&mut publisher, true);
+        let publish = Publish {
+            dup: false,
+            qos: 1,
+            retain: false,
+            topic_name: "as/tillero".to_owned(),
+            packet_identifier: Some(30),
+            properties: Vec::new(),
+            payload: b"nuevo".to_vec(),
+        };
+        publisher
+            .write_all(&publish.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (package_type, _) = read_whole_packet(&mut publisher);
+        assert_eq!(package_type, 0x40);
+        let connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: false,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "persistente".to_owned(),
+            will: None,
+            username: None,
+            password: None,
+        };
+        let mut stream = TcpStream::connect("127.0.0.1:1906").unwrap();
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+        //Assert
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![1, 0]);
+        let mut payloads: Vec<Vec<u8>> = Vec::new();
+        while payloads.len() < 2 {
+            let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+            let publish = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION).unwrap();
+            if !payloads.contains(&publish.payload) {
+                payloads.push(publish.payload);
+            }
+        }
+        assert!(payloads.contains(&b"pendiente".to_vec()));
+        assert!(payloads.contains(&b"nuevo".to_vec()));
+    }
+
+    #[test]
+    fn test_24_listener_tls_acepta_clientes_mqtts_junto_al_plano() {
+        //Arrange self-signed certificate generated for the test
+        let directory = "target/tls_test";
+        std::fs::create_dir_all(directory).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        std::fs::write(format!("{}/cert.pem", directory), certified.cert.pem()).unwrap();
+        std::fs::write(
+            format!("{}/key.pem", directory),
+            certified.key_pair.serialize_pem(),
+        )
+        .unwrap();
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgx.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(50)); //Wait for server to start
+        let mut stream = connect_tls("127.0.0.1:1908", certified.cert.der(), None);
+        let connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "cifrado".to_owned(),
+            will: None,
+            username: None,
+            password: None,
+        };
+        //Act
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 0]);
+        let mut plain = TcpStream::connect("127.0.0.1:1907").unwrap();
+        connect_and_assert_connection(&mut plain, true);
+        let mut plain_on_tls = TcpStream::connect("127.0.0.1:1908").unwrap();
+        plain_on_tls.write_all(&connect.encode().unwrap()).unwrap();
+        let mut rest: Vec<u8> = Vec::new();
+        let _ = plain_on_tls.read_to_end(&mut rest);
+        assert_ne!(rest.first(), Some(&0x20));
+    }
+
+    #[test]
+    fn test_25_certificado_de_cliente_reemplaza_usuario_y_su_cn_es_el_client_id() {
+        //Arrange certificate authority of the devices and certificates signed by it
+        let directory = "target/mtls_test";
+        std::fs::create_dir_all(directory).unwrap();
+        let server_cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let mut device_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        device_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "sensor-01");
+        device_params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let device_key = rcgen::KeyPair::generate().unwrap();
+        let device_cert = device_params
+            .signed_by(&device_key, &ca_cert, &ca_key)
+            .unwrap();
+        std::fs::write(format!("{}/cert.pem", directory), server_cert.cert.pem()).unwrap();
+        std::fs::write(
+            format!("{}/key.pem", directory),
+            server_cert.key_pair.serialize_pem(),
+        )
+        .unwrap();
+        std::fs::write(format!("{}/ca.pem", directory), ca_cert.pem()).unwrap();
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgy.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(50)); //Wait for server to start
+        let device_identity = (
+            device_cert.der().clone(),
+            rustls::pki_types::PrivateKeyDer::Pkcs8(device_key.serialize_der().into()),
+        );
+        let mut stream = connect_tls(
+            "127.0.0.1:1909",
+            server_cert.cert.der(),
+            Some(device_identity),
+        );
+        let connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "".to_owned(),
+            will: None,
+            username: None,
+            password: None,
+        };
+        //Act connect without username, password nor client id
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 0]);
+        let mut anonymous = connect_tls("127.0.0.1:1909", server_cert.cert.der(), None);
+        let _ = anonymous.write_all(&connect.encode().unwrap());
+        let mut buffer = [0u8; 4];
+        assert!(!matches!(anonymous.read(&mut buffer), Ok(size) if size > 0));
+    }
+
+    #[test]
+    fn test_26_usuario_del_archivo_de_contrasenas_hasheadas_se_conecta() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgz.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "franco".to_owned(),
+            will: None,
+            username: Some("franco".to_owned()),
+            password: Some(b"123pop".to_vec()),
+        };
+        //Act
+        let mut stream = TcpStream::connect("127.0.0.1:1910").unwrap();
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        connect.client_id = "impostor".to_owned();
+        connect.password = Some(b"321triat".to_vec());
+        let mut impostor = TcpStream::connect("127.0.0.1:1910").unwrap();
+        impostor.write_all(&connect.encode().unwrap()).unwrap();
+        let (impostor_type, impostor_buffer) = read_whole_packet(&mut impostor);
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 0]);
+        assert_eq!(impostor_type, 0x20);
+        assert_eq!(impostor_buffer, vec![0, 4]);
+    }
+
+    #[test]
+    fn test_27_acl_rechaza_suscripciones_y_descarta_publicaciones_no_permitidas() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgaa.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1911").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        let subscribe = Subscribe {
+            packet_identifier: 61,
+            properties: Vec::new(),
+            topics: vec![
+                SubscribeTopic {
+                    topic_filter: "anuncios/#".to_owned(),
+                    qos: 0,
+                },
+                SubscribeTopic {
+                    topic_filter: "sensores/otro/#".to_owned(),
+                    qos: 0,
+                },
+                SubscribeTopic {
+                    topic_filter: "sensores/23/+".to_owned(),
+                    qos: 1,
+                },
+            ],
+        };
+        //Act
+        stream
+            .write_all(&subscribe.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        for topic_name in ["anuncios/corte", "sensores/23/temperatura"].iter() {
+            let publish = Publish {
+                dup: false,
+                qos: 0,
+                retain: false,
+                topic_name: topic_name.to_string(),
+                packet_identifier: None,
+                properties: Vec::new(),
+                payload: b"22".to_vec(),
+            };
+            stream
+                .write_all(&publish.encode(MQTT_VERSION).unwrap())
+                .unwrap();
+        }
+        //Assert
+        assert_eq!(package_type, 0x90);
+        assert_eq!(buffer_paquete, vec![0, 61, 0, 0x80, 1]);
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        let publish = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION).unwrap();
+        assert_eq!(publish.topic_name, "sensores/23/temperatura");
+    }
+
+    #[test]
+    fn test_28_comando_externo_autentica_a_los_usuarios() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgab.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "franco".to_owned(),
+            will: None,
+            username: Some("franco".to_owned()),
+            password: Some(b"desde-comando".to_vec()),
+        };
+        //Act
+        let mut stream = TcpStream::connect("127.0.0.1:1912").unwrap();
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        connect.client_id = "archivo".to_owned();
+        connect.password = Some(b"123pop".to_vec());
+        let mut rejected = TcpStream::connect("127.0.0.1:1912").unwrap();
+        rejected.write_all(&connect.encode().unwrap()).unwrap();
+        let (rejected_type, rejected_buffer) = read_whole_packet(&mut rejected);
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 0]);
+        assert_eq!(rejected_type, 0x20);
+        assert_eq!(rejected_buffer, vec![0, 4]);
+    }
+
+    #[test]
+    fn test_29_broker_embebido_publica_y_se_suscribe_sin_tcp_y_se_apaga() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgac.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        let subscriber = broker.subscribe("sensores/#").unwrap();
+        let mut stream = TcpStream::connect("127.0.0.1:1913").unwrap();
+        connect_and_assert_connection(&mut stream, true);
+        let subscribe = Subscribe {
+            packet_identifier: 3,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "avisos".to_owned(),
+                qos: 0,
+            }],
+        };
+        stream
+            .write_all(&subscribe.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (package_type, _) = read_whole_packet(&mut stream);
+        assert_eq!(package_type, 0x90);
+        let publish = Publish {
+            dup: false,
+            qos: 0,
+            retain: false,
+            topic_name: "sensores/7".to_owned(),
+            packet_identifier: None,
+            properties: Vec::new(),
+            payload: b"21.5".to_vec(),
+        };
+        //Act
+        stream
+            .write_all(&publish.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let received = subscriber
+            .recv_timeout(time::Duration::from_secs(5))
+            .unwrap();
+        broker
+            .publish("avisos", b"hola".to_vec(), 1, false)
+            .unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(received.topic_name, "sensores/7");
+        assert_eq!(received.payload, b"21.5".to_vec());
+        let publish = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION).unwrap();
+        assert_eq!(publish.topic_name, "avisos");
+        assert_eq!(publish.payload, b"hola".to_vec());
+        assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+        assert!(TcpStream::connect("127.0.0.1:1913").is_err());
+        assert!(subscriber
+            .recv_timeout(time::Duration::from_millis(100))
+            .is_none());
+    }
+
+    #[test]
+    fn test_30_drenado_rechaza_clientes_y_apagado_cierra_sin_publicar_lastwills() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgad.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        let subscriber = broker.subscribe("adios/#").unwrap();
+        let mut streams: Vec<TcpStream> = Vec::new();
+        for (protocol_version, client_id) in [(MQTT_VERSION_5, "v5"), (MQTT_VERSION, "v311")].iter()
+        {
+            let connect = Connect {
+                protocol_version: *protocol_version,
+                clean_session: true,
+                keep_alive: 100,
+                properties: Vec::new(),
+                client_id: client_id.to_string(),
+                will: Some(LastWill {
+                    topic: format!("adios/{}", client_id),
+                    payload: b"chau".to_vec(),
+                    qos: 0,
+                    retain: false,
+                    properties: Vec::new(),
+                }),
+                username: None,
+                password: None,
+            };
+            let mut stream = TcpStream::connect("127.0.0.1:1914").unwrap();
+            stream.write_all(&connect.encode().unwrap()).unwrap();
+            let (package_type, _) = read_whole_packet(&mut stream);
+            assert_eq!(package_type, 0x20);
+            streams.push(stream);
+        }
+        //Act drain
+        broker.handle().set_draining(true);
+        let mut refused = TcpStream::connect("127.0.0.1:1914").unwrap();
+        let (refused_type, refused_buffer) = connect_mqtt31(&mut refused, "tarde");
+        //Act shutdown
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(refused_type, 0x20);
+        assert_eq!(refused_buffer, vec![0, 3]);
+        assert_eq!(refused.read(&mut [0u8; 1]).unwrap(), 0);
+        let (package_type, buffer_paquete) = read_whole_packet(&mut streams[0]);
+        assert_eq!(package_type, 0xE0);
+        assert_eq!(buffer_paquete[0], 0x8B);
+        for stream in streams.iter_mut() {
+            assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+        }
+        assert!(subscriber
+            .recv_timeout(time::Duration::from_millis(100))
+            .is_none());
+        assert!(TcpStream::connect("127.0.0.1:1914").is_err());
+    }
+
+    #[test]
+    fn test_31_listeners_con_su_propia_autenticacion_comparten_el_coordinador() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgae.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "anonimo".to_owned(),
+            will: None,
+            username: None,
+            password: None,
+        };
+        //Act
+        let mut anonymous = TcpStream::connect("127.0.0.1:1915").unwrap();
+        anonymous.write_all(&connect.encode().unwrap()).unwrap();
+        let (anonymous_type, anonymous_buffer) = read_whole_packet(&mut anonymous);
+        let mut refused = TcpStream::connect("[::1]:1916").unwrap();
+        refused.write_all(&connect.encode().unwrap()).unwrap();
+        let (refused_type, refused_buffer) = read_whole_packet(&mut refused);
+        connect.client_id = "franco".to_owned();
+        connect.username = Some("franco".to_owned());
+        connect.password = Some(b"123pop".to_vec());
+        let mut logged_in = TcpStream::connect("[::1]:1916").unwrap();
+        logged_in.write_all(&connect.encode().unwrap()).unwrap();
+        let (logged_in_type, logged_in_buffer) = read_whole_packet(&mut logged_in);
+        let subscribe = Subscribe {
+            packet_identifier: 5,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "compartido".to_owned(),
+                qos: 0,
+            }],
+        };
+        logged_in
+            .write_all(&subscribe.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (suback_type, _) = read_whole_packet(&mut logged_in);
+        let publish = Publish {
+            dup: false,
+            qos: 0,
+            retain: false,
+            topic_name: "compartido".to_owned(),
+            packet_identifier: None,
+            properties: Vec::new(),
+            payload: b"entre listeners".to_vec(),
+        };
+        anonymous
+            .write_all(&publish.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut logged_in);
+        //Assert
+        assert_eq!(anonymous_type, 0x20);
+        assert_eq!(anonymous_buffer, vec![0, 0]);
+        assert_eq!(refused_type, 0x20);
+        assert_ne!(refused_buffer[1], 0);
+        assert_eq!(logged_in_type, 0x20);
+        assert_eq!(logged_in_buffer, vec![0, 0]);
+        assert_eq!(suback_type, 0x90);
+        let publish = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION).unwrap();
+        assert_eq!(publish.payload, b"entre listeners".to_vec());
+    }
+
+    #[test]
+    fn test_32_limites_de_conexiones_y_de_tamanio_de_paquete() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgaf.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut connected = TcpStream::connect("127.0.0.1:1917").unwrap();
+        let (connack_type, connack) = connect_mqtt5(&mut connected, "limitado");
+        //Act
+        let mut refused = TcpStream::connect("127.0.0.1:1917").unwrap();
+        refused
+            .set_read_timeout(Some(time::Duration::from_secs(2)))
+            .unwrap();
+        let mut byte = [0u8; 1];
+        let refused_read = refused.read(&mut byte);
+        let publish = Publish {
+            dup: false,
+            qos: 0,
+            retain: false,
+            topic_name: "grande".to_owned(),
+            packet_identifier: None,
+            properties: Vec::new(),
+            payload: vec![7; 100],
+        };
+        connected
+            .write_all(&publish.encode(MQTT_VERSION_5).unwrap())
+            .unwrap();
+        let (disconnect_type, disconnect) = read_whole_packet(&mut connected);
+        //Assert
+        assert_eq!(connack_type, 0x20);
+        assert_eq!(connack[1], 0);
+        match refused_read {
+            Ok(read) => assert_eq!(read, 0),
+            Err(error) => assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset),
+        }
+        assert_eq!(disconnect_type, 0xE0);
+        assert_eq!(disconnect[0], 0x95);
+    }
+
+    #[test]
+    fn test_33_metricas_por_http() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgag.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1918").unwrap();
+        let (connack_type, _) = connect_mqtt5(&mut stream, "medido");
+        //Act
+        let mut http = TcpStream::connect("127.0.0.1:1919").unwrap();
+        http.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).unwrap();
+        //Assert
+        assert_eq!(connack_type, 0x20);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nmqtt_connections_accepted_total 1\n"));
+        assert!(response.contains("\nmqtt_packets_received_total{type=\"connect\"} 1\n"));
+        assert!(response.contains("\nmqtt_packets_sent_total{type=\"connack\"} 1\n"));
+    }
+
+    #[test]
+    fn test_34_estado_del_broker_en_topicos_sys() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgah.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        let everything = broker.subscribe("#").unwrap();
+        let sys = broker.subscribe("$SYS/broker/subscriptions/count").unwrap();
+        //Act
+        let received = sys.recv_timeout(time::Duration::from_secs(3)).unwrap();
+        let leaked = everything.recv_timeout(time::Duration::from_millis(100));
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(received.topic_name, "$SYS/broker/subscriptions/count");
+        assert_eq!(received.payload, b"2".to_vec());
+        assert!(!received.retain);
+        assert!(leaked.is_none());
+    }
+
+    #[test]
+    fn test_35_api_de_administracion() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgai.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        broker
+            .publish("casa/luz", b"encendida".to_vec(), 0, true)
+            .unwrap();
+        let mut stream = TcpStream::connect("127.0.0.1:1921").unwrap();
+        connect_mqtt5(&mut stream, "inspeccionado");
+        let subscribe = Subscribe {
+            packet_identifier: 4,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "avisos/#".to_owned(),
+                qos: 1,
+            }],
+        };
+        stream
+            .write_all(&subscribe.encode(MQTT_VERSION_5).unwrap())
+            .unwrap();
+        let (package_type, _) = read_whole_packet(&mut stream);
+        assert_eq!(package_type, 0x90);
+        //Act
+        let (unauthorized, _) = admin_request("GET", "/clients", None, "");
+        let (_, clients) = admin_request("GET", "/clients", Some("secreto"), "");
+        let (_, retained) =
+            admin_request("GET", "/retained?filter=casa%2F%23", Some("secreto"), "");
+        let (published, _) = admin_request(
+            "POST",
+            "/publish",
+            Some("secreto"),
+            r#"{"topic": "avisos/corte", "payload": "a las 10"}"#,
+        );
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        let (deleted, _) = admin_request("DELETE", "/retained/casa%2Fluz", Some("secreto"), "");
+        let (deleted_again, _) =
+            admin_request("DELETE", "/retained/casa%2Fluz", Some("secreto"), "");
+        let (kicked, _) = admin_request("DELETE", "/clients/inspeccionado", Some("secreto"), "");
+        let (disconnect_type, disconnect) = read_whole_packet(&mut stream);
+        let (_, sessions) = admin_request("GET", "/sessions", Some("secreto"), "");
+        let (purged, _) = admin_request("DELETE", "/sessions/inspeccionado", Some("secreto"), "");
+        let (_, sessions_after_purge) = admin_request("GET", "/sessions", Some("secreto"), "");
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(unauthorized, 401);
+        let clients: serde_json::Value = serde_json::from_str(&clients).unwrap();
+        let client = clients
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|client| client["client_id"] == "inspeccionado")
+            .unwrap();
+        assert_eq!(client["subscriptions"][0]["topic"], "avisos/#");
+        assert_eq!(client["persistent"], true);
+        assert!(retained.contains(r#""payload":"encendida""#));
+        assert_eq!(published, 204);
+        let publish = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION_5).unwrap();
+        assert_eq!(publish.topic_name, "avisos/corte");
+        assert_eq!(publish.payload, b"a las 10".to_vec());
+        assert_eq!((deleted, deleted_again), (204, 404));
+        assert_eq!(kicked, 204);
+        assert_eq!(disconnect_type, 0xE0);
+        assert_eq!(disconnect[0], 0x98);
+        assert!(sessions.contains(r#""client_id":"inspeccionado","connected":false"#));
+        assert_eq!(purged, 204);
+        assert!(!sessions_after_purge.contains("inspeccionado"));
+    }
+
+    /// Status and body of a request to the admin API of cfgai.txt.
+    fn admin_request(method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, String) {
+        let mut http = TcpStream::connect("127.0.0.1:1922").unwrap();
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            authorization,
+            body.len(),
+            body
+        );
+        http.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = match response.split_once("\r\n\r\n") {
+            Some((_, body)) => body.to_owned(),
+            None => String::new(),
+        };
+        (status, body)
+    }
+
+    #[test]
+    fn test_36_herramienta_mqtt_admin() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgaj.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        broker
+            .publish("casa/luz", b"encendida".to_vec(), 0, true)
+            .unwrap();
+        let mut stream = TcpStream::connect("127.0.0.1:1923").unwrap();
+        connect_mqtt5(&mut stream, "inspeccionado");
+        let admin = |args: &[&str]| {
+            let mut argv = vec!["--config", "src/testingConfigs/cfgaj.txt"];
+            argv.extend(args);
+            let argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+            admin_tool::run(&admin_tool::AdminOptions::parse(&argv, |_| None).unwrap())
+        };
+        //Act
+        let clients = admin(&["clients", "list"]).unwrap();
+        let clients_json = admin(&["--json", "clients", "list"]).unwrap();
+        let retained = admin(&["retained", "list", "casa/+"]).unwrap();
+        let wrong_token = admin(&["--token", "otro", "clients", "list"]);
+        let kicked = admin(&["clients", "kick", "inspeccionado"]);
+        let (disconnect_type, _) = read_whole_packet(&mut stream);
+        let not_found = admin(&["--json", "retained", "delete", "casa/agua"]);
+        let check = admin(&["config", "check"]);
+        broker.shutdown().unwrap();
+        //Assert
+        assert!(clients.starts_with("CLIENT ID "));
+        assert!(clients
+            .lines()
+            .any(|line| line.starts_with("inspeccionado ") && line.contains(" yes ")));
+        let clients_json: serde_json::Value = serde_json::from_str(&clients_json).unwrap();
+        assert!(clients_json
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|client| client["client_id"] == "inspeccionado"));
+        assert!(retained.contains("casa/luz  0    9     encendida"));
+        assert_eq!(
+            wrong_token,
+            Err("The admin API refused the token".to_owned())
+        );
+        assert_eq!(kicked, Ok("Disconnected client inspeccionado\n".to_owned()));
+        assert_eq!(disconnect_type, 0xE0);
+        assert_eq!(
+            not_found,
+            Err("No retained message on casa/agua".to_owned())
+        );
+        assert_eq!(check, Ok("Configuration OK\n".to_owned()));
+    }
+
+    #[test]
+    fn test_37_publish_sin_connect_cierra_la_conexion_y_no_se_entrega() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgak.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "franco".to_owned(),
+            will: None,
+            username: Some("franco".to_owned()),
+            password: Some(b"123pop".to_vec()),
+        };
+        let mut subscriber = TcpStream::connect("127.0.0.1:1925").unwrap();
+        subscriber.write_all(&connect.encode().unwrap()).unwrap();
+        let (connack_type, connack) = read_whole_packet(&mut subscriber);
+        let subscribe = Subscribe {
+            packet_identifier: 3,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "intruso/#".to_owned(),
+                qos: 0,
+            }],
+        };
+        subscriber
+            .write_all(&subscribe.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (suback_type, _) = read_whole_packet(&mut subscriber);
+        let publish = |payload: &[u8]| Publish {
+            dup: false,
+            qos: 0,
+            retain: false,
+            topic_name: "intruso/mensaje".to_owned(),
+            packet_identifier: None,
+            properties: Vec::new(),
+            payload: payload.to_vec(),
+        };
+        //Act
+        let mut intruder = TcpStream::connect("127.0.0.1:1925").unwrap();
+        intruder
+            .write_all(&publish(b"sin connect").encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        intruder
+            .set_read_timeout(Some(time::Duration::from_secs(2)))
+            .unwrap();
+        let mut byte = [0u8; 1];
+        let intruder_read = intruder.read(&mut byte);
+        subscriber
+            .write_all(&publish(b"autenticado").encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut subscriber);
+        //Assert
+        assert_eq!(connack_type, 0x20);
+        assert_eq!(connack, vec![0, 0]);
+        assert_eq!(suback_type, 0x90);
+        match intruder_read {
+            Ok(read) => assert_eq!(read, 0),
+            Err(error) => assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset),
+        }
+        let received = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION).unwrap();
+        assert_eq!(received.payload, b"autenticado".to_vec());
+    }
+
+    #[test]
+    fn test_38_conexion_sin_connect_se_cierra_al_vencer_el_plazo() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgal.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut silent = TcpStream::connect("127.0.0.1:1926").unwrap();
+        silent
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        let mut connected = TcpStream::connect("127.0.0.1:1926").unwrap();
+        let (connack_type, _) = connect_mqtt5(&mut connected, "puntual");
+        //Act
+        let start = time::Instant::now();
+        let mut byte = [0u8; 1];
+        let silent_read = silent.read(&mut byte);
+        let waited = start.elapsed();
+        connected.write_all(&[0xC0, 0]).unwrap();
+        let (pingresp_type, _) = read_whole_packet(&mut connected);
+        //Assert
+        match silent_read {
+            Ok(read) => assert_eq!(read, 0),
+            Err(error) => assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset),
+        }
+        assert!(waited < time::Duration::from_secs(4));
+        assert_eq!(connack_type, 0x20);
+        assert_eq!(pingresp_type, 0xD0);
+    }
+
+    #[test]
+    fn test_39_autenticacion_lenta_no_frena_a_los_demas_clientes() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgam.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let connect = |client_id: &str, username: &str| Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: client_id.to_owned(),
+            will: None,
+            username: Some(username.to_owned()),
+            password: Some(b"desde-comando".to_vec()),
+        };
+        let mut connected = TcpStream::connect("127.0.0.1:1927").unwrap();
+        connected
+            .write_all(&connect("conectado", "franco").encode().unwrap())
+            .unwrap();
+        let (connack_type, _) = read_whole_packet(&mut connected);
+        //Act
+        let start = time::Instant::now();
+        let mut slow = TcpStream::connect("127.0.0.1:1927").unwrap();
+        slow.write_all(&connect("lento", "lento").encode().unwrap())
+            .unwrap();
+        thread::sleep(time::Duration::from_millis(200)); //Let the slow check start
+        connected.write_all(&[0xC0, 0]).unwrap();
+        let (pingresp_type, _) = read_whole_packet(&mut connected);
+        let mut other = TcpStream::connect("127.0.0.1:1927").unwrap();
+        other
+            .write_all(&connect("otro", "emanuel").encode().unwrap())
+            .unwrap();
+        let (other_type, other_buffer) = read_whole_packet(&mut other);
+        let served = start.elapsed();
+        let (slow_type, slow_buffer) = read_whole_packet(&mut slow);
+        //Assert
+        assert_eq!(connack_type, 0x20);
+        assert_eq!(pingresp_type, 0xD0);
+        assert_eq!((other_type, other_buffer), (0x20, vec![0, 0]));
+        assert!(served < time::Duration::from_secs(2));
+        assert_eq!((slow_type, slow_buffer), (0x20, vec![0, 0]));
+        assert!(start.elapsed() >= time::Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_40_rafaga_de_logins_no_frena_a_los_clientes_conectados() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgan.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let connect = |client_id: String| Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id,
+            will: None,
+            username: Some("franco".to_owned()),
+            password: Some(b"123pop".to_vec()),
+        };
+        let mut connected = TcpStream::connect("127.0.0.1:1928").unwrap();
+        connected
+            .write_all(&connect("conectado".to_owned()).encode().unwrap())
+            .unwrap();
+        let (connack_type, _) = read_whole_packet(&mut connected);
+        let mut burst: Vec<TcpStream> = (0..30)
+            .map(|_| TcpStream::connect("127.0.0.1:1928").unwrap())
+            .collect();
+        //Act
+        let start = time::Instant::now();
+        for (index, stream) in burst.iter_mut().enumerate() {
+            stream
+                .write_all(&connect(format!("rafaga-{}", index)).encode().unwrap())
+                .unwrap();
+        }
+        connected.write_all(&[0xC0, 0]).unwrap();
+        let (pingresp_type, _) = read_whole_packet(&mut connected);
+        let ping_answered = start.elapsed();
+        let connacks: Vec<(u8, Vec<u8>)> = burst.iter_mut().map(read_whole_packet).collect();
+        let logged_in = start.elapsed();
+        //Assert
+        assert_eq!(connack_type, 0x20);
+        assert_eq!(pingresp_type, 0xD0);
+        assert!(connacks
+            .iter()
+            .all(|connack| *connack == (0x20, vec![0, 0])));
+        assert!(ping_answered < logged_in / 2);
+    }
+
+    /// TLS client trusting `server_certificate`, optionally authenticating with a certificate
+    /// and its key.
+    fn connect_tls(
+        address: &str,
+        server_certificate: &rustls::pki_types::CertificateDer<'static>,
+        client_identity: Option<(
+            rustls::pki_types::CertificateDer<'static>,
+            rustls::pki_types::PrivateKeyDer<'static>,
+        )>,
+    ) -> rustls::StreamOwned<rustls::ClientConnection, TcpStream> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(server_certificate.clone()).unwrap();
+        let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+        let config = match client_identity {
+            Some((certificate, key)) => builder
+                .with_client_auth_cert(vec![certificate], key)
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        let tls = rustls::ClientConnection::new(
+            Arc::new(config),
+            ServerName::try_from("localhost").unwrap(),
+        )
+        .unwrap();
+        rustls::StreamOwned::new(tls, TcpStream::connect(address).unwrap())
+    }
+
+    fn connect_mqtt31(stream: &mut TcpStream, client_id: &str) -> (u8, Vec<u8>) {
+        let mut variable: Vec<u8> = vec![0, 6, 77, 81, 73, 115, 100, 112]; // MQIsdp
+        variable.push(3); // Protocol Level
+        variable.push(2); // Clean session
+        variable.push(0);
+        variable.push(60);
+        variable.push(0);
+        variable.push(client_id.len() as u8);
+        variable.append(&mut client_id.as_bytes().to_vec());
+        let mut buffer: Vec<u8> = vec![0x10];
+        buffer.append(&mut remaining_length_encode(variable.len()));
+        buffer.append(&mut variable);
+        stream.write_all(&buffer).unwrap();
+        read_whole_packet(stream)
+    }
+
+    fn connect_mqtt5(stream: &mut TcpStream, client_id: &str) -> (u8, Vec<u8>) {
+        let properties = encode_properties(&[Property::new(
+            SESSION_EXPIRY_INTERVAL,
+            PropertyValue::FourByteInteger(60),
+        )])
+        .unwrap();
+        let mut buffer: Vec<u8> = vec![0x10, (12 + properties.len() + client_id.len()) as u8];
+        buffer.append(&mut vec![0, 4, 77, 81, 84, 84]); // MQTT
+        buffer.push(5); // Protocol Level
+        buffer.push(2); // Connect flags
+        buffer.push(0);
+        buffer.push(100);
+        buffer.append(&mut properties.clone());
+        buffer.push(0);
+        buffer.push(client_id.len() as u8);
+        buffer.append(&mut client_id.as_bytes().to_vec());
+        stream.write_all(&buffer).unwrap();
+        read_whole_packet(stream)
+    }
+
+    /// Hook que descarta los mensajes con payload "descartar".
+    struct DescartarHook;
+
+    impl MessageHook for DescartarHook {
+        fn on_publish(&mut self, _client: &Client, publish: &mut Publish) -> PublishAction {
+            if publish.payload == b"descartar" {
+                PublishAction::Drop
+            } else {
+                PublishAction::Deliver
+            }
+        }
+    }
+
+    #[test]
+    fn test_41_client_id_reservado_y_publish_del_broker_rechazado_devuelve_error() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgao.txt").unwrap();
+        let broker = Broker::builder(config)
+            .hook(Box::new(DescartarHook))
+            .start()
+            .unwrap();
+        let mut stream = TcpStream::connect("127.0.0.1:1929").unwrap();
+        //Act
+        let (package_type, buffer_paquete) = connect_mqtt31(&mut stream, "$local-publisher");
+        let denegado = broker.publish("anuncios/corte", b"22".to_vec(), 1, false);
+        let descartado = broker.publish(
+            "sensores/$local-publisher/temperatura",
+            b"descartar".to_vec(),
+            2,
+            false,
+        );
+        let entregado = broker.publish(
+            "sensores/$local-publisher/temperatura",
+            b"22".to_vec(),
+            2,
+            false,
+        );
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 2]); // Identifier rejected
+        assert_eq!(denegado.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(descartado.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert!(entregado.is_ok());
+    }
+
+    #[test]
+    fn test_42_publish_de_clientes_a_topicos_con_dolar_se_rechaza() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgap.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut subscriber = TcpStream::connect("127.0.0.1:1930").unwrap();
+        let (package_type, _) = connect_mqtt31(&mut subscriber, "observador");
+        assert_eq!(package_type, 0x20);
+        let subscribe = Subscribe {
+            packet_identifier: 8,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "$SYS/falso".to_owned(),
+                qos: 1,
+            }],
+        };
+        subscriber
+            .write_all(&subscribe.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (package_type, _) = read_whole_packet(&mut subscriber);
+        assert_eq!(package_type, 0x90);
+        let publish = Publish {
+            dup: false,
+            qos: 1,
+            retain: true,
+            topic_name: "$SYS/falso".to_owned(),
+            packet_identifier: Some(5),
+            properties: Vec::new(),
+            payload: b"0".to_vec(),
+        };
+        let mut stream_v5 = TcpStream::connect("127.0.0.1:1930").unwrap();
+        let (package_type, _) = connect_mqtt5(&mut stream_v5, "sensor5");
+        assert_eq!(package_type, 0x20);
+        let mut stream_v3 = TcpStream::connect("127.0.0.1:1930").unwrap();
+        let (package_type, _) = connect_mqtt31(&mut stream_v3, "sensor3");
+        assert_eq!(package_type, 0x20);
+        //Act
+        stream_v5
+            .write_all(&publish.encode(MQTT_VERSION_5).unwrap())
+            .unwrap();
+        let (puback_v5, buffer_v5) = read_whole_packet(&mut stream_v5);
+        stream_v3
+            .write_all(&publish.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (puback_v3, buffer_v3) = read_whole_packet(&mut stream_v3);
+        //Assert
+        assert_eq!(puback_v5, 0x40);
+        assert_eq!(buffer_v5[..3], [0, 5, 0x87]); // Not authorized
+        assert_eq!(puback_v3, 0x40);
+        assert_eq!(buffer_v3, vec![0, 5]);
+        subscriber
+            .set_read_timeout(Some(time::Duration::from_millis(500)))
+            .unwrap();
+        assert!(subscriber.read(&mut [0u8; 1]).is_err());
+    }
+
+    #[test]
+    fn test_43_publish_denegado_por_la_acl_se_confirma_con_codigo_de_error() {
+        //Arrange
+        thread::spawn(move || {
+            let server = Server::new("src/testingConfigs/cfgaq.txt");
+            server.run().unwrap();
+        });
+        thread::sleep(time::Duration::from_millis(20)); //Wait for server to start
+        let mut stream = TcpStream::connect("127.0.0.1:1931").unwrap();
+        let (package_type, _) = connect_mqtt5(&mut stream, "sensor5");
+        assert_eq!(package_type, 0x20);
+        let subscribe = Subscribe {
+            packet_identifier: 4,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "sensores/sensor5/#".to_owned(),
+                qos: 0,
+            }],
+        };
+        stream
+            .write_all(&subscribe.encode(MQTT_VERSION_5).unwrap())
+            .unwrap();
+        let (package_type, _) = read_whole_packet(&mut stream);
+        assert_eq!(package_type, 0x90);
+        let publish = |qos: u8, packet_identifier: u16, topic_name: &str| Publish {
+            dup: false,
+            qos,
+            retain: false,
+            topic_name: topic_name.to_owned(),
+            packet_identifier: Some(packet_identifier),
+            properties: Vec::new(),
+            payload: b"22".to_vec(),
+        };
+        //Act
+        stream
+            .write_all(
+                &publish(1, 5, "anuncios/corte")
+                    .encode(MQTT_VERSION_5)
+                    .unwrap(),
+            )
+            .unwrap();
+        let (puback, buffer_puback) = read_whole_packet(&mut stream);
+        stream
+            .write_all(
+                &publish(2, 6, "anuncios/corte")
+                    .encode(MQTT_VERSION_5)
+                    .unwrap(),
+            )
+            .unwrap();
+        let (pubrec, buffer_pubrec) = read_whole_packet(&mut stream);
+        stream
+            .write_all(
+                &publish(2, 6, "sensores/sensor5/temperatura")
+                    .encode(MQTT_VERSION_5)
+                    .unwrap(),
+            )
+            .unwrap();
+        let (pubrec_permitido, buffer_pubrec_permitido) = read_whole_packet(&mut stream);
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        //Assert
+        assert_eq!(puback, 0x40);
+        assert_eq!(buffer_puback[..3], [0, 5, 0x87]); // Not authorized
+        assert_eq!(pubrec, 0x50);
+        assert_eq!(buffer_pubrec[..3], [0, 6, 0x87]);
+        assert_eq!(pubrec_permitido, 0x50);
+        assert_eq!(buffer_pubrec_permitido, vec![0, 6]);
+        let publish = Publish::decode(package_type, &buffer_paquete, MQTT_VERSION_5).unwrap();
+        assert_eq!(publish.topic_name, "sensores/sensor5/temperatura");
+    }
+
+    #[test]
+    fn test_44_clientes_con_sesion_limpia_no_quedan_en_el_coordinador() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgar.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        let iniciales = broker.client_count();
+        //Act
+        for i in 0..10 {
+            let mut stream = TcpStream::connect("127.0.0.1:1932").unwrap();
+            let (package_type, _) = connect_mqtt31(&mut stream, &format!("pasajero{}", i));
+            assert_eq!(package_type, 0x20);
+            if i % 2 == 0 {
+                stream.write_all(&[0xE0, 0]).unwrap(); // Disconnect
+            }
+        }
+        for _ in 0..10 {
+            TcpStream::connect("127.0.0.1:1932").unwrap();
+        }
+        drop(broker.subscribe("pasajeros/#").unwrap());
+        let deadline = time::Instant::now() + time::Duration::from_secs(3);
+        while broker.client_count() != iniciales && time::Instant::now() < deadline {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        let finales = broker.client_count();
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(finales, iniciales);
+    }
+
+    #[test]
+    fn test_45_reconexion_mqtt311_con_sesion_limpia_descarta_la_sesion_anterior() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgas.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        let connect = |clean_session: bool| Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "persistente".to_owned(),
+            will: None,
+            username: None,
+            password: None,
+        };
+        let mut stream = TcpStream::connect("127.0.0.1:1933").unwrap();
+        stream.write_all(&connect(false).encode().unwrap()).unwrap();
+        let (connack_type, _) = read_whole_packet(&mut stream);
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            properties: Vec::new(),
+            topics: vec![SubscribeTopic {
+                topic_filter: "avisos/#".to_owned(),
+                qos: 1,
+            }],
+        };
+        stream
+            .write_all(&subscribe.encode(MQTT_VERSION).unwrap())
+            .unwrap();
+        let (suback_type, _) = read_whole_packet(&mut stream);
+        stream.write_all(&[0xE0, 0]).unwrap(); // Disconnect
+        thread::sleep(time::Duration::from_millis(100));
+        broker
+            .publish("avisos/corte", b"corte manana".to_vec(), 1, false)
+            .unwrap();
+        //Act
+        let mut stream = TcpStream::connect("127.0.0.1:1933").unwrap();
+        stream.write_all(&connect(true).encode().unwrap()).unwrap();
+        let (reconnack_type, reconnack) = read_whole_packet(&mut stream);
+        stream
+            .set_read_timeout(Some(time::Duration::from_millis(500)))
+            .unwrap();
+        let mut byte = [0u8; 1];
+        let backlog = stream.read(&mut byte);
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(connack_type, 0x20);
+        assert_eq!(suback_type, 0x90);
+        assert_eq!(reconnack_type, 0x20);
+        assert_eq!(reconnack, vec![0, 0]); // Session present 0
+        assert!(backlog.is_err());
+    }
+
+    #[test]
+    fn test_46_comando_de_autenticacion_recibe_los_argumentos_de_la_configuracion() {
+        //Arrange
+        let mut config = Configuration::new();
+        config.set_config("src/testingConfigs/cfgat.txt").unwrap();
+        let broker = Broker::builder(config).start().unwrap();
+        let mut connect = Connect {
+            protocol_version: MQTT_VERSION,
+            clean_session: true,
+            keep_alive: 100,
+            properties: Vec::new(),
+            client_id: "franco".to_owned(),
+            will: None,
+            username: Some("franco".to_owned()),
+            password: Some(b"desde argumentos".to_vec()),
+        };
+        //Act
+        let mut stream = TcpStream::connect("127.0.0.1:1934").unwrap();
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+        let (package_type, buffer_paquete) = read_whole_packet(&mut stream);
+        connect.client_id = "otro".to_owned();
+        connect.password = Some(b"desde-comando".to_vec());
+        let mut rejected = TcpStream::connect("127.0.0.1:1934").unwrap();
+        rejected.write_all(&connect.encode().unwrap()).unwrap();
+        let (rejected_type, rejected_buffer) = read_whole_packet(&mut rejected);
+        broker.shutdown().unwrap();
+        //Assert
+        assert_eq!(package_type, 0x20);
+        assert_eq!(buffer_paquete, vec![0, 0]);
+        assert_eq!(rejected_type, 0x20);
+        assert_eq!(rejected_buffer, vec![0, 4]);
+    }
+
+    /// Convert bytes to UTF-8 string.
+    fn bytes2string(bytes: &[u8]) -> String {
+        match std::str::from_utf8(bytes) {
+            Ok(str) => str.to_owned(),
+            Err(_) => "".to_owned(),
+        }
+    }
+
+    fn read_whole_packet<R: Read>(stream: &mut R) -> (u8, Vec<u8>) {
+        let mut num_buffer = [0u8; 1];
+        stream.read_exact(&mut num_buffer).unwrap();
+        let buff_size = remaining_length_read(stream).unwrap();
+        let mut buffer_paquete: Vec<u8> = vec![0; buff_size];
+        stream.read_exact(&mut buffer_paquete).unwrap();
+        (num_buffer[0], buffer_paquete)
+    }
+
+    fn connect_and_assert_connection(stream: &mut TcpStream, clean_session: bool) {
+        let mut buffer: Vec<u8> = Vec::with_capacity(16);
+        buffer.push(0x10); //Connect packet
+        buffer.push(14); //Hardcoded length
+        buffer.push(0);
+        buffer.push(4);
+        buffer.push(77); // M
+        buffer.push(81); // Q
+        buffer.push(84); // T
+        buffer.push(84); // T
+        buffer.push(4); // Protocol Level
+        if clean_session {
+            buffer.push(2); // Connect flags
+        } else {
+            buffer.push(0);
+        }
+        buffer.push(0);
+        buffer.push(200);
+        buffer.push(0);
+        buffer.push(2);
+        let client_id = "23".to_owned();
+        let client_id_bytes = client_id.as_bytes();
+        for byte in client_id_bytes.iter() {
+            buffer.push(*byte);
+        }
+        stream.write_all(&buffer).unwrap();
+        let mut can_go_on = false;
+        //Assert connect exitoso
+        while !can_go_on {
+            let mut num_buffer = [0u8; 2]; //Recibimos 2 bytes
+            match stream.read_exact(&mut num_buffer) {
+                Ok(_) => {
+                    let package_type = num_buffer[0];
+                    assert_eq!(package_type, 0x20);
+                    let mut buffer_paquete: Vec<u8> = vec![0; num_buffer[1] as usize];
+                    stream.read_exact(&mut buffer_paquete).unwrap();
+                    let return_code = buffer_paquete[1];
+                    assert_eq!(return_code, 0);
+                    can_go_on = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! # Logging
+//!
+//! Subscriber that writes the events of the server to the log file of the configuration, as
+//! plain text or one JSON object per line, rotating the file and deleting the oldest ones.
+//!
+//! Events of a client connection carry the `connection` span, with its peer address and client
+//! id, and the ones of the coordinator the `coordinator` span and, while it handles a packet,
+//! the `client` span of its sender.
+
+use crate::configuration::{LogFormat, LogRotation, LogSettings};
+use std::path::Path;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::EnvFilter;
+
+/// Log the events of the whole process as `settings` say. The returned guard writes the
+/// pending events when dropped, so it has to live until the process ends.
+///
+/// # Errors
+///
+/// Returns Error if the log file couldn't be created or a subscriber was already set.
+pub fn init(settings: &LogSettings) -> Result<WorkerGuard, String> {
+    let path = Path::new(&settings.file);
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().into_owned(),
+        None => return Err(format!("Invalid log file {}", settings.file)),
+    };
+    let rotation = match settings.rotation {
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+    };
+    let mut appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name);
+    if let Some(max_files) = settings.max_files {
+        appender = appender.max_log_files(max_files);
+    }
+    let appender = appender
+        .build(directory)
+        .map_err(|error| format!("Couldn't open the log file {}: {}", settings.file, error))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let filter =
+        EnvFilter::try_new(filter_directives(settings)).map_err(|error| error.to_string())?;
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_env_filter(filter)
+        .with_ansi(false);
+    let result = match settings.format {
+        LogFormat::Text => subscriber.try_init(),
+        LogFormat::Json => subscriber.json().try_init(),
+    };
+    result.map_err(|error| error.to_string())?;
+    Ok(guard)
+}
+
+/// Filter with the global level followed by the level of each module.
+fn filter_directives(settings: &LogSettings) -> String {
+    let mut directives = settings.level.clone();
+    for (module, level) in settings.modules.iter() {
+        directives.push_str(&format!(",{}={}", module, level));
+    }
+    directives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_nivel_global_y_por_modulo() {
+        let settings = LogSettings {
+            file: "file.log".to_owned(),
+            level: "warn".to_owned(),
+            modules: vec![
+                ("server::network".to_owned(), "debug".to_owned()),
+                ("rustls".to_owned(), "off".to_owned()),
+            ],
+            rotation: LogRotation::Daily,
+            max_files: Some(7),
+            format: LogFormat::Json,
+        };
+        assert_eq!(
+            filter_directives(&settings),
+            "warn,server::network=debug,rustls=off"
+        );
+        assert!(EnvFilter::try_new(filter_directives(&settings)).is_ok());
+    }
+}
@@ -0,0 +1,367 @@
+//! # Metrics
+//!
+//! Counters and gauges of a broker, and a small HTTP server that serves them at `/metrics` in
+//! the Prometheus text format.
+//!
+//! The event loop counts the connections, and the packets and bytes read from and written to
+//! them. The coordinator times the routing of each publish and keeps the count of retained
+//! messages, and the stacked messages coordinator counts the retransmissions and, on each pass,
+//! the connected clients, persistent sessions and messages awaiting acknowledgement.
+
+use crate::http::{HttpServer, Request, Response};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const PACKET_TYPES: [&str; 16] = [
+    "reserved",
+    "connect",
+    "connack",
+    "publish",
+    "puback",
+    "pubrec",
+    "pubrel",
+    "pubcomp",
+    "subscribe",
+    "suback",
+    "unsubscribe",
+    "unsuback",
+    "pingreq",
+    "pingresp",
+    "disconnect",
+    "auth",
+];
+/// Index of _Publish_ in [`PACKET_TYPES`].
+const PUBLISH: usize = 3;
+/// Upper bounds, in microseconds, of the buckets of the routing latency histogram.
+const LATENCY_BUCKETS: [u64; 10] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000, 250_000,
+];
+const METRICS_PATH: &str = "/metrics";
+
+/// Distribution of durations, counted in the buckets of [`LATENCY_BUCKETS`].
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            if micros <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Publish packets and bytes read from and written to the clients since the broker started.
+pub struct Totals {
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+/// Numbers of a broker, shared by its threads.
+#[derive(Default)]
+pub struct Metrics {
+    connections_accepted: AtomicU64,
+    connections_refused: AtomicU64,
+    packets_received: [AtomicU64; 16],
+    packets_sent: [AtomicU64; 16],
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    retransmissions: AtomicU64,
+    connected_clients: AtomicU64,
+    persistent_sessions: AtomicU64,
+    retained_messages: AtomicU64,
+    queued_messages: AtomicU64,
+    routing_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn connection_accepted(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection closed as soon as it was accepted, because of the connection limit.
+    pub fn connection_refused(&self) {
+        self.connections_refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A packet starting with `byte_0` read from a client.
+    pub fn packet_received(&self, byte_0: u8) {
+        self.packets_received[usize::from(byte_0 >> 4)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Bytes read from a client, complete packets or not.
+    pub fn bytes_received(&self, size: usize) {
+        self.bytes_received
+            .fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    /// Count the packets of `bytes`, which are complete packets queued for a client.
+    pub fn packets_sent(&self, bytes: &[u8]) {
+        let mut start: usize = 0;
+        while start < bytes.len() {
+            let (remaining_length, length_size) =
+                match codec::length::variable_byte_integer_decode(&bytes[(start + 1)..]) {
+                    Ok(length) => length,
+                    Err(_) => break,
+                };
+            self.packets_sent[usize::from(bytes[start] >> 4)].fetch_add(1, Ordering::Relaxed);
+            start += 1 + length_size + remaining_length;
+        }
+        self.bytes_sent
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn retransmitted(&self, count: usize) {
+        self.retransmissions
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Time from the coordinator receiving a publish to queuing it for every subscriber.
+    pub fn routed(&self, duration: Duration) {
+        self.routing_latency.observe(duration);
+    }
+
+    pub fn set_retained_messages(&self, count: usize) {
+        self.retained_messages
+            .store(count as u64, Ordering::Relaxed);
+    }
+
+    /// Clients connected, sessions kept after their client disconnects, and QoS 1 and 2
+    /// messages awaiting acknowledgement.
+    pub fn set_sessions(&self, connected: usize, persistent: usize, queued: usize) {
+        self.connected_clients
+            .store(connected as u64, Ordering::Relaxed);
+        self.persistent_sessions
+            .store(persistent as u64, Ordering::Relaxed);
+        self.queued_messages.store(queued as u64, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> Totals {
+        Totals {
+            messages_received: self.packets_received[PUBLISH].load(Ordering::Relaxed),
+            messages_sent: self.packets_sent[PUBLISH].load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &AtomicU64| {
+            text.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n{} {}\n",
+                name,
+                help,
+                name,
+                kind,
+                name,
+                value.load(Ordering::Relaxed)
+            ));
+        };
+        metric(
+            "mqtt_connections_accepted_total",
+            "counter",
+            "Connections accepted by the listeners.",
+            &self.connections_accepted,
+        );
+        metric(
+            "mqtt_connections_refused_total",
+            "counter",
+            "Connections closed because of the connection limit.",
+            &self.connections_refused,
+        );
+        metric(
+            "mqtt_bytes_received_total",
+            "counter",
+            "Bytes of the packets read from the clients.",
+            &self.bytes_received,
+        );
+        metric(
+            "mqtt_bytes_sent_total",
+            "counter",
+            "Bytes of the packets written to the clients.",
+            &self.bytes_sent,
+        );
+        metric(
+            "mqtt_retransmissions_total",
+            "counter",
+            "Publish and Pubrel packets sent again while awaiting acknowledgement.",
+            &self.retransmissions,
+        );
+        metric(
+            "mqtt_connected_clients",
+            "gauge",
+            "Clients connected.",
+            &self.connected_clients,
+        );
+        metric(
+            "mqtt_persistent_sessions",
+            "gauge",
+            "Sessions kept while their client is disconnected.",
+            &self.persistent_sessions,
+        );
+        metric(
+            "mqtt_retained_messages",
+            "gauge",
+            "Retained messages.",
+            &self.retained_messages,
+        );
+        metric(
+            "mqtt_queued_messages",
+            "gauge",
+            "QoS 1 and 2 messages awaiting acknowledgement.",
+            &self.queued_messages,
+        );
+        for (name, help, packets) in [
+            (
+                "mqtt_packets_received_total",
+                "Packets read from the clients, by type.",
+                &self.packets_received,
+            ),
+            (
+                "mqtt_packets_sent_total",
+                "Packets written to the clients, by type.",
+                &self.packets_sent,
+            ),
+        ] {
+            text.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} counter\n",
+                name, help, name
+            ));
+            for (packet_type, count) in PACKET_TYPES.iter().zip(packets.iter()).skip(1) {
+                text.push_str(&format!(
+                    "{}{{type=\"{}\"}} {}\n",
+                    name,
+                    packet_type,
+                    count.load(Ordering::Relaxed)
+                ));
+            }
+        }
+        let latency = &self.routing_latency;
+        let name = "mqtt_routing_latency_seconds";
+        text.push_str(&format!(
+            "# HELP {} Time to queue a publish for its subscribers.\n# TYPE {} histogram\n",
+            name, name
+        ));
+        for (bucket, bound) in latency.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            text.push_str(&format!(
+                "{}_bucket{{le=\"{}\"}} {}\n",
+                name,
+                *bound as f64 / 1_000_000.0,
+                bucket.load(Ordering::Relaxed)
+            ));
+        }
+        let count = latency.count.load(Ordering::Relaxed);
+        text.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, count));
+        text.push_str(&format!(
+            "{}_sum {}\n{}_count {}\n",
+            name,
+            latency.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+            name,
+            count
+        ));
+        text
+    }
+}
+
+/// HTTP server of the metrics, on its own thread.
+pub struct MetricsServer {
+    server: HttpServer,
+}
+
+impl MetricsServer {
+    /// Serve `metrics` on `address`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the address couldn't be bound or the thread couldn't be spawned.
+    pub fn start(address: &str, metrics: Arc<Metrics>) -> std::io::Result<Self> {
+        let server =
+            HttpServer::start("Metrics", address, move |request| answer(request, &metrics))?;
+        Ok(MetricsServer { server })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.server.address()
+    }
+
+    /// Stop accepting requests and wait for the thread to finish.
+    pub fn stop(&mut self) {
+        self.server.stop();
+    }
+}
+
+/// Answer a GET of the metrics path with the metrics, and anything else with not found.
+fn answer(request: &Request, metrics: &Metrics) -> Response {
+    if request.method == "GET" && request.path == METRICS_PATH {
+        Response::new(
+            200,
+            "text/plain; version=0.0.4",
+            metrics.render().into_bytes(),
+        )
+    } else {
+        Response::empty(404)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn test01_contadores_e_histograma_en_formato_prometheus() {
+        let metrics = Metrics::new();
+        metrics.packet_received(0x10);
+        metrics.packets_sent(&[0x20, 2, 0, 0, 0x90, 3, 0, 1, 0]);
+        metrics.routed(Duration::from_micros(80));
+        metrics.routed(Duration::from_millis(3));
+        let text = metrics.render();
+        assert!(text.contains("mqtt_packets_received_total{type=\"connect\"} 1\n"));
+        assert!(text.contains("mqtt_packets_sent_total{type=\"connack\"} 1\n"));
+        assert!(text.contains("mqtt_packets_sent_total{type=\"suback\"} 1\n"));
+        assert!(text.contains("mqtt_bytes_sent_total 9\n"));
+        assert!(text.contains("mqtt_routing_latency_seconds_bucket{le=\"0.0001\"} 1\n"));
+        assert!(text.contains("mqtt_routing_latency_seconds_bucket{le=\"0.005\"} 2\n"));
+        assert!(text.contains("mqtt_routing_latency_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn test02_servidor_http() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.connection_accepted();
+        let mut server = MetricsServer::start("127.0.0.1:0", metrics).unwrap();
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut not_found = String::new();
+        stream.read_to_string(&mut not_found).unwrap();
+        server.stop();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nmqtt_connections_accepted_total 1\n"));
+        assert!(not_found.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,31 @@
+use server::admin_tool::{failed, run, AdminOptions, USAGE};
+use std::env::{args, var};
+
+fn main() -> Result<(), ()> {
+    let argv = args().collect::<Vec<String>>();
+    let options = match AdminOptions::parse(&argv[1..], |name| var(name).ok()) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return Err(());
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    match run(&options) {
+        Ok(output) => {
+            print!("{}", output);
+            Ok(())
+        }
+        Err(error) if options.json => {
+            print!("{}", failed(&options, &error));
+            Err(())
+        }
+        Err(error) => {
+            eprint!("{}", failed(&options, &error));
+            Err(())
+        }
+    }
+}
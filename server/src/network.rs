@@ -0,0 +1,915 @@
+//! # Network
+//!
+//! Event loop that handles every client connection on a single thread: accepts new connections,
+//! reads and processes their packets, writes the bytes queued for them and closes the ones whose
+//! keep alive expired or that didn't send their _Connect_ in time.
+//!
+//! The coordinator queues bytes for a connection through its [`ClientChannel`], which wakes up
+//! the event loop. An [`EventLoopHandle`] makes the event loop refuse new clients, or stop
+//! listening, close every connection and return.
+//!
+//! The [`Limits`] of the configuration cap the open connections, the size of the packets the
+//! clients send and how long they have to send their _Connect_.
+//!
+//! Each listener accepts either plain TCP or TLS connections. The TLS of a connection is handled
+//! with a non-blocking `rustls` session between the socket and the packet buffers.
+//!
+//! Credentials are checked by the [`AuthProvider`] on worker threads, since it may take a while.
+//! Meanwhile the connection isn't read, and the packets it already sent wait for the result.
+
+use crate::auth::AuthProvider;
+use crate::client::Client;
+use crate::configuration::Limits;
+use crate::coordinator::{ClientCommand, CoordinatorCommand};
+use crate::metrics::Metrics;
+use crate::packet::{
+    finish_authentication, handle_packet, inform_coordinator, send_disconnect, Authentication,
+    REASON_KEEP_ALIVE_TIMEOUT, REASON_PACKET_TOO_LARGE, REASON_SERVER_SHUTTING_DOWN,
+};
+use crate::server::ClientFlags;
+use crate::tls::peer_identity;
+use codec::length::variable_byte_integer_decode;
+use codec::{CodecError, Disconnect, MQTT_VERSION_5};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use rustls::{ServerConfig, ServerConnection};
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span, warn, Span};
+
+const WAKER: Token = Token(0);
+/// Listeners use the tokens after the waker, up to this many.
+const MAX_LISTENERS: usize = 16;
+const FIRST_CONNECTION_ID: usize = 1 + MAX_LISTENERS;
+const EVENTS_CAPACITY: usize = 1024;
+const READ_CHUNK_SIZE: usize = 4096;
+/// Marker sent through a [`ClientChannel`] to close the connection.
+pub const CLOSE_CONNECTION: u8 = 255;
+/// How often the keep alive of the connections is checked.
+const KEEP_ALIVE_CHECK: Duration = Duration::from_secs(1);
+/// Fewest threads checking credentials, so a slow check doesn't hold up the other logins. There
+/// is one per core if there are more cores, since hashing passwords keeps them busy.
+const MIN_AUTH_THREADS: usize = 4;
+
+/// Credentials of a connection to check.
+type AuthRequest = (usize, Authentication);
+/// Credentials of a connection and whether they were accepted.
+type AuthResult = (usize, Authentication, bool);
+
+/// Channel to queue bytes for a client connection. Every send wakes up the event loop.
+#[derive(Clone)]
+pub struct ClientChannel {
+    id: usize,
+    sender: Sender<(usize, Vec<u8>)>,
+    waker: Arc<Waker>,
+}
+
+impl ClientChannel {
+    pub fn new(id: usize, sender: Sender<(usize, Vec<u8>)>, waker: Arc<Waker>) -> Self {
+        ClientChannel { id, sender, waker }
+    }
+
+    /// Queue `buffer` to be written to the connection. `[CLOSE_CONNECTION]` closes it once
+    /// everything queued before was written.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the event loop is no longer running.
+    pub fn send(&self, buffer: Vec<u8>) -> Result<(), String> {
+        if self.sender.send((self.id, buffer)).is_err() {
+            return Err("Event loop channel closed".to_owned());
+        }
+        match self.waker.wake() {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Error waking up the event loop".to_owned()),
+        }
+    }
+}
+
+/// Handle to control an [`EventLoop`] from another thread.
+#[derive(Clone)]
+pub struct EventLoopHandle {
+    stopping: Arc<AtomicBool>,
+    draining: Arc<AtomicBool>,
+    waker: Arc<Waker>,
+}
+
+impl EventLoopHandle {
+    /// Ask the event loop to stop listening, close its connections and return.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if self.waker.wake().is_err() {
+            warn!("Error waking up the event loop to stop it.");
+        }
+    }
+
+    /// While draining, the event loop refuses every new _Connect_ but keeps serving the clients
+    /// already connected.
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::SeqCst);
+    }
+}
+
+/// Address to listen on, the TLS configuration of the connections if it is a MQTTS listener, and
+/// whether its clients must log in with a username and password.
+pub struct ListenerConfig {
+    pub address: String,
+    pub tls: Option<Arc<ServerConfig>>,
+    pub password_required: bool,
+}
+
+struct Listener {
+    socket: TcpListener,
+    tls: Option<Arc<ServerConfig>>,
+    password_required: bool,
+}
+
+/// A client connection, the bytes read from it that don't make a complete packet yet and the
+/// packets waiting while its credentials are checked. Its events are logged within `span`, with
+/// the peer address and the client id.
+struct Connection {
+    accepted: Instant,
+    stream: TcpStream,
+    span: Span,
+    tls: Option<ServerConnection>,
+    password_required: bool,
+    flags: ClientFlags,
+    read_buffer: Vec<u8>,
+    packets: VecDeque<(u8, Vec<u8>)>,
+    authenticating: bool,
+    last_activity: Instant,
+}
+
+/// Non-blocking connection layer of the server.
+pub struct EventLoop {
+    poll: Poll,
+    listeners: Vec<Listener>,
+    waker: Arc<Waker>,
+    connections: HashMap<usize, Connection>,
+    outgoing_sender: Sender<(usize, Vec<u8>)>,
+    outgoing_receiver: Receiver<(usize, Vec<u8>)>,
+    lock_clients: Arc<Mutex<HashMap<usize, Client>>>,
+    coordinator_sender: Arc<Mutex<Sender<ClientCommand>>>,
+    auth_requests: Sender<AuthRequest>,
+    auth_results: Receiver<AuthResult>,
+    wills_on_shutdown: bool,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+    stopping: Arc<AtomicBool>,
+    draining: Arc<AtomicBool>,
+    next_id: usize,
+}
+
+impl EventLoop {
+    /// Bind a listener to the address of each of `listeners`, and start the threads checking
+    /// credentials with `auth`. `wills_on_shutdown` tells whether the clients closed when the
+    /// event loop stops publish their last will, and `limits` caps the connections and their
+    /// packets. The connections and their traffic are counted in `metrics`.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if there are no listeners or too many, an address is invalid or couldn't be
+    /// bound, or a thread couldn't be spawned.
+    pub fn new(
+        listeners: Vec<ListenerConfig>,
+        lock_clients: Arc<Mutex<HashMap<usize, Client>>>,
+        coordinator_sender: Arc<Mutex<Sender<ClientCommand>>>,
+        auth: Arc<dyn AuthProvider>,
+        wills_on_shutdown: bool,
+        limits: Limits,
+        metrics: Arc<Metrics>,
+    ) -> std::io::Result<Self> {
+        if listeners.is_empty() || listeners.len() > MAX_LISTENERS {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("The server needs between 1 and {} listeners", MAX_LISTENERS),
+            ));
+        }
+        let poll = Poll::new()?;
+        let mut bound: Vec<Listener> = Vec::new();
+        for (index, config) in listeners.into_iter().enumerate() {
+            let address = match config.address.to_socket_addrs()?.next() {
+                Some(address) => address,
+                None => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "Invalid server address",
+                    ))
+                }
+            };
+            let mut socket = TcpListener::bind(address)?;
+            poll.registry()
+                .register(&mut socket, Token(1 + index), Interest::READABLE)?;
+            match config.tls {
+                Some(_) => info!("Listening for TLS connections on {}", address),
+                None => info!("Listening on {}", address),
+            }
+            bound.push(Listener {
+                socket,
+                tls: config.tls,
+                password_required: config.password_required,
+            });
+        }
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (outgoing_sender, outgoing_receiver) = mpsc::channel();
+        let (results_sender, auth_results) = mpsc::channel();
+        let auth_requests = spawn_auth_workers(auth, results_sender, waker.clone())?;
+        Ok(EventLoop {
+            poll,
+            listeners: bound,
+            waker,
+            connections: HashMap::new(),
+            outgoing_sender,
+            outgoing_receiver,
+            lock_clients,
+            coordinator_sender,
+            auth_requests,
+            auth_results,
+            wills_on_shutdown,
+            limits,
+            metrics,
+            stopping: Arc::new(AtomicBool::new(false)),
+            draining: Arc::new(AtomicBool::new(false)),
+            next_id: FIRST_CONNECTION_ID,
+        })
+    }
+
+    pub fn handle(&self) -> EventLoopHandle {
+        EventLoopHandle {
+            stopping: self.stopping.clone(),
+            draining: self.draining.clone(),
+            waker: self.waker.clone(),
+        }
+    }
+
+    /// Waker for the channels of clients that aren't connections of the event loop.
+    pub fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    /// Channel for a session without connection, such as one restored from disk, with an id that
+    /// no connection will use.
+    pub fn detached_channel(&mut self) -> (usize, ClientChannel) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let channel = ClientChannel::new(id, self.outgoing_sender.clone(), self.waker.clone());
+        (id, channel)
+    }
+
+    /// Handle the connections until polling fails or the event loop is stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if couldn't poll for events.
+    pub fn run(&mut self) -> std::io::Result<()> {
+        let mut events = Events::with_capacity(EVENTS_CAPACITY);
+        let mut last_keep_alive_check = Instant::now();
+        info!("Launched event loop.");
+        loop {
+            if let Err(error) = self.poll.poll(&mut events, Some(KEEP_ALIVE_CHECK)) {
+                if error.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+            if self.stopping.load(Ordering::SeqCst) {
+                self.close_all();
+                info!("Event loop stopped.");
+                return Ok(());
+            }
+            for event in events.iter() {
+                match event.token() {
+                    WAKER => {
+                        self.finish_authentications();
+                        self.queue_outgoing();
+                    }
+                    Token(token) if token < FIRST_CONNECTION_ID => {
+                        self.accept_connections(token - 1)
+                    }
+                    Token(id) => {
+                        if event.is_readable() {
+                            self.read_connection(id);
+                        }
+                        self.flush(id);
+                    }
+                }
+            }
+            if last_keep_alive_check.elapsed() >= KEEP_ALIVE_CHECK {
+                self.check_keep_alive();
+                last_keep_alive_check = Instant::now();
+            }
+        }
+    }
+
+    /// Accept every pending connection of the listener and register it as a new client, or
+    /// close it if there are already as many connections as allowed.
+    fn accept_connections(&mut self, index: usize) {
+        let listener = match self.listeners.get(index) {
+            Some(listener) => listener,
+            None => return,
+        };
+        loop {
+            match listener.socket.accept() {
+                Ok((stream, _)) if self.is_full() => {
+                    warn!("Connection limit reached, refusing a new client.");
+                    self.metrics.connection_refused();
+                    if stream.shutdown(Shutdown::Both).is_err() {
+                        debug!("Stream with client already closed.");
+                    }
+                }
+                Ok((mut stream, peer_address)) => {
+                    let tls = match &listener.tls {
+                        Some(config) => match ServerConnection::new(config.clone()) {
+                            Ok(mut tls) => {
+                                tls.set_buffer_limit(None);
+                                Some(tls)
+                            }
+                            Err(error) => {
+                                error!("Error starting a TLS session: {}", error);
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+                    self.metrics.connection_accepted();
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    let span = info_span!(
+                        "connection",
+                        id,
+                        peer = %peer_address,
+                        client_id = tracing::field::Empty
+                    );
+                    let _enter = span.enter();
+                    if self
+                        .poll
+                        .registry()
+                        .register(
+                            &mut stream,
+                            Token(id),
+                            Interest::READABLE | Interest::WRITABLE,
+                        )
+                        .is_err()
+                    {
+                        error!("Error registering a new client connection");
+                        continue;
+                    }
+                    if stream.set_nodelay(true).is_err() {
+                        debug!("Couldn't disable Nagle's algorithm.");
+                    }
+                    let channel =
+                        ClientChannel::new(id, self.outgoing_sender.clone(), self.waker.clone());
+                    match self.lock_clients.lock() {
+                        Ok(mut locked) => {
+                            let mut client = Client::new(id, channel);
+                            client.peer_address = Some(peer_address.to_string());
+                            locked.insert(id, client);
+                        }
+                        Err(_) => {
+                            error!("Error adding new client");
+                        }
+                    }
+                    info!("New client connection");
+                    drop(_enter);
+                    let connection = Connection {
+                        accepted: Instant::now(),
+                        stream,
+                        span,
+                        tls,
+                        password_required: listener.password_required,
+                        flags: ClientFlags::new(id, self.coordinator_sender.clone()),
+                        read_buffer: Vec::new(),
+                        packets: VecDeque::new(),
+                        authenticating: false,
+                        last_activity: Instant::now(),
+                    };
+                    self.connections.insert(id, connection);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    error!("Error accepting a new client");
+                    break;
+                }
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self.limits.max_connections {
+            Some(max_connections) => self.connections.len() >= max_connections,
+            None => false,
+        }
+    }
+
+    /// Move the bytes queued by the coordinator to the write queue of each connection.
+    fn queue_outgoing(&mut self) {
+        let mut pending: Vec<usize> = Vec::new();
+        while let Ok((id, buffer)) = self.outgoing_receiver.try_recv() {
+            match self.connections.get_mut(&id) {
+                Some(connection) => {
+                    if buffer == [CLOSE_CONNECTION] {
+                        connection.flags.closing = true;
+                    } else {
+                        self.metrics.packets_sent(&buffer);
+                        connection.flags.output.extend(buffer);
+                    }
+                    if !pending.contains(&id) {
+                        pending.push(id);
+                    }
+                }
+                None => {
+                    debug!("Bytes queued for a closed connection.");
+                }
+            }
+        }
+        for id in pending {
+            self.flush(id);
+        }
+    }
+
+    /// Read everything available on the connection and process its complete packets. While
+    /// its credentials are checked it isn't read, so the packets are processed in order.
+    fn read_connection(&mut self, id: usize) {
+        let max_packet_size = self.limits.max_packet_size;
+        let metrics = self.metrics.clone();
+        let connection = match self.connections.get_mut(&id) {
+            Some(connection) if !connection.authenticating => connection,
+            _ => return,
+        };
+        let span = connection.span.clone();
+        let _enter = span.enter();
+        let buffered = connection.read_buffer.len();
+        let closed = match connection.tls.as_mut() {
+            Some(tls) => read_tls(&mut connection.stream, tls, &mut connection.read_buffer),
+            None => read_plain(&mut connection.stream, &mut connection.read_buffer),
+        };
+        metrics.bytes_received(connection.read_buffer.len().saturating_sub(buffered));
+        if let Some(tls) = &connection.tls {
+            if connection.flags.certificate.is_none() && !tls.is_handshaking() {
+                connection.flags.certificate = peer_identity(tls);
+            }
+        }
+        connection.last_activity = Instant::now();
+        match split_packets(&mut connection.read_buffer, max_packet_size) {
+            Ok(packets) => connection.packets.extend(packets),
+            Err(CodecError::PacketTooLarge(size)) => {
+                warn!("Client sent a packet of {} bytes, over the limit", size);
+                if connection.flags.protocol_version == MQTT_VERSION_5 {
+                    send_disconnect(&mut connection.flags, REASON_PACKET_TOO_LARGE);
+                }
+                connection.flags.closing = true;
+            }
+            Err(_) => {
+                error!("Error trying to read buffer size");
+                connection.flags.closing = true;
+            }
+        }
+        drop(_enter);
+        let authenticating = self.process_packets(id);
+        // A connection closed while its credentials are checked is read again afterwards, which
+        // closes it once the packets it sent are processed.
+        if closed && !authenticating {
+            self.flush(id);
+            self.close_connection(id);
+        }
+    }
+
+    /// Handle the packets read from the connection until it is closing or its credentials must
+    /// be checked, which are sent to the auth threads. Returns whether they are being checked.
+    fn process_packets(&mut self, id: usize) -> bool {
+        let draining = self.draining.load(Ordering::SeqCst);
+        let connection = match self.connections.get_mut(&id) {
+            Some(connection) => connection,
+            None => return false,
+        };
+        let span = connection.span.clone();
+        let _enter = span.enter();
+        while !connection.flags.closing && !connection.authenticating {
+            let (byte_0, buffer) = match connection.packets.pop_front() {
+                Some(packet) => packet,
+                None => break,
+            };
+            self.metrics.packet_received(byte_0);
+            let queued = connection.flags.output.len();
+            let authentication = handle_packet(
+                &mut connection.flags,
+                byte_0,
+                &buffer,
+                connection.password_required,
+                draining,
+            );
+            self.metrics
+                .packets_sent(&connection.flags.output[queued..]);
+            if let Some(authentication) = authentication {
+                if self.auth_requests.send((id, authentication)).is_ok() {
+                    connection.authenticating = true;
+                } else {
+                    error!("The auth threads stopped, closing the connection.");
+                    connection.flags.closing = true;
+                }
+            }
+        }
+        if let Some(client_id) = &connection.flags.client_id {
+            span.record("client_id", client_id.as_str());
+        }
+        connection.authenticating
+    }
+
+    /// Accept or refuse the _Connect_ of each connection whose credentials were checked, and
+    /// go on with what it sent afterwards.
+    fn finish_authentications(&mut self) {
+        while let Ok((id, authentication, accepted)) = self.auth_results.try_recv() {
+            let connection = match self.connections.get_mut(&id) {
+                Some(connection) => connection,
+                None => {
+                    debug!("Credentials checked for a closed connection.");
+                    continue;
+                }
+            };
+            let span = connection.span.clone();
+            let _enter = span.enter();
+            connection.authenticating = false;
+            let queued = connection.flags.output.len();
+            finish_authentication(&mut connection.flags, authentication, accepted);
+            self.metrics
+                .packets_sent(&connection.flags.output[queued..]);
+            drop(_enter);
+            self.read_connection(id);
+            self.flush(id);
+        }
+    }
+
+    /// Write the queued bytes until the socket would block, and close the connection if it
+    /// failed or was asked to close.
+    fn flush(&mut self, id: usize) {
+        let connection = match self.connections.get_mut(&id) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let span = connection.span.clone();
+        let _enter = span.enter();
+        let written = match connection.tls.as_mut() {
+            Some(tls) => write_tls(&mut connection.stream, tls, &mut connection.flags.output),
+            None => write_plain(&mut connection.stream, &mut connection.flags.output),
+        };
+        match written {
+            Ok(true) if connection.flags.closing => self.close_connection(id),
+            Ok(_) => {}
+            Err(_) => {
+                warn!("Error sending packet to client.");
+                self.close_connection(id);
+            }
+        }
+    }
+
+    /// Close the connections that didn't send anything in one and a half times their keep alive,
+    /// and the ones that didn't send a valid _Connect_ within the connect timeout.
+    ///
+    /// MQTT 5 clients receive a _Disconnect_ with the keep alive timeout reason code.
+    fn check_keep_alive(&mut self) {
+        let mut expired: Vec<usize> = Vec::new();
+        for (id, connection) in self.connections.iter_mut() {
+            if connection.flags.client_id.is_none() {
+                if connection.accepted.elapsed() > self.limits.connect_timeout {
+                    let _enter = connection.span.enter();
+                    info!("No Connect received in time");
+                    expired.push(*id);
+                }
+                continue;
+            }
+            let keep_alive = connection.flags.keep_alive;
+            if keep_alive == 0 {
+                continue;
+            }
+            let wait = Duration::from_millis(u64::from(keep_alive) * 1500);
+            if connection.last_activity.elapsed() > wait {
+                let _enter = connection.span.enter();
+                if connection.flags.protocol_version == MQTT_VERSION_5 {
+                    send_disconnect(&mut connection.flags, REASON_KEEP_ALIVE_TIMEOUT);
+                }
+                expired.push(*id);
+            }
+        }
+        for id in expired {
+            info!("Keep alive expired");
+            self.flush(id);
+            self.close_connection(id);
+        }
+    }
+
+    /// Stop listening, and close every connection after writing what is queued for it.
+    ///
+    /// MQTT 5 clients receive a _Disconnect_ with the server shutting down reason code. Without
+    /// `wills_on_shutdown`, the connected clients are disconnected as if they had sent a
+    /// _Disconnect_, so their last will isn't published.
+    fn close_all(&mut self) {
+        for mut listener in self.listeners.drain(..) {
+            if self
+                .poll
+                .registry()
+                .deregister(&mut listener.socket)
+                .is_err()
+            {
+                debug!("Error deregistering a listener.");
+            }
+        }
+        self.queue_outgoing();
+        let ids: Vec<usize> = self.connections.keys().copied().collect();
+        for id in ids {
+            if let Some(connection) = self.connections.get_mut(&id) {
+                if connection.flags.client_id.is_some() {
+                    if connection.flags.protocol_version == MQTT_VERSION_5 {
+                        send_disconnect(&mut connection.flags, REASON_SERVER_SHUTTING_DOWN);
+                    }
+                    if !self.wills_on_shutdown {
+                        let disconnect = Disconnect::new(REASON_SERVER_SHUTTING_DOWN);
+                        let command = CoordinatorCommand::Disconnect(disconnect);
+                        if inform_coordinator(&connection.flags, command).is_err() {
+                            warn!("Error sending disconnect to Coordinator.");
+                        }
+                    }
+                }
+            }
+            self.flush(id);
+            self.close_connection(id);
+        }
+    }
+
+    /// Close the connection and inform the coordinator, which discards the session if needed.
+    fn close_connection(&mut self, id: usize) {
+        let mut connection = match self.connections.remove(&id) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let _enter = connection.span.clone().entered();
+        if self
+            .poll
+            .registry()
+            .deregister(&mut connection.stream)
+            .is_err()
+        {
+            debug!("Error deregistering client connection.");
+        }
+        if let Some(tls) = connection.tls.as_mut() {
+            tls.send_close_notify();
+            if write_tls(&mut connection.stream, tls, &mut Vec::new()).is_err() {
+                debug!("Couldn't send the TLS close notify.");
+            }
+        }
+        if connection.stream.shutdown(Shutdown::Both).is_err() {
+            debug!("Stream with client already closed.");
+        }
+        match inform_coordinator(&connection.flags, CoordinatorCommand::ConnectionLost) {
+            Ok(_) => {
+                info!("Client disconnected");
+            }
+            Err(_) => {
+                warn!("Error sending disgraceful connection to Coordinator.");
+            }
+        }
+    }
+}
+
+/// Start the threads checking credentials with `auth`. Each result is sent to `results`, waking
+/// up the event loop. Returns the channel of the credentials to check; the threads stop when it
+/// is dropped.
+///
+/// # Errors
+///
+/// Returns Error if a thread couldn't be spawned.
+fn spawn_auth_workers(
+    auth: Arc<dyn AuthProvider>,
+    results: Sender<AuthResult>,
+    waker: Arc<Waker>,
+) -> std::io::Result<Sender<AuthRequest>> {
+    let (sender, receiver) = mpsc::channel();
+    let requests = Arc::new(Mutex::new(receiver));
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+    for _ in 0..cores.max(MIN_AUTH_THREADS) {
+        let auth = auth.clone();
+        let requests = requests.clone();
+        let results = results.clone();
+        let waker = waker.clone();
+        thread::Builder::new()
+            .name("Auth".into())
+            .spawn(move || run_auth_worker(auth.as_ref(), &requests, &results, &waker))?;
+    }
+    Ok(sender)
+}
+
+fn run_auth_worker(
+    auth: &dyn AuthProvider,
+    requests: &Mutex<Receiver<AuthRequest>>,
+    results: &Sender<AuthResult>,
+    waker: &Waker,
+) {
+    loop {
+        let request = match requests.lock() {
+            Ok(requests) => requests.recv(),
+            Err(_) => return,
+        };
+        let (id, authentication) = match request {
+            Ok(request) => request,
+            Err(_) => return,
+        };
+        let accepted = auth.authenticate(
+            &authentication.client_id,
+            &authentication.username,
+            &authentication.password,
+        );
+        if results.send((id, authentication, accepted)).is_err() || waker.wake().is_err() {
+            return;
+        }
+    }
+}
+
+/// Read everything available on a plain connection. Returns whether the connection was closed.
+fn read_plain(stream: &mut TcpStream, read_buffer: &mut Vec<u8>) -> bool {
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return true,
+            Ok(size) => read_buffer.extend_from_slice(&chunk[..size]),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => return false,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// Read the TLS records available on the connection and decrypt them. Returns whether the
+/// connection was closed, which includes any TLS error after trying to send its alert.
+fn read_tls(stream: &mut TcpStream, tls: &mut ServerConnection, read_buffer: &mut Vec<u8>) -> bool {
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    loop {
+        let closed = match tls.read_tls(stream) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => return false,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return true,
+        };
+        if let Err(error) = tls.process_new_packets() {
+            if tls.is_handshaking() {
+                warn!("TLS handshake failed: {}", error);
+            } else {
+                warn!("TLS error: {}", error);
+            }
+            if tls.write_tls(stream).is_err() {
+                debug!("Couldn't send the TLS alert.");
+            }
+            return true;
+        }
+        loop {
+            match tls.reader().read(&mut chunk) {
+                Ok(0) => return true,
+                Ok(size) => read_buffer.extend_from_slice(&chunk[..size]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => return true,
+            }
+        }
+        if closed {
+            return true;
+        }
+    }
+}
+
+/// Write `output` until the socket would block. Returns whether everything was written.
+///
+/// # Errors
+///
+/// Returns Error if the socket failed.
+fn write_plain(stream: &mut TcpStream, output: &mut Vec<u8>) -> std::io::Result<bool> {
+    while !output.is_empty() {
+        match stream.write(output) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(size) => {
+                output.drain(..size);
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(true)
+}
+
+/// Encrypt `output` and write the pending TLS records until the socket would block. Returns
+/// whether everything was written.
+///
+/// # Errors
+///
+/// Returns Error if the socket failed.
+fn write_tls(
+    stream: &mut TcpStream,
+    tls: &mut ServerConnection,
+    output: &mut Vec<u8>,
+) -> std::io::Result<bool> {
+    if !output.is_empty() {
+        tls.writer().write_all(output)?;
+        output.clear();
+    }
+    while tls.wants_write() {
+        match tls.write_tls(stream) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(_) => {}
+            Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(true)
+}
+
+/// Take the complete packets from the start of `read_buffer`, as their first byte and the rest
+/// of the packet after the remaining length. Incomplete packets stay in the buffer.
+///
+/// # Errors
+///
+/// Returns Error if a remaining length is malformed or greater than `max_packet_size`, which is
+/// checked before the rest of the packet arrives.
+fn split_packets(
+    read_buffer: &mut Vec<u8>,
+    max_packet_size: usize,
+) -> Result<Vec<(u8, Vec<u8>)>, CodecError> {
+    let mut packets: Vec<(u8, Vec<u8>)> = Vec::new();
+    let mut start: usize = 0;
+    while start < read_buffer.len() {
+        let (remaining_length, length_size) =
+            match variable_byte_integer_decode(&read_buffer[(start + 1)..]) {
+                Ok(length) => length,
+                Err(CodecError::UnexpectedEnd) => break,
+                Err(error) => return Err(error),
+            };
+        if remaining_length > max_packet_size {
+            return Err(CodecError::PacketTooLarge(remaining_length));
+        }
+        let body_start = start + 1 + length_size;
+        let end = body_start + remaining_length;
+        if read_buffer.len() < end {
+            break;
+        }
+        packets.push((read_buffer[start], read_buffer[body_start..end].to_vec()));
+        start = end;
+    }
+    read_buffer.drain(..start);
+    Ok(packets)
+}
+
+#[cfg(test)]
+pub fn test_channel(id: usize) -> (ClientChannel, Receiver<(usize, Vec<u8>)>) {
+    let poll = Poll::new().unwrap();
+    let waker = Arc::new(Waker::new(poll.registry(), WAKER).unwrap());
+    let (sender, receiver) = mpsc::channel();
+    (ClientChannel::new(id, sender, waker), receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_paquetes_completos_se_separan_y_el_incompleto_queda_en_el_buffer() {
+        let mut read_buffer: Vec<u8> = vec![0xC0, 0, 0x40, 2, 0, 9, 0x30, 5, 0, 2];
+        let packets = split_packets(&mut read_buffer, usize::MAX).unwrap();
+        assert_eq!(packets, vec![(0xC0, vec![]), (0x40, vec![0, 9])]);
+        assert_eq!(read_buffer, vec![0x30, 5, 0, 2]);
+        read_buffer.append(&mut vec![97, 115, 33]);
+        let packets = split_packets(&mut read_buffer, usize::MAX).unwrap();
+        assert_eq!(packets, vec![(0x30, vec![0, 2, 97, 115, 33])]);
+        assert!(read_buffer.is_empty());
+    }
+
+    #[test]
+    fn test02_remaining_length_incompleto_espera_y_malformado_es_error() {
+        let mut read_buffer: Vec<u8> = vec![0x30, 0x80];
+        assert_eq!(split_packets(&mut read_buffer, usize::MAX), Ok(vec![]));
+        assert_eq!(read_buffer, vec![0x30, 0x80]);
+        let mut read_buffer: Vec<u8> = vec![0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(
+            split_packets(&mut read_buffer, usize::MAX),
+            Err(CodecError::MalformedVariableByteInteger)
+        );
+    }
+
+    #[test]
+    fn test03_paquete_mas_grande_que_el_limite_es_error_antes_de_llegar_completo() {
+        let mut read_buffer: Vec<u8> = vec![0xC0, 0, 0x30, 0x80, 0x01, 0, 2];
+        assert_eq!(
+            split_packets(&mut read_buffer, 127),
+            Err(CodecError::PacketTooLarge(128))
+        );
+        let mut read_buffer: Vec<u8> = vec![0x40, 2, 0, 9];
+        assert_eq!(
+            split_packets(&mut read_buffer, 2),
+            Ok(vec![(0x40, vec![0, 9])])
+        );
+    }
+}
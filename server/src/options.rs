@@ -0,0 +1,210 @@
+//! # Options
+//!
+//! Command line flags of the `server` binary and the `MQTT_RUSTICO_*` environment variables,
+//! which override the configuration file. A flag takes precedence over its environment variable,
+//! and both over the value of the file.
+
+/// Prefix of the environment variables that override the configuration.
+pub const ENV_PREFIX: &str = "MQTT_RUSTICO_";
+
+pub const USAGE: &str = "Usage: server [OPTIONS] [CONFIG]
+       server passwd <FILE> add|update <USER> [-]
+       server passwd <FILE> remove <USER>
+
+Options:
+  -c, --config <FILE>      Configuration file         [env: MQTT_RUSTICO_CONFIG]
+  -p, --port <PORT>        Port of the first listener [env: MQTT_RUSTICO_PORT]
+  -b, --bind <HOST>        Host of the first listener [env: MQTT_RUSTICO_BIND]
+  -l, --log-level <LEVEL>  trace, debug, info, warn or error [env: MQTT_RUSTICO_LOG_LEVEL]
+  -d, --data-dir <DIR>     Directory of the retained messages and persistent sessions
+                           [env: MQTT_RUSTICO_DATA_DIR]
+      --check-config       Validate the configuration and exit
+  -h, --help               Print this help and exit
+  -V, --version            Print the version and exit
+
+Flags override environment variables, which override the configuration file.";
+
+/// Settings given to the binary instead of in the configuration file. `None` keeps the value of
+/// the file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub config: Option<String>,
+    pub port: Option<u16>,
+    pub bind: Option<String>,
+    pub log_level: Option<String>,
+    pub data_dir: Option<String>,
+    pub check_config: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Options {
+    /// Options of the arguments after the program name, filling the missing ones with the
+    /// environment variables that `env` returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if a flag is unknown or lacks its value, a port is invalid, or there is more
+    /// than one configuration file.
+    pub fn parse<F>(args: &[String], env: F) -> Result<Self, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || match inline_value.clone().or_else(|| args.next().cloned()) {
+                Some(value) => Ok(value),
+                None => Err(format!("{} needs a value", flag)),
+            };
+            match flag {
+                "-c" | "--config" => options.set_config(value()?)?,
+                "-p" | "--port" => options.port = Some(parse_port(&value()?)?),
+                "-b" | "--bind" => options.bind = Some(value()?),
+                "-l" | "--log-level" => options.log_level = Some(value()?),
+                "-d" | "--data-dir" => options.data_dir = Some(value()?),
+                "--check-config" => options.check_config = true,
+                "-h" | "--help" => options.help = true,
+                "-V" | "--version" => options.version = true,
+                _ if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+                _ => options.set_config(arg.clone())?,
+            }
+        }
+        let var = |name: &str| env(&format!("{}{}", ENV_PREFIX, name));
+        if options.config.is_none() {
+            options.config = var("CONFIG");
+        }
+        if options.port.is_none() {
+            if let Some(port) = var("PORT") {
+                options.port = Some(parse_port(&port)?);
+            }
+        }
+        if options.bind.is_none() {
+            options.bind = var("BIND");
+        }
+        if options.log_level.is_none() {
+            options.log_level = var("LOG_LEVEL");
+        }
+        if options.data_dir.is_none() {
+            options.data_dir = var("DATA_DIR");
+        }
+        Ok(options)
+    }
+
+    fn set_config(&mut self, path: String) -> Result<(), String> {
+        if self.config.is_some() {
+            return Err("Only one configuration file can be given".into());
+        }
+        self.config = Some(path);
+        Ok(())
+    }
+
+    /// Override the settings of `cfg` with the given options.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the log level is unknown.
+    pub fn apply(&self, cfg: &mut crate::Configuration) -> Result<(), String> {
+        if let Some(bind) = &self.bind {
+            cfg.set_bind(bind);
+        }
+        if let Some(port) = self.port {
+            cfg.set_port(port);
+        }
+        if let Some(level) = &self.log_level {
+            cfg.set_log_level(level)?;
+        }
+        if let Some(directory) = &self.data_dir {
+            cfg.set_data_dir(directory);
+        }
+        Ok(())
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(format!(
+            "Invalid port {}, it must be between 1 and 65535",
+            port
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Configuration;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test01_flags_y_archivo_posicional() {
+        let options = Options::parse(
+            &args(&[
+                "--port",
+                "1999",
+                "-b",
+                "0.0.0.0",
+                "--log-level=info",
+                "cfg.txt",
+            ]),
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(options.config, Some("cfg.txt".to_owned()));
+        assert_eq!(options.port, Some(1999));
+        assert_eq!(options.bind, Some("0.0.0.0".to_owned()));
+        assert_eq!(options.log_level, Some("info".to_owned()));
+        assert!(Options::parse(&args(&["--port", "0"]), |_| None).is_err());
+        assert!(Options::parse(&args(&["--port"]), |_| None).is_err());
+        assert!(Options::parse(&args(&["--puerto", "1"]), |_| None).is_err());
+        assert!(Options::parse(&args(&["a.txt", "-c", "b.txt"]), |_| None).is_err());
+    }
+
+    #[test]
+    fn test02_flags_tienen_precedencia_sobre_el_entorno() {
+        let env = |name: &str| match name {
+            "MQTT_RUSTICO_PORT" => Some("2000".to_owned()),
+            "MQTT_RUSTICO_CONFIG" => Some("env.txt".to_owned()),
+            "MQTT_RUSTICO_DATA_DIR" => Some("/data".to_owned()),
+            _ => None,
+        };
+        let options = Options::parse(&args(&["-p", "1999"]), env).unwrap();
+        assert_eq!(options.port, Some(1999));
+        assert_eq!(options.config, Some("env.txt".to_owned()));
+        assert_eq!(options.data_dir, Some("/data".to_owned()));
+        assert!(Options::parse(&[], |_| Some("no".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test03_se_aplican_sobre_la_configuracion() {
+        let mut cfg = Configuration::new();
+        cfg.set_config("src/testingConfigs/cfgae.txt").unwrap();
+        let options = Options {
+            port: Some(2001),
+            bind: Some("0.0.0.0".to_owned()),
+            data_dir: Some("datos".to_owned()),
+            ..Options::default()
+        };
+        options.apply(&mut cfg).unwrap();
+        let listeners = cfg.get_listeners();
+        assert_eq!(listeners[0].address, "0.0.0.0:2001");
+        assert_eq!(listeners[1].address, "[::1]:1916");
+        assert_eq!(
+            cfg.get_retained_store(),
+            Some(format!("datos{}retained.log", std::path::MAIN_SEPARATOR))
+        );
+        let options = Options {
+            log_level: Some("verbose".to_owned()),
+            ..Options::default()
+        };
+        assert!(options.apply(&mut cfg).is_err());
+    }
+}
@@ -1,620 +1,702 @@
 //! # Packet
 //!
 //! Different packet management and processing.
-use crate::server::{ClientFlags, PacketThings};
-use std::io::{Read, Write};
-use std::net::Shutdown;
-use std::time::Duration;
+use crate::coordinator::{ClientCommand, CoordinatorCommand, NewConnection};
+use crate::server::ClientFlags;
+use codec::properties::{four_byte_property, SESSION_EXPIRY_INTERVAL};
+use codec::{
+    CodecError, ConnAck, Connect, ControlPacket, Disconnect, PubAck, PubComp, PubRec,
+    MQTT_VERSION_3, MQTT_VERSION_5,
+};
+use rand::Rng;
 use tracing::{debug, error, info, warn};
 
-const MQTT_VERSION: u8 = 4;
-const MQTT_NAME: [u8; 6] = [0x00, 0x04, 0x4D, 0x51, 0x54, 0x54];
+const MAX_CLIENT_ID_LENGTH_V3: usize = 23;
+/// Topics of the broker, such as `$SYS`, which clients can't publish to.
+const RESERVED_TOPIC_PREFIX: char = '$';
+/// Client ids of the in-process clients of the broker, which network clients can't take.
+const RESERVED_CLIENT_ID_PREFIX: char = '$';
 const CONNECTION_USER_OR_PASS_REFUSED: u8 = 4;
 const CONNECTION_IDENTIFIER_REFUSED: u8 = 2;
 const CONNECTION_PROTOCOL_REJECTED: u8 = 1;
-const INCORRECT_SERVER_CONNECTION: u8 = 1;
+const CONNECTION_SERVER_UNAVAILABLE: u8 = 3;
 pub const SUCCESSFUL_CONNECTION: u8 = 0;
-
-/// Type of recognized packets.
-pub enum Packet {
-    Connect,
-    ConnAck,
-    Publish,
-    PubAck,
-    PubRel,
-    PubComp,
-    Subscribe,
-    SubAck,
-    Unsubscribe,
-    UnsubAck,
-    PingReq,
-    PingResp,
-    Disconnect,
-    Disgrace,
-}
-
-impl From<u8> for Packet {
-    fn from(code: u8) -> Self {
-        match code & 0xF0 {
-            0x10 => Packet::Connect,
-            0x20 => Packet::ConnAck,
-            0x30 => Packet::Publish,
-            0x40 => Packet::PubAck,
-            0x60 => Packet::PubRel,
-            0x70 => Packet::PubComp,
-            0x80 => Packet::Subscribe,
-            0x90 => Packet::SubAck,
-            0xA0 => Packet::Unsubscribe,
-            0xB0 => Packet::UnsubAck,
-            0xC0 => Packet::PingReq,
-            0xD0 => Packet::PingResp,
-            0xE0 => Packet::Disconnect,
-            _ => Packet::Disgrace,
-        }
-    }
-}
-
-impl From<Packet> for u8 {
-    fn from(code: Packet) -> Self {
-        match code {
-            Packet::Connect => 0x10,
-            Packet::ConnAck => 0x20,
-            Packet::Publish => 0x30,
-            Packet::PubAck => 0x40,
-            Packet::PubRel => 0x62,
-            Packet::PubComp => 0x70,
-            Packet::Subscribe => 0x82,
-            Packet::SubAck => 0x90,
-            Packet::Unsubscribe => 0xA2,
-            Packet::UnsubAck => 0xB0,
-            Packet::PingReq => 0xC0,
-            Packet::PingResp => 0xD0,
-            Packet::Disconnect => 0xE0,
-            _ => 0x00,
-        }
-    }
-}
-
-/// Read entire packet from stream and execute the function according to type.
+pub const REASON_DISCONNECT_WITH_WILL: u8 = 0x04;
+pub const REASON_MALFORMED_PACKET: u8 = 0x81;
+pub const REASON_PROTOCOL_ERROR: u8 = 0x82;
+pub const REASON_NOT_AUTHORIZED: u8 = 0x87;
+pub const REASON_PACKET_TOO_LARGE: u8 = 0x95;
+pub const REASON_SERVER_SHUTTING_DOWN: u8 = 0x8B;
+pub const REASON_KEEP_ALIVE_TIMEOUT: u8 = 0x8D;
+pub const REASON_SESSION_TAKEN_OVER: u8 = 0x8E;
+pub const REASON_ADMINISTRATIVE_ACTION: u8 = 0x98;
+
+/// Decode a packet read from the client, given its first byte and the rest of the packet after
+/// the remaining length, and execute the function according to type. While `draining`, a
+/// _Connect_ is refused with server unavailable and the connection closed.
 ///
-/// # Errors
-///
-/// Returns Error if couldn't read from stream.
-pub fn read_packet(
+/// Until a _Connect_ is accepted, any other packet closes the connection, and so does a second
+/// _Connect_. Returns the credentials of a _Connect_ that must be checked before accepting it.
+pub fn handle_packet(
     client: &mut ClientFlags,
-    packet_type: Packet,
-    buffer_size: usize,
     byte_0: u8,
+    buffer: &[u8],
     password_required: bool,
-) -> Result<(), std::io::Error> {
-    let mut buffer_packet: Vec<u8> = vec![0; buffer_size];
-    client.connection.read_exact(&mut buffer_packet)?;
-    match packet_type {
-        Packet::Connect => match make_connection(client, buffer_packet, password_required) {
-            Ok(_) => {}
-            Err(error_code) => {
-                send_connection_error(client, error_code);
-            }
-        },
-        Packet::Publish => match make_publication(client, buffer_packet, byte_0) {
-            Ok(paquete_identifier) => {
-                if (byte_0 & 0x02) == 2 {
-                    send_publication_results(client, paquete_identifier);
-                }
-            }
-            Err(_) => {
-                warn!("Error when publishing.");
+    draining: bool,
+) -> Option<Authentication> {
+    let packet = match ControlPacket::decode(byte_0, buffer, client.protocol_version) {
+        Ok(packet) => packet,
+        Err(error) => {
+            warn!("Malformed packet received: {}", error);
+            reject_malformed_packet(client, error);
+            return None;
+        }
+    };
+    let connected = client.client_id.is_some();
+    match packet {
+        ControlPacket::Connect(_) if connected => {
+            warn!("Second Connect received, closing the connection.");
+            if client.protocol_version == MQTT_VERSION_5 {
+                send_disconnect(client, REASON_PROTOCOL_ERROR);
             }
-        },
-        Packet::Subscribe => {
-            change_subscription(client, buffer_packet, Packet::Subscribe);
+            client.closing = true;
         }
-        Packet::Unsubscribe => {
-            change_subscription(client, buffer_packet, Packet::Unsubscribe);
+        ControlPacket::Connect(connect) if draining => {
+            info!("Refusing a connection while draining.");
+            client.protocol_version = connect.protocol_version;
+            send_connection_error(client, CONNECTION_SERVER_UNAVAILABLE);
         }
-        Packet::PingReq => {
-            send_pingresp(client);
+        ControlPacket::Connect(connect) => {
+            match make_connection(client, connect, password_required) {
+                Ok(authentication) => return authentication,
+                Err(error_code) => send_connection_error(client, error_code),
+            }
         }
-        Packet::Disconnect => {
-            inform_client_disconnect_to_coordinator(client, buffer_packet, Packet::Disconnect);
-            close_streams(client);
+        _ if !connected => {
+            warn!("Packet received before a successful Connect, closing the connection.");
+            client.closing = true;
         }
-        Packet::PubAck => {
-            remove_from_client_publishes(client, buffer_packet);
+        ControlPacket::Publish(publish)
+            if publish.topic_name.starts_with(RESERVED_TOPIC_PREFIX) =>
+        {
+            warn!(
+                "Publish to the reserved topic {} discarded.",
+                publish.topic_name
+            );
+            let acknowledgement = publish_acknowledgement(
+                publish.qos,
+                publish.packet_identifier,
+                REASON_NOT_AUTHORIZED,
+            );
+            if let Some(acknowledgement) = acknowledgement {
+                write_packet(client, acknowledgement);
+            }
         }
-        _ => {}
-    }
-
-    Ok(())
-}
-
-/// Inform coordinator that the client sent a _Disconnect_ packet.
-pub fn inform_client_disconnect_to_coordinator(
-    client: &mut ClientFlags,
-    buffer_packet: Vec<u8>,
-    tipo: Packet,
-) {
-    let packet_to_server = PacketThings {
-        thread_id: client.id,
-        packet_type: tipo,
-        bytes: buffer_packet,
-    };
-    let sender = client.sender.lock();
-    match sender {
-        Ok(sender_ok) => {
-            match sender_ok.send(packet_to_server) {
-                Ok(_) => {
-                    info!("Success sending disconnect to coordinator thread.")
-                }
+        ControlPacket::Publish(publish) => {
+            // The coordinator acknowledges it once it knows whether the publish is accepted.
+            match inform_coordinator(client, CoordinatorCommand::Publish(publish)) {
+                Ok(_) => {}
                 Err(_) => {
-                    warn!("Error sending sending disconnect to coordinator thread.")
+                    warn!("Error when publishing.");
                 }
-            };
+            }
         }
-        Err(_) => {
-            warn!("Error reading coordinator channel.")
+        ControlPacket::Subscribe(subscribe) => {
+            change_subscription(client, CoordinatorCommand::Subscribe(subscribe));
         }
-    }
-}
-
-/// Close the stream with the client.
-fn close_streams(client: &mut ClientFlags) {
-    let mut buffer = Vec::new();
-    let aux = client.connection.read_to_end(&mut buffer);
-
-    match aux {
-        Ok(0) => {
-            info!("Client has closed the stream.")
+        ControlPacket::Unsubscribe(unsubscribe) => {
+            change_subscription(client, CoordinatorCommand::Unsubscribe(unsubscribe));
         }
-        _ => {
-            client
-                .connection
-                .shutdown(Shutdown::Both)
-                .expect("shutdown call failed");
-            info!("Stream with client closed.");
+        ControlPacket::PingReq => {
+            send_pingresp(client);
         }
-    }
-    match client.sender.lock() {
-        Ok(locked) => {
-            drop(locked);
+        ControlPacket::Disconnect(disconnect) => {
+            inform_client_disconnect_to_coordinator(
+                client,
+                CoordinatorCommand::Disconnect(disconnect),
+            );
+            client.closing = true;
         }
-        Err(_) => {
-            error!("Error accessing the lock.");
+        ControlPacket::PubAck(puback) => {
+            inform_acknowledgement(client, CoordinatorCommand::PubAck(puback));
+        }
+        ControlPacket::PubRec(pubrec) => {
+            inform_acknowledgement(client, CoordinatorCommand::PubRec(pubrec));
+        }
+        ControlPacket::PubRel(pubrel) => {
+            let packet_identifier = pubrel.packet_identifier;
+            inform_acknowledgement(client, CoordinatorCommand::PubRel(pubrel));
+            write_packet(
+                client,
+                ControlPacket::PubComp(PubComp::new(packet_identifier)),
+            );
+        }
+        ControlPacket::PubComp(pubcomp) => {
+            inform_acknowledgement(client, CoordinatorCommand::PubComp(pubcomp));
         }
+        _ => {}
     }
-    info!("Closed stream with Coordinator.");
+    None
 }
 
-/// Inform the coordinator that a new client has connected.
-fn inform_new_connection(
-    client: &mut ClientFlags,
-    will_topic: Option<String>,
-    will_message: Option<String>,
-    will_qos: u8,
-    will_retain: bool,
-) {
-    if let Some(client_id) = &client.client_id {
-        let mut client_id_bytes = client_id.as_bytes().to_vec();
-        let mut bytes: Vec<u8> = vec![client_id_bytes.len() as u8];
-        bytes.append(&mut client_id_bytes);
-        bytes.push(client.clean_session);
-        match will_topic {
-            Some(topic) => {
-                bytes.push(1);
-                if let Some(message) = will_message {
-                    let mut topic_bytes = topic.as_bytes().to_vec();
-                    let mut message_bytes = message.as_bytes().to_vec();
-                    bytes.push(topic_bytes.len() as u8);
-                    bytes.append(&mut topic_bytes);
-                    bytes.push(message_bytes.len() as u8);
-                    bytes.append(&mut message_bytes);
-                    bytes.push(will_qos);
-                    if will_retain {
-                        bytes.push(1);
-                    } else {
-                        bytes.push(0);
-                    }
-                }
-            }
-            None => {
-                bytes.push(0);
-            }
+/// Answer a packet that couldn't be decoded.
+///
+/// A _Connect_ with an unknown protocol gets a _Connack_ refusing it. Otherwise MQTT 5 clients
+/// receive a _Disconnect_ with the malformed packet reason code, and the connection is closed.
+fn reject_malformed_packet(client: &mut ClientFlags, error: CodecError) {
+    match error {
+        CodecError::InvalidProtocolName | CodecError::UnsupportedProtocolLevel(_) => {
+            send_connection_error(client, CONNECTION_PROTOCOL_REJECTED);
         }
-        let packet_to_server = PacketThings {
-            thread_id: client.id,
-            packet_type: Packet::Connect,
-            bytes,
-        };
-        let sender = client.sender.lock();
-        match sender {
-            Ok(sender_ok) => {
-                match sender_ok.send(packet_to_server) {
-                    Ok(_) => {
-                        info!("Success sending client id change to the Coordinator thread")
-                    }
-                    Err(_) => {
-                        warn!("Error sending client id change to the Coordinator thread")
-                    }
-                };
-            }
-            Err(_) => {
-                warn!("Error reading coordinator channel.")
+        _ => {
+            if client.protocol_version == MQTT_VERSION_5 {
+                send_disconnect(client, REASON_MALFORMED_PACKET);
+            } else {
+                client.closing = true;
             }
         }
     }
 }
 
-/// Inform coordinator that the client has changed a subscription.
-fn change_subscription(client: &mut ClientFlags, buffer_packet: Vec<u8>, tipo: Packet) {
-    let packet_to_server = PacketThings {
+/// Send `command` from the client to the coordinator.
+///
+/// # Errors
+///
+/// Returns Error if:
+/// - Couldn't lock de Sender Channel.
+/// - Couldn't send the message to the coordinator.
+pub fn inform_coordinator(client: &ClientFlags, command: CoordinatorCommand) -> Result<(), String> {
+    let client_command = ClientCommand {
         thread_id: client.id,
-        packet_type: tipo,
-        bytes: buffer_packet,
+        command,
     };
     let sender = client.sender.lock();
     match sender {
-        Ok(sender_ok) => {
-            match sender_ok.send(packet_to_server) {
-                Ok(_) => {
-                    info!("Success sending subscription change to the coordinator thread.")
-                }
-                Err(_) => {
-                    debug!("Error sending subscription change to coordinator thread.")
-                }
-            };
-        }
-        Err(_) => {
-            warn!("Error reading subscription change.")
-        }
+        Ok(sender_ok) => match sender_ok.send(client_command) {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Error sending to the coordinator".to_owned()),
+        },
+        Err(_) => Err("Error reading coordinator channel".to_owned()),
     }
 }
 
-/// Check if protocol name is the required for a new connection.
-pub fn verify_protocol_name(buffer: &[u8]) -> Result<(), u8> {
-    for i in 0..6 {
-        if buffer[i] != MQTT_NAME[i] {
-            debug!("Wrong connection protocol name");
-            return Err(INCORRECT_SERVER_CONNECTION);
+/// Encode `packet` with the client protocol version and queue it to be written to the client.
+fn write_packet(client: &mut ClientFlags, packet: ControlPacket) {
+    match packet.encode(client.protocol_version) {
+        Ok(mut buffer) => {
+            client.output.append(&mut buffer);
+            debug!("Packet queued for client.");
+        }
+        Err(error) => {
+            error!("Error encoding packet: {}", error);
         }
     }
-    Ok(())
-}
-
-/// Check if protocol version is the required for a new connection.
-pub fn verify_version_protocol(level: &u8) -> Result<(), u8> {
-    if *level == MQTT_VERSION {
-        return Ok(());
-    }
-    Err(CONNECTION_PROTOCOL_REJECTED)
 }
 
-/// Send a _Connack_ packet to the client with the connection error code.
-pub fn send_connection_error(client: &mut ClientFlags, result_code: u8) {
-    let mut buffer = [0u8; 4];
-    buffer[0] = Packet::ConnAck.into();
-    buffer[1] = 0x02;
-    buffer[2] = 0;
-    buffer[3] = result_code;
-
-    match client.connection.write_all(&buffer) {
+/// Inform coordinator that the client sent a _Disconnect_ packet.
+pub fn inform_client_disconnect_to_coordinator(
+    client: &mut ClientFlags,
+    command: CoordinatorCommand,
+) {
+    match inform_coordinator(client, command) {
         Ok(_) => {
-            info!("Sent Connack packet with error code.");
+            info!("Success sending disconnect to coordinator thread.")
         }
         Err(_) => {
-            error!("Error communicating with the client.");
+            warn!("Error sending sending disconnect to coordinator thread.")
         }
     }
 }
 
-/// Send _Puback_ packet to the client.
-fn send_publication_results(client: &mut ClientFlags, packet_identifier: [u8; 2]) {
-    let mut buffer = [0u8; 4];
-    buffer[0] = Packet::PubAck.into();
-    buffer[1] = 0x02;
-    buffer[2] = packet_identifier[0];
-    buffer[3] = packet_identifier[1];
+/// Send a MQTT 5 _Disconnect_ packet with `reason_code` to the client and close the connection.
+pub fn send_disconnect(client: &mut ClientFlags, reason_code: u8) {
+    info!(
+        "Sending Disconnect packet with reason code {}.",
+        reason_code
+    );
+    write_packet(
+        client,
+        ControlPacket::Disconnect(Disconnect::new(reason_code)),
+    );
+    client.closing = true;
+}
 
-    match client.connection.write_all(&buffer) {
+/// Inform coordinator that the client has changed a subscription.
+fn change_subscription(client: &mut ClientFlags, command: CoordinatorCommand) {
+    match inform_coordinator(client, command) {
         Ok(_) => {
-            info!("Puback packet sent");
+            info!("Success sending subscription change to the coordinator thread.")
         }
         Err(_) => {
-            warn!("Error sending Puback packet");
+            debug!("Error sending subscription change to coordinator thread.")
         }
     }
 }
 
-/// Send new _Publish_ packet to the coordinator.
-///
-/// # Errors
+/// Send a _Connack_ packet to the client with the connection error code, and close the
+/// connection.
 ///
-/// Returns Error if:
-/// - Couldn't lock de Sender Channel.
-/// - Couldn't send the message to the coordinator.
-fn make_publication(
-    client: &mut ClientFlags,
-    mut buffer_packet: Vec<u8>,
-    byte_0: u8,
-) -> Result<[u8; 2], String> {
-    let topic_size: usize = ((buffer_packet[0] as usize) << 8) + buffer_packet[1] as usize;
-    let mut packet_identifier = [0u8; 2];
-    if(byte_0 & 0x02) == 2 {
-        packet_identifier[0] = buffer_packet[topic_size + 2];
-        packet_identifier[1] = buffer_packet[topic_size + 3];
-    }
-    buffer_packet.insert(0, byte_0);
-    let packet_to_server = PacketThings {
-        thread_id: client.id,
-        packet_type: Packet::Publish,
-        bytes: buffer_packet,
+/// MQTT 5 clients receive the equivalent reason code and an empty property section.
+pub fn send_connection_error(client: &mut ClientFlags, result_code: u8) {
+    let mut return_code = result_code;
+    if client.protocol_version == MQTT_VERSION_5 {
+        return_code = connack_reason_code_v5(result_code);
+    }
+    let connack = ConnAck {
+        session_present: false,
+        return_code,
+        properties: Vec::new(),
     };
-    let sender = client.sender.lock();
-    match sender {
-        Ok(sender_ok) => match sender_ok.send(packet_to_server) {
-            Ok(_) => Ok(packet_identifier),
-            Err(_) => Err("Error".to_owned()),
-        },
-        Err(_) => Err("Error".to_owned()),
+    info!("Sending Connack packet with error code.");
+    write_packet(client, ControlPacket::ConnAck(connack));
+    client.closing = true;
+}
+
+/// MQTT 5 reason code equivalent to a MQTT 3.1.1 _Connack_ return code.
+pub fn connack_reason_code_v5(return_code: u8) -> u8 {
+    match return_code {
+        SUCCESSFUL_CONNECTION => 0x00,
+        CONNECTION_PROTOCOL_REJECTED => 0x84,
+        CONNECTION_IDENTIFIER_REFUSED => 0x85,
+        CONNECTION_SERVER_UNAVAILABLE => 0x88,
+        CONNECTION_USER_OR_PASS_REFUSED => 0x86,
+        5 => 0x87,
+        REASON_MALFORMED_PACKET => REASON_MALFORMED_PACKET,
+        _ => 0x80,
     }
 }
 
-fn remove_from_client_publishes(client: &mut ClientFlags, buffer_packet: Vec<u8>) {
-    let packet_to_server = PacketThings {
-        thread_id: client.id,
-        packet_type: Packet::PubAck,
-        bytes: buffer_packet,
-    };
-    let sender = client.sender.lock();
-    match sender {
-        Ok(sender_ok) => match sender_ok.send(packet_to_server) {
-            Ok(_) => {}
-            Err(_) => {
-                error!("Error sending message to client.")
-            }
-        },
-        Err(_) => error!("Error sending message to client."),
+/// _Puback_ or _Pubrec_ with `reason_code` for a publish with `qos` 1 or 2. MQTT 3 clients
+/// only receive the packet identifier.
+pub fn publish_acknowledgement(
+    qos: u8,
+    packet_identifier: Option<u16>,
+    reason_code: u8,
+) -> Option<ControlPacket> {
+    match (qos, packet_identifier) {
+        (1, Some(packet_identifier)) => Some(ControlPacket::PubAck(PubAck {
+            reason_code,
+            ..PubAck::new(packet_identifier)
+        })),
+        (2, Some(packet_identifier)) => Some(ControlPacket::PubRec(PubRec {
+            reason_code,
+            ..PubRec::new(packet_identifier)
+        })),
+        _ => None,
+    }
+}
+
+/// Inform coordinator that the client sent a _Puback_, _Pubrec_, _Pubrel_ or _Pubcomp_ packet.
+fn inform_acknowledgement(client: &mut ClientFlags, command: CoordinatorCommand) {
+    if inform_coordinator(client, command).is_err() {
+        error!("Error sending message to client.")
     }
 }
 
 /// Send _Pingresp_ to the client.
 fn send_pingresp(client: &mut ClientFlags) {
-    let buffer = [Packet::PingResp.into(), 0];
+    debug!("Sending Pingresp packet.");
+    write_packet(client, ControlPacket::PingResp);
+}
 
-    match client.connection.write_all(&buffer) {
-        Ok(_) => {
-            debug!("Pingresp packet sent.");
-        }
-        Err(_) => {
-            error!("Error sending Pingresp.");
-        }
-    }
+/// Credentials of a _Connect_, which is accepted once an
+/// [`AuthProvider`](crate::auth::AuthProvider) checks them.
+#[derive(Debug, PartialEq)]
+pub struct Authentication {
+    pub client_id: String,
+    pub username: String,
+    pub password: Vec<u8>,
+    assigned_client_id: bool,
+    connect: Connect,
 }
 
-/// Process _Connection_ packet.
+/// Process _Connection_ packet. Returns the credentials to check before accepting it, if it has
+/// a username and password and the client didn't present a certificate.
 pub fn make_connection(
     client: &mut ClientFlags,
-    buffer_packet: Vec<u8>,
+    mut connect: Connect,
     password_required: bool,
-) -> Result<u8, u8> {
-    verify_protocol_name(&buffer_packet)?;
-    verify_version_protocol(&buffer_packet[6])?;
-
-    let flag_username = buffer_packet[7] & 0x80 == 0x80;
-    let flag_password = buffer_packet[7] & 0x40 == 0x40;
-    let flag_will_retain = buffer_packet[7] & 0x20 == 0x20;
-    let flag_will_qos = (buffer_packet[7] & 0x18) >> 3;
-    let flag_will_flag = buffer_packet[7] & 0x04 == 0x04;
-    let flag_clean_session = buffer_packet[7] & 0x02 == 0x02;
-
-    let keep_alive: u16 = ((buffer_packet[8] as u16) << 8) + buffer_packet[9] as u16;
-
-    let size_client_id: usize = ((buffer_packet[10] as usize) << 8) + buffer_packet[11] as usize;
-
-    let mut client_id = None;
-
-    if size_client_id != 0 {
-        client_id = Some(bytes2string(&buffer_packet[12..12 + size_client_id]));
-    }
-
-    if client_id == None {
+) -> Result<Option<Authentication>, u8> {
+    client.protocol_version = connect.protocol_version;
+    let common_name = client
+        .certificate
+        .as_ref()
+        .and_then(|identity| identity.common_name.clone());
+
+    let (client_id, assigned_client_id) = if !connect.client_id.is_empty() {
+        (connect.client_id.clone(), false)
+    } else if let Some(common_name) = common_name {
+        (common_name, client.protocol_version == MQTT_VERSION_5)
+    } else if client.protocol_version == MQTT_VERSION_5 {
+        (generate_client_id(), true)
+    } else {
         return Err(CONNECTION_IDENTIFIER_REFUSED);
-    }
+    };
 
-    if password_required && (!flag_username || !flag_password) {
+    if client_id.starts_with(RESERVED_CLIENT_ID_PREFIX) {
+        debug!("Client id {} is reserved for the broker", client_id);
         return Err(CONNECTION_IDENTIFIER_REFUSED);
     }
 
-    let mut index: usize = (12 + size_client_id) as usize;
-
-    let mut will_topic = None;
-    let mut will_message = None;
-    if flag_will_flag {
-        let size_will_topic: usize =
-            ((buffer_packet[index] as usize) << 8) + buffer_packet[(index + 1)] as usize;
-        index += 2_usize;
-        will_topic = Some(bytes2string(
-            &buffer_packet[index..(index + size_will_topic)],
-        ));
-        index += size_will_topic;
-
-        let size_will_message: usize =
-            ((buffer_packet[index] as usize) << 8) + buffer_packet[(index + 1) as usize] as usize;
-        index += 2_usize;
-        will_message = Some(bytes2string(
-            &buffer_packet[index..(index + size_will_message)],
-        ));
-        index += size_will_message;
-    }
-
-    let mut username: Option<String> = None;
-    if flag_username {
-        let size_username: usize =
-            ((buffer_packet[index] as usize) << 8) + buffer_packet[(index + 1) as usize] as usize;
-        index += 2_usize;
-        username = Some(bytes2string(&buffer_packet[index..(index + size_username)]));
-        index += size_username;
-    }
-
-    let mut password: Option<String> = None;
-    if flag_password {
-        let size_password: usize =
-            ((buffer_packet[index] as usize) << 8) + buffer_packet[(index + 1) as usize] as usize;
-        index += 2_usize;
-        password = Some(bytes2string(&buffer_packet[index..(index + size_password)]));
+    if client.protocol_version == MQTT_VERSION_3
+        && connect.client_id.len() > MAX_CLIENT_ID_LENGTH_V3
+    {
+        debug!("MQTT 3.1 client id longer than 23 characters");
+        return Err(CONNECTION_IDENTIFIER_REFUSED);
     }
 
-    if let Some(user) = &username {
-        if let Some(pass) = &password {
-            if !user_and_password_correct(user, pass) {
-                return Err(CONNECTION_USER_OR_PASS_REFUSED);
-            }
+    // A verified client certificate replaces the username and password.
+    let certificate_user = client
+        .certificate
+        .as_ref()
+        .and_then(|identity| identity.username())
+        .map(str::to_owned);
+    if let Some(username) = certificate_user {
+        accept_connection(
+            client,
+            connect,
+            client_id,
+            assigned_client_id,
+            Some(username),
+        );
+        return Ok(None);
+    }
+    if password_required && (connect.username.is_none() || connect.password.is_none()) {
+        return Err(CONNECTION_USER_OR_PASS_REFUSED);
+    }
+    match (connect.username.take(), connect.password.take()) {
+        (Some(username), Some(password)) => Ok(Some(Authentication {
+            client_id,
+            username,
+            password,
+            assigned_client_id,
+            connect,
+        })),
+        // A username without password isn't authenticated, so it can't be used for ACLs.
+        _ => {
+            accept_connection(client, connect, client_id, assigned_client_id, None);
+            Ok(None)
         }
     }
+}
 
-    if keep_alive > 0 {
-        let wait = (f32::from(keep_alive) * 1.5) as u64;
-        if client
-            .connection
-            .set_read_timeout(Some(Duration::new(wait, 0)))
-            .is_err()
-        {
-            error!("Error establishing the limit time for a client.")
-        }
+/// Accept the _Connect_ of `authentication` if its credentials were `accepted`, or refuse it
+/// and close the connection.
+pub fn finish_authentication(
+    client: &mut ClientFlags,
+    authentication: Authentication,
+    accepted: bool,
+) {
+    if !accepted {
+        send_connection_error(client, CONNECTION_USER_OR_PASS_REFUSED);
+        return;
     }
-
-    client.client_id = client_id;
-    client.clean_session = flag_clean_session as u8;
-    debug!("Clean session {}", flag_clean_session);
-    client.keep_alive = keep_alive;
-
-    inform_new_connection(
+    accept_connection(
         client,
-        will_topic,
-        will_message,
-        flag_will_qos,
-        flag_will_retain,
+        authentication.connect,
+        authentication.client_id,
+        authentication.assigned_client_id,
+        Some(authentication.username),
     );
-    Ok(1)
 }
 
-/// Convert bytes to UTF-8 string.
-pub fn bytes2string(bytes: &[u8]) -> String {
-    match std::str::from_utf8(bytes) {
-        Ok(str) => str.to_owned(),
-        Err(_) => "".to_owned(),
-    }
-}
-
-/// Check if username and password are valid.
-fn user_and_password_correct(user: &str, password: &str) -> bool {
-    let file: String = match std::fs::read_to_string("./src/users.txt") {
-        Ok(file) => file,
-        Err(_) => return false,
+/// Keep the session settings of the _Connect_ and inform the coordinator, which answers it.
+fn accept_connection(
+    client: &mut ClientFlags,
+    connect: Connect,
+    client_id: String,
+    assigned_client_id: bool,
+    username: Option<String>,
+) {
+    let session_expiry_interval =
+        four_byte_property(&connect.properties, SESSION_EXPIRY_INTERVAL).unwrap_or(0);
+    if client.protocol_version == MQTT_VERSION_5 {
+        client.clean_session = (session_expiry_interval == 0) as u8;
+    } else {
+        client.clean_session = connect.clean_session as u8;
+    }
+    debug!("Clean session {}", connect.clean_session);
+    client.keep_alive = connect.keep_alive;
+    client.client_id = Some(client_id.clone());
+
+    let connection = NewConnection {
+        client_id,
+        username,
+        protocol_version: client.protocol_version,
+        clean_session: client.clean_session == 1,
+        clean_start: connect.clean_session,
+        assigned_client_id,
+        will: connect.will,
     };
-    let lines = file.lines();
-
-    for line in lines {
-        let name_and_pass: Vec<&str> = line.split('=').collect();
-        let username: String = name_and_pass[0].to_string();
-        let pass: String = name_and_pass[1].to_string();
-        if username == user {
-            if pass == password {
-                return true;
-            }
-            return false;
+    match inform_coordinator(client, CoordinatorCommand::Connect(connection)) {
+        Ok(_) => {
+            info!("Success sending client id change to the Coordinator thread")
+        }
+        Err(_) => {
+            warn!("Error sending client id change to the Coordinator thread")
         }
     }
-    false
+}
+
+/// Client id assigned by the server to MQTT 5 clients that connect with an empty one.
+fn generate_client_id() -> String {
+    let mut rng = rand::thread_rng();
+    let suffix: u64 = rng.gen();
+    format!("rustico-{:016x}", suffix)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::Rng;
-    use std::net::{TcpListener, TcpStream};
+    use codec::{Publish, MQTT_VERSION};
     use std::sync::mpsc::{Receiver, Sender};
     use std::sync::{mpsc, Arc, Mutex};
-    use std::thread;
 
     #[test]
-    fn test01_nombre_protocolo_correto() {
-        let nombre: [u8; 6] = [0x00, 0x04, 0x4D, 0x51, 0x54, 0x54];
-        assert_eq!(verify_protocol_name(&nombre.to_vec()), Ok(()));
+    fn test01_publish_pasado_al_coordinador() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mutex_clients_sender = Arc::new(Mutex::new(clients_sender));
+        let client_sender = Arc::clone(&mutex_clients_sender);
+        let client = ClientFlags::new(1, client_sender);
+        let mut buffer_packet: Vec<u8> = Vec::new();
+        let topic_subscribed = "as/tor".to_owned();
+        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
+        buffer_packet.push(0);
+        buffer_packet.push(topic_subscribed_bytes.len() as u8);
+        buffer_packet.append(&mut topic_subscribed_bytes);
+        buffer_packet.push(3);
+        buffer_packet.push(4);
+        buffer_packet.push(5);
+        buffer_packet.push(6);
+        buffer_packet.push(7);
+        buffer_packet.push(8);
+        let byte_0: u8 = 0x33;
+        let publish = Publish::decode(byte_0, &buffer_packet, MQTT_VERSION).unwrap();
+        inform_coordinator(&client, CoordinatorCommand::Publish(publish)).unwrap();
+        let packet_read = coordinator_receiver.recv().unwrap();
+        assert_eq!(packet_read.thread_id, 1);
+        match packet_read.command {
+            CoordinatorCommand::Publish(publish) => {
+                assert!(publish.retain);
+                assert_eq!(publish.qos, 1);
+                assert_eq!(publish.topic_name, "as/tor");
+                assert_eq!(publish.packet_identifier, Some(0x0304));
+                assert_eq!(publish.payload, vec![5, 6, 7, 8]);
+            }
+            _ => panic!("Expected a publish command"),
+        }
+    }
+
+    fn connect_mqtt31(client_id: &str) -> Connect {
+        Connect {
+            protocol_version: MQTT_VERSION_3,
+            clean_session: true,
+            keep_alive: 60,
+            properties: Vec::new(),
+            client_id: client_id.to_owned(),
+            will: None,
+            username: None,
+            password: None,
+        }
+    }
+
+    #[test]
+    fn test02_connect_mqtt31_se_informa_al_coordinador_y_client_id_largo_es_rechazado() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let client_sender = Arc::new(Mutex::new(clients_sender));
+        let mut client = ClientFlags::new(1, client_sender);
+        assert_eq!(
+            make_connection(&mut client, connect_mqtt31("sensor-31"), false),
+            Ok(None)
+        );
+        assert_eq!(client.protocol_version, MQTT_VERSION_3);
+        assert_eq!(client.client_id, Some("sensor-31".to_owned()));
+        let packet_read = coordinator_receiver.recv().unwrap();
+        match packet_read.command {
+            CoordinatorCommand::Connect(connection) => {
+                assert_eq!(connection.client_id, "sensor-31");
+                assert_eq!(connection.protocol_version, MQTT_VERSION_3);
+            }
+            _ => panic!("Expected a connect command"),
+        }
+
+        let client_id_largo = "a".repeat(MAX_CLIENT_ID_LENGTH_V3 + 1);
+        assert_eq!(
+            make_connection(&mut client, connect_mqtt31(&client_id_largo), false),
+            Err(CONNECTION_IDENTIFIER_REFUSED)
+        );
+        assert_eq!(
+            make_connection(&mut client, connect_mqtt31(""), false),
+            Err(CONNECTION_IDENTIFIER_REFUSED)
+        );
+    }
+
+    #[test]
+    fn test03_respuestas_se_encolan_y_disconnect_cierra_la_conexion() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mut client = ClientFlags::new(1, Arc::new(Mutex::new(clients_sender)));
+        client.client_id = Some("sensor".to_owned());
+        handle_packet(&mut client, 0xC0, &[], false, false);
+        handle_packet(&mut client, 0x62, &[0, 9], false, false);
+        assert_eq!(client.output, vec![0xD0, 0, 0x70, 2, 0, 9]);
+        assert!(!client.closing);
+        handle_packet(&mut client, 0xE0, &[], false, false);
+        assert!(client.closing);
+        assert!(matches!(
+            coordinator_receiver.recv().unwrap().command,
+            CoordinatorCommand::PubRel(_)
+        ));
+        assert!(matches!(
+            coordinator_receiver.recv().unwrap().command,
+            CoordinatorCommand::Disconnect(_)
+        ));
     }
 
     #[test]
-    fn test02_nombre_protocolo_incorrecto() {
-        let nombre: [u8; 6] = [0x00, 0x04, 0x4E, 0x51, 0x54, 0x14];
+    fn test04_connect_durante_el_drenado_se_rechaza_y_cierra_la_conexion() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mut client = ClientFlags::new(1, Arc::new(Mutex::new(clients_sender)));
+        let connect = connect_mqtt31("sensor").encode().unwrap();
+        handle_packet(&mut client, connect[0], &connect[2..], false, true);
         assert_eq!(
-            verify_protocol_name(&nombre.to_vec()),
-            Err(INCORRECT_SERVER_CONNECTION)
+            client.output,
+            vec![0x20, 2, 0, CONNECTION_SERVER_UNAVAILABLE]
         );
+        assert!(client.closing);
+        assert!(client.client_id.is_none());
+        assert!(coordinator_receiver.try_recv().is_err());
     }
 
     #[test]
-    fn test03_version_protocolo_incorrecta() {
-        let version: u8 = 45;
+    fn test05_paquetes_antes_del_connect_cierran_la_conexion() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mut client = ClientFlags::new(1, Arc::new(Mutex::new(clients_sender)));
+        let publish = Publish {
+            dup: false,
+            qos: 0,
+            retain: false,
+            topic_name: "intruso".to_owned(),
+            packet_identifier: None,
+            properties: Vec::new(),
+            payload: b"sin connect".to_vec(),
+        }
+        .encode(MQTT_VERSION)
+        .unwrap();
+        handle_packet(&mut client, publish[0], &publish[2..], false, false);
+        assert!(client.closing);
+        assert!(client.output.is_empty());
+        assert!(coordinator_receiver.try_recv().is_err());
+
+        let mut client = ClientFlags::new(2, client.sender.clone());
+        let connect = connect_mqtt31("").encode().unwrap();
+        handle_packet(&mut client, connect[0], &connect[2..], false, false);
         assert_eq!(
-            verify_version_protocol(&version),
-            Err(CONNECTION_PROTOCOL_REJECTED)
+            client.output,
+            vec![0x20, 2, 0, CONNECTION_IDENTIFIER_REFUSED]
         );
+        assert!(client.closing);
     }
 
     #[test]
-    fn test04_get_tipo_connect_correcto() {
-        let mut rng = rand::thread_rng();
-        let header: u8 = 16 + rng.gen_range(0..16);
-        let tipo = header.into();
-        assert!(matches!(tipo, Packet::Connect));
+    fn test06_connect_con_credenciales_se_acepta_recien_al_verificarlas() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mut client = ClientFlags::new(1, Arc::new(Mutex::new(clients_sender)));
+        let mut connect = connect_mqtt31("sensor");
+        connect.username = Some("franco".to_owned());
+        connect.password = Some(b"123pop".to_vec());
+        let authentication = make_connection(&mut client, connect, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(authentication.client_id, "sensor");
+        assert_eq!(authentication.username, "franco");
+        assert_eq!(authentication.password, b"123pop".to_vec());
+        assert!(client.client_id.is_none());
+        assert!(coordinator_receiver.try_recv().is_err());
+        finish_authentication(&mut client, authentication, true);
+        assert_eq!(client.client_id, Some("sensor".to_owned()));
+        match coordinator_receiver.recv().unwrap().command {
+            CoordinatorCommand::Connect(connection) => {
+                assert_eq!(connection.username, Some("franco".to_owned()));
+            }
+            _ => panic!("Expected a connect command"),
+        }
+
+        let mut refused = ClientFlags::new(2, client.sender.clone());
+        let mut connect = connect_mqtt31("sensor");
+        connect.username = Some("franco".to_owned());
+        connect.password = Some(b"321triat".to_vec());
+        let authentication = make_connection(&mut refused, connect, true)
+            .unwrap()
+            .unwrap();
+        finish_authentication(&mut refused, authentication, false);
+        assert_eq!(
+            refused.output,
+            vec![0x20, 2, 0, CONNECTION_USER_OR_PASS_REFUSED]
+        );
+        assert!(refused.closing);
+        assert!(refused.client_id.is_none());
+        assert!(coordinator_receiver.try_recv().is_err());
     }
 
     #[test]
-    fn test05_get_tipo_distinto_a_connect() {
-        let header: u8 = 32;
-        let tipo = header.into();
-        assert_ne!(matches!(tipo, Packet::Connect), true);
+    fn test07_client_id_con_prefijo_reservado_es_rechazado() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mut client = ClientFlags::new(1, Arc::new(Mutex::new(clients_sender)));
+        assert_eq!(
+            make_connection(&mut client, connect_mqtt31("$local-publisher"), false),
+            Err(CONNECTION_IDENTIFIER_REFUSED)
+        );
+        assert_eq!(
+            make_connection(&mut client, connect_mqtt31("$sensor"), false),
+            Err(CONNECTION_IDENTIFIER_REFUSED)
+        );
+        assert!(client.client_id.is_none());
+        assert!(coordinator_receiver.try_recv().is_err());
     }
 
     #[test]
-    fn test06_publish_pasado_al_coordinador() {
-        let (clients_sender, coordinator_receiver): (Sender<PacketThings>, Receiver<PacketThings>) =
-            mpsc::channel();
-        let mutex_clients_sender = Arc::new(Mutex::new(clients_sender));
-        let client_sender = Arc::clone(&mutex_clients_sender);
-        let listener = TcpListener::bind("127.0.0.1:25525").unwrap();
-        thread::spawn(move || loop {
-            let _connection = listener.accept().unwrap();
-        });
-        let mut client = ClientFlags {
-            id: 1,
-            client_id: None,
-            connection: &mut TcpStream::connect("127.0.0.1:25525").unwrap(),
-            sender: client_sender,
-            clean_session: 1,
-            keep_alive: 1000,
-        };
-        let mut buffer_packet: Vec<u8> = Vec::new();
-        let topic_subscribed = "as/tor".to_owned();
-        let mut topic_subscribed_bytes: Vec<u8> = topic_subscribed.as_bytes().to_vec();
-        buffer_packet.push(0);
-        buffer_packet.push(topic_subscribed_bytes.len() as u8);
-        buffer_packet.append(&mut topic_subscribed_bytes);
-        buffer_packet.push(3);
-        buffer_packet.push(4);
-        buffer_packet.push(5);
-        buffer_packet.push(6);
-        buffer_packet.push(7);
-        buffer_packet.push(8);
-        let byte_0: u8 = 0x31;
-        make_publication(&mut client, buffer_packet, byte_0).unwrap();
-        let packet_read = coordinator_receiver.recv().unwrap();
-        assert_eq!(packet_read.thread_id, 1);
-        let buff_read = packet_read.bytes;
-        assert_eq!(buff_read[0], 0x31);
-        assert_eq!(buff_read[2], 6);
-        assert_eq!(buff_read[9], 3);
-        assert_eq!(buff_read[10], 4);
-        assert_eq!(buff_read[12], 6);
-        assert_eq!(buff_read[14], 8);
+    fn test08_connect_sin_credenciales_en_listener_con_contrasena_es_rechazado() {
+        let (clients_sender, coordinator_receiver): (
+            Sender<ClientCommand>,
+            Receiver<ClientCommand>,
+        ) = mpsc::channel();
+        let mut client = ClientFlags::new(1, Arc::new(Mutex::new(clients_sender)));
+        assert_eq!(
+            make_connection(&mut client, connect_mqtt31("sensor"), true),
+            Err(CONNECTION_USER_OR_PASS_REFUSED)
+        );
+        let mut connect = connect_mqtt31("sensor");
+        connect.username = Some("franco".to_owned());
+        assert_eq!(
+            make_connection(&mut client, connect, true),
+            Err(CONNECTION_USER_OR_PASS_REFUSED)
+        );
+        assert_eq!(
+            connack_reason_code_v5(CONNECTION_USER_OR_PASS_REFUSED),
+            0x86
+        );
+        assert!(client.client_id.is_none());
+        assert!(coordinator_receiver.try_recv().is_err());
     }
 }
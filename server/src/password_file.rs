@@ -0,0 +1,379 @@
+//! # Password file
+//!
+//! Users allowed to connect with a username and password. Passwords are stored hashed with
+//! PBKDF2-HMAC-SHA256 and a random salt, one user per line:
+//!
+//! `user:pbkdf2-sha256$<iterations>$<salt>$<hash>`, with the salt and hash in hexadecimal.
+
+use ring::pbkdf2::{self, PBKDF2_HMAC_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{stderr, stdin, BufRead, ErrorKind, IsTerminal, Write};
+use std::num::NonZeroU32;
+use std::process::{Command, Stdio};
+use tracing::{info, warn};
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const DEFAULT_ITERATIONS: u32 = 100_000;
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+
+/// Salted hash of a password.
+struct PasswordHash {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct PasswordFile {
+    users: HashMap<String, PasswordHash>,
+}
+
+impl PasswordFile {
+    /// File without users.
+    pub fn new() -> Self {
+        PasswordFile::default()
+    }
+
+    /// Load the users of the file at `path`. A file that doesn't exist has no users.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error, with the line number, if the file can't be read or a line is invalid.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = match fs::read_to_string(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                warn!("Password file {} not found, no user can log in.", path);
+                return Ok(PasswordFile::new());
+            }
+            Err(error) => return Err(format!("Error reading {}: {}", path, error)),
+        };
+        let mut users: HashMap<String, PasswordHash> = HashMap::new();
+        for (number, line) in file.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (user, hash) = match decode_line(line) {
+                Some(entry) => entry,
+                None => return Err(format!("{}:{}: invalid password entry", path, number + 1)),
+            };
+            users.insert(user, hash);
+        }
+        info!("Loaded {} users from {}.", users.len(), path);
+        Ok(PasswordFile { users })
+    }
+
+    /// Write the users to `path`, sorted by name.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the file can't be written.
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut names: Vec<&String> = self.users.keys().collect();
+        names.sort();
+        let mut file = String::new();
+        for name in names {
+            let hash = &self.users[name];
+            file.push_str(&format!(
+                "{}:{}${}${}${}\n",
+                name,
+                HASH_SCHEME,
+                hash.iterations,
+                to_hex(&hash.salt),
+                to_hex(&hash.hash)
+            ));
+        }
+        let temporary_path = format!("{}.tmp", path);
+        fs::write(&temporary_path, file)?;
+        fs::rename(&temporary_path, path)
+    }
+
+    /// Whether `password` is the one of `user`.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some(hash) => pbkdf2::verify(
+                PBKDF2_HMAC_SHA256,
+                hash.iterations,
+                &hash.salt,
+                password.as_bytes(),
+                &hash.hash,
+            )
+            .is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.contains_key(user)
+    }
+
+    /// Set the password of `user`, adding it if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the username is empty or has a `:` or a line break, or no salt could be
+    /// generated.
+    pub fn set(&mut self, user: &str, password: &str) -> Result<(), String> {
+        self.set_with_iterations(user, password, DEFAULT_ITERATIONS)
+    }
+
+    /// Remove `user`. Returns whether it existed.
+    pub fn remove(&mut self, user: &str) -> bool {
+        self.users.remove(user).is_some()
+    }
+
+    fn set_with_iterations(
+        &mut self,
+        user: &str,
+        password: &str,
+        iterations: u32,
+    ) -> Result<(), String> {
+        if user.is_empty() || user.contains([':', '\n', '\r']) {
+            return Err("The username can't be empty nor contain ':' or line breaks".to_owned());
+        }
+        let iterations = match NonZeroU32::new(iterations) {
+            Some(iterations) => iterations,
+            None => return Err("Invalid number of iterations".to_owned()),
+        };
+        let mut salt = vec![0u8; SALT_LENGTH];
+        if SystemRandom::new().fill(&mut salt).is_err() {
+            return Err("Couldn't generate a salt".to_owned());
+        }
+        let mut hash = vec![0u8; HASH_LENGTH];
+        pbkdf2::derive(
+            PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            password.as_bytes(),
+            &mut hash,
+        );
+        self.users.insert(
+            user.to_owned(),
+            PasswordHash {
+                iterations,
+                salt,
+                hash,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Run the `passwd` subcommand, with arguments `<file> add|update <user> [-]` or
+/// `<file> remove <user>`. The password is asked for on stderr, or read from stdin if it is `-`.
+///
+/// # Errors
+///
+/// Returns Error with the usage if the arguments are invalid, or why the file wasn't changed.
+pub fn run_password_command(arguments: &[String]) -> Result<(), String> {
+    change_passwords(arguments, |user, piped| {
+        if piped {
+            read_password(stdin().lock())
+        } else {
+            ask_password(user)
+        }
+    })
+}
+
+/// Run the `passwd` subcommand, getting the password of the user with `password`, which also
+/// receives whether it was given as `-`.
+fn change_passwords<F>(arguments: &[String], password: F) -> Result<(), String>
+where
+    F: FnOnce(&str, bool) -> Result<String, String>,
+{
+    let usage =
+        "Usage: server passwd <file> add|update <user> [-] | server passwd <file> remove <user>";
+    let (path, action, user) = match arguments {
+        [path, action, user, ..] => (path, action.as_str(), user),
+        _ => return Err(usage.to_owned()),
+    };
+    let mut passwords = PasswordFile::load(path)?;
+    let piped = match &arguments[3..] {
+        [] => false,
+        [dash] if dash == "-" => true,
+        _ => return Err(usage.to_owned()),
+    };
+    match action {
+        "add" => {
+            if passwords.contains(user) {
+                return Err(format!("User {} already exists", user));
+            }
+            passwords.set(user, &password(user, piped)?)?;
+        }
+        "update" => {
+            if !passwords.contains(user) {
+                return Err(format!("User {} doesn't exist", user));
+            }
+            passwords.set(user, &password(user, piped)?)?;
+        }
+        "remove" if !piped => {
+            if !passwords.remove(user) {
+                return Err(format!("User {} doesn't exist", user));
+            }
+        }
+        _ => return Err(usage.to_owned()),
+    }
+    match passwords.save(path) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(format!("Error writing {}: {}", path, error)),
+    }
+}
+
+/// Ask for the password of `user` on stderr, without echoing it if stdin is a terminal.
+fn ask_password(user: &str) -> Result<String, String> {
+    eprint!("Password for {}: ", user);
+    let _ = stderr().flush();
+    let terminal = stdin().is_terminal();
+    let echo = |mode: &str| {
+        if terminal {
+            let _ = Command::new("stty")
+                .arg(mode)
+                .stdin(Stdio::inherit())
+                .status();
+        }
+    };
+    echo("-echo");
+    let password = read_password(stdin().lock());
+    echo("echo");
+    if terminal {
+        eprintln!();
+    }
+    password
+}
+
+/// First line of `input`, without the line break.
+fn read_password<R: BufRead>(mut input: R) -> Result<String, String> {
+    let mut line = String::new();
+    input
+        .read_line(&mut line)
+        .map_err(|error| format!("Couldn't read the password: {}", error))?;
+    let password = line.trim_end_matches(['\n', '\r']);
+    if password.is_empty() {
+        return Err("The password can't be empty".into());
+    }
+    Ok(password.to_owned())
+}
+
+/// Returns None if the line isn't `user:scheme$iterations$salt$hash`.
+fn decode_line(line: &str) -> Option<(String, PasswordHash)> {
+    let (user, hash) = line.split_once(':')?;
+    let fields: Vec<&str> = hash.split('$').collect();
+    if user.is_empty() || fields.len() != 4 || fields[0] != HASH_SCHEME {
+        return None;
+    }
+    let hash = PasswordHash {
+        iterations: NonZeroU32::new(fields[1].parse::<u32>().ok()?)?,
+        salt: from_hex(fields[2])?,
+        hash: from_hex(fields[3])?,
+    };
+    if hash.hash.is_empty() {
+        return None;
+    }
+    Some((user.to_owned(), hash))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test01_usuarios_guardados_se_verifican_con_su_contrasena() {
+        let path = file_path("passwords01.txt");
+        let mut passwords = PasswordFile::new();
+        passwords
+            .set_with_iterations("franco", "123pop", 10)
+            .unwrap();
+        passwords
+            .set_with_iterations("cuarzo", "simpon1", 10)
+            .unwrap();
+        assert!(passwords.set_with_iterations("a:b", "c", 10).is_err());
+        passwords.save(&path).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("123pop"));
+
+        let mut passwords = PasswordFile::load(&path).unwrap();
+        assert!(passwords.verify("franco", "123pop"));
+        assert!(!passwords.verify("franco", "simpon1"));
+        assert!(!passwords.verify("nadie", "123pop"));
+        assert!(passwords.remove("franco"));
+        assert!(!passwords.verify("franco", "123pop"));
+        assert!(passwords.verify("cuarzo", "simpon1"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test02_linea_invalida_se_informa_con_su_numero() {
+        let path = file_path("passwords02.txt");
+        let mut passwords = PasswordFile::new();
+        passwords
+            .set_with_iterations("franco", "123pop", 10)
+            .unwrap();
+        passwords.save(&path).unwrap();
+        let mut file = fs::read_to_string(&path).unwrap();
+        file.push_str("emanuel=321triat\n");
+        fs::write(&path, file).unwrap();
+        let error = PasswordFile::load(&path).err().unwrap();
+        assert!(error.ends_with(":2: invalid password entry"));
+        assert!(PasswordFile::load(&file_path("passwords03.txt"))
+            .unwrap()
+            .users
+            .is_empty());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test03_subcomando_agrega_actualiza_y_elimina_usuarios() {
+        let path = file_path("passwords04.txt");
+        let command = |arguments: &[&str], password: &str| {
+            let mut all = vec![path.clone()];
+            all.extend(arguments.iter().map(|argument| argument.to_string()));
+            change_passwords(&all, |_, _| Ok(password.to_owned()))
+        };
+        command(&["add", "percules"], "miriam5").unwrap();
+        assert!(command(&["add", "percules"], "otra").is_err());
+        assert!(command(&["add", "percules", "miriam5"], "otra").is_err());
+        command(&["update", "percules", "-"], "otra").unwrap();
+        assert!(PasswordFile::load(&path)
+            .unwrap()
+            .verify("percules", "otra"));
+        assert!(command(&["update", "nadie"], "otra").is_err());
+        assert!(command(&["add", "nadie\nfranco"], "otra").is_err());
+        assert!(command(&["remove"], "").is_err());
+        assert!(command(&["remove", "percules", "-"], "").is_err());
+        command(&["remove", "percules"], "").unwrap();
+        assert!(!PasswordFile::load(&path).unwrap().contains("percules"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test04_contraseña_leida_hasta_el_fin_de_linea() {
+        assert_eq!(
+            read_password(&b"secreto\r\notra\n"[..]),
+            Ok("secreto".to_owned())
+        );
+        assert!(read_password(&b"\n"[..]).is_err());
+        assert!(read_password(&b""[..]).is_err());
+        assert!(PasswordFile::new()
+            .set_with_iterations("franco\r", "123pop", 10)
+            .is_err());
+    }
+}
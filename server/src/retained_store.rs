@@ -0,0 +1,289 @@
+//! # Retained store
+//!
+//! Retained messages by topic. When a storage path is configured, every change is appended to a
+//! log file that is loaded at startup, so retained messages survive restarts. The log is
+//! compacted once it holds too many records that were replaced or deleted.
+
+use crate::coordinator::Message;
+use codec::properties::{decode_properties, encode_properties};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use tracing::{error, info, warn};
+
+const RECORD_DELETE: u8 = 0;
+const RECORD_RETAIN: u8 = 1;
+/// The log isn't compacted while it holds fewer records than this.
+const COMPACTION_MIN_RECORDS: usize = 1000;
+
+/// Append-only log of the retained messages.
+struct RetainedLog {
+    path: String,
+    file: File,
+    records: usize,
+}
+
+pub struct RetainedStore {
+    messages: HashMap<String, Message>,
+    log: Option<RetainedLog>,
+}
+
+impl RetainedStore {
+    /// Store kept only in memory.
+    pub fn new() -> Self {
+        RetainedStore {
+            messages: HashMap::new(),
+            log: None,
+        }
+    }
+
+    /// Load the retained messages logged at `path`, creating the log if it doesn't exist, and
+    /// compact it.
+    ///
+    /// A record cut short by a crash ends the log, and is discarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the log can't be read or written.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let buffer = match fs::read(path) {
+            Ok(buffer) => buffer,
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        let mut messages: HashMap<String, Message> = HashMap::new();
+        let mut index: usize = 0;
+        while index < buffer.len() {
+            match decode_record(&buffer[index..]) {
+                Some((topic, message, size)) => {
+                    match message {
+                        Some(message) => messages.insert(topic, message),
+                        None => messages.remove(&topic),
+                    };
+                    index += size;
+                }
+                None => {
+                    warn!("Discarding the incomplete end of the retained messages log.");
+                    break;
+                }
+            }
+        }
+        let mut store = RetainedStore {
+            messages,
+            log: None,
+        };
+        store.log = Some(store.write_log(path)?);
+        info!("Loaded {} retained messages.", store.messages.len());
+        Ok(store)
+    }
+
+    /// Retain `message` on `topic`. A message with an empty payload deletes the retained one.
+    pub fn retain(&mut self, topic: String, message: Message) {
+        let record = if message.payload.is_empty() {
+            if self.messages.remove(&topic).is_none() {
+                return;
+            }
+            encode_record(&topic, None)
+        } else {
+            let record = encode_record(&topic, Some(&message));
+            self.messages.insert(topic, message);
+            record
+        };
+        let log = match self.log.as_mut() {
+            Some(log) => log,
+            None => return,
+        };
+        match record {
+            Some(record) => match log.file.write_all(&record) {
+                Ok(_) => log.records += 1,
+                Err(_) => error!("Error appending to the retained messages log."),
+            },
+            None => error!("Error encoding the properties of a retained message."),
+        }
+        if log.records > COMPACTION_MIN_RECORDS.max(2 * self.messages.len()) {
+            self.compact();
+        }
+    }
+
+    /// Delete the retained message of `topic`. Returns whether there was one.
+    pub fn remove(&mut self, topic: &str) -> bool {
+        let existed = self.messages.contains_key(topic);
+        let deletion = Message {
+            payload: Vec::new(),
+            qos: 0,
+            properties: Vec::new(),
+        };
+        self.retain(topic.to_owned(), deletion);
+        existed
+    }
+
+    /// Number of retained messages.
+    pub fn count(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Message)> {
+        self.messages.iter()
+    }
+
+    /// Rewrite the log with only the current retained messages.
+    fn compact(&mut self) {
+        let path = match &self.log {
+            Some(log) => log.path.clone(),
+            None => return,
+        };
+        match self.write_log(&path) {
+            Ok(log) => {
+                info!("Retained messages log compacted.");
+                self.log = Some(log);
+            }
+            Err(_) => error!("Error compacting the retained messages log."),
+        }
+    }
+
+    /// Write the current retained messages to a new log that replaces the one at `path`, and open
+    /// it to append.
+    fn write_log(&self, path: &str) -> std::io::Result<RetainedLog> {
+        let temporary_path = format!("{}.tmp", path);
+        let mut buffer: Vec<u8> = Vec::new();
+        for (topic, message) in self.messages.iter() {
+            match encode_record(topic, Some(message)) {
+                Some(mut record) => buffer.append(&mut record),
+                None => error!("Error encoding the properties of a retained message."),
+            }
+        }
+        let mut file = File::create(&temporary_path)?;
+        file.write_all(&buffer)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, path)?;
+        Ok(RetainedLog {
+            path: path.to_owned(),
+            file: OpenOptions::new().append(true).open(path)?,
+            records: self.messages.len(),
+        })
+    }
+}
+
+/// Record of the log: its type, the topic and, if retained, the QoS, properties and payload.
+///
+/// Returns None if the properties can't be encoded.
+fn encode_record(topic: &str, message: Option<&Message>) -> Option<Vec<u8>> {
+    let mut record: Vec<u8> = Vec::new();
+    match message {
+        Some(_) => record.push(RECORD_RETAIN),
+        None => record.push(RECORD_DELETE),
+    }
+    record.extend(&(topic.len() as u16).to_be_bytes());
+    record.extend(topic.as_bytes());
+    if let Some(message) = message {
+        record.push(message.qos);
+        record.append(&mut encode_properties(&message.properties).ok()?);
+        record.extend(&(message.payload.len() as u32).to_be_bytes());
+        record.extend(&message.payload);
+    }
+    Some(record)
+}
+
+/// Decode the record at the start of `buffer`, returning its topic, the message retained or None
+/// if it was deleted, and the size of the record.
+///
+/// Returns None if the record is incomplete or invalid.
+fn decode_record(buffer: &[u8]) -> Option<(String, Option<Message>, usize)> {
+    let kind = *buffer.first()?;
+    let topic_length = u16::from_be_bytes([*buffer.get(1)?, *buffer.get(2)?]) as usize;
+    let mut index = 3 + topic_length;
+    let topic = std::str::from_utf8(buffer.get(3..index)?).ok()?.to_owned();
+    if kind == RECORD_DELETE {
+        return Some((topic, None, index));
+    }
+    if kind != RECORD_RETAIN {
+        return None;
+    }
+    let qos = *buffer.get(index)?;
+    let (properties, properties_size) = decode_properties(buffer.get((index + 1)..)?).ok()?;
+    index += 1 + properties_size;
+    let payload_length = buffer.get(index..(index + 4))?;
+    let payload_length = u32::from_be_bytes([
+        payload_length[0],
+        payload_length[1],
+        payload_length[2],
+        payload_length[3],
+    ]) as usize;
+    index += 4;
+    let payload = buffer.get(index..(index + payload_length))?.to_vec();
+    index += payload_length;
+    let message = Message {
+        payload,
+        qos,
+        properties,
+    };
+    Some((topic, Some(message), index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::properties::{Property, PropertyValue, USER_PROPERTY};
+
+    fn message(payload: &[u8], qos: u8) -> Message {
+        Message {
+            payload: payload.to_vec(),
+            qos,
+            properties: Vec::new(),
+        }
+    }
+
+    fn log_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test01_mensajes_retenidos_y_borrados_se_recuperan_al_reiniciar() {
+        let path = log_path("retained01.log");
+        let mut store = RetainedStore::load(&path).unwrap();
+        store.retain("as/tillero".to_owned(), message(b"viejo", 0));
+        store.retain("as/tillero".to_owned(), message(b"nuevo", 1));
+        store.retain("ma/derero".to_owned(), message(&[0, 159, 255], 2));
+        store.retain("ma/derero".to_owned(), message(&[], 0));
+        let mut with_properties = message(b"miau", 0);
+        with_properties.properties = vec![Property::new(
+            USER_PROPERTY,
+            PropertyValue::Utf8StringPair("sala".to_owned(), "3".to_owned()),
+        )];
+        store.retain("ga/to".to_owned(), with_properties);
+        drop(store);
+
+        let store = RetainedStore::load(&path).unwrap();
+        let messages: HashMap<&String, &Message> = store.iter().collect();
+        assert_eq!(messages.len(), 2);
+        let retained = messages.get(&"as/tillero".to_owned()).unwrap();
+        assert_eq!(retained.payload, b"nuevo".to_vec());
+        assert_eq!(retained.qos, 1);
+        assert_eq!(
+            messages.get(&"ga/to".to_owned()).unwrap().properties.len(),
+            1
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test02_registro_cortado_se_descarta_y_el_log_se_compacta() {
+        let path = log_path("retained02.log");
+        let mut buffer = encode_record("as", Some(&message(b"uno", 0))).unwrap();
+        buffer.append(&mut encode_record("as", Some(&message(b"dos", 0))).unwrap());
+        let complete_size = buffer.len();
+        let mut cut = encode_record("ma", Some(&message(b"tres", 0))).unwrap();
+        cut.truncate(6);
+        buffer.append(&mut cut);
+        fs::write(&path, &buffer).unwrap();
+
+        let store = RetainedStore::load(&path).unwrap();
+        let messages: Vec<(&String, &Message)> = store.iter().collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1.payload, b"dos".to_vec());
+        assert!(fs::read(&path).unwrap().len() < complete_size);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,361 @@
+//! # Session store
+//!
+//! Sessions of the clients that connect without clean session. When a storage directory is
+//! configured, each session is saved to its own file and restored at startup, so subscriptions
+//! and unacknowledged messages survive restarts. Connections and subscriptions are saved right
+//! away, while the messages queued and acknowledged are only marked, and the coordinator saves the
+//! marked sessions in batches.
+//!
+//! Files are named after the SHA-256 of the client id, which is stored inside the file, so any id
+//! fits in a file name.
+
+use crate::client::{Client, Subscription};
+use codec::{decode_packet, ControlPacket, Publish, MQTT_VERSION_5};
+use ring::digest::{digest, SHA256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tracing::{error, info, warn};
+
+const SESSION_EXTENSION: &str = "session";
+
+/// Persistent state of a client session.
+pub struct Session {
+    pub client_id: String,
+    pub protocol_version: u8,
+    pub topics: Vec<Subscription>,
+    pub publishes_received: Vec<Publish>,
+    pub pubrels_pending: Vec<u16>,
+    pub qos2_received: Vec<u16>,
+    pub last_packet_id: u16,
+}
+
+pub struct SessionStore {
+    directory: Option<PathBuf>,
+    /// Client ids with a saved session.
+    saved: HashSet<String>,
+    /// Clients whose session changed since it was last saved.
+    changed: HashSet<usize>,
+}
+
+impl SessionStore {
+    /// Store that doesn't save sessions.
+    pub fn new() -> Self {
+        SessionStore {
+            directory: None,
+            saved: HashSet::new(),
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Store saving sessions in `directory`, which is created if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the directory can't be created.
+    pub fn open(directory: &str) -> std::io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        Ok(SessionStore {
+            directory: Some(PathBuf::from(directory)),
+            saved: HashSet::new(),
+            changed: HashSet::new(),
+        })
+    }
+
+    /// Sessions saved in the store. Files that can't be decoded are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the directory can't be read.
+    pub fn load_all(&mut self) -> std::io::Result<Vec<Session>> {
+        let directory = match &self.directory {
+            Some(directory) => directory,
+            None => return Ok(Vec::new()),
+        };
+        let mut sessions: Vec<Session> = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(SESSION_EXTENSION)
+            {
+                continue;
+            }
+            match fs::read(&path)
+                .ok()
+                .and_then(|buffer| decode_session(&buffer))
+            {
+                Some(session) => {
+                    self.saved.insert(session.client_id.clone());
+                    sessions.push(session);
+                }
+                None => warn!("Skipping invalid session file {}", path.display()),
+            }
+        }
+        info!("Loaded {} persistent sessions.", sessions.len());
+        Ok(sessions)
+    }
+
+    /// Mark the session of `client` to be saved with the next [`SessionStore::take_changed`].
+    pub fn mark_changed(&mut self, client: &Client) {
+        if self.directory.is_some() && client.clean_session == 0 {
+            self.changed.insert(client.thread_id);
+        }
+    }
+
+    /// Clients whose session was marked since the last call.
+    pub fn take_changed(&mut self) -> HashSet<usize> {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Save the session of `client`, or remove the saved one if the client uses clean session.
+    pub fn save(&mut self, client: &Client) {
+        self.changed.remove(&client.thread_id);
+        if client.client_id.is_empty() {
+            return;
+        }
+        let path = match self.path(&client.client_id) {
+            Some(path) => path,
+            None => return,
+        };
+        if client.clean_session == 1 {
+            if self.saved.remove(&client.client_id) {
+                match fs::remove_file(&path) {
+                    Ok(_) => {}
+                    Err(error) if error.kind() == ErrorKind::NotFound => {}
+                    Err(_) => error!("Error removing a persistent session."),
+                }
+            }
+            return;
+        }
+        let temporary_path = path.with_extension("tmp");
+        let written = fs::write(&temporary_path, encode_session(client))
+            .and_then(|_| fs::rename(&temporary_path, &path));
+        match written {
+            Ok(_) => {
+                self.saved.insert(client.client_id.clone());
+            }
+            Err(_) => error!("Error saving a persistent session."),
+        }
+    }
+
+    /// File of the session, named after the SHA-256 of the client id in hexadecimal.
+    fn path(&self, client_id: &str) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        let name: String = digest(&SHA256, client_id.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Some(directory.join(format!("{}.{}", name, SESSION_EXTENSION)))
+    }
+}
+
+/// Client id, protocol version, last packet identifier, subscriptions, QoS 2 identifiers and
+/// unacknowledged publishes, which are encoded as MQTT 5 packets to keep their properties.
+fn encode_session(client: &Client) -> Vec<u8> {
+    let mut buffer: Vec<u8> = Vec::new();
+    write_string(&mut buffer, &client.client_id);
+    buffer.push(client.protocol_version);
+    buffer.extend(&client.last_packet_id.to_be_bytes());
+    buffer.extend(&(client.topics.len() as u32).to_be_bytes());
+    for subscription in client.topics.iter() {
+        write_string(&mut buffer, &subscription.topic);
+        buffer.push(subscription.qos);
+    }
+    for identifiers in [&client.pubrels_pending, &client.qos2_received].iter() {
+        buffer.extend(&(identifiers.len() as u32).to_be_bytes());
+        for packet_identifier in identifiers.iter() {
+            buffer.extend(&packet_identifier.to_be_bytes());
+        }
+    }
+    let publishes: Vec<Vec<u8>> = client
+        .publishes_received
+        .iter()
+        .filter(|publish| publish.qos > 0)
+        .filter_map(|publish| publish.encode(MQTT_VERSION_5).ok())
+        .collect();
+    buffer.extend(&(publishes.len() as u32).to_be_bytes());
+    for publish in publishes {
+        buffer.extend(&(publish.len() as u32).to_be_bytes());
+        buffer.extend(publish);
+    }
+    buffer
+}
+
+fn write_string(buffer: &mut Vec<u8>, string: &str) {
+    buffer.extend(&(string.len() as u32).to_be_bytes());
+    buffer.extend(string.as_bytes());
+}
+
+/// Returns None if the session is truncated or invalid.
+fn decode_session(buffer: &[u8]) -> Option<Session> {
+    let mut reader = SessionReader { buffer, index: 0 };
+    let client_id = reader.read_string()?;
+    let protocol_version = reader.read_bytes(1)?[0];
+    let last_packet_id = reader.read_u16()?;
+    let mut topics: Vec<Subscription> = Vec::new();
+    for _ in 0..reader.read_u32()? {
+        let topic = reader.read_string()?;
+        let qos = reader.read_bytes(1)?[0];
+        topics.push(Subscription { topic, qos });
+    }
+    let mut pubrels_pending: Vec<u16> = Vec::new();
+    for _ in 0..reader.read_u32()? {
+        pubrels_pending.push(reader.read_u16()?);
+    }
+    let mut qos2_received: Vec<u16> = Vec::new();
+    for _ in 0..reader.read_u32()? {
+        qos2_received.push(reader.read_u16()?);
+    }
+    let mut publishes_received: Vec<Publish> = Vec::new();
+    for _ in 0..reader.read_u32()? {
+        let size = reader.read_u32()? as usize;
+        match decode_packet(reader.read_bytes(size)?, MQTT_VERSION_5) {
+            Ok(ControlPacket::Publish(publish)) => publishes_received.push(publish),
+            _ => return None,
+        }
+    }
+    Some(Session {
+        client_id,
+        protocol_version,
+        topics,
+        publishes_received,
+        pubrels_pending,
+        qos2_received,
+        last_packet_id,
+    })
+}
+
+/// Cursor over a saved session that returns None instead of going out of bounds.
+struct SessionReader<'a> {
+    buffer: &'a [u8],
+    index: usize,
+}
+
+impl<'a> SessionReader<'a> {
+    fn read_bytes(&mut self, size: usize) -> Option<&'a [u8]> {
+        let bytes = self.buffer.get(self.index..self.index.checked_add(size)?)?;
+        self.index += size;
+        Some(bytes)
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        let bytes = self.read_bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        let bytes = self.read_bytes(4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_string(&mut self) -> Option<String> {
+        let size = self.read_u32()? as usize;
+        let bytes = self.read_bytes(size)?;
+        std::str::from_utf8(bytes).ok().map(str::to_owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::test_channel;
+
+    fn store_directory(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test01_sesion_guardada_se_restaura_con_suscripciones_y_mensajes() {
+        let directory = store_directory("sessions01");
+        let mut store = SessionStore::open(&directory).unwrap();
+        let (channel, _receiver) = test_channel(1);
+        let mut client = Client::new(1, channel);
+        client.client_id = "sensor/ñ".to_owned();
+        client.clean_session = 0;
+        client.subscribe("as/tillero".to_owned(), 1);
+        client.subscribe("ma/#".to_owned(), 2);
+        client.pubrels_pending.push(7);
+        client.last_packet_id = 9;
+        client.publishes_received.push(Publish {
+            dup: true,
+            qos: 1,
+            retain: false,
+            topic_name: "as/tillero".to_owned(),
+            packet_identifier: Some(9),
+            properties: Vec::new(),
+            payload: vec![0, 159, 255],
+        });
+        store.save(&client);
+
+        let sessions = SessionStore::open(&directory).unwrap().load_all().unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.client_id, "sensor/ñ");
+        assert_eq!(session.topics.len(), 2);
+        assert_eq!(session.topics[1].topic, "ma/#");
+        assert_eq!(session.topics[1].qos, 2);
+        assert_eq!(session.pubrels_pending, vec![7]);
+        assert_eq!(session.last_packet_id, 9);
+        assert_eq!(session.publishes_received, client.publishes_received);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test02_cliente_con_clean_session_borra_la_sesion_guardada() {
+        let directory = store_directory("sessions02");
+        let mut store = SessionStore::open(&directory).unwrap();
+        let (channel, _receiver) = test_channel(1);
+        let mut client = Client::new(1, channel);
+        client.client_id = "medidor".to_owned();
+        client.clean_session = 0;
+        store.save(&client);
+        assert_eq!(store.load_all().unwrap().len(), 1);
+        client.clean_session = 1;
+        store.save(&client);
+        assert!(store.load_all().unwrap().is_empty());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test03_client_id_largo_se_guarda_con_nombre_de_largo_fijo() {
+        let directory = store_directory("sessions03");
+        let mut store = SessionStore::open(&directory).unwrap();
+        let (channel, _receiver) = test_channel(1);
+        let mut client = Client::new(1, channel);
+        client.client_id = "sensor".repeat(200);
+        client.clean_session = 0;
+        store.save(&client);
+        let names: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[0].len(), 64 + 1 + SESSION_EXTENSION.len());
+        let sessions = SessionStore::open(&directory).unwrap().load_all().unwrap();
+        assert_eq!(sessions[0].client_id, client.client_id);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test04_sesiones_marcadas_se_entregan_una_vez() {
+        let directory = store_directory("sessions05");
+        let mut store = SessionStore::open(&directory).unwrap();
+        let (channel, _receiver) = test_channel(3);
+        let mut client = Client::new(3, channel);
+        client.clean_session = 0;
+        store.mark_changed(&client);
+        store.mark_changed(&client);
+        assert_eq!(
+            store.take_changed().into_iter().collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert!(store.take_changed().is_empty());
+        client.clean_session = 1;
+        store.mark_changed(&client);
+        assert!(store.take_changed().is_empty());
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
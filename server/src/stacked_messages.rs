@@ -1,41 +1,46 @@
 //! # Stacked Messages
 //!
-//! Thread to send messages to client (specifically QoS 1 publish messages) until it returns the _Puback_ packet.
+//! Thread to send messages to client (specifically QoS 1 and 2 publish messages) until it returns the _Puback_ or _Pubrec_ packet,
+//! and _Pubrel_ packets until it returns the _Pubcomp_ packet.
 
 use crate::client::Client;
+use crate::metrics::Metrics;
+use codec::{ControlPacket, PubRel};
 use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::Duration;
 use tracing::{info, warn};
 
-/// Send queued publish messages to client periodically.
-pub fn run_stacked_coordinator(lock_clients: Arc<Mutex<HashMap<usize, Client>>>) {
+/// Send queued publish messages to client periodically, until `stop` receives a message or its
+/// sender is dropped. Each pass updates the session gauges of `metrics`.
+pub fn run_stacked_coordinator(
+    lock_clients: Arc<Mutex<HashMap<usize, Client>>>,
+    stop: Receiver<()>,
+    metrics: Arc<Metrics>,
+) {
     loop {
-        thread::sleep(Duration::from_secs(1));
+        match stop.recv_timeout(Duration::from_secs(1)) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => {
+                info!("Stacked messages coordinator stopped.");
+                return;
+            }
+        }
         match lock_clients.lock() {
             Ok(mut locked) => {
+                let (mut connected, mut persistent, mut queued) = (0, 0, 0);
                 for (_, client) in locked.iter_mut() {
+                    if is_kept_session(client) {
+                        persistent += 1;
+                    }
                     if !client.disconnected {
-                        for message in client.publishes_received.iter() {
-                            match client.channel.send(message.clone()) {
-                                Ok(_) => {
-                                    info!("Publish sent successfully.");
-                                }
-                                Err(_) => warn!("Error sending publish to client."),
-                            }
-                        }
-                        let mut i: usize = 0;
-                        while i < client.publishes_received.len() {
-                            let byte_0 = client.publishes_received[i as usize][0];
-                            if (byte_0 & 0x02) == 0 {
-                                client.publishes_received.remove(i as usize);
-                            } else {
-                                i += 1;
-                            }
-                        }
+                        connected += 1;
+                        metrics.retransmitted(resend_pending(client));
                     }
+                    queued += client.publishes_received.len() + client.pubrels_pending.len();
                 }
+                metrics.set_sessions(connected, persistent, queued);
             }
             Err(_) => {
                 warn!("Unable to get the clients lock.");
@@ -43,3 +48,84 @@ pub fn run_stacked_coordinator(lock_clients: Arc<Mutex<HashMap<usize, Client>>>)
         }
     }
 }
+
+/// Whether `client` is disconnected and the broker keeps its session until it connects again.
+fn is_kept_session(client: &Client) -> bool {
+    client.disconnected && client.clean_session == 0 && !client.client_id.is_empty()
+}
+
+/// Send the queued publishes and pending pubrels of `client`, dropping the QoS 0 publishes from
+/// the queue. Returns how many QoS 1 and 2 publishes and pubrels were sent again.
+fn resend_pending(client: &mut Client) -> usize {
+    let mut resent = 0;
+    for message in client.publishes_received.iter() {
+        match client.send_packet(&ControlPacket::Publish(message.clone())) {
+            Ok(_) => {
+                info!("Publish sent successfully.");
+                if message.qos > 0 {
+                    resent += 1;
+                }
+            }
+            Err(_) => warn!("Error sending publish to client."),
+        }
+    }
+    for packet_identifier in client.pubrels_pending.iter() {
+        let pubrel = PubRel::new(*packet_identifier);
+        match client.send_packet(&ControlPacket::PubRel(pubrel)) {
+            Ok(_) => {
+                info!("Pubrel sent successfully.");
+                resent += 1;
+            }
+            Err(_) => warn!("Error sending pubrel to client."),
+        }
+    }
+    client.publishes_received.retain(|publish| publish.qos > 0);
+    resent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::test_channel;
+    use codec::Publish;
+
+    fn publish(qos: u8, packet_identifier: Option<u16>) -> Publish {
+        Publish {
+            dup: false,
+            qos,
+            retain: false,
+            topic_name: "casa/luz".to_owned(),
+            packet_identifier,
+            properties: Vec::new(),
+            payload: b"on".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test01_solo_cuenta_los_paquetes_reenviados() {
+        let (channel, receiver) = test_channel(1);
+        let mut client = Client::new(1, channel);
+        client.publishes_received =
+            vec![publish(0, None), publish(1, Some(1)), publish(2, Some(2))];
+        client.pubrels_pending = vec![3];
+        assert_eq!(resend_pending(&mut client), 3);
+        assert_eq!(receiver.try_iter().count(), 4);
+        assert_eq!(client.publishes_received.len(), 2);
+        drop(receiver);
+        assert_eq!(resend_pending(&mut client), 0);
+        assert_eq!(client.publishes_received.len(), 2);
+    }
+
+    #[test]
+    fn test02_solo_cuenta_sesiones_de_clientes_desconectados() {
+        let (channel, _receiver) = test_channel(1);
+        let mut client = Client::new(1, channel);
+        client.client_id = "medidor".to_owned();
+        assert!(is_kept_session(&client));
+        client.disconnected = false;
+        assert!(!is_kept_session(&client));
+        client.disconnected = true;
+        client.clean_session = 1;
+        assert!(!is_kept_session(&client));
+    }
+}
@@ -0,0 +1,125 @@
+//! # $SYS topics
+//!
+//! Status of the broker, which the coordinator publishes to the `$SYS/broker/...` topics every
+//! interval of the configuration. A timer thread asks the coordinator to publish it, so the
+//! messages are routed like the ones of the clients, with QoS 0 and without being retained.
+//!
+//! Topics starting with `$` don't match filters starting with `#` or `+`, so only the clients
+//! subscribed to `$SYS/...` receive them.
+
+use crate::coordinator::{ClientCommand, CoordinatorCommand};
+use crate::metrics::Totals;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const SYS_TOPIC_PREFIX: &str = "$SYS/broker/";
+const VERSION: &str = concat!("server ", env!("CARGO_PKG_VERSION"));
+
+/// Numbers of the broker when its status is published.
+pub struct BrokerStatus {
+    pub uptime: Duration,
+    pub connected_clients: usize,
+    /// Subscriptions of every session, including the ones of disconnected clients.
+    pub subscriptions: usize,
+    /// Subscriptions of the connected clients.
+    pub active_subscriptions: usize,
+    pub retained_messages: usize,
+    pub totals: Totals,
+}
+
+impl BrokerStatus {
+    /// Topic and payload of each `$SYS` message.
+    pub fn messages(&self) -> Vec<(String, String)> {
+        let values = [
+            ("version", VERSION.to_owned()),
+            ("uptime", format!("{} seconds", self.uptime.as_secs())),
+            ("clients/connected", self.connected_clients.to_string()),
+            ("subscriptions/count", self.subscriptions.to_string()),
+            (
+                "subscriptions/active",
+                self.active_subscriptions.to_string(),
+            ),
+            (
+                "messages/received",
+                self.totals.messages_received.to_string(),
+            ),
+            ("messages/sent", self.totals.messages_sent.to_string()),
+            ("bytes/received", self.totals.bytes_received.to_string()),
+            ("bytes/sent", self.totals.bytes_sent.to_string()),
+            (
+                "retained messages/count",
+                self.retained_messages.to_string(),
+            ),
+        ];
+        values
+            .iter()
+            .map(|(topic, payload)| (format!("{}{}", SYS_TOPIC_PREFIX, topic), payload.clone()))
+            .collect()
+    }
+}
+
+/// Ask the coordinator to publish the status of the broker every `interval`, until `stop`
+/// receives a message or its sender is dropped.
+pub fn run_sys_timer(
+    coordinator_sender: Arc<Mutex<Sender<ClientCommand>>>,
+    interval: Duration,
+    stop: Receiver<()>,
+) {
+    info!("Launched thread $SYS timer.");
+    loop {
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => {
+                info!("$SYS timer stopped.");
+                return;
+            }
+        }
+        let command = ClientCommand {
+            thread_id: 0,
+            command: CoordinatorCommand::PublishSysTopics,
+        };
+        let sent = match coordinator_sender.lock() {
+            Ok(sender) => sender.send(command).is_ok(),
+            Err(_) => false,
+        };
+        if !sent {
+            warn!("The coordinator stopped, $SYS timer stopped.");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_mensajes_del_estado_del_broker() {
+        let status = BrokerStatus {
+            uptime: Duration::from_millis(61_500),
+            connected_clients: 2,
+            subscriptions: 5,
+            active_subscriptions: 3,
+            retained_messages: 1,
+            totals: Totals {
+                messages_received: 10,
+                messages_sent: 20,
+                bytes_received: 300,
+                bytes_sent: 400,
+            },
+        };
+        let messages = status.messages();
+        assert_eq!(messages.len(), 10);
+        assert!(messages
+            .iter()
+            .all(|(topic, _)| topic.starts_with("$SYS/broker/")));
+        assert!(messages.contains(&("$SYS/broker/uptime".to_owned(), "61 seconds".to_owned())));
+        assert!(messages.contains(&(
+            "$SYS/broker/subscriptions/active".to_owned(),
+            "3".to_owned()
+        )));
+        assert!(messages.contains(&("$SYS/broker/bytes/sent".to_owned(), "400".to_owned())));
+    }
+}
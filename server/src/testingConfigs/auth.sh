@@ -0,0 +1,6 @@
+#!/bin/sh
+# Acepta solo al usuario franco con la contraseña guardada en el almacen externo
+read -r client_id
+read -r username
+read -r password
+[ "$username" = "franco" ] && [ "$password" = "desde-comando" ]
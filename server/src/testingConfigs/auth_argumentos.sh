@@ -0,0 +1,6 @@
+#!/bin/sh
+# Acepta al usuario del primer argumento con la contraseña del segundo
+read -r client_id
+read -r username
+read -r password
+[ "$username" = "$1" ] && [ "$password" = "$2" ]
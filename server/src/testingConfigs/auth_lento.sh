@@ -0,0 +1,7 @@
+#!/bin/sh
+# Tarda en responder por el usuario lento, mientras el servidor debe seguir atendiendo a los demas
+read -r client_id
+read -r username
+read -r password
+[ "$username" = "lento" ] && sleep 3
+[ "$password" = "desde-comando" ]
@@ -0,0 +1,180 @@
+//! # TLS
+//!
+//! Configuration of the MQTTS listener, loaded from PEM certificate and key files, and the
+//! identity of the clients that authenticate with a certificate.
+
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::sync::Arc;
+use webpki::EndEntityCert;
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_OID: u8 = 0x06;
+/// Object identifier 2.5.4.3 of the common name attribute.
+const OID_COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+
+/// Names of the certificate a client authenticated with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateIdentity {
+    pub common_name: Option<String>,
+    pub dns_names: Vec<String>,
+}
+
+impl CertificateIdentity {
+    /// Name that stands in for the username: the common name, or else the first DNS name.
+    pub fn username(&self) -> Option<&str> {
+        self.common_name
+            .as_deref()
+            .or_else(|| self.dns_names.first().map(String::as_str))
+    }
+}
+
+/// Server TLS configuration with the certificate chain at `certificate_path` and the private key
+/// at `key_path`. With `client_ca_path`, clients must present a certificate issued by one of the
+/// certificate authorities in that bundle.
+///
+/// # Errors
+///
+/// Returns Error if a file can't be read, or doesn't hold a valid certificate chain or key.
+pub fn load_server_config(
+    certificate_path: &str,
+    key_path: &str,
+    client_ca_path: Option<&str>,
+) -> std::io::Result<Arc<ServerConfig>> {
+    let certificates = load_certificates(certificate_path)?;
+    let key = load_private_key(key_path)?;
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?;
+    let builder = match client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(path)? {
+                roots.add(certificate).map_err(invalid_data)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(invalid_data)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certificates, key)
+        .map_err(invalid_data)?;
+    Ok(Arc::new(config))
+}
+
+/// Identity of the verified certificate the client presented, if any.
+pub fn peer_identity(connection: &ServerConnection) -> Option<CertificateIdentity> {
+    certificate_identity(connection.peer_certificates()?.first()?)
+}
+
+/// Common name of the subject and DNS names of the certificate.
+///
+/// Returns None if the certificate can't be parsed.
+fn certificate_identity(certificate: &CertificateDer<'_>) -> Option<CertificateIdentity> {
+    let certificate = EndEntityCert::try_from(certificate).ok()?;
+    Some(CertificateIdentity {
+        common_name: common_name(certificate.subject()),
+        dns_names: certificate.valid_dns_names().map(str::to_owned).collect(),
+    })
+}
+
+/// Common name in the DER encoded attributes of a subject.
+fn common_name(mut subject: &[u8]) -> Option<String> {
+    while let Some((tag, set, rest)) = der_element(subject) {
+        subject = rest;
+        if tag != TAG_SET {
+            continue;
+        }
+        let (tag, attribute, _) = der_element(set)?;
+        if tag != TAG_SEQUENCE {
+            continue;
+        }
+        let (tag, oid, value) = der_element(attribute)?;
+        if tag == TAG_OID && oid == OID_COMMON_NAME {
+            let (_, value, _) = der_element(value)?;
+            return std::str::from_utf8(value).ok().map(str::to_owned);
+        }
+    }
+    None
+}
+
+/// Tag, content and the bytes after the DER element at the start of `buffer`.
+fn der_element(buffer: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *buffer.first()?;
+    let first_length = *buffer.get(1)? as usize;
+    let (length, start) = if first_length < 0x80 {
+        (first_length, 2)
+    } else {
+        let size = first_length & 0x7F;
+        if size == 0 || size > 4 {
+            return None;
+        }
+        let length = buffer
+            .get(2..2 + size)?
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+        (length, 2 + size)
+    };
+    let content = buffer.get(start..start.checked_add(length)?)?;
+    Some((tag, content, &buffer[start + length..]))
+}
+
+fn load_certificates(path: &str) -> std::io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certificates = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err(invalid_data(format!("No certificates found in {}", path)));
+    }
+    Ok(certificates)
+}
+
+fn load_private_key(path: &str) -> std::io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match rustls_pemfile::private_key(&mut reader)? {
+        Some(key) => Ok(key),
+        None => Err(invalid_data(format!("No private key found in {}", path))),
+    }
+}
+
+fn invalid_data<E: ToString>(error: E) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+
+    #[test]
+    fn test01_identidad_del_certificado_con_cn_y_san() {
+        let mut params = CertificateParams::new(vec!["sensor.local".to_owned()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::OrganizationName, "Rustico");
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "sensor-01");
+        let certificate = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+        let identity = certificate_identity(certificate.der()).unwrap();
+        assert_eq!(identity.common_name, Some("sensor-01".to_owned()));
+        assert_eq!(identity.dns_names, vec!["sensor.local".to_owned()]);
+        assert_eq!(identity.username(), Some("sensor-01"));
+
+        let mut params = CertificateParams::new(vec!["sensor.local".to_owned()]).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        let certificate = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+        let identity = certificate_identity(certificate.der()).unwrap();
+        assert_eq!(identity.common_name, None);
+        assert_eq!(identity.username(), Some("sensor.local"));
+    }
+}
@@ -0,0 +1,189 @@
+//! # Topic tree
+//!
+//! Subscription index keyed by topic level, to find every session subscribed to a topic in a
+//! single lookup instead of comparing the topic with each subscription.
+
+use std::collections::HashMap;
+
+const LEVEL_SEPARATOR: char = '/';
+const SINGLE_LEVEL_WILDCARD: &str = "+";
+const MULTI_LEVEL_WILDCARD: &str = "#";
+/// First character of the topics of the server, such as `$SYS/...`.
+const SERVER_TOPIC_PREFIX: char = '$';
+
+/// A topic level: the sessions whose filter ends on it, with their QoS, and the next levels.
+#[derive(Default)]
+struct Node {
+    subscribers: HashMap<usize, u8>,
+    children: HashMap<String, Node>,
+}
+
+impl Node {
+    fn is_empty(&self) -> bool {
+        self.subscribers.is_empty() && self.children.is_empty()
+    }
+}
+
+/// Topic filters of every session, identified by the `thread_id` of its client.
+#[derive(Default)]
+pub struct SubscriptionTree {
+    root: Node,
+}
+
+impl SubscriptionTree {
+    pub fn new() -> Self {
+        SubscriptionTree::default()
+    }
+
+    /// Subscribe the session to `filter` with `qos`, replacing the QoS if it was already subscribed.
+    pub fn subscribe(&mut self, filter: &str, thread_id: usize, qos: u8) {
+        let mut node = &mut self.root;
+        for level in filter.split(LEVEL_SEPARATOR) {
+            node = node.children.entry(level.to_owned()).or_default();
+        }
+        node.subscribers.insert(thread_id, qos);
+    }
+
+    /// Unsubscribe the session from `filter`. Returns whether it was subscribed to it.
+    pub fn unsubscribe(&mut self, filter: &str, thread_id: usize) -> bool {
+        let levels: Vec<&str> = filter.split(LEVEL_SEPARATOR).collect();
+        remove_subscription(&mut self.root, &levels, thread_id)
+    }
+
+    /// Remove every subscription of the session.
+    pub fn remove_session(&mut self, thread_id: usize) {
+        remove_subscriber(&mut self.root, thread_id);
+    }
+
+    /// Sessions subscribed to `topic`, with the maximum QoS granted among their matching filters.
+    /// Topics starting with `$` don't match filters starting with a wildcard.
+    pub fn matches(&self, topic: &str) -> HashMap<usize, u8> {
+        let levels: Vec<&str> = topic.split(LEVEL_SEPARATOR).collect();
+        let mut sessions: HashMap<usize, u8> = HashMap::new();
+        if topic.starts_with(SERVER_TOPIC_PREFIX) {
+            if let Some(child) = self.root.children.get(levels[0]) {
+                collect_matches(child, &levels[1..], &mut sessions);
+            }
+        } else {
+            collect_matches(&self.root, &levels, &mut sessions);
+        }
+        sessions
+    }
+}
+
+fn remove_subscription(node: &mut Node, levels: &[&str], thread_id: usize) -> bool {
+    let (level, rest) = match levels.split_first() {
+        Some(split) => split,
+        None => return node.subscribers.remove(&thread_id).is_some(),
+    };
+    let (removed, empty) = match node.children.get_mut(*level) {
+        Some(child) => (
+            remove_subscription(child, rest, thread_id),
+            child.is_empty(),
+        ),
+        None => return false,
+    };
+    if empty {
+        node.children.remove(*level);
+    }
+    removed
+}
+
+fn remove_subscriber(node: &mut Node, thread_id: usize) {
+    node.subscribers.remove(&thread_id);
+    for child in node.children.values_mut() {
+        remove_subscriber(child, thread_id);
+    }
+    node.children.retain(|_, child| !child.is_empty());
+}
+
+/// A `#` filter also matches its parent level, so `sport/#` receives `sport`.
+fn collect_matches(node: &Node, levels: &[&str], sessions: &mut HashMap<usize, u8>) {
+    if let Some(child) = node.children.get(MULTI_LEVEL_WILDCARD) {
+        add_subscribers(child, sessions);
+    }
+    match levels.split_first() {
+        Some((level, rest)) => {
+            if let Some(child) = node.children.get(*level) {
+                collect_matches(child, rest, sessions);
+            }
+            if let Some(child) = node.children.get(SINGLE_LEVEL_WILDCARD) {
+                collect_matches(child, rest, sessions);
+            }
+        }
+        None => add_subscribers(node, sessions),
+    }
+}
+
+fn add_subscribers(node: &Node, sessions: &mut HashMap<usize, u8>) {
+    for (thread_id, qos) in node.subscribers.iter() {
+        let granted = sessions.entry(*thread_id).or_insert(*qos);
+        if *granted < *qos {
+            *granted = *qos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_wildcards_devuelven_sesiones_con_qos_maximo() {
+        let mut tree = SubscriptionTree::new();
+        tree.subscribe("sport/tennis/+/coconut", 1, 0);
+        tree.subscribe("sport/tennis/player1/#", 2, 1);
+        tree.subscribe("sport/#", 1, 2);
+        tree.subscribe("sport/+", 3, 1);
+        let sessions = tree.matches("sport/tennis/player1/coconut");
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.get(&1), Some(&2));
+        assert_eq!(sessions.get(&2), Some(&1));
+        let sessions = tree.matches("sport/tennis/player1");
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.contains_key(&2));
+        assert!(!sessions.contains_key(&3));
+        assert_eq!(tree.matches("sport").len(), 1);
+        assert!(tree.matches("music/tennis").is_empty());
+    }
+
+    #[test]
+    fn test02_desuscribir_y_eliminar_sesion_limpia_el_arbol() {
+        let mut tree = SubscriptionTree::new();
+        tree.subscribe("as/tillero", 1, 1);
+        tree.subscribe("as/+", 1, 0);
+        tree.subscribe("as/tillero", 2, 2);
+        assert!(tree.unsubscribe("as/tillero", 1));
+        assert!(!tree.unsubscribe("as/tillero", 1));
+        assert!(!tree.unsubscribe("ma/derero", 1));
+        assert_eq!(tree.matches("as/tillero").get(&1), Some(&0));
+        tree.remove_session(1);
+        let sessions = tree.matches("as/tillero");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions.get(&2), Some(&2));
+        tree.remove_session(2);
+        assert!(tree.root.is_empty());
+    }
+
+    #[test]
+    fn test03_suscribirse_de_nuevo_reemplaza_el_qos() {
+        let mut tree = SubscriptionTree::new();
+        tree.subscribe("as/tor", 1, 2);
+        tree.subscribe("as/tor", 1, 0);
+        assert_eq!(tree.matches("as/tor").get(&1), Some(&0));
+    }
+
+    #[test]
+    fn test04_topicos_con_dolar_no_matchean_wildcards_del_primer_nivel() {
+        let mut tree = SubscriptionTree::new();
+        tree.subscribe("#", 1, 0);
+        tree.subscribe("+/broker/#", 2, 0);
+        tree.subscribe("$SYS/#", 3, 0);
+        tree.subscribe("$SYS/+/uptime", 4, 0);
+        let sessions = tree.matches("$SYS/broker/uptime");
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.contains_key(&3));
+        assert!(sessions.contains_key(&4));
+        assert_eq!(tree.matches("otro/broker/uptime").len(), 2);
+    }
+}
@@ -1,4 +1,9 @@
+/// Whether `topic_publish` matches the filter `topic_subscribed`. Topics starting with `$`, such
+/// as `$SYS/...`, don't match filters starting with a wildcard.
 pub fn compare_topic(topic_publish: &str, topic_subscribed: &str) -> bool {
+    if topic_publish.starts_with('$') && topic_subscribed.starts_with(['#', '+']) {
+        return false;
+    }
     if *topic_publish == *topic_subscribed || topic_subscribed == "#" {
         return true;
     }
@@ -85,4 +90,12 @@ mod tests {
         let subscription = String::from("sport/tennis/+");
         assert!(!compare_topic(&topic, &subscription));
     }
+
+    #[test]
+    fn test09_topicos_con_dolar_no_matchean_wildcards_del_primer_nivel() {
+        assert!(!compare_topic("$SYS/broker/uptime", "#"));
+        assert!(!compare_topic("$SYS/broker/uptime", "+/broker/uptime"));
+        assert!(compare_topic("$SYS/broker/uptime", "$SYS/#"));
+        assert!(compare_topic("$SYS/broker/uptime", "$SYS/+/uptime"));
+    }
 }